@@ -1 +1 @@
-cargo run --release --bin sdftool data\ganymede-and-jupiter.sdf data\ganymede-and-jupiter-compressed.sdf -o xZy -oz
\ No newline at end of file
+cargo run --release --bin sdftool data\ganymede-and-jupiter.sdf data\ganymede-and-jupiter-compressed.sdf -o xzy -oz
\ No newline at end of file
@@ -0,0 +1,459 @@
+use std::ffi::CStr;
+use std::fs;
+use std::io;
+
+use ash::vk;
+
+use crate::minivector::*;
+use crate::vulkan_base::VulkanBase;
+
+pub const DEFAULT_BENCHMARK_WARMUP_FRAMES: u32 = 60;
+pub const DEFAULT_BENCHMARK_FRAMES: u32 = 600;
+
+// Name of the CPU frame time pass in the reports
+pub const CPU_FRAME_PASS_NAME: &str = "frame (cpu)";
+
+pub struct BenchmarkParams {
+    // .csv = CSV report, anything else = JSON
+    pub report_file: String,
+    pub warmup_frames: u32,
+    pub frames: u32,
+}
+
+pub struct DeviceInfo {
+    pub name: String,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    pub driver_version: u32,
+    pub api_version: u32,
+}
+
+impl DeviceInfo {
+    pub fn new(base: &VulkanBase) -> DeviceInfo {
+        let properties = unsafe { base.instance.get_physical_device_properties(base.pdevice) };
+        let name = unsafe { CStr::from_ptr(properties.device_name.as_ptr()) };
+        DeviceInfo {
+            name: name.to_string_lossy().into_owned(),
+            vendor_id: properties.vendor_id,
+            device_id: properties.device_id,
+            driver_version: properties.driver_version,
+            api_version: properties.api_version,
+        }
+    }
+
+    // Driver version packing is vendor specific
+    pub fn driver_version_string(&self) -> String {
+        let v = self.driver_version;
+        match self.vendor_id {
+            // NVIDIA
+            0x10de => format!(
+                "{}.{}.{}.{}",
+                v >> 22,
+                (v >> 14) & 0xff,
+                (v >> 6) & 0xff,
+                v & 0x3f
+            ),
+            // Intel (Windows)
+            0x8086 if cfg!(windows) => format!("{}.{}", v >> 14, v & 0x3fff),
+            _ => format!(
+                "{}.{}.{}",
+                vk::api_version_major(v),
+                vk::api_version_minor(v),
+                vk::api_version_patch(v)
+            ),
+        }
+    }
+
+    pub fn api_version_string(&self) -> String {
+        let v = self.api_version;
+        format!(
+            "{}.{}.{}",
+            vk::api_version_major(v),
+            vk::api_version_minor(v),
+            vk::api_version_patch(v)
+        )
+    }
+}
+
+pub struct PassStats {
+    pub name: String,
+    pub samples: usize,
+    pub average_ms: f64,
+    pub min_ms: f64,
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+impl PassStats {
+    // Non-finite samples (broken timer readings) are ignored
+    pub fn new(name: &str, samples: &[f64]) -> PassStats {
+        let mut sorted: Vec<f64> = samples.iter().copied().filter(|v| v.is_finite()).collect();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        // Nearest rank
+        let percentile = |p: f64| -> f64 {
+            if sorted.is_empty() {
+                return 0.0;
+            }
+            let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+            sorted[rank.clamp(1, sorted.len()) - 1]
+        };
+
+        let average_ms = if sorted.is_empty() {
+            0.0
+        } else {
+            sorted.iter().sum::<f64>() / sorted.len() as f64
+        };
+
+        PassStats {
+            name: name.to_string(),
+            samples: sorted.len(),
+            average_ms,
+            min_ms: sorted.first().copied().unwrap_or(0.0),
+            p50_ms: percentile(50.0),
+            p90_ms: percentile(90.0),
+            p95_ms: percentile(95.0),
+            p99_ms: percentile(99.0),
+            max_ms: sorted.last().copied().unwrap_or(0.0),
+        }
+    }
+}
+
+pub struct BenchmarkRun {
+    pub technique: String,
+    pub passes: Vec<PassStats>,
+}
+
+impl BenchmarkRun {
+    // CPU frame times first, then GPU passes (GpuProfiler::take_samples order)
+    pub fn new(
+        technique: &str,
+        frame_times_ms: &[f64],
+        pass_names: &[String],
+        pass_samples: &[Vec<f64>],
+    ) -> BenchmarkRun {
+        let mut passes = vec![PassStats::new(CPU_FRAME_PASS_NAME, frame_times_ms)];
+        for (name, samples) in pass_names.iter().zip(pass_samples.iter()) {
+            passes.push(PassStats::new(name, samples));
+        }
+        BenchmarkRun {
+            technique: technique.to_string(),
+            passes,
+        }
+    }
+}
+
+pub struct BenchmarkReport {
+    pub application: String,
+    pub device: DeviceInfo,
+    pub width: u32,
+    pub height: u32,
+    pub num_instances: usize,
+    pub warmup_frames: u32,
+    pub frames: u32,
+    pub runs: Vec<BenchmarkRun>,
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// JSON has no NaN or infinity
+fn json_number(v: f64) -> String {
+    if v.is_finite() {
+        format!("{:.4}", v)
+    } else {
+        "null".to_string()
+    }
+}
+
+fn csv_string(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+impl BenchmarkReport {
+    pub fn to_json(&self) -> String {
+        let mut out = String::from("{\n");
+        out += &format!("  \"application\": {},\n", json_string(&self.application));
+        out += "  \"device\": {\n";
+        out += &format!("    \"name\": {},\n", json_string(&self.device.name));
+        out += &format!("    \"vendor_id\": {},\n", self.device.vendor_id);
+        out += &format!("    \"device_id\": {},\n", self.device.device_id);
+        out += &format!(
+            "    \"driver_version\": {},\n",
+            json_string(&self.device.driver_version_string())
+        );
+        out += &format!(
+            "    \"driver_version_raw\": {},\n",
+            self.device.driver_version
+        );
+        out += &format!(
+            "    \"api_version\": {}\n",
+            json_string(&self.device.api_version_string())
+        );
+        out += "  },\n";
+        out += &format!("  \"width\": {},\n", self.width);
+        out += &format!("  \"height\": {},\n", self.height);
+        out += &format!("  \"num_instances\": {},\n", self.num_instances);
+        out += &format!("  \"warmup_frames\": {},\n", self.warmup_frames);
+        out += &format!("  \"frames\": {},\n", self.frames);
+        out += "  \"runs\": [\n";
+        for (run_index, run) in self.runs.iter().enumerate() {
+            out += "    {\n";
+            out += &format!("      \"technique\": {},\n", json_string(&run.technique));
+            out += "      \"passes\": [\n";
+            for (pass_index, pass) in run.passes.iter().enumerate() {
+                out += &format!(
+                    "        {{\"name\": {}, \"samples\": {}, \"average_ms\": {}, \"min_ms\": {}, \"p50_ms\": {}, \"p90_ms\": {}, \"p95_ms\": {}, \"p99_ms\": {}, \"max_ms\": {}}}",
+                    json_string(&pass.name),
+                    pass.samples,
+                    json_number(pass.average_ms),
+                    json_number(pass.min_ms),
+                    json_number(pass.p50_ms),
+                    json_number(pass.p90_ms),
+                    json_number(pass.p95_ms),
+                    json_number(pass.p99_ms),
+                    json_number(pass.max_ms)
+                );
+                out += if pass_index + 1 < run.passes.len() {
+                    ",\n"
+                } else {
+                    "\n"
+                };
+            }
+            out += "      ]\n";
+            out += if run_index + 1 < self.runs.len() {
+                "    },\n"
+            } else {
+                "    }\n"
+            };
+        }
+        out += "  ]\n";
+        out += "}\n";
+        out
+    }
+
+    // One row per technique and pass
+    pub fn to_csv(&self) -> String {
+        let mut out = String::from("application,device,vendor_id,device_id,driver_version,api_version,width,height,num_instances,warmup_frames,frames,technique,pass,samples,average_ms,min_ms,p50_ms,p90_ms,p95_ms,p99_ms,max_ms\n");
+        for run in self.runs.iter() {
+            for pass in run.passes.iter() {
+                out += &format!(
+                    "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{:.4},{:.4},{:.4},{:.4},{:.4},{:.4},{:.4}\n",
+                    csv_string(&self.application),
+                    csv_string(&self.device.name),
+                    self.device.vendor_id,
+                    self.device.device_id,
+                    self.device.driver_version_string(),
+                    self.device.api_version_string(),
+                    self.width,
+                    self.height,
+                    self.num_instances,
+                    self.warmup_frames,
+                    self.frames,
+                    csv_string(&run.technique),
+                    csv_string(&pass.name),
+                    pass.samples,
+                    pass.average_ms,
+                    pass.min_ms,
+                    pass.p50_ms,
+                    pass.p90_ms,
+                    pass.p95_ms,
+                    pass.p99_ms,
+                    pass.max_ms
+                );
+            }
+        }
+        out
+    }
+
+    pub fn store(&self, filename: &str) -> io::Result<()> {
+        let text = if filename.to_lowercase().ends_with(".csv") {
+            self.to_csv()
+        } else {
+            self.to_json()
+        };
+        fs::write(filename, text)
+    }
+}
+
+// Deterministic benchmark camera: one orbit around the origin over the frame count,
+// looking at the origin. Frame 0 is at (0, height, radius).
+pub fn orbit_camera(radius: f32, height: f32, frame: u32, frames: u32) -> (Vec3, Vec3) {
+    let angle = std::f32::consts::PI * 2.0 * frame as f32 / frames.max(1) as f32;
+    let position = Vec3 {
+        x: angle.sin() * radius,
+        y: height,
+        z: angle.cos() * radius,
+    };
+    (position, (-position).normalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_report(runs: Vec<BenchmarkRun>) -> BenchmarkReport {
+        BenchmarkReport {
+            application: "test".to_string(),
+            device: DeviceInfo {
+                name: "Device \"X\", rev 2".to_string(),
+                vendor_id: 0x1002,
+                device_id: 1,
+                driver_version: vk::make_api_version(0, 2, 0, 1),
+                api_version: vk::make_api_version(0, 1, 2, 3),
+            },
+            width: 64,
+            height: 32,
+            num_instances: 128,
+            warmup_frames: 1,
+            frames: 3,
+            runs,
+        }
+    }
+
+    #[test]
+    fn pass_stats_empty() {
+        let stats = PassStats::new("empty", &[]);
+        assert_eq!(stats.samples, 0);
+        for v in [
+            stats.average_ms,
+            stats.min_ms,
+            stats.p50_ms,
+            stats.p99_ms,
+            stats.max_ms,
+        ] {
+            assert_eq!(v, 0.0);
+        }
+    }
+
+    #[test]
+    fn pass_stats_one_sample() {
+        let stats = PassStats::new("one", &[2.5]);
+        assert_eq!(stats.samples, 1);
+        for v in [
+            stats.average_ms,
+            stats.min_ms,
+            stats.p50_ms,
+            stats.p90_ms,
+            stats.p95_ms,
+            stats.p99_ms,
+            stats.max_ms,
+        ] {
+            assert_eq!(v, 2.5);
+        }
+    }
+
+    #[test]
+    fn pass_stats_percentiles() {
+        // 1..=100 shuffled, nearest rank percentile of N is N
+        let samples: Vec<f64> = (0..100).map(|i| ((i * 37) % 100 + 1) as f64).collect();
+        let stats = PassStats::new("many", &samples);
+        assert_eq!(stats.samples, 100);
+        assert_eq!(stats.min_ms, 1.0);
+        assert_eq!(stats.p50_ms, 50.0);
+        assert_eq!(stats.p90_ms, 90.0);
+        assert_eq!(stats.p95_ms, 95.0);
+        assert_eq!(stats.p99_ms, 99.0);
+        assert_eq!(stats.max_ms, 100.0);
+        assert_eq!(stats.average_ms, 50.5);
+
+        let stats = PassStats::new("three", &[3.0, 1.0, 2.0]);
+        assert_eq!(stats.p50_ms, 2.0);
+        assert_eq!(stats.p90_ms, 3.0);
+        assert_eq!(stats.average_ms, 2.0);
+    }
+
+    #[test]
+    fn pass_stats_non_finite() {
+        let stats = PassStats::new(
+            "nan",
+            &[f64::NAN, 1.0, f64::INFINITY, 3.0, f64::NEG_INFINITY],
+        );
+        assert_eq!(stats.samples, 2);
+        assert_eq!(stats.min_ms, 1.0);
+        assert_eq!(stats.max_ms, 3.0);
+        assert_eq!(stats.average_ms, 2.0);
+
+        let stats = PassStats::new("all nan", &[f64::NAN]);
+        assert_eq!(stats.samples, 0);
+        assert_eq!(stats.max_ms, 0.0);
+    }
+
+    #[test]
+    fn json_strings() {
+        assert_eq!(json_string(""), "\"\"");
+        assert_eq!(json_string("plain"), "\"plain\"");
+        assert_eq!(json_string("a \"b\", c"), "\"a \\\"b\\\", c\"");
+        assert_eq!(json_string("back\\slash"), "\"back\\\\slash\"");
+        assert_eq!(
+            json_string("tab\tline\n\u{1}"),
+            "\"tab\\u0009line\\u000a\\u0001\""
+        );
+        assert_eq!(json_string("µs"), "\"µs\"");
+    }
+
+    #[test]
+    fn json_numbers() {
+        assert_eq!(json_number(1.5), "1.5000");
+        assert_eq!(json_number(f64::NAN), "null");
+        assert_eq!(json_number(f64::INFINITY), "null");
+        assert_eq!(json_number(f64::NEG_INFINITY), "null");
+    }
+
+    #[test]
+    fn csv_strings() {
+        assert_eq!(csv_string(""), "");
+        assert_eq!(csv_string("plain"), "plain");
+        assert_eq!(csv_string("a,b"), "\"a,b\"");
+        assert_eq!(csv_string("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_string("two\nlines"), "\"two\nlines\"");
+        assert_eq!(csv_string("cr\r"), "\"cr\r\"");
+    }
+
+    #[test]
+    fn report_formats() {
+        let mut run = BenchmarkRun::new(
+            "culling",
+            &[16.0, 17.0, 15.0],
+            &["pass, \"quoted\"".to_string()],
+            &[vec![1.0, 2.0]],
+        );
+        // Stats can't produce non-finite values, but the JSON writer must not break on them
+        run.passes[1].max_ms = f64::INFINITY;
+        let report = test_report(vec![run, BenchmarkRun::new("empty", &[], &[], &[])]);
+
+        let json = report.to_json();
+        assert!(!json.contains("inf") && !json.contains("NaN"));
+        assert!(json.contains("\"max_ms\": null"));
+        assert!(json.contains("\"name\": \"Device \\\"X\\\", rev 2\""));
+        assert!(json.contains("\"p50_ms\": 16.0000"));
+        assert_eq!(json.matches('{').count(), json.matches('}').count());
+        assert_eq!(json.matches('[').count(), json.matches(']').count());
+
+        let csv = report.to_csv();
+        let lines: Vec<&str> = csv.lines().collect();
+        // Header + 2 passes + 1 pass
+        assert_eq!(lines.len(), 4);
+        assert!(lines[1].starts_with("test,\"Device \"\"X\"\", rev 2\",4098,1,2.0.1,1.2.3,"));
+        assert!(lines[2].contains(",culling,\"pass, \"\"quoted\"\"\",2,"));
+        assert!(lines[3].contains(",empty,frame (cpu),0,"));
+    }
+}
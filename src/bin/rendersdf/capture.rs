@@ -0,0 +1,71 @@
+use std::fs;
+use std::io;
+
+use rust_test::minivector::*;
+
+// Occlusion culling uses the previous frame depth pyramid, let it settle before capturing
+pub const CAPTURE_WARMUP_FRAMES: u32 = 3;
+
+pub struct CameraKeyframe {
+    pub position: Vec3,
+    pub direction: Vec3,
+}
+
+pub struct CaptureParams {
+    // Single capture after the warmup frames, then exit
+    pub screenshot: Option<String>,
+    // Scripted camera path, one keyframe per frame. Exits at the end of the path.
+    pub camera_path: Option<String>,
+    pub capture_every: u32,
+    pub capture_dir: String,
+}
+
+// Keyframe per line: "px py pz dx dy dz". Empty lines and # comments are skipped.
+pub fn load_camera_path(filename: &str) -> io::Result<Vec<CameraKeyframe>> {
+    let text = fs::read_to_string(filename)?;
+
+    let mut path = Vec::new();
+    for (line_index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let values: Vec<f32> = line
+            .split_whitespace()
+            .map(|v| v.parse())
+            .collect::<Result<_, _>>()
+            .ok()
+            .filter(|values: &Vec<f32>| values.len() == 6)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid camera keyframe at line {}", line_index + 1),
+                )
+            })?;
+
+        path.push(CameraKeyframe {
+            position: Vec3 {
+                x: values[0],
+                y: values[1],
+                z: values[2],
+            },
+            direction: Vec3 {
+                x: values[3],
+                y: values[4],
+                z: values[5],
+            }
+            .normalize(),
+        });
+    }
+
+    Ok(path)
+}
+
+// Alpha is forced opaque: the clear color alpha is zero, but the swapchain composites opaque
+pub fn store_capture(mut image: image::RgbaImage, filename: &str) -> image::ImageResult<()> {
+    for pixel in image.pixels_mut() {
+        pixel[3] = 255;
+    }
+    image.save(filename)
+}
@@ -316,6 +316,14 @@ impl Culling {
         }
     }
 
+    // Culling disabled: all instances visible, drawn without the argument buffer
+    pub fn set_all_visible(&self, num_instances: usize) {
+        let visibility: Vec<VisibilityData> = (0..num_instances as u32)
+            .map(|index| VisibilityData { index })
+            .collect();
+        self.visibility_buffer.copy_from_slice(&visibility[..], 0);
+    }
+
     pub fn update(&self, uniforms: &CullingUniforms) {
         self.uniform_buffer.copy_from_slice(&[*uniforms], 0);
     }
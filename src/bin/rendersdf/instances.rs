@@ -1,5 +1,5 @@
-pub const NUM_INSTANCES: usize = 1024 * 1024;
-pub const CLOUD_RADIUS: f32 = 8000.0;
+pub const DEFAULT_NUM_INSTANCES: usize = 1024 * 1024;
+pub const DEFAULT_CLOUD_RADIUS: f32 = 8000.0;
 
 use rand::Rng;
 use rand::SeedableRng;
@@ -24,9 +24,16 @@ pub struct Instances {
 }
 
 impl Instances {
-    pub fn new(device: &Device, allocator: &mut Allocator, instance_radius: f32) -> Instances {
+    pub fn new(
+        device: &Device,
+        allocator: &mut Allocator,
+        instance_radius: f32,
+        num_instances: usize,
+        cloud_radius: f32,
+        seed: Option<u64>,
+    ) -> Instances {
         let instances_buffer_info = vk::BufferCreateInfo {
-            size: (std::mem::size_of::<InstanceData>() * NUM_INSTANCES) as u64,
+            size: (std::mem::size_of::<InstanceData>() * num_instances) as u64,
             usage: vk::BufferUsageFlags::STORAGE_BUFFER,
             sharing_mode: vk::SharingMode::EXCLUSIVE,
             ..Default::default()
@@ -42,21 +49,24 @@ impl Instances {
         let instances_buffer_descriptor = vk::DescriptorBufferInfo {
             buffer: instances_buffer.buffer,
             offset: 0,
-            range: (std::mem::size_of::<InstanceData>() * NUM_INSTANCES) as u64,
+            range: (std::mem::size_of::<InstanceData>() * num_instances) as u64,
         };
 
         // Random cloud of SDF box instances
         //let mut rng = rand::thread_rng();
-        let mut rng = rand::rngs::StdRng::from_seed([
-            0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10,
-            11, 12, 13, 14, 15,
-        ]);
-        let instances_buffer_data: Vec<InstanceData> = (0..NUM_INSTANCES)
+        let mut rng = match seed {
+            Some(seed) => rand::rngs::StdRng::seed_from_u64(seed),
+            None => rand::rngs::StdRng::from_seed([
+                0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9,
+                10, 11, 12, 13, 14, 15,
+            ]),
+        };
+        let instances_buffer_data: Vec<InstanceData> = (0..num_instances)
             .map(|_i| InstanceData {
                 position: Vec4 {
-                    x: rng.gen_range(-CLOUD_RADIUS, CLOUD_RADIUS),
-                    y: rng.gen_range(-CLOUD_RADIUS, CLOUD_RADIUS),
-                    z: rng.gen_range(-CLOUD_RADIUS, CLOUD_RADIUS),
+                    x: rng.gen_range(-cloud_radius, cloud_radius),
+                    y: rng.gen_range(-cloud_radius, cloud_radius),
+                    z: rng.gen_range(-cloud_radius, cloud_radius),
                     w: instance_radius,
                 },
             })
@@ -1,12 +1,21 @@
 #![allow(dead_code)]
 
-const SDF_LEVELS: u32 = 6;
+const DEFAULT_SDF_FILE: &str = "data/ganymede-and-jupiter.sdf";
+const DEFAULT_SDF_LEVELS: u32 = 6;
+const DEFAULT_PYRAMID_DIMENSION: u32 = 512;
 const NUM_DESCRIPTORS_PER_TYPE: u32 = 1024;
 const NUM_DESCRIPTOR_SETS: u32 = 1024;
-const ENABLE_CULLING_DEBUG: bool = false;
+
+// GPU profiler passes
+const GPU_PASS_NAMES: [&str; 4] = ["cube render", "culling debug", "depth pyramid", "culling"];
+const GPU_PASS_CUBES: usize = 0;
+const GPU_PASS_CULLING_DEBUG: usize = 1;
+const GPU_PASS_DEPTH_PYRAMID: usize = 2;
+const GPU_PASS_CULLING: usize = 3;
 
 extern crate winit;
 
+mod capture;
 mod culling;
 mod culling_debug;
 mod depth_pyramid;
@@ -14,11 +23,17 @@ mod instances;
 mod render_cubes;
 mod sdf_texture;
 
+use rust_test::benchmark;
+use rust_test::gpu_profiler;
 use rust_test::minivector;
 use rust_test::sdf;
+use rust_test::sdf_mips;
 use rust_test::vulkan_base;
 use rust_test::vulkan_helpers;
 
+use std::env;
+use std::fs;
+use std::process;
 use std::time::Instant;
 
 use ash::vk;
@@ -30,12 +45,16 @@ use winit::{
     window::WindowBuilder,
 };
 
+use benchmark::*;
+use gpu_profiler::*;
 use minivector::*;
 use sdf::*;
+use sdf_mips::*;
 
 use vulkan_base::*;
 use vulkan_helpers::*;
 
+use capture::*;
 use culling::*;
 use culling_debug::*;
 use depth_pyramid::*;
@@ -49,24 +68,260 @@ pub struct Vertex {
     pub uv: [f32; 2],
 }
 
-pub struct SdfLevel {
-    pub sdf: Sdf,
-    pub offset: u32,
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SdfFileFormat {
+    // Versioned SDF file or legacy raw / grad+zlib file (detected)
+    Auto,
+    Raw,
+    Zlib,
+}
+
+pub struct RenderParams {
+    pub file_in: String,
+    pub format: SdfFileFormat,
+    pub sdf_levels: u32,
+    pub mip_filter: MipFilter,
+    pub width: u32,
+    pub height: u32,
+    pub num_instances: usize,
+    pub cloud_radius: f32,
+    // None = fixed default seed
+    pub seed: Option<u64>,
+    pub culling: bool,
+    pub culling_debug: bool,
+    pub vsync: bool,
+    // Frame count for offscreen rendering without a window
+    pub headless: Option<u32>,
+    pub pyramid_dimension: u32,
+    pub capture: CaptureParams,
+    pub benchmark: Option<BenchmarkParams>,
+}
+
+fn parse_value<T: std::str::FromStr>(
+    args: &[String],
+    i: usize,
+    err: &'static str,
+) -> Result<T, &'static str> {
+    args.get(i).and_then(|v| v.parse().ok()).ok_or(err)
+}
+
+fn parse_args(args: &[String]) -> Result<RenderParams, &str> {
+    let mut params = RenderParams {
+        file_in: DEFAULT_SDF_FILE.to_string(),
+        format: SdfFileFormat::Auto,
+        sdf_levels: DEFAULT_SDF_LEVELS,
+        mip_filter: DEFAULT_MIP_FILTER,
+        width: 1920,
+        height: 1080,
+        num_instances: DEFAULT_NUM_INSTANCES,
+        cloud_radius: DEFAULT_CLOUD_RADIUS,
+        seed: None,
+        culling: true,
+        culling_debug: false,
+        vsync: false,
+        headless: None,
+        pyramid_dimension: DEFAULT_PYRAMID_DIMENSION,
+        capture: CaptureParams {
+            screenshot: None,
+            camera_path: None,
+            capture_every: 1,
+            capture_dir: "capture".to_string(),
+        },
+        benchmark: None,
+    };
+    let mut warmup_frames = DEFAULT_BENCHMARK_WARMUP_FRAMES;
+    let mut frames = DEFAULT_BENCHMARK_FRAMES;
+
+    let mut i = 1;
+    if args.len() > 1 && !args[1].starts_with('-') {
+        params.file_in = args[1].clone();
+        i = 2;
+    }
+
+    while i < args.len() {
+        match &args[i][..] {
+            "-raw" => params.format = SdfFileFormat::Raw,
+            "-zlib" => params.format = SdfFileFormat::Zlib,
+            "-levels" => {
+                i += 1;
+                params.sdf_levels = parse_value(args, i, "Invalid mip level count")?;
+            }
+            "-mipfilter" => {
+                i += 1;
+                params.mip_filter = args
+                    .get(i)
+                    .and_then(|name| MipFilter::from_name(name))
+                    .ok_or("Unknown mip filter")?;
+            }
+            "-size" => {
+                params.width = parse_value(args, i + 1, "Invalid resolution")?;
+                params.height = parse_value(args, i + 2, "Invalid resolution")?;
+                i += 2;
+            }
+            "-instances" => {
+                i += 1;
+                params.num_instances = parse_value(args, i, "Invalid instance count")?;
+            }
+            "-radius" => {
+                i += 1;
+                params.cloud_radius = parse_value(args, i, "Invalid cloud radius")?;
+            }
+            "-seed" => {
+                i += 1;
+                params.seed = Some(parse_value(args, i, "Invalid seed")?);
+            }
+            "-noculling" => params.culling = false,
+            "-debug" => params.culling_debug = true,
+            "-vsync" => params.vsync = true,
+            "-headless" => {
+                i += 1;
+                params.headless = Some(parse_value(args, i, "Invalid headless frame count")?);
+            }
+            "-pyramid" => {
+                i += 1;
+                params.pyramid_dimension = parse_value(args, i, "Invalid depth pyramid size")?;
+            }
+            "-screenshot" => {
+                i += 1;
+                params.capture.screenshot =
+                    Some(args.get(i).ok_or("Missing screenshot file")?.clone());
+            }
+            "-path" => {
+                i += 1;
+                params.capture.camera_path =
+                    Some(args.get(i).ok_or("Missing camera path file")?.clone());
+            }
+            "-every" => {
+                i += 1;
+                params.capture.capture_every = parse_value(args, i, "Invalid capture interval")?;
+            }
+            "-out" => {
+                i += 1;
+                params.capture.capture_dir =
+                    args.get(i).ok_or("Missing capture directory")?.clone();
+            }
+            "-benchmark" => {
+                i += 1;
+                params.benchmark = Some(BenchmarkParams {
+                    report_file: args.get(i).ok_or("Missing benchmark report file")?.clone(),
+                    warmup_frames: 0,
+                    frames: 0,
+                });
+            }
+            "-warmup" => {
+                i += 1;
+                warmup_frames = parse_value(args, i, "Invalid warmup frame count")?;
+            }
+            "-frames" => {
+                i += 1;
+                frames = parse_value(args, i, "Invalid benchmark frame count")?;
+            }
+            _ => return Err("Unknown argument"),
+        }
+        i += 1;
+    }
+
+    if params.sdf_levels == 0 {
+        return Err("Mip level count must be positive");
+    }
+    if params.width == 0 || params.height == 0 {
+        return Err("Resolution must be positive");
+    }
+    // Culling dispatches groups of 64 instances
+    if params.num_instances == 0 || !params.num_instances.is_multiple_of(64) {
+        return Err("Instance count must be a positive multiple of 64");
+    }
+    if params.cloud_radius.is_nan() || params.cloud_radius <= 0.0 {
+        return Err("Cloud radius must be positive");
+    }
+    if !params.pyramid_dimension.is_power_of_two()
+        || !(16..=4096).contains(&params.pyramid_dimension)
+    {
+        return Err("Depth pyramid size must be a power of two (16-4096)");
+    }
+    if params.culling_debug && !params.culling {
+        return Err("Culling debug view requires culling");
+    }
+    if params.headless == Some(0) {
+        return Err("Headless frame count must be positive");
+    }
+    if params.capture.capture_every == 0 {
+        return Err("Capture interval must be positive");
+    }
+    if let Some(benchmark) = &mut params.benchmark {
+        if params.capture.screenshot.is_some() || params.capture.camera_path.is_some() {
+            return Err("Benchmark mode can't be combined with capture");
+        }
+        if frames == 0 {
+            return Err("Benchmark frame count must be positive");
+        }
+        benchmark.warmup_frames = warmup_frames;
+        benchmark.frames = frames;
+    }
+
+    Ok(params)
+}
+
+fn print_usage() {
+    println!("Usage: rendersdf [input.sdf] args");
+    println!("Input format: -raw | -zlib (default: detect versioned and legacy files)");
+    println!(
+        "SDF mip levels: -levels [count] (default {}) -mipfilter [box|min|conservative] (default {})",
+        DEFAULT_SDF_LEVELS,
+        DEFAULT_MIP_FILTER.name()
+    );
+    println!("Resolution: -size [width] [height] (default 1920 1080)");
+    println!(
+        "Instances: -instances [count] (multiple of 64, default {}) -radius [cloud radius] (default {}) -seed [seed]",
+        DEFAULT_NUM_INSTANCES, DEFAULT_CLOUD_RADIUS
+    );
+    println!(
+        "Culling: -noculling, -debug (culling debug view), -pyramid [size] (default {})",
+        DEFAULT_PYRAMID_DIMENSION
+    );
+    println!(
+        "Presentation: -vsync | -headless [frames] (no window, runs until capture or benchmark ends)"
+    );
+    println!("Capture: -screenshot [file.png] | -path [camera.txt] -every [N] -out [dir]");
+    println!(
+        "Benchmark: -benchmark [report.json|report.csv] -warmup [N] (default {}) -frames [N] (default {})",
+        DEFAULT_BENCHMARK_WARMUP_FRAMES, DEFAULT_BENCHMARK_FRAMES
+    );
 }
 
 fn main() {
+    let args: Vec<String> = env::args().collect();
+    let params = parse_args(&args).unwrap_or_else(|err| {
+        println!("Argument error: {}", err);
+        print_usage();
+        process::exit(1);
+    });
+    let capture_params = &params.capture;
+    let camera_path = capture_params.camera_path.as_ref().map(|filename| {
+        let path = load_camera_path(filename).unwrap_or_else(|err| {
+            println!("Camera path loading failed: {}", err);
+            process::exit(1);
+        });
+        fs::create_dir_all(&capture_params.capture_dir).expect("Capture directory creation failed");
+        println!("Camera path: {} keyframes", path.len());
+        path
+    });
+
     // Distance field
-    let sdf = load_sdf_zlib("data/ganymede-and-jupiter.sdf").expect("SDF loading failed");
-
-    let mut sdf_levels = Vec::new();
-    let mut sdf_total_voxels = sdf.header.dim.0 * sdf.header.dim.1 * sdf.header.dim.2;
-    sdf_levels.push(SdfLevel { sdf, offset: 0 });
-    for _ in 1..SDF_LEVELS {
-        let sdf = downsample_2x_sdf(&sdf_levels.last().unwrap().sdf);
-        let offset = sdf_total_voxels;
-        sdf_total_voxels += sdf.header.dim.0 * sdf.header.dim.1 * sdf.header.dim.2;
-        sdf_levels.push(SdfLevel { sdf, offset });
+    println!("Load SDF: {}", params.file_in);
+    let sdf = match params.format {
+        SdfFileFormat::Auto => load_any_sdf(&params.file_in),
+        SdfFileFormat::Raw => load_sdf(&params.file_in),
+        SdfFileFormat::Zlib => load_sdf_zlib(&params.file_in),
     }
+    .unwrap_or_else(|err| {
+        println!("SDF loading failed: {}", err);
+        process::exit(1);
+    });
+
+    println!("Generate mips: {} filter", params.mip_filter.name());
+    let sdf_levels = build_sdf_mips(sdf, params.sdf_levels, params.mip_filter);
+    let sdf_total_voxels = sdf_mips_total_voxels(&sdf_levels);
 
     let dx = sdf_levels[0].sdf.header.dx;
     let dim = sdf_levels[0].sdf.header.dim;
@@ -88,22 +343,30 @@ fn main() {
     };
     let texel_scale = Vec3::from_scalar(1.0) / texels;
 
-    // Window
-    let window_width = 1920;
-    let window_height = 1080;
-
-    let mut events_loop = EventLoop::new();
-    let window = WindowBuilder::new()
-        .with_title("Vulkan Test")
-        .with_inner_size(winit::dpi::PhysicalSize::new(
-            f64::from(window_width),
-            f64::from(window_height),
-        ))
-        .build(&events_loop)
-        .unwrap();
+    // Window (offscreen render target in headless mode)
+    let window_width = params.width;
+    let window_height = params.height;
+
+    let mut window = if params.headless.is_none() {
+        let events_loop = EventLoop::new();
+        let window = WindowBuilder::new()
+            .with_title("Vulkan Test")
+            .with_inner_size(winit::dpi::PhysicalSize::new(
+                f64::from(window_width),
+                f64::from(window_height),
+            ))
+            .build(&events_loop)
+            .unwrap();
+        Some((events_loop, window))
+    } else {
+        None
+    };
 
     // Vulkan base initialization
-    let mut base = VulkanBase::new(&window, window_width, window_height);
+    let mut base = match &window {
+        Some((_, window)) => VulkanBase::new(window, window_width, window_height, params.vsync),
+        None => VulkanBase::new_headless(window_width, window_height),
+    };
 
     // Render passes
     let render_pass_attachments = [
@@ -112,7 +375,7 @@ fn main() {
             samples: vk::SampleCountFlags::TYPE_1,
             load_op: vk::AttachmentLoadOp::CLEAR,
             store_op: vk::AttachmentStoreOp::STORE,
-            final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            final_layout: base.present_layout,
             ..Default::default()
         },
         vk::AttachmentDescription {
@@ -232,11 +495,18 @@ fn main() {
     );
 
     // Cube instances
-    let mut instances = Instances::new(&base.device, &mut base.allocator, diagonal_length);
+    let mut instances = Instances::new(
+        &base.device,
+        &mut base.allocator,
+        diagonal_length,
+        params.num_instances,
+        params.cloud_radius,
+        params.seed,
+    );
 
     // Occlusion culling (depth pyramid)
-    let pyramid_dimension = 512;
-    let pyramid_mips = 7;
+    let pyramid_dimension = params.pyramid_dimension;
+    let pyramid_mips = pyramid_dimension.trailing_zeros() - 2;
     let pyramid_texture_dimensions = (pyramid_dimension * 3 / 2, pyramid_dimension);
     let mut depth_pyramid = DepthPyramid::new(
         &base.device,
@@ -253,7 +523,7 @@ fn main() {
         &depth_pyramid.descriptor_sample,
         &depth_pyramid.descriptor_debug_sample,
         &instances.instances_buffer_descriptor,
-        NUM_INSTANCES,
+        params.num_instances,
     );
 
     let mut culling_debug = CullingDebug::new(
@@ -275,7 +545,7 @@ fn main() {
         &sdf_texture.descriptor,
         &instances.instances_buffer_descriptor,
         &culling.visibility_buffer_descriptor,
-        NUM_INSTANCES,
+        params.num_instances,
     );
     // Submit initialization command buffer before rendering starts
     base.record_submit_commandbuffer(
@@ -293,6 +563,10 @@ fn main() {
         },
     );
 
+    if !params.culling {
+        culling.set_all_visible(params.num_instances);
+    }
+
     // Camera
     struct Camera {
         position: Vec3,
@@ -334,302 +608,431 @@ fn main() {
         }
     }
 
-    // Window event loop
-    println!("Start window event loop");
-
     let mut inputs_prev: Inputs = Default::default();
     let mut inputs: Inputs = Default::default();
 
     let mut time_start = Instant::now();
     let mut frame = 0u32;
     let mut active_command_buffer = 0;
+    let mut gpu_profiler = GpuProfiler::new(&base, &GPU_PASS_NAMES);
+
+    // Benchmark orbits around the origin starting from the default camera
+    let orbit_radius = Vec3 {
+        x: camera.position.x,
+        y: 0.0,
+        z: camera.position.z,
+    }
+    .length();
+    let orbit_height = camera.position.y;
+    let mut benchmark_frame_times: Vec<f64> = Vec::new();
+    let mut frame_time_prev = Instant::now();
+
+    // Renders one frame, returns false when the run is finished
+    let mut render_frame = |inputs: Inputs, screenshot_requested: bool| -> bool {
+        let mut running = true;
+        let cursor_delta = (
+            inputs.cursor_position.0 - inputs_prev.cursor_position.0,
+            inputs.cursor_position.1 - inputs_prev.cursor_position.1,
+        );
+
+        inputs_prev = inputs;
+
+        // Update camera based in inputs
+        let view_rot = view(
+            Vec3 {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            camera.direction,
+            Vec3 {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            },
+        );
 
-    events_loop.run_return(|event, _, control_flow| {
-        *control_flow = ControlFlow::Poll;
+        let forward_speed = inputs.wheel_delta * 5.0 + inputs.keyboard_forward as f32 * 1.5;
+        camera.position = camera.position + camera.direction * forward_speed;
 
-        match event {
-            Event::NewEvents(_) => {
-                inputs.wheel_delta = 0.0;
+        let side_speed = inputs.keyboard_side as f32 * 1.5;
+        let side_vec = Vec3 {
+            x: view_rot.r0.x,
+            y: view_rot.r1.x,
+            z: view_rot.r2.x,
+        };
+        camera.position = camera.position + side_vec * side_speed;
+
+        // Scripted camera path overrides inputs (first keyframe held during warmup)
+        if let Some(path) = &camera_path {
+            let keyframe = frame.saturating_sub(CAPTURE_WARMUP_FRAMES) as usize;
+            if keyframe >= path.len() {
+                return false;
             }
+            camera.position = path[keyframe].position;
+            camera.direction = path[keyframe].direction;
+        }
 
-            Event::MainEventsCleared => {
-                let cursor_delta = (
-                    inputs.cursor_position.0 - inputs_prev.cursor_position.0,
-                    inputs.cursor_position.1 - inputs_prev.cursor_position.1,
-                );
+        if let Some(benchmark) = &params.benchmark {
+            let total_frames = benchmark.warmup_frames + benchmark.frames;
+            let (position, direction) =
+                orbit_camera(orbit_radius, orbit_height, frame, total_frames);
+            camera.position = position;
+            camera.direction = direction;
+            gpu_profiler.recording = frame >= benchmark.warmup_frames;
+        }
 
-                inputs_prev = inputs;
-
-                // Update camera based in inputs
-                let view_rot = view(
-                    Vec3 {
-                        x: 0.0,
-                        y: 0.0,
-                        z: 0.0,
-                    },
-                    camera.direction,
-                    Vec3 {
-                        x: 0.0,
-                        y: 1.0,
-                        z: 0.0,
-                    },
-                );
+        if inputs.is_left_clicked {
+            let rot = rot_y_axis(cursor_delta.0 as f32 * 0.0015)
+                * rot_x_axis(cursor_delta.1 as f32 * 0.0015);
 
-                let forward_speed = inputs.wheel_delta * 5.0 + inputs.keyboard_forward as f32 * 1.5;
-                camera.position = camera.position + camera.direction * forward_speed;
+            let rot = rot * inverse(view_rot);
 
-                let side_speed = inputs.keyboard_side as f32 * 1.5;
-                let side_vec = Vec3 {
-                    x: view_rot.r0.x,
-                    y: view_rot.r1.x,
-                    z: view_rot.r2.x,
-                };
-                camera.position = camera.position + side_vec * side_speed;
+            camera.direction = Vec3 {
+                x: 0.0,
+                y: 0.0,
+                z: 1.0,
+            } * rot;
+
+            camera.direction = camera.direction.normalize();
+        }
 
-                if inputs.is_left_clicked {
-                    let rot = rot_y_axis(cursor_delta.0 as f32 * 0.0015)
-                        * rot_x_axis(cursor_delta.1 as f32 * 0.0015);
+        // Render
+        let present_index = base.acquire_next_image();
 
-                    let rot = rot * inverse(view_rot);
+        // Update uniform buffer
+        let color = Vec4 {
+            x: 1.0,
+            y: 0.1,
+            z: 0.0,
+            w: 0.0,
+        };
 
-                    camera.direction = Vec3 {
-                        x: 0.0,
-                        y: 0.0,
-                        z: 1.0,
-                    } * rot;
+        let world_to_screen = view(
+            camera.position,
+            camera.direction,
+            Vec3 {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            },
+        ) * projection(
+            std::f32::consts::PI / 2.0,
+            window_width as f32 / window_height as f32,
+            1.0,
+            10000000.0,
+        );
+
+        let cube_uniforms = CubeUniforms {
+            world_to_screen,
+            color,
+            camera_position: camera.position.to_4d(),
+            volume_scale: volume_scale.to_4d(),
+            center_to_edge: center_to_edge.to_4d(),
+            texel_scale: texel_scale.to_4d(),
+        };
 
-                    camera.direction = camera.direction.normalize();
-                }
+        let pyramid_uniforms = DepthPyramidUniforms {
+            depth_buffer_dimensions: (window_width, window_height),
+            depth_pyramid_dimension: pyramid_dimension,
+        };
 
-                // Render
-                let (present_index, _) = unsafe {
-                    base.swapchain_loader.acquire_next_image(
-                        base.swapchain,
-                        std::u64::MAX,
-                        base.present_complete_semaphore,
-                        vk::Fence::null(),
-                    )
-                }
-                .unwrap();
-
-                // Update uniform buffer
-                let color = Vec4 {
-                    x: 1.0,
-                    y: 0.1,
-                    z: 0.0,
-                    w: 0.0,
-                };
+        let culling_uniforms = CullingUniforms {
+            world_to_screen,
+            depth_pyramid_dimension: pyramid_dimension,
+        };
 
-                let world_to_screen = view(
-                    camera.position,
-                    camera.direction,
-                    Vec3 {
-                        x: 0.0,
-                        y: 1.0,
-                        z: 0.0,
-                    },
-                ) * projection(
-                    std::f32::consts::PI / 2.0,
-                    window_width as f32 / window_height as f32,
-                    1.0,
-                    10000000.0,
-                );
+        let culling_debug_uniforms = CullingDebugUniforms {
+            depth_pyramid_dimension: pyramid_dimension,
+        };
 
-                let cube_uniforms = CubeUniforms {
-                    world_to_screen,
-                    color,
-                    camera_position: camera.position.to_4d(),
-                    volume_scale: volume_scale.to_4d(),
-                    center_to_edge: center_to_edge.to_4d(),
-                    texel_scale: texel_scale.to_4d(),
-                };
+        render_cubes.update(&cube_uniforms);
+        if params.culling {
+            depth_pyramid.update(&pyramid_uniforms);
+            culling.update(&culling_uniforms);
+        }
+        if params.culling_debug {
+            culling_debug.update(&culling_debug_uniforms);
+        }
 
-                let pyramid_uniforms = DepthPyramidUniforms {
-                    depth_buffer_dimensions: (window_width, window_height),
-                    depth_pyramid_dimension: pyramid_dimension,
-                };
+        // Setup render passs
+        let clear_values = [
+            vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: [0.0, 0.0, 0.0, 0.0],
+                },
+            },
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 0.0,
+                    stencil: 0,
+                },
+            },
+        ];
+
+        let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(render_pass)
+            .framebuffer(framebuffers[present_index as usize])
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: base.surface_resolution,
+            })
+            .clear_values(&clear_values);
+
+        // Submit main command buffer
+        let frame_slot = active_command_buffer;
+        active_command_buffer = base.record_submit_commandbuffer(
+            active_command_buffer,
+            base.present_queue,
+            &[vk::PipelineStageFlags::BOTTOM_OF_PIPE],
+            &[base.present_complete_semaphore],
+            &[base.rendering_complete_semaphore],
+            |device, command_buffer| {
+                gpu_profiler.begin_frame(device, &command_buffer, frame_slot);
+
+                // Draw/setup (before main render pass)
+                render_cubes.gpu_draw(device, &command_buffer);
+                culling_debug.gpu_draw(device, &command_buffer);
+
+                // Render pass
+                unsafe {
+                    device.cmd_begin_render_pass(
+                        command_buffer,
+                        &render_pass_begin_info,
+                        vk::SubpassContents::INLINE,
+                    );
+                    device.cmd_set_viewport(command_buffer, 0, &[view_scissor.viewport]);
+                    device.cmd_set_scissor(command_buffer, 0, &[view_scissor.scissor]);
+                }
 
-                let culling_uniforms = CullingUniforms {
-                    world_to_screen,
-                    depth_pyramid_dimension: pyramid_dimension,
+                // Draw (main render pass)
+                let argument_buffer = if params.culling {
+                    Some(&culling.visibility_arguments.buffer)
+                } else {
+                    None
                 };
+                gpu_profiler.begin_pass(device, &command_buffer, GPU_PASS_CUBES);
+                render_cubes.gpu_draw_main_render_pass(device, &command_buffer, argument_buffer);
+                gpu_profiler.end_pass(device, &command_buffer, GPU_PASS_CUBES);
+                if params.culling_debug {
+                    gpu_profiler.begin_pass(device, &command_buffer, GPU_PASS_CULLING_DEBUG);
+                    culling_debug.gpu_draw_main_render_pass(device, &command_buffer);
+                    gpu_profiler.end_pass(device, &command_buffer, GPU_PASS_CULLING_DEBUG);
+                }
 
-                let culling_debug_uniforms = CullingDebugUniforms {
-                    depth_pyramid_dimension: pyramid_dimension,
-                };
+                unsafe {
+                    device.cmd_end_render_pass(command_buffer);
+                }
 
-                render_cubes.update(&cube_uniforms);
-                depth_pyramid.update(&pyramid_uniforms);
-                culling.update(&culling_uniforms);
-                if ENABLE_CULLING_DEBUG {
-                    culling_debug.update(&culling_debug_uniforms);
+                // Draw/setup (after main render pass)
+                if !params.culling {
+                    return;
                 }
+                gpu_profiler.begin_pass(device, &command_buffer, GPU_PASS_DEPTH_PYRAMID);
+                depth_pyramid.gpu_draw(
+                    device,
+                    &command_buffer,
+                    &base.depth_image.image,
+                    pyramid_dimension,
+                    pyramid_mips,
+                );
+                gpu_profiler.end_pass(device, &command_buffer, GPU_PASS_DEPTH_PYRAMID);
+                gpu_profiler.begin_pass(device, &command_buffer, GPU_PASS_CULLING);
+                culling.gpu_draw(
+                    device,
+                    &command_buffer,
+                    &depth_pyramid.image.image,
+                    &depth_pyramid.image_debug.image,
+                    params.num_instances as u32,
+                );
+                gpu_profiler.end_pass(device, &command_buffer, GPU_PASS_CULLING);
+            },
+        );
+
+        // Capture (before present, the swapchain owns the image afterwards)
+        let capture_file = if screenshot_requested {
+            Some(format!("screenshot_{:05}.png", frame))
+        } else if frame == CAPTURE_WARMUP_FRAMES && capture_params.screenshot.is_some() {
+            running = false;
+            capture_params.screenshot.clone()
+        } else if camera_path.is_some()
+            && frame >= CAPTURE_WARMUP_FRAMES
+            && (frame - CAPTURE_WARMUP_FRAMES).is_multiple_of(capture_params.capture_every)
+        {
+            Some(format!(
+                "{}/frame_{:05}.png",
+                capture_params.capture_dir,
+                frame - CAPTURE_WARMUP_FRAMES
+            ))
+        } else {
+            None
+        };
 
-                // Setup render passs
-                let clear_values = [
-                    vk::ClearValue {
-                        color: vk::ClearColorValue {
-                            float32: [0.0, 0.0, 0.0, 0.0],
-                        },
-                    },
-                    vk::ClearValue {
-                        depth_stencil: vk::ClearDepthStencilValue {
-                            depth: 0.0,
-                            stencil: 0,
-                        },
-                    },
-                ];
-
-                let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
-                    .render_pass(render_pass)
-                    .framebuffer(framebuffers[present_index as usize])
-                    .render_area(vk::Rect2D {
-                        offset: vk::Offset2D { x: 0, y: 0 },
-                        extent: base.surface_resolution,
-                    })
-                    .clear_values(&clear_values);
-
-                // Submit main command buffer
-                active_command_buffer = base.record_submit_commandbuffer(
-                    active_command_buffer,
-                    base.present_queue,
-                    &[vk::PipelineStageFlags::BOTTOM_OF_PIPE],
-                    &[base.present_complete_semaphore],
-                    &[base.rendering_complete_semaphore],
-                    |device, command_buffer| {
-                        // Draw/setup (before main render pass)
-                        render_cubes.gpu_draw(device, &command_buffer);
-                        culling_debug.gpu_draw(device, &command_buffer);
-
-                        // Render pass
-                        unsafe {
-                            device.cmd_begin_render_pass(
-                                command_buffer,
-                                &render_pass_begin_info,
-                                vk::SubpassContents::INLINE,
-                            );
-                            device.cmd_set_viewport(command_buffer, 0, &[view_scissor.viewport]);
-                            device.cmd_set_scissor(command_buffer, 0, &[view_scissor.scissor]);
-                        }
+        if let Some(capture_file) = capture_file {
+            match base.read_present_image(present_index) {
+                Some(image) => {
+                    println!("Capture: {}", capture_file);
+                    store_capture(image, &capture_file).expect("Capture store failed");
+                }
+                None => println!("Capture not supported by the swapchain"),
+            }
+        }
 
-                        // Draw (main render pass)
-                        //render_cubes.gpu_draw_main_render_pass(device, &command_buffer, None);
-                        render_cubes.gpu_draw_main_render_pass(
-                            device,
-                            &command_buffer,
-                            Some(&culling.visibility_arguments.buffer),
-                        );
-                        if ENABLE_CULLING_DEBUG {
-                            culling_debug.gpu_draw_main_render_pass(device, &command_buffer);
-                        }
+        // Present frame
+        base.present(present_index);
 
-                        unsafe {
-                            device.cmd_end_render_pass(command_buffer);
-                        }
+        let frame_time_now = Instant::now();
+        if let Some(benchmark) = &params.benchmark {
+            if frame >= benchmark.warmup_frames {
+                let frame_time = (frame_time_now - frame_time_prev).as_secs_f64();
+                benchmark_frame_times.push(frame_time * 1000.0);
+            }
+            if frame + 1 == benchmark.warmup_frames + benchmark.frames {
+                running = false;
+            }
+        }
+        frame_time_prev = frame_time_now;
 
-                        // Draw/setup (after main render pass)
-                        depth_pyramid.gpu_draw(
-                            device,
-                            &command_buffer,
-                            &base.depth_image.image,
-                            pyramid_dimension,
-                            pyramid_mips,
-                        );
-                        culling.gpu_draw(
-                            device,
-                            &command_buffer,
-                            &depth_pyramid.image.image,
-                            &depth_pyramid.image_debug.image,
-                            NUM_INSTANCES as u32,
-                        );
-                    },
-                );
+        // Output performance info every 60 frames
+        frame += 1;
+        if frame.is_multiple_of(60) {
+            let time_now = Instant::now();
+            let interval = (time_now - time_start).as_millis();
+            println!("Average frame time: {} ms", interval as f32 / 60.0f32);
+            gpu_profiler.report();
 
-                // Present frame
-                let present_info = vk::PresentInfoKHR {
-                    wait_semaphore_count: 1,
-                    p_wait_semaphores: &base.rendering_complete_semaphore,
-                    swapchain_count: 1,
-                    p_swapchains: &base.swapchain,
-                    p_image_indices: &present_index,
-                    ..Default::default()
-                };
+            time_start = time_now;
+        }
 
-                unsafe {
-                    base.swapchain_loader
-                        .queue_present(base.present_queue, &present_info)
-                }
-                .unwrap();
+        running
+    };
+
+    if let Some(frames) = params.headless {
+        // Capture and benchmark runs end on their own
+        let run_to_end = params.benchmark.is_some()
+            || capture_params.screenshot.is_some()
+            || camera_path.is_some();
+
+        println!("Start headless rendering");
+        let mut headless_frames = 0;
+        while render_frame(Default::default(), false) {
+            headless_frames += 1;
+            if !run_to_end && headless_frames == frames {
+                break;
+            }
+        }
+        println!("End headless rendering: {} frames", headless_frames);
+    }
 
-                // Output performance info every 60 frames
-                frame += 1;
-                if (frame % 60) == 0 {
-                    let time_now = Instant::now();
-                    let interval = (time_now - time_start).as_millis();
-                    println!("Average frame time: {} ms", interval as f32 / 60.0f32);
+    let mut screenshot_requested = false;
+    if let Some((events_loop, _)) = &mut window {
+        println!("Start window event loop");
+        events_loop.run_return(|event, _, control_flow| {
+            *control_flow = ControlFlow::Poll;
 
-                    time_start = time_now;
+            match event {
+                Event::NewEvents(_) => {
+                    inputs.wheel_delta = 0.0;
                 }
-            }
 
-            Event::WindowEvent { event, .. } => match event {
-                WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+                Event::MainEventsCleared => {
+                    if !render_frame(inputs, screenshot_requested) {
+                        *control_flow = ControlFlow::Exit;
+                    }
+                    screenshot_requested = false;
+                }
 
-                // TODO: Handle swapchain resize
-                WindowEvent::Resized { .. } => {}
+                Event::WindowEvent { event, .. } => match event {
+                    WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
 
-                // Keyboard
-                WindowEvent::KeyboardInput { input, .. } => {
-                    let pressed = input.state == ElementState::Pressed;
+                    // TODO: Handle swapchain resize
+                    WindowEvent::Resized { .. } => {}
 
-                    if input.virtual_keycode == Some(VirtualKeyCode::W) {
-                        inputs.keyboard_forward = if pressed { 1 } else { 0 };
-                    }
+                    // Keyboard
+                    WindowEvent::KeyboardInput { input, .. } => {
+                        let pressed = input.state == ElementState::Pressed;
 
-                    if input.virtual_keycode == Some(VirtualKeyCode::S) {
-                        inputs.keyboard_forward = if pressed { -1 } else { 0 };
-                    }
+                        if input.virtual_keycode == Some(VirtualKeyCode::W) {
+                            inputs.keyboard_forward = if pressed { 1 } else { 0 };
+                        }
+
+                        if input.virtual_keycode == Some(VirtualKeyCode::S) {
+                            inputs.keyboard_forward = if pressed { -1 } else { 0 };
+                        }
+
+                        if input.virtual_keycode == Some(VirtualKeyCode::D) {
+                            inputs.keyboard_side = if pressed { 1 } else { 0 };
+                        }
+
+                        if input.virtual_keycode == Some(VirtualKeyCode::A) {
+                            inputs.keyboard_side = if pressed { -1 } else { 0 };
+                        }
 
-                    if input.virtual_keycode == Some(VirtualKeyCode::D) {
-                        inputs.keyboard_side = if pressed { 1 } else { 0 };
+                        if input.virtual_keycode == Some(VirtualKeyCode::F12) && pressed {
+                            screenshot_requested = true;
+                        }
                     }
 
-                    if input.virtual_keycode == Some(VirtualKeyCode::A) {
-                        inputs.keyboard_side = if pressed { -1 } else { 0 };
+                    // Mouse
+                    WindowEvent::MouseInput {
+                        button: MouseButton::Left,
+                        state,
+                        ..
+                    } => {
+                        inputs.is_left_clicked = state == ElementState::Pressed;
                     }
-                }
+                    WindowEvent::CursorMoved { position, .. } => {
+                        let position: (i32, i32) = position.into();
+                        inputs.cursor_position = position;
+                    }
+                    WindowEvent::MouseWheel {
+                        delta: MouseScrollDelta::LineDelta(_, v_lines),
+                        ..
+                    } => {
+                        inputs.wheel_delta += v_lines;
+                    }
+                    _ => (),
+                },
 
-                // Mouse
-                WindowEvent::MouseInput {
-                    button: MouseButton::Left,
-                    state,
-                    ..
-                } => {
-                    inputs.is_left_clicked = state == ElementState::Pressed;
-                }
-                WindowEvent::CursorMoved { position, .. } => {
-                    let position: (i32, i32) = position.into();
-                    inputs.cursor_position = position;
-                }
-                WindowEvent::MouseWheel {
-                    delta: MouseScrollDelta::LineDelta(_, v_lines),
-                    ..
-                } => {
-                    inputs.wheel_delta += v_lines;
-                }
                 _ => (),
-            },
-
-            Event::LoopDestroyed => unsafe { base.device.device_wait_idle() }.unwrap(),
-            _ => (),
-        }
-    });
-
-    println!("End window event loop");
+            }
+        });
+        println!("End window event loop");
+    }
 
     unsafe { base.device.device_wait_idle() }.unwrap();
 
+    if let Some(benchmark) = &params.benchmark {
+        gpu_profiler.flush(&base.device);
+        let gpu_samples = gpu_profiler.take_samples();
+        let technique = if params.culling {
+            "culling"
+        } else {
+            "no culling"
+        };
+        let report = BenchmarkReport {
+            application: "rendersdf".to_string(),
+            device: DeviceInfo::new(&base),
+            width: window_width,
+            height: window_height,
+            num_instances: params.num_instances,
+            warmup_frames: benchmark.warmup_frames,
+            frames: benchmark.frames,
+            runs: vec![BenchmarkRun::new(
+                technique,
+                &benchmark_frame_times,
+                &gpu_profiler.pass_names,
+                &gpu_samples,
+            )],
+        };
+        match report.store(&benchmark.report_file) {
+            Ok(()) => println!("Benchmark report: {}", benchmark.report_file),
+            Err(err) => println!("Benchmark report store failed: {}", err),
+        }
+    }
+
     // Cleanup
+    gpu_profiler.destroy(&base.device);
     culling_debug.destroy(&base.device, &mut base.allocator);
     culling.destroy(&base.device, &mut base.allocator);
     instances.destroy(&base.device, &mut base.allocator);
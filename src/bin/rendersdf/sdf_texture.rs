@@ -1,11 +1,42 @@
 use ash::{vk, Device};
+use std::borrow::Cow;
 use std::default::Default;
 
 use gpu_allocator::vulkan::*;
 use gpu_allocator::MemoryLocation;
 
 use crate::vulkan_helpers::*;
-use crate::SdfLevel;
+use rust_test::sdf::{Sdf, VoxelType};
+use rust_test::sdf_mips::SdfLevel;
+use rust_test::sdf_voxel::SdfVoxel;
+
+// NOTE: main.frag decodes UNORM distances (relative to the volume diagonal). Float volumes
+// are remapped to the same range on upload, see texture_voxels.
+pub fn voxel_type_format(voxel_type: VoxelType) -> vk::Format {
+    match voxel_type {
+        VoxelType::U8Unorm => vk::Format::R8_UNORM,
+        VoxelType::U16Unorm => vk::Format::R16_UNORM,
+        VoxelType::F16 => vk::Format::R16_SFLOAT,
+        VoxelType::F32 => vk::Format::R32_SFLOAT,
+    }
+}
+
+// Float voxels store world space distances: remapped to the UNORM range (0.5 = surface,
+// relative to the level 0 diagonal) as floats. UNORM voxels are uploaded as is.
+fn texture_voxels<T: SdfVoxel>(sdf: &Sdf<T>, diagonal_length: f32) -> Cow<'_, [T]> {
+    match T::VOXEL_TYPE {
+        VoxelType::U8Unorm | VoxelType::U16Unorm => Cow::Borrowed(&sdf.voxels[..]),
+        VoxelType::F16 | VoxelType::F32 => Cow::Owned(
+            sdf.voxels
+                .iter()
+                .map(|&v| {
+                    let s = v.to_distance(&sdf.header) / diagonal_length as f64 * 0.5 + 0.5;
+                    T::from_distance(s, &sdf.header)
+                })
+                .collect(),
+        ),
+    }
+}
 
 pub struct SdfTexture {
     pub image: VkImage,
@@ -16,14 +47,16 @@ pub struct SdfTexture {
 }
 
 impl SdfTexture {
-    pub fn new(
+    pub fn new<T: SdfVoxel>(
         device: &Device,
         allocator: &mut Allocator,
-        sdf_levels: &[SdfLevel],
+        sdf_levels: &[SdfLevel<T>],
         sdf_total_voxels: usize,
     ) -> SdfTexture {
+        let voxel_size = T::VOXEL_TYPE.size();
+
         let image_buffer_info = vk::BufferCreateInfo {
-            size: (std::mem::size_of::<u16>() * sdf_total_voxels) as u64,
+            size: (voxel_size * sdf_total_voxels) as u64,
             usage: vk::BufferUsageFlags::TRANSFER_SRC,
             sharing_mode: vk::SharingMode::EXCLUSIVE,
             ..Default::default()
@@ -36,18 +69,17 @@ impl SdfTexture {
             MemoryLocation::CpuToGpu,
         );
 
+        let diagonal_length = sdf_levels[0].sdf.header.diagonal_length();
         for level in sdf_levels {
-            upload_buffer.copy_from_slice(
-                &level.sdf.voxels[..],
-                level.offset as usize * std::mem::size_of::<u16>(),
-            );
+            let voxels = texture_voxels(&level.sdf, diagonal_length);
+            upload_buffer.copy_from_slice(&voxels[..], level.offset as usize * voxel_size);
         }
 
         let image_dimensions = sdf_levels[0].sdf.header.dim;
 
         let texture_create_info = vk::ImageCreateInfo {
             image_type: vk::ImageType::TYPE_3D,
-            format: vk::Format::R16_UNORM,
+            format: voxel_type_format(T::VOXEL_TYPE),
             extent: vk::Extent3D {
                 width: image_dimensions.0,
                 height: image_dimensions.1,
@@ -122,12 +154,14 @@ impl SdfTexture {
         }
     }
 
-    pub fn gpu_setup(
+    pub fn gpu_setup<T: SdfVoxel>(
         &self,
         device: &Device,
         command_buffer: &vk::CommandBuffer,
-        sdf_levels: &[SdfLevel],
+        sdf_levels: &[SdfLevel<T>],
     ) {
+        let voxel_size = T::VOXEL_TYPE.size() as u64;
+
         // Setup distance field texture
         let texture_barrier = vk::ImageMemoryBarrier {
             dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
@@ -145,7 +179,7 @@ impl SdfTexture {
         let image_copys: Vec<vk::BufferImageCopy> = (0..sdf_levels.len())
             .map(|i| {
                 let buffer_image_copy_regions = vk::BufferImageCopy::builder()
-                    .buffer_offset(std::mem::size_of::<u16>() as u64 * sdf_levels[i].offset as u64)
+                    .buffer_offset(voxel_size * sdf_levels[i].offset as u64)
                     .image_subresource(
                         vk::ImageSubresourceLayers::builder()
                             .aspect_mask(vk::ImageAspectFlags::COLOR)
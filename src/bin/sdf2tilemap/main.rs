@@ -1,29 +1,76 @@
 const SDF_LEVELS: u32 = 6;
+const TILE_SIZE_OUTER: u32 = 8;
+const TILE_BORDER: u32 = 1;
+
+// Padding (border << levels) must fit in u32
+const MAX_LEVELS: u32 = 16;
+const MAX_TILE_SIZE: u32 = 256;
 
 use std::env;
 use std::process;
 
 use rust_test::sdf;
-use rust_test::serialization;
+use rust_test::sdf_edit;
+use rust_test::sdf_mips;
+use rust_test::sdf_tilemap;
 //use rust_test::sparse_sdf;
 
 use sdf::*;
-use serialization::*;
+use sdf_edit::*;
+use sdf_mips::*;
+use sdf_tilemap::*;
 //use sparse_sdf::*;
 
-pub struct SdfLevel {
-    pub sdf: Sdf,
-    pub offset: u32,
+// Payload multiple (tile count x payload) that is also a multiple of 2^(levels - 1). The
+// padding already is, so the volume halves evenly down to the last mip and build_sdf_mips
+// doesn't pad it any further (payload 7 and 6 levels = 224).
+fn size_step(tile_size_payload: u32, levels: u32) -> u32 {
+    let mips = levels.saturating_sub(1);
+    tile_size_payload << mips.saturating_sub(tile_size_payload.trailing_zeros())
+}
+
+// Smallest valid size >= v: padding + n * step (n >= 1). u64, padding can be up to
+// 255 << 16.
+fn valid_size(v: u32, step: u32, padding: u32) -> u64 {
+    let steps = v.saturating_sub(padding).div_ceil(step).max(1);
+    padding as u64 + steps as u64 * step as u64
+}
+
+fn is_correct_size(v: u32, step: u32, padding: u32) -> bool {
+    valid_size(v, step, padding) == v as u64
+}
+
+// Valid dimensions >= dim. None if the volume would have more than u32::MAX voxels (mip
+// chain voxel offsets are u32).
+fn fit_dimensions(dim: (u32, u32, u32), step: u32, padding: u32) -> Option<(u32, u32, u32)> {
+    let x = valid_size(dim.0, step, padding);
+    let y = valid_size(dim.1, step, padding);
+    let z = valid_size(dim.2, step, padding);
+    if x.checked_mul(y)?.checked_mul(z)? > u32::MAX as u64 {
+        return None;
+    }
+    Some((x as u32, y as u32, z as u32))
 }
 
-fn is_correct_size(v: u32, tile_size_payload: u32, padding: u32) -> bool {
-    let v_no_pad = v - padding;
-    (v_no_pad % tile_size_payload) == 0
+// What to do with volumes of invalid size
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SizeFit {
+    // Reject the volume
+    None,
+    // Pad to the next valid size (extrapolated outside distances)
+    Pad,
+    // Resample the largest axis to the next valid size, pad the others
+    Resample,
 }
 
 pub struct Params {
     pub file_in: String,
     pub file_out: String,
+    pub fit: SizeFit,
+    pub tile_size_outer: u32,
+    // Filtering border, tile_size_payload = tile_size_outer - border
+    pub border: u32,
+    pub levels: u32,
 }
 
 fn parse_args(args: &[String]) -> Result<Params, &str> {
@@ -33,13 +80,93 @@ fn parse_args(args: &[String]) -> Result<Params, &str> {
 
     let file_in = args[1].clone();
     let file_out = args[2].clone();
-    Ok(Params { file_in, file_out })
+    let mut fit = SizeFit::Pad;
+    let mut tile_size_outer = TILE_SIZE_OUTER;
+    let mut border = TILE_BORDER;
+    let mut levels = SDF_LEVELS;
+
+    let value = |i: usize, err| args.get(i).and_then(|v| v.parse().ok()).ok_or(err);
+
+    let mut i = 3;
+    while i < args.len() {
+        match &args[i][..] {
+            "-fit" => {
+                i += 1;
+                fit = match args.get(i).map(|v| &v[..]) {
+                    Some("none") => SizeFit::None,
+                    Some("pad") => SizeFit::Pad,
+                    Some("resample") => SizeFit::Resample,
+                    _ => return Err("Invalid size fit (none, pad or resample)"),
+                };
+            }
+            "-t" => {
+                i += 1;
+                tile_size_outer = value(i, "Invalid tile size")?;
+            }
+            "-b" => {
+                i += 1;
+                border = value(i, "Invalid tile border")?;
+            }
+            "-l" => {
+                i += 1;
+                levels = value(i, "Invalid level count")?;
+            }
+            _ => return Err("Unknown argument"),
+        }
+        i += 1;
+    }
+
+    if tile_size_outer == 0 || tile_size_outer > MAX_TILE_SIZE {
+        return Err("Tile size must be 1-256");
+    }
+    if border >= tile_size_outer {
+        return Err("Tile border must be smaller than the tile size (payload = size - border)");
+    }
+    if levels == 0 || levels > MAX_LEVELS {
+        return Err("Level count must be 1-16");
+    }
+
+    Ok(Params {
+        file_in,
+        file_out,
+        fit,
+        tile_size_outer,
+        border,
+        levels,
+    })
+}
+
+// Padding of the volume: the lowest mip level keeps 2 x border voxels for filtering
+// (border 1 and 6 levels = 64)
+fn level_padding(border: u32, levels: u32) -> u32 {
+    border.max(1) << levels
+}
+
+// Voxel size that maps the largest axis to its next valid size. Same bounds, so the
+// other axes grow proportionally and get padded.
+fn fit_dx(header: &SdfHeader, step: u32, padding: u32) -> f32 {
+    let dim = header.dim.0.max(header.dim.1).max(header.dim.2);
+    if dim < 2 {
+        return header.dx;
+    }
+    let target = valid_size(dim, step, padding);
+    header.dx * (dim - 1) as f32 / (target - 1) as f32
 }
 
 fn print_usage() {
-    println!("Usage: sfd2tilemap input.sdf output.map");
-    println!("(TODO)Tile size: -t [size] (outer size, default 8)");
-    println!("(TODO)Levels: -l [levels] (mip levels, default 6)");
+    println!("Usage: sfd2tilemap input.sdf output.map args");
+    println!("Invalid volume size: -fit none|pad|resample (default pad)");
+    println!(
+        "Tile size: -t [size] (outer size, default {})",
+        TILE_SIZE_OUTER
+    );
+    println!(
+        "Tile border: -b [border] (payload = size - border, default {})",
+        TILE_BORDER
+    );
+    println!("Levels: -l [levels] (mip levels, default {})", SDF_LEVELS);
+    println!("Volume size must be padding + N x payload, padding = border x 2^levels");
+    println!("N x payload must be a multiple of 2^(levels - 1)");
 }
 
 fn main() {
@@ -51,37 +178,76 @@ fn main() {
     });
 
     println!("Load SDF {}", params.file_in);
-    let sdf = load_sdf_zlib(&params.file_in).expect("SDF loading failed");
+    let sdf = load_any_sdf(&params.file_in).unwrap_or_else(|err| {
+        println!("SDF loading failed: {}", err);
+        process::exit(1);
+    });
 
-    let tile_size_payload = 7;
-    let tile_size_outer = 8;
+    let tile_size_outer = params.tile_size_outer;
+    let tile_size_payload = tile_size_outer - params.border;
 
-    let padding = 1 << SDF_LEVELS;
+    let padding = level_padding(params.border, params.levels);
+    let step = size_step(tile_size_payload, params.levels);
 
     // Check size
-    // - Must be dividable by: tile_size_payload + 2^SFD_LEVELS
-    // - This way the lowest mip level still has 1 pixel filtering border
-    let correct_size = is_correct_size(sdf.header.dim.0, tile_size_payload, padding)
-        & is_correct_size(sdf.header.dim.1, tile_size_payload, padding)
-        & is_correct_size(sdf.header.dim.2, tile_size_payload, padding);
+    // - Must be dividable by: tile_size_payload + border * 2^levels
+    // - This way the lowest mip level still has a filtering border
+    // - The payload part halves evenly down to the last mip
+    let correct_size = |dim: (u32, u32, u32)| {
+        is_correct_size(dim.0, step, padding)
+            && is_correct_size(dim.1, step, padding)
+            && is_correct_size(dim.2, step, padding)
+    };
 
-    if !correct_size {
+    let sdf = if correct_size(sdf.header.dim) {
+        sdf
+    } else if params.fit == SizeFit::None {
         println!(
             "ERROR: SDF volume size must be dividable with {} + padding {}",
-            tile_size_payload, padding
+            step, padding
         );
-        return;
-    }
+        process::exit(1);
+    } else {
+        let too_large = || {
+            println!(
+                "ERROR: Padded SDF volume is too large (more than {} voxels), use fewer levels or a smaller border",
+                u32::MAX
+            );
+            process::exit(1);
+        };
+        if fit_dimensions(sdf.header.dim, step, padding).is_none() {
+            too_large();
+        }
+
+        let sdf = if params.fit == SizeFit::Resample {
+            let dx = fit_dx(&sdf.header, step, padding);
+            println!("Resample SDF: dx = {}", dx);
+            resample_sdf(&sdf, dx).unwrap_or_else(|err| {
+                println!("SDF resampling failed: {}", err);
+                process::exit(1);
+            })
+        } else {
+            sdf
+        };
+
+        let dim = sdf.header.dim;
+        let fit_dim = fit_dimensions(dim, step, padding).unwrap_or_else(too_large);
+        let sdf = pad_sdf(&sdf, fit_dim).unwrap_or_else(|err| {
+            println!("SDF padding failed: {}", err);
+            process::exit(1);
+        });
+        println!("Fit SDF to valid size: {:?}", sdf.header);
+        sdf
+    };
 
     // Generate mips
-    let mut sdf_levels = Vec::new();
-    let mut sdf_total_voxels = sdf.header.dim.0 * sdf.header.dim.1 * sdf.header.dim.2;
-    sdf_levels.push(SdfLevel { sdf, offset: 0 });
-    for _ in 1..SDF_LEVELS {
-        let sdf = downsample_2x_sdf(&sdf_levels.last().unwrap().sdf);
-        let offset = sdf_total_voxels;
-        sdf_total_voxels += sdf.header.dim.0 * sdf.header.dim.1 * sdf.header.dim.2;
-        sdf_levels.push(SdfLevel { sdf, offset });
+    let sdf_levels = build_sdf_mips(sdf, params.levels, DEFAULT_MIP_FILTER);
+    if (sdf_levels.len() as u32) < params.levels {
+        println!(
+            "WARNING: Volume too small for {} levels, {} levels generated",
+            params.levels,
+            sdf_levels.len()
+        );
     }
 
     // Find all edge tiles
@@ -95,85 +261,97 @@ fn main() {
         tile_size_outer
     );
 
-    let mut storer_header = StorerVec::new();
-    let mut storer_voxels = StorerVec::new();
-    let mut total_tile_count = 0;
-
-    for (i, level) in sdf_levels.iter().enumerate() {
-        let dim = level.sdf.header.dim;
-
-        let stride_y = dim.0;
-        let stride_z = dim.0 * dim.1;
-        let level_zero = (65536 / 2) as u16;
-        let mut mip_tile_count = 0;
-        let mut edge_tile_count = 0;
-
-        for z in 0..(dim.2 / tile_size_payload) {
-            for y in 0..(dim.1 / tile_size_payload) {
-                for x in 0..(dim.0 / tile_size_payload) {
-                    // Test edge: contains both positive and negative voxels
-                    let tile_offset = tile_size_payload * (z * stride_z + y * stride_y + x);
-                    let mut has_inside = false;
-                    let mut has_outside = false;
-                    for iz in 0..tile_size_outer {
-                        for iy in 0..tile_size_outer {
-                            for ix in 0..tile_size_outer {
-                                let voxel_offset = iz * stride_z + iy * stride_y + ix;
-                                let d =
-                                    level.sdf.voxels[tile_offset as usize + voxel_offset as usize];
-                                if d < level_zero {
-                                    has_inside = true;
-                                };
-                                if d > level_zero {
-                                    has_outside = true;
-                                };
-                            }
-                        }
-                    }
-
-                    // Edge tile?
-                    if has_inside && has_outside {
-                        edge_tile_count += 1;
-
-                        // Store voxels
-                        for iz in 0..tile_size_outer {
-                            for iy in 0..tile_size_outer {
-                                for ix in 0..tile_size_outer {
-                                    let voxel_offset = iz * stride_z + iy * stride_y + ix;
-                                    let d = level.sdf.voxels
-                                        [tile_offset as usize + voxel_offset as usize];
-                                    storer_voxels.store_u16(d);
-                                }
-                            }
-                        }
-                    }
-                    mip_tile_count += 1;
-                }
-            }
-        }
+    // Level 0 header keeps the world placement of the (fitted) volume
+    let tilemap =
+        build_tilemap(&sdf_levels, tile_size_outer, tile_size_payload).unwrap_or_else(|err| {
+            println!("Tilemap build failed: {}", err);
+            process::exit(1);
+        });
 
+    for (i, level) in tilemap.levels.iter().enumerate() {
+        let grid = level.tile_grid;
+        let mip_tile_count = grid.0 * grid.1 * grid.2;
+        if mip_tile_count == 0 {
+            let dim = level.header.dim;
+            println!(
+                "WARNING: Level {} ({}x{}x{}) is smaller than a tile",
+                i, dim.0, dim.1, dim.2
+            );
+        }
         println!(
             "Level = {}: Tiles = {}, Edge tiles = {} ({}%)",
             i,
             mip_tile_count,
-            edge_tile_count,
-            edge_tile_count as f32 * 100.0 / mip_tile_count as f32
+            level.tile_count,
+            level.tile_count as f32 * 100.0 / mip_tile_count.max(1) as f32
         );
-
-        storer_header.store_u32(edge_tile_count);
-        total_tile_count += edge_tile_count;
     }
 
-    let mut storer = StorerVec::new();
-    storer.store_array_u8(&storer_header.v);
-    storer.store_array_u8(&storer_voxels.v);
-
     println!(
-        "Storing tiles = {}, bytes = {} to {}",
-        total_tile_count,
-        storer.v.len(),
+        "Storing tiles = {} to {}",
+        tilemap.tile_count(),
         params.file_out
     );
+    store_tilemap(&params.file_out, &tilemap).unwrap_or_else(|err| {
+        println!("Tilemap store failed: {}", err);
+        process::exit(1);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_test::sdf_sampling::*;
+
+    #[test]
+    fn size_steps() {
+        assert_eq!(size_step(7, 6), 7 * 32);
+        assert_eq!(size_step(8, 6), 32);
+        assert_eq!(size_step(12, 6), 12 * 8);
+        assert_eq!(size_step(64, 6), 64);
+        assert_eq!(size_step(7, 1), 7);
 
-    std::fs::write(params.file_out, storer.v).expect("Tilemap store failed");
+        let padding = level_padding(1, 6);
+        assert_eq!(valid_size(71, size_step(7, 6), padding), 64 + 224);
+        assert!(is_correct_size(64 + 448, size_step(7, 6), padding));
+        assert!(!is_correct_size(64 + 7, size_step(7, 6), padding));
+    }
+
+    #[test]
+    fn fitted_dimensions_survive_mips() {
+        for &(tile_size_outer, border, levels) in
+            [(8, 1, 6), (8, 1, 3), (12, 2, 4), (5, 1, 2)].iter()
+        {
+            let payload = tile_size_outer - border;
+            let padding = level_padding(border, levels);
+            let step = size_step(payload, levels);
+            for &dim in [(71, 1, 300), (2, 97, 64), (200, 150, 129)].iter() {
+                let fit = fit_dimensions(dim, step, padding).unwrap();
+                assert!(fit.0 >= dim.0 && fit.1 >= dim.1 && fit.2 >= dim.2);
+                assert_eq!(
+                    padded_mip_dimensions(fit, levels),
+                    fit,
+                    "{:?} -> {:?}",
+                    dim,
+                    fit
+                );
+            }
+        }
+
+        // Full chain: level 0 keeps the fitted size, every mip halves it
+        let (payload, padding, levels) = (7, level_padding(1, 3), 3);
+        let fit = fit_dimensions((20, 30, 40), size_step(payload, levels), padding).unwrap();
+        assert_eq!(fit, (8 + 28, 8 + 28, 8 + 56));
+        let header = SdfHeader {
+            dim: fit,
+            box_min: (0.0, 0.0, 0.0),
+            dx: 0.1,
+        };
+        let sdf: Sdf = sample_sdf(header, |p| p.length() - 2.0).unwrap();
+        let mips = build_sdf_mips(sdf, levels, DEFAULT_MIP_FILTER);
+        assert_eq!(mips.len(), levels as usize);
+        for (i, level) in mips.iter().enumerate() {
+            assert_eq!(level.sdf.header.dim, (fit.0 >> i, fit.1 >> i, fit.2 >> i));
+        }
+    }
 }
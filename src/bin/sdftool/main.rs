@@ -1,96 +1,1100 @@
+use std::any::Any;
 use std::env;
+use std::fs;
+use std::io::Read;
 use std::process;
 
+use rust_test::error;
+use rust_test::mesh;
+use rust_test::mesh_to_sdf;
+use rust_test::minivector;
+use rust_test::quantized_sdf;
 use rust_test::sdf;
+use rust_test::sdf_csg;
+use rust_test::sdf_edit;
+use rust_test::sdf_mips;
+use rust_test::sdf_to_mesh;
+use rust_test::sdf_tracer;
+use rust_test::sdf_voxel;
 
+use error::*;
+use mesh::*;
+use mesh_to_sdf::*;
+use minivector::*;
+use quantized_sdf::*;
 use sdf::*;
+use sdf_csg::*;
+use sdf_edit::*;
+use sdf_mips::*;
+use sdf_to_mesh::*;
+use sdf_tracer::*;
+use sdf_voxel::*;
 
-pub struct SdfLevel {
-    pub sdf: Sdf,
-    pub offset: u32,
+const DEFAULT_PREDICTOR: SdfPredictor = SdfPredictor::Eikonal;
+
+// "xzy": swaps y and z (Y-up <-> Z-up), the old hard coded conversion. Used when sdftool is
+// run without a subcommand, convert keeps the axes by default.
+const DEFAULT_ORIENTATION: SdfOrientation = [
+    AxisFlip::PositiveX,
+    AxisFlip::PositiveZ,
+    AxisFlip::PositiveY,
+];
+
+// Lossy quantized output, world units
+#[derive(Clone, Copy)]
+pub struct QuantizeParams {
+    pub max_error: f32,
+    pub band: Option<f32>,
+}
+
+// Output file flags shared by the subcommands
+#[derive(Clone, Copy)]
+pub struct OutputParams {
+    pub encoding: SdfEncoding,
+    pub voxel_type: VoxelType,
+    pub legacy: bool,
+    pub predictor: SdfPredictor,
+    pub quantize: Option<QuantizeParams>,
 }
 
 pub struct Params {
     pub file_in: String,
     pub file_out: String,
-    pub axis_x: AxisFlip,
-    pub axis_y: AxisFlip,
-    pub axis_z: AxisFlip,
+    pub orientation: SdfOrientation,
     pub compressed_input: bool,
-    pub compressed_output: bool,
+    pub output: OutputParams,
 }
 
-fn parse_args(args: &[String]) -> Result<Params, &str> {
-    if args.len() < 3 {
+pub struct GenParams {
+    pub file_in: String,
+    pub file_out: String,
+    pub dx: f32,
+    pub padding: u32,
+    pub output: OutputParams,
+}
+
+fn parse_predictor(name: Option<&String>) -> Result<SdfPredictor, &'static str> {
+    match name.map(|name| &name[..]) {
+        Some("planar") => Ok(SdfPredictor::Planar),
+        Some("gradient") => Ok(SdfPredictor::Gradient),
+        Some("eikonal") => Ok(SdfPredictor::Eikonal),
+        _ => Err("Invalid predictor (planar, gradient or eikonal)"),
+    }
+}
+
+fn parse_world_size(value: Option<&String>, err: &'static str) -> Result<f32, &'static str> {
+    value
+        .and_then(|v| v.parse().ok())
+        .filter(|&v: &f32| v >= 0.0)
+        .ok_or(err)
+}
+
+// Band without -q is ignored
+fn quantize_params(
+    max_error: Option<f32>,
+    band: Option<f32>,
+    legacy_output: bool,
+) -> Result<Option<QuantizeParams>, &'static str> {
+    match max_error {
+        Some(_) if legacy_output => Err("Quantized output (-q) is not supported with -legacy"),
+        Some(max_error) => Ok(Some(QuantizeParams { max_error, band })),
+        None => Ok(None),
+    }
+}
+
+fn parse_encoding(name: Option<&String>) -> Result<SdfEncoding, &'static str> {
+    match name.map(|name| &name[..]) {
+        Some("raw") => Ok(SdfEncoding::Raw),
+        Some("zlib") => Ok(SdfEncoding::Zlib),
+        Some("gradient") => Ok(SdfEncoding::GradientZlib),
+        _ => Err("Invalid encoding (raw, zlib or gradient)"),
+    }
+}
+
+fn parse_voxel_type(name: Option<&String>) -> Result<VoxelType, &'static str> {
+    match name.map(|name| &name[..]) {
+        Some("u16") => Ok(VoxelType::U16Unorm),
+        Some("u8") => Ok(VoxelType::U8Unorm),
+        Some("f16") => Ok(VoxelType::F16),
+        Some("f32") => Ok(VoxelType::F32),
+        _ => Err("Invalid voxel type (u16, u8, f16 or f32)"),
+    }
+}
+
+struct OutputArgs {
+    encoding: SdfEncoding,
+    voxel_type: VoxelType,
+    legacy: bool,
+    predictor: SdfPredictor,
+    max_error: Option<f32>,
+    band: Option<f32>,
+}
+
+impl OutputArgs {
+    fn new() -> OutputArgs {
+        OutputArgs {
+            encoding: SdfEncoding::Raw,
+            voxel_type: VoxelType::U16Unorm,
+            legacy: false,
+            predictor: DEFAULT_PREDICTOR,
+            max_error: None,
+            band: None,
+        }
+    }
+
+    // Output flag at args[i], other arguments are errors
+    fn parse(&mut self, args: &[String], i: &mut usize) -> Result<(), &'static str> {
+        match &args[*i][..] {
+            "-oz" => self.encoding = SdfEncoding::GradientZlib,
+            "-e" => {
+                *i += 1;
+                self.encoding = parse_encoding(args.get(*i))?;
+            }
+            "-v" => {
+                *i += 1;
+                self.voxel_type = parse_voxel_type(args.get(*i))?;
+            }
+            "-legacy" => self.legacy = true,
+            "-p" => {
+                *i += 1;
+                self.predictor = parse_predictor(args.get(*i))?;
+            }
+            "-q" => {
+                *i += 1;
+                self.max_error = Some(parse_world_size(args.get(*i), "Invalid max error")?);
+            }
+            "-band" => {
+                *i += 1;
+                self.band = Some(parse_world_size(args.get(*i), "Invalid band width")?);
+            }
+            _ => return Err("Unknown argument"),
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<OutputParams, &'static str> {
+        let quantize = quantize_params(self.max_error, self.band, self.legacy)?;
+        let u16_voxels = self.voxel_type == VoxelType::U16Unorm;
+        if self.legacy && (!u16_voxels || self.encoding == SdfEncoding::Zlib) {
+            return Err("Legacy output (-legacy) supports raw or gradient encoded u16 voxels");
+        }
+        if quantize.is_some() && !u16_voxels {
+            return Err("Quantized output (-q) supports u16 voxels only");
+        }
+        if !self.voxel_type.supports_encoding(self.encoding) {
+            return Err("Gradient encoding supports u16 voxels only");
+        }
+
+        Ok(OutputParams {
+            encoding: self.encoding,
+            voxel_type: self.voxel_type,
+            legacy: self.legacy,
+            predictor: self.predictor,
+            quantize,
+        })
+    }
+}
+
+fn parse_gen_args(args: &[String]) -> Result<GenParams, &str> {
+    if args.len() < 6 {
+        return Err("Not enough arguments");
+    }
+
+    let file_in = args[2].clone();
+    let file_out = args[3].clone();
+    let dx = args[4].parse().map_err(|_| "Invalid voxel size")?;
+    let padding = args[5].parse().map_err(|_| "Invalid padding")?;
+
+    if dx <= 0.0 {
+        return Err("Voxel size must be positive");
+    }
+
+    let mut output = OutputArgs::new();
+
+    let mut i = 6;
+    while i < args.len() {
+        output.parse(args, &mut i)?;
+        i += 1;
+    }
+
+    let output = output.finish()?;
+
+    Ok(GenParams {
+        file_in,
+        file_out,
+        dx,
+        padding,
+        output,
+    })
+}
+
+pub struct ExtractParams {
+    pub file_in: String,
+    pub file_out: String,
+    pub level: u16,
+    pub compressed_input: bool,
+}
+
+fn parse_extract_args(args: &[String]) -> Result<ExtractParams, &str> {
+    if args.len() < 4 {
+        return Err("Not enough arguments");
+    }
+
+    let file_in = args[2].clone();
+    let file_out = args[3].clone();
+
+    let mut level = SDF_LEVEL_ZERO;
+    let mut compressed_input = false;
+
+    let mut i = 4;
+    while i < args.len() {
+        match &args[i][..] {
+            "-iz" => compressed_input = true,
+            "-l" => {
+                i += 1;
+                level = args
+                    .get(i)
+                    .and_then(|v| v.parse().ok())
+                    .ok_or("Invalid iso-level")?;
+            }
+            _ => return Err("Unknown argument"),
+        }
+        i += 1;
+    }
+
+    Ok(ExtractParams {
+        file_in,
+        file_out,
+        level,
+        compressed_input,
+    })
+}
+
+pub struct CsgParams {
+    pub operation: CsgOperation,
+    pub file_a: String,
+    pub file_b: String,
+    pub file_out: String,
+    pub output: OutputParams,
+}
+
+fn parse_csg_args(args: &[String]) -> Result<CsgParams, &str> {
+    if args.len() < 6 {
+        return Err("Not enough arguments");
+    }
+
+    let file_a = args[3].clone();
+    let file_b = args[4].clone();
+    let file_out = args[5].clone();
+
+    let mut blend = None;
+    let mut output = OutputArgs::new();
+
+    let mut i = 6;
+    while i < args.len() {
+        match &args[i][..] {
+            "-k" => {
+                i += 1;
+                blend = Some(parse_world_size(args.get(i), "Invalid blend radius")?);
+            }
+            _ => output.parse(args, &mut i)?,
+        }
+        i += 1;
+    }
+
+    let operation =
+        CsgOperation::from_name(&args[2], blend.unwrap_or(0.0)).ok_or("Unknown CSG operation")?;
+    let smooth = matches!(
+        operation,
+        CsgOperation::SmoothUnion(_)
+            | CsgOperation::SmoothIntersection(_)
+            | CsgOperation::SmoothSubtraction(_)
+    );
+    if smooth && blend.is_none() {
+        return Err("Smooth CSG operations need a blend radius (-k)");
+    }
+
+    let output = output.finish()?;
+
+    Ok(CsgParams {
+        operation,
+        file_a,
+        file_b,
+        file_out,
+        output,
+    })
+}
+
+pub struct TraceParams {
+    pub file_in: String,
+    pub file_out: String,
+    pub width: u32,
+    pub height: u32,
+    // Default: rendersdf camera direction, one diagonal length away from the volume
+    pub position: Option<Vec3>,
+    pub direction: Vec3,
+    pub fovy: f32,
+    pub golden: Option<String>,
+    pub tolerance: u8,
+    pub mip_filter: MipFilter,
+}
+
+fn parse_vec3(args: &[String], i: usize) -> Result<Vec3, &'static str> {
+    let v = |i: usize| -> Result<f32, &'static str> {
+        args.get(i)
+            .and_then(|v| v.parse().ok())
+            .ok_or("Invalid vector (x y z)")
+    };
+    Ok(Vec3 {
+        x: v(i)?,
+        y: v(i + 1)?,
+        z: v(i + 2)?,
+    })
+}
+
+fn parse_trace_args(args: &[String]) -> Result<TraceParams, &str> {
+    if args.len() < 4 {
         return Err("Not enough arguments");
     }
 
-    let file_in = args[1].clone();
-    let file_out = args[2].clone();
+    let mut params = TraceParams {
+        file_in: args[2].clone(),
+        file_out: args[3].clone(),
+        width: 960,
+        height: 540,
+        position: None,
+        direction: Vec3 {
+            x: 0.0,
+            y: -0.5,
+            z: -1.0,
+        },
+        fovy: std::f32::consts::PI / 2.0,
+        golden: None,
+        tolerance: 2,
+        mip_filter: DEFAULT_MIP_FILTER,
+    };
 
-    // TODO: FIXME! Hard coded axis flip!
-    let axis_x = AxisFlip::PositiveX;
-    let axis_y = AxisFlip::PositiveZ;
-    let axis_z = AxisFlip::PositiveY;
+    let mut i = 4;
+    while i < args.len() {
+        match &args[i][..] {
+            "-s" => {
+                let size = |i: usize| args.get(i).and_then(|v| v.parse().ok());
+                params.width = size(i + 1).ok_or("Invalid image size")?;
+                params.height = size(i + 2).ok_or("Invalid image size")?;
+                i += 2;
+            }
+            "-c" => {
+                params.position = Some(parse_vec3(args, i + 1)?);
+                i += 3;
+            }
+            "-d" => {
+                params.direction = parse_vec3(args, i + 1)?;
+                i += 3;
+            }
+            "-fov" => {
+                i += 1;
+                let degrees: f32 = args
+                    .get(i)
+                    .and_then(|v| v.parse().ok())
+                    .ok_or("Invalid field of view")?;
+                params.fovy = degrees.to_radians();
+            }
+            "-golden" => {
+                i += 1;
+                params.golden = Some(args.get(i).ok_or("Missing golden image")?.clone());
+            }
+            "-tolerance" => {
+                i += 1;
+                params.tolerance = args
+                    .get(i)
+                    .and_then(|v| v.parse().ok())
+                    .ok_or("Invalid tolerance")?;
+            }
+            "-mipfilter" => {
+                i += 1;
+                params.mip_filter = args
+                    .get(i)
+                    .and_then(|name| MipFilter::from_name(name))
+                    .ok_or("Unknown mip filter")?;
+            }
+            _ => return Err("Unknown argument"),
+        }
+        i += 1;
+    }
+
+    if params.width == 0 || params.height == 0 {
+        return Err("Image size must be positive");
+    }
+
+    Ok(params)
+}
+
+// Source axis per output axis: lowercase = axis, uppercase = negated axis. "xZy" = (x, -z, y).
+fn parse_orientation(spec: &str) -> Result<SdfOrientation, &'static str> {
+    let flips: Vec<AxisFlip> = spec
+        .chars()
+        .map(AxisFlip::from_char)
+        .collect::<Option<_>>()
+        .ok_or("Invalid orientation, use x, y, z (capital letter = negate)")?;
+    if flips.len() != 3 {
+        return Err("Orientation must have three axes");
+    }
+    let orientation = [flips[0], flips[1], flips[2]];
+    if !is_permutation(&orientation) {
+        return Err("Orientation must use each axis once");
+    }
+    Ok(orientation)
+}
+
+// Axis and counterclockwise angle (multiple of 90 degrees)
+fn parse_rotation(
+    axis: Option<&String>,
+    degrees: Option<&String>,
+) -> Result<SdfOrientation, &'static str> {
+    let axis = match axis.map(|axis| &axis[..]) {
+        Some("x") | Some("X") => 0,
+        Some("y") | Some("Y") => 1,
+        Some("z") | Some("Z") => 2,
+        _ => return Err("Invalid rotation axis, use x, y or z"),
+    };
+    let degrees = degrees
+        .and_then(|degrees| degrees.parse::<i32>().ok())
+        .ok_or("Invalid rotation angle")?;
+    if degrees % 90 != 0 {
+        return Err("Rotation angle must be a multiple of 90 degrees");
+    }
+    Ok(rotation_orientation(axis, degrees / 90))
+}
+
+// Input and output files at args[first], args[first + 1]
+fn parse_args(
+    args: &[String],
+    first: usize,
+    default_orientation: SdfOrientation,
+) -> Result<Params, &str> {
+    if args.len() < first + 2 {
+        return Err("Not enough arguments");
+    }
+
+    let file_in = args[first].clone();
+    let file_out = args[first + 1].clone();
+
+    let mut orientation = default_orientation;
+    let mut rotations = Vec::new();
 
     let mut compressed_input = false;
-    let mut compressed_output = false;
+    let mut output = OutputArgs::new();
 
-    for arg in args.iter().skip(3) {
-        match &arg[..] {
+    let mut i = first + 2;
+    while i < args.len() {
+        match &args[i][..] {
             "-iz" => compressed_input = true,
-            "-oz" => compressed_output = true,
-            _ => (),
+            "-o" => {
+                i += 1;
+                orientation = parse_orientation(args.get(i).ok_or("Missing orientation")?)?;
+            }
+            "-r" => {
+                rotations.push(parse_rotation(args.get(i + 1), args.get(i + 2))?);
+                i += 2;
+            }
+            _ => output.parse(args, &mut i)?,
         }
+        i += 1;
+    }
+
+    // Rotations are applied after the axis orientation, in the given order
+    for rotation in rotations.iter() {
+        orientation = combine_orientations(&orientation, rotation);
     }
 
+    let output = output.finish()?;
+
     Ok(Params {
         file_in,
         file_out,
-        axis_x,
-        axis_y,
-        axis_z,
+        orientation,
         compressed_input,
-        compressed_output,
+        output,
+    })
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum SdfEdit {
+    // Margin in voxels around the surface
+    Crop(u32),
+    Pad((u32, u32, u32)),
+    Resample(f32),
+}
+
+pub struct EditParams {
+    pub edit: SdfEdit,
+    pub file_in: String,
+    pub file_out: String,
+    pub compressed_input: bool,
+    pub output: OutputParams,
+}
+
+// crop input output margin, pad input output x y z, resample input output dx
+fn parse_edit_args(args: &[String]) -> Result<EditParams, &str> {
+    let value_count = if args[1] == "pad" { 3 } else { 1 };
+    if args.len() < 4 + value_count {
+        return Err("Not enough arguments");
+    }
+
+    let value = |i: usize| args[4 + i].parse::<u32>();
+    let edit = match &args[1][..] {
+        "crop" => SdfEdit::Crop(value(0).map_err(|_| "Invalid margin")?),
+        "pad" => {
+            let size = |i: usize| value(i).map_err(|_| "Invalid size");
+            SdfEdit::Pad((size(0)?, size(1)?, size(2)?))
+        }
+        _ => {
+            let dx: f32 = args[4].parse().map_err(|_| "Invalid voxel size")?;
+            if dx <= 0.0 {
+                return Err("Voxel size must be positive");
+            }
+            SdfEdit::Resample(dx)
+        }
+    };
+
+    let mut compressed_input = false;
+    let mut output = OutputArgs::new();
+
+    let mut i = 4 + value_count;
+    while i < args.len() {
+        match &args[i][..] {
+            "-iz" => compressed_input = true,
+            _ => output.parse(args, &mut i)?,
+        }
+        i += 1;
+    }
+
+    let output = output.finish()?;
+
+    Ok(EditParams {
+        edit,
+        file_in: args[2].clone(),
+        file_out: args[3].clone(),
+        compressed_input,
+        output,
     })
 }
 
 fn print_usage() {
-    println!("Usage: sdftool input.sdf output.sdf args");
-    println!("Compressed input (grad+zlib): -iz");
+    println!("Usage: sdftool input.sdf output.sdf args (convert, default orientation xzy)");
+    println!("       sdftool info input.sdf");
+    println!("       sdftool convert input.sdf output.sdf args");
+    println!(
+        "       sdftool crop input.sdf output.sdf margin args (surface bounds + margin voxels)"
+    );
+    println!(
+        "       sdftool pad input.sdf output.sdf x y z args (centered, extrapolated distances)"
+    );
+    println!("       sdftool resample input.sdf output.sdf dx args");
+    println!("       sdftool gen input.obj output.sdf dx padding args");
+    println!("       sdftool extract input.sdf output.obj|output.ply args");
+    println!("       sdftool trace input.sdf output.png args");
+    println!("       sdftool csg operation a.sdf b.sdf output.sdf args");
+    println!("Input format is detected automatically");
+    println!("Force legacy compressed input (grad+zlib): -iz");
     println!("Compress output (grad+zlib): -oz");
-    println!("(TODO) Orient/flip axis: -o xZy (xyz = axis, capital letter = negate)");
+    println!("Output encoding: -e raw|zlib|gradient (default raw, gradient = grad+zlib)");
+    println!("Output voxel type: -v u16|u8|f16|f32 (default u16)");
+    println!("Legacy output without file header (SDFGen compatible): -legacy");
+    println!("Compression predictor: -p planar|gradient|eikonal (default eikonal)");
+    println!("Lossy quantized output: -q [max error] (world units)");
+    println!("Quantized narrow band: -band [width] (world units, default 127 x quantization step)");
+    println!("Iso-level (extract): -l [level] (default 32768)");
+    println!("Image size (trace): -s [width] [height] (default 960 540)");
+    println!("Camera (trace): -c [x y z] -d [x y z] -fov [degrees] (volume centered at origin)");
+    println!("Golden image test (trace): -golden [file.png] -tolerance [0-255] (default 2)");
+    println!(
+        "Mip filter (trace): -mipfilter [box|min|conservative] (default {})",
+        DEFAULT_MIP_FILTER.name()
+    );
+    println!("CSG operations: union, intersection, subtraction (a - b), smooth_union, smooth_intersection, smooth_subtraction");
+    println!("Blend radius (csg, smooth operations): -k [radius] (world units)");
+    println!("Orient/flip axis (convert): -o [xyz] (source axis per output axis, lowercase = keep sign, uppercase = negate: xZy = (x, -z, y))");
+    println!("Rotate (convert): -r [x|y|z] [degrees] (multiple of 90, counterclockwise, applied after -o, repeatable)");
 }
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    let params = parse_args(&args).unwrap_or_else(|err| {
+fn load_input(file_in: &str, compressed_input: bool) -> Sdf {
+    load_input_as(file_in, compressed_input)
+}
+
+// Versioned files are loaded as T directly, legacy (u16) files are converted
+fn load_input_as<T: SdfVoxel>(file_in: &str, compressed_input: bool) -> Sdf<T> {
+    if compressed_input {
+        println!("Load SDF with zlib: {}", file_in);
+        load_sdf_zlib(file_in).map(|sdf| sdf.convert())
+    } else if read_file_header(file_in).is_some() {
+        println!("Load SDF: {}", file_in);
+        load_sdf_file_as(file_in)
+    } else {
+        println!("Load SDF: {}", file_in);
+        load_any_sdf(file_in).map(|sdf| sdf.convert())
+    }
+    .unwrap_or_else(|err| {
+        println!("SDF loading failed: {}", err);
+        process::exit(1);
+    })
+}
+
+// Voxel type stored in the input file, legacy files are u16
+fn input_voxel_type(file_in: &str, compressed_input: bool) -> VoxelType {
+    if compressed_input {
+        return VoxelType::U16Unorm;
+    }
+    read_file_header(file_in).map_or(VoxelType::U16Unorm, |header| header.voxel_type)
+}
+
+// None for legacy (headerless) files
+fn read_file_header(filename: &str) -> Option<SdfFileHeader> {
+    let mut bytes = Vec::new();
+    fs::File::open(filename)
+        .ok()?
+        .take(SDF_FILE_HEADER_SIZE as u64)
+        .read_to_end(&mut bytes)
+        .ok()?;
+    parse_sdf_file_header(&bytes).ok()
+}
+
+// Stored size relative to the voxel count (raw = 16 bits per voxel)
+fn report_bits_per_voxel<T>(label: &str, filename: &str, sdf: &Sdf<T>) {
+    if let Ok(metadata) = fs::metadata(filename) {
+        let bits = metadata.len() as f64 * 8.0 / sdf.voxels.len().max(1) as f64;
+        println!(
+            "{}: {} bytes, {:.3} bits per voxel",
+            label,
+            metadata.len(),
+            bits
+        );
+    }
+}
+
+// Converted copy only if the voxel type differs
+fn with_voxel_type<T: SdfVoxel + 'static, U: SdfVoxel + 'static, F: FnOnce(&Sdf<U>)>(
+    sdf: &Sdf<T>,
+    f: F,
+) {
+    match (sdf as &dyn Any).downcast_ref::<Sdf<U>>() {
+        Some(sdf) => f(sdf),
+        None => f(&sdf.convert()),
+    }
+}
+
+fn store_output<T: SdfVoxel + 'static>(file_out: &str, sdf: &Sdf<T>, output: &OutputParams) {
+    match output.voxel_type {
+        VoxelType::U16Unorm => with_voxel_type(sdf, |sdf| store_u16_output(file_out, sdf, output)),
+        VoxelType::U8Unorm => {
+            with_voxel_type::<T, u8, _>(sdf, |sdf| store_file_output(file_out, sdf, output))
+        }
+        VoxelType::F16 => {
+            with_voxel_type::<T, F16, _>(sdf, |sdf| store_file_output(file_out, sdf, output))
+        }
+        VoxelType::F32 => {
+            with_voxel_type::<T, f32, _>(sdf, |sdf| store_file_output(file_out, sdf, output))
+        }
+    }
+}
+
+// Quantized and legacy outputs are u16 only
+fn store_u16_output(file_out: &str, sdf: &Sdf, output: &OutputParams) {
+    if let Some(quantize) = output.quantize {
+        store_quantized_output(file_out, sdf, quantize);
+        return;
+    }
+    if !output.legacy {
+        store_file_output(file_out, sdf, output);
+        return;
+    }
+
+    if output.encoding == SdfEncoding::GradientZlib {
+        println!("Store legacy SDF with zlib: {}", file_out);
+        store_sdf_zlib(file_out, sdf)
+    } else {
+        println!("Store legacy SDF: {}", file_out);
+        store_sdf(file_out, sdf)
+    }
+    .unwrap_or_else(|err| {
+        println!("SDF store failed: {}", err);
+        process::exit(1);
+    });
+
+    report_bits_per_voxel("Output", file_out, sdf);
+}
+
+fn store_file_output<T: SdfVoxel>(file_out: &str, sdf: &Sdf<T>, output: &OutputParams) {
+    println!("Store SDF: {}", file_out);
+    store_sdf_file(file_out, sdf, output.encoding, output.predictor).unwrap_or_else(|err| {
+        println!("SDF store failed: {}", err);
+        process::exit(1);
+    });
+
+    report_bits_per_voxel("Output", file_out, sdf);
+}
+
+fn store_quantized_output(file_out: &str, sdf: &Sdf, quantize: QuantizeParams) {
+    let quantization = SdfQuantization::new(&sdf.header, quantize.max_error, quantize.band);
+    println!(
+        "Store quantized SDF: {}, max error = {}, band = {}",
+        file_out,
+        quantization.max_error(&sdf.header),
+        quantization.band_width(&sdf.header)
+    );
+    store_sdf_quantized(file_out, sdf, &quantization).unwrap_or_else(|err| {
+        println!("SDF store failed: {}", err);
+        process::exit(1);
+    });
+
+    // Error report from the stored file
+    let decoded = load_any_sdf(file_out).unwrap_or_else(|err| {
+        println!("SDF loading failed: {}", err);
+        process::exit(1);
+    });
+    let report = quantization_report(sdf, &decoded, &quantization);
+    println!(
+        "Quantization error: band voxels = {}, clamped voxels = {}",
+        report.band_voxels, report.clamped_voxels
+    );
+    println!(
+        "Quantization error: band max = {}, band rms = {}, max (with clamping) = {}",
+        report.max_error_band, report.rms_error_band, report.max_error
+    );
+
+    report_bits_per_voxel("Output", file_out, sdf);
+}
+
+fn generate(args: &[String]) {
+    let params = parse_gen_args(args).unwrap_or_else(|err| {
+        println!("Argument error: {}", err);
+        print_usage();
+        process::exit(1);
+    });
+
+    println!("Load OBJ: {}", params.file_in);
+    let mesh = load_obj(&params.file_in).unwrap_or_else(|err| {
+        println!("OBJ loading failed: {}", err);
+        process::exit(1);
+    });
+
+    println!(
+        "Generate SDF: dx = {}, padding = {}",
+        params.dx, params.padding
+    );
+    let sdf = mesh_to_sdf(&mesh, params.dx, params.padding).unwrap_or_else(|err| {
+        println!("SDF generation failed: {}", err);
+        process::exit(1);
+    });
+
+    store_output(&params.file_out, &sdf, &params.output);
+}
+
+fn csg(args: &[String]) {
+    let params = parse_csg_args(args).unwrap_or_else(|err| {
         println!("Argument error: {}", err);
         print_usage();
         process::exit(1);
     });
 
-    let sdf = if params.compressed_input {
-        println!("Load SDF with zlib: {}", params.file_in);
-        load_sdf_zlib(&params.file_in)
+    let a = load_input(&params.file_a, false);
+    let b = load_input(&params.file_b, false);
+
+    println!("CSG: {:?}", params.operation);
+    let sdf = csg_sdf(&a, &b, params.operation).unwrap_or_else(|err| {
+        println!("CSG failed: {}", err);
+        process::exit(1);
+    });
+    println!("Result SDF: {:?}", sdf.header);
+
+    store_output(&params.file_out, &sdf, &params.output);
+}
+
+fn extract(args: &[String]) {
+    let params = parse_extract_args(args).unwrap_or_else(|err| {
+        println!("Argument error: {}", err);
+        print_usage();
+        process::exit(1);
+    });
+
+    let sdf = load_input(&params.file_in, params.compressed_input);
+
+    println!("Extract mesh: level = {}", params.level);
+    let mesh = sdf_to_mesh(&sdf, params.level);
+    println!(
+        "Extracted mesh: vertices = {}, triangles = {}",
+        mesh.positions.len(),
+        mesh.triangle_count()
+    );
+
+    if params.file_out.to_lowercase().ends_with(".ply") {
+        println!("Store PLY: {}", params.file_out);
+        store_ply(&params.file_out, &mesh)
     } else {
-        println!("Load SDF: {}", params.file_in);
-        load_sdf(&params.file_in)
+        println!("Store OBJ: {}", params.file_out);
+        store_obj(&params.file_out, &mesh)
+    }
+    .unwrap_or_else(|err| {
+        println!("Mesh store failed: {}", err);
+        process::exit(1);
+    });
+}
+
+fn trace(args: &[String]) {
+    let params = parse_trace_args(args).unwrap_or_else(|err| {
+        println!("Argument error: {}", err);
+        print_usage();
+        process::exit(1);
+    });
+
+    let sdf = load_input(&params.file_in, false);
+
+    let direction = params.direction.normalize();
+    let position = params
+        .position
+        .unwrap_or_else(|| direction * -sdf.header.diagonal_length());
+    let camera = TraceCamera {
+        position,
+        direction,
+        fovy: params.fovy,
+    };
+
+    println!(
+        "Trace SDF: {} x {}, camera position = {:?}, direction = {:?}",
+        params.width, params.height, camera.position, camera.direction
+    );
+    let tracer = SdfTracer::new(
+        &sdf,
+        TRACE_DEFAULT_LEVELS,
+        params.mip_filter,
+        Vec3::from_scalar(0.0),
+    );
+    let pixels = tracer.render(&camera, params.width, params.height);
+
+    println!("Store PNG: {}", params.file_out);
+    store_png(&params.file_out, params.width, params.height, &pixels).unwrap_or_else(|err| {
+        println!("PNG store failed: {}", err);
+        process::exit(1);
+    });
+
+    if let Some(golden) = params.golden {
+        println!("Compare with golden image: {}", golden);
+        let diff = compare_png(
+            &golden,
+            params.width,
+            params.height,
+            &pixels,
+            params.tolerance,
+        )
+        .unwrap_or_else(|err| {
+            println!("Golden image loading failed: {}", err);
+            process::exit(1);
+        });
+
+        match diff {
+            Some(diff) => {
+                println!(
+                    "Different pixels: {}, max difference: {}",
+                    diff.different_pixels, diff.max_difference
+                );
+                if diff.different_pixels > 0 {
+                    println!("Golden image test FAILED");
+                    process::exit(1);
+                }
+                println!("Golden image test passed");
+            }
+            None => {
+                println!("Golden image test FAILED: image size mismatch");
+                process::exit(1);
+            }
+        }
     }
-    .expect("SDF loading failed");
+}
 
+// Volumes are processed in the stored voxel type, the output is converted to the requested
+// voxel type
+fn convert(args: &[String], first: usize, default_orientation: SdfOrientation) {
+    let params = parse_args(args, first, default_orientation).unwrap_or_else(|err| {
+        println!("Argument error: {}", err);
+        print_usage();
+        process::exit(1);
+    });
+
+    match input_voxel_type(&params.file_in, params.compressed_input) {
+        VoxelType::U16Unorm => convert_as::<u16>(&params),
+        VoxelType::U8Unorm => convert_as::<u8>(&params),
+        VoxelType::F16 => convert_as::<F16>(&params),
+        VoxelType::F32 => convert_as::<f32>(&params),
+    }
+}
+
+fn convert_as<T: SdfVoxel + 'static>(params: &Params) {
+    let sdf: Sdf<T> = load_input_as(&params.file_in, params.compressed_input);
+    report_bits_per_voxel("Input", &params.file_in, &sdf);
+
+    let [axis_x, axis_y, axis_z] = params.orientation;
     println!(
         "Orient SDF x = {:?}, y = {:?}, z = {:?}",
-        params.axis_x, params.axis_y, params.axis_z
+        axis_x, axis_y, axis_z
     );
-    let sdf = orient_sdf(&sdf, params.axis_x, params.axis_y, params.axis_z);
+    let sdf = orient_sdf(&sdf, axis_x, axis_y, axis_z);
 
-    if params.compressed_output {
-        println!("Store SDF with zlib: {}", params.file_out);
-        store_sdf_zlib(&params.file_out, &sdf)
-    } else {
-        println!("Store SDF: {}", params.file_out);
-        store_sdf(&params.file_out, &sdf)
+    store_output(&params.file_out, &sdf, &params.output);
+}
+
+fn edit(args: &[String]) {
+    let params = parse_edit_args(args).unwrap_or_else(|err| {
+        println!("Argument error: {}", err);
+        print_usage();
+        process::exit(1);
+    });
+
+    match input_voxel_type(&params.file_in, params.compressed_input) {
+        VoxelType::U16Unorm => edit_as::<u16>(&params),
+        VoxelType::U8Unorm => edit_as::<u8>(&params),
+        VoxelType::F16 => edit_as::<F16>(&params),
+        VoxelType::F32 => edit_as::<f32>(&params),
+    }
+}
+
+// Crop and pad copy voxels in the stored type, resample samples f32 distances
+fn edit_as<T: SdfVoxel + Send + Sync + 'static>(params: &EditParams) {
+    let sdf: Sdf<T> = load_input_as(&params.file_in, params.compressed_input);
+
+    println!("Edit SDF: {:?}", params.edit);
+    match params.edit {
+        SdfEdit::Crop(margin) => {
+            let result = crop_sdf(&sdf, margin).unwrap_or_else(|| {
+                println!("SDF has no surface, nothing to crop");
+                process::exit(1);
+            });
+            store_edit_result(params, result);
+        }
+        SdfEdit::Pad(dim) => store_edit_result(params, pad_sdf(&sdf, dim)),
+        SdfEdit::Resample(dx) => store_edit_result(params, resample_sdf::<T, f32>(&sdf, dx)),
     }
-    .expect("SDF store failed");
+}
+
+fn store_edit_result<T: SdfVoxel + 'static>(params: &EditParams, result: SdfResult<Sdf<T>>) {
+    let sdf = result.unwrap_or_else(|err| {
+        println!("SDF edit failed: {}", err);
+        process::exit(1);
+    });
+    println!("Result SDF: {:?}", sdf.header);
+
+    store_output(&params.file_out, &sdf, &params.output);
+}
+
+fn info(args: &[String]) {
+    if args.len() < 3 {
+        println!("Argument error: Not enough arguments");
+        print_usage();
+        process::exit(1);
+    }
+    let file_in = &args[2];
+
+    match input_voxel_type(file_in, false) {
+        VoxelType::U16Unorm => info_as::<u16>(file_in),
+        VoxelType::U8Unorm => info_as::<u8>(file_in),
+        VoxelType::F16 => info_as::<F16>(file_in),
+        VoxelType::F32 => info_as::<f32>(file_in),
+    }
+}
+
+fn info_as<T: SdfVoxel>(file_in: &str) {
+    let file_header = read_file_header(file_in);
+    let sdf: Sdf<T> = load_input_as(file_in, false);
+    let header = &sdf.header;
+    let voxels = sdf.voxels.len();
+
+    // Uncompressed size of the stored voxel type
+    let (format, raw_size) = match &file_header {
+        Some(file_header) => (
+            format!(
+                "version {}, {:?}, {:?}, {:?}",
+                file_header.version,
+                file_header.encoding,
+                file_header.voxel_type,
+                file_header.predictor
+            ),
+            sdf_file_header_size(file_header.version) + voxels * file_header.voxel_type.size(),
+        ),
+        None => (
+            "legacy (no file header)".to_string(),
+            SDF_LEGACY_HEADER_SIZE + voxels * VoxelType::U16Unorm.size(),
+        ),
+    };
+    let file_size = fs::metadata(file_in).map_or(0, |metadata| metadata.len());
+
+    let box_max = sdf.box_max();
+    let stats = sdf_stats(&sdf);
+
+    println!("File: {}", file_in);
+    println!("Format: {}", format);
+    println!(
+        "Dimensions: {} x {} x {} ({} voxels)",
+        header.dim.0, header.dim.1, header.dim.2, voxels
+    );
+    println!("Voxel size (dx): {}", header.dx);
+    println!(
+        "Bounds: min ({}, {}, {}), max ({}, {}, {})",
+        header.box_min.0, header.box_min.1, header.box_min.2, box_max.x, box_max.y, box_max.z
+    );
+    println!("Diagonal length: {}", header.diagonal_length());
+    println!(
+        "Distance: min {}, max {}",
+        stats.min_distance, stats.max_distance
+    );
+    println!(
+        "Voxels: inside {}, surface {} ({:.2}%)",
+        stats.inside_voxels,
+        stats.surface_voxels,
+        stats.surface_voxels as f64 * 100.0 / voxels.max(1) as f64
+    );
+    match surface_bounds(&sdf) {
+        Some((first, last)) => println!("Surface voxel bounds: {:?} - {:?}", first, last),
+        None => println!("Surface voxel bounds: none (no zero crossing)"),
+    }
+    println!(
+        "File size: {} bytes, uncompressed {} bytes, ratio {:.2}:1, {:.3} bits per voxel",
+        file_size,
+        raw_size,
+        raw_size as f64 / file_size.max(1) as f64,
+        file_size as f64 * 8.0 / voxels.max(1) as f64
+    );
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() > 1 && args[1] == "gen" {
+        generate(&args);
+        return;
+    }
+    if args.len() > 1 && args[1] == "extract" {
+        extract(&args);
+        return;
+    }
+    if args.len() > 1 && args[1] == "trace" {
+        trace(&args);
+        return;
+    }
+    if args.len() > 1 && args[1] == "csg" {
+        csg(&args);
+        return;
+    }
+
+    if args.len() > 1 && args[1] == "info" {
+        info(&args);
+        return;
+    }
+    if args.len() > 1 && args[1] == "convert" {
+        convert(&args, 2, IDENTITY_ORIENTATION);
+        return;
+    }
+    if args.len() > 1 && ["crop", "pad", "resample"].contains(&&args[1][..]) {
+        edit(&args);
+        return;
+    }
+
+    convert(&args, 1, DEFAULT_ORIENTATION);
 }
@@ -2,16 +2,26 @@
 
 const NUM_DESCRIPTORS_PER_TYPE: u32 = 1024;
 const NUM_DESCRIPTOR_SETS: u32 = 1024;
+const DEFAULT_HEADLESS_FRAMES: u32 = 60;
+
+// GPU profiler passes
+const GPU_PASS_NAMES: [&str; 1] = ["grid render"];
+const GPU_PASS_GRIDS: usize = 0;
 
 extern crate winit;
 
 mod instances;
 mod render_grids;
+mod sweep;
 
+use rust_test::benchmark;
+use rust_test::gpu_profiler;
 use rust_test::minivector;
 use rust_test::vulkan_base;
 use rust_test::vulkan_helpers;
 
+use std::env;
+use std::process;
 use std::time::Instant;
 
 use ash::vk;
@@ -23,6 +33,8 @@ use winit::{
     window::WindowBuilder,
 };
 
+use benchmark::*;
+use gpu_profiler::*;
 use minivector::*;
 
 use vulkan_base::*;
@@ -30,6 +42,7 @@ use vulkan_helpers::*;
 
 use instances::*;
 use render_grids::*;
+use sweep::*;
 
 #[derive(Clone, Copy)]
 pub struct Vertex {
@@ -37,7 +50,142 @@ pub struct Vertex {
     pub uv: [f32; 2],
 }
 
+pub struct BenchParams {
+    pub technique: Option<GridTechnique>,
+    // Frames per technique
+    pub sweep: Option<u32>,
+    // Current technique, or all supported techniques with --sweep
+    pub benchmark: Option<BenchmarkParams>,
+    // Frame count for offscreen rendering without a window
+    pub headless: Option<u32>,
+    // Last headless frame
+    pub screenshot: Option<String>,
+}
+
+fn parse_args(args: &[String]) -> Result<BenchParams, &str> {
+    let mut params = BenchParams {
+        technique: None,
+        sweep: None,
+        benchmark: None,
+        headless: None,
+        screenshot: None,
+    };
+    let mut warmup_frames = DEFAULT_BENCHMARK_WARMUP_FRAMES;
+    let mut frames = DEFAULT_BENCHMARK_FRAMES;
+
+    let mut i = 1;
+    while i < args.len() {
+        match &args[i][..] {
+            "--technique" => {
+                i += 1;
+                params.technique = Some(
+                    args.get(i)
+                        .and_then(|name| GridTechnique::from_name(name))
+                        .ok_or("Unknown technique")?,
+                );
+            }
+            "--sweep" => {
+                // Optional frame count
+                params.sweep = Some(DEFAULT_SWEEP_FRAMES);
+                if let Some(frames) = args.get(i + 1).and_then(|v| v.parse().ok()) {
+                    if frames == 0 {
+                        return Err("Sweep frame count must be positive");
+                    }
+                    params.sweep = Some(frames);
+                    i += 1;
+                }
+            }
+            "--headless" => {
+                // Optional frame count
+                params.headless = Some(DEFAULT_HEADLESS_FRAMES);
+                if let Some(frames) = args.get(i + 1).and_then(|v| v.parse().ok()) {
+                    if frames == 0 {
+                        return Err("Headless frame count must be positive");
+                    }
+                    params.headless = Some(frames);
+                    i += 1;
+                }
+            }
+            "--screenshot" => {
+                i += 1;
+                params.screenshot = Some(args.get(i).ok_or("Missing screenshot file")?.clone());
+            }
+            "--benchmark" => {
+                i += 1;
+                params.benchmark = Some(BenchmarkParams {
+                    report_file: args.get(i).ok_or("Missing benchmark report file")?.clone(),
+                    warmup_frames: 0,
+                    frames: 0,
+                });
+            }
+            "--warmup" => {
+                i += 1;
+                warmup_frames = args
+                    .get(i)
+                    .and_then(|v| v.parse().ok())
+                    .ok_or("Invalid warmup frame count")?;
+            }
+            "--frames" => {
+                i += 1;
+                frames = args
+                    .get(i)
+                    .and_then(|v| v.parse().ok())
+                    .ok_or("Invalid benchmark frame count")?;
+            }
+            _ => return Err("Unknown argument"),
+        }
+        i += 1;
+    }
+
+    if params.screenshot.is_some() {
+        if params.headless.is_none() {
+            return Err("Screenshot requires --headless");
+        }
+        if params.sweep.is_some() {
+            return Err("Screenshot can't be combined with --sweep");
+        }
+    }
+    if let Some(benchmark) = &mut params.benchmark {
+        if frames == 0 {
+            return Err("Benchmark frame count must be positive");
+        }
+        benchmark.warmup_frames = warmup_frames;
+        benchmark.frames = frames;
+    }
+
+    Ok(params)
+}
+
+fn print_usage() {
+    println!("Usage: vbufferbench args");
+    let names: Vec<&str> = GRID_TECHNIQUES.iter().map(|t| t.name()).collect();
+    println!("Technique: --technique [{}]", names.join("|"));
+    println!(
+        "Sweep all supported techniques: --sweep [frames] (default {})",
+        DEFAULT_SWEEP_FRAMES
+    );
+    println!(
+        "Benchmark: --benchmark [report.json|report.csv] --warmup [N] (default {}) --frames [N] (default {})",
+        DEFAULT_BENCHMARK_WARMUP_FRAMES, DEFAULT_BENCHMARK_FRAMES
+    );
+    println!(
+        "Headless: --headless [frames] (default {}, a sweep runs to the end) --screenshot [file.png] (last frame)",
+        DEFAULT_HEADLESS_FRAMES
+    );
+    println!(
+        "Keys 1-{} switch the technique at runtime",
+        GRID_TECHNIQUES.len()
+    );
+}
+
 fn main() {
+    let args: Vec<String> = env::args().collect();
+    let params = parse_args(&args).unwrap_or_else(|err| {
+        println!("Argument error: {}", err);
+        print_usage();
+        process::exit(1);
+    });
+
     let diagonal = Vec3 {
         x: 150.0,
         y: 150.0,
@@ -47,22 +195,30 @@ fn main() {
     let center_to_edge = diagonal * 0.5;
     let diagonal_length = diagonal.length();
 
-    // Window
+    // Window (offscreen render target in headless mode)
     let window_width = 1920;
     let window_height = 1080;
 
-    let mut events_loop = EventLoop::new();
-    let window = WindowBuilder::new()
-        .with_title("Vulkan Test")
-        .with_inner_size(winit::dpi::PhysicalSize::new(
-            f64::from(window_width),
-            f64::from(window_height),
-        ))
-        .build(&events_loop)
-        .unwrap();
+    let mut window = if params.headless.is_none() {
+        let events_loop = EventLoop::new();
+        let window = WindowBuilder::new()
+            .with_title("Vulkan Test")
+            .with_inner_size(winit::dpi::PhysicalSize::new(
+                f64::from(window_width),
+                f64::from(window_height),
+            ))
+            .build(&events_loop)
+            .unwrap();
+        Some((events_loop, window))
+    } else {
+        None
+    };
 
     // Vulkan base initialization
-    let mut base = VulkanBase::new(&window, window_width, window_height);
+    let mut base = match &window {
+        Some((_, window)) => VulkanBase::new(window, window_width, window_height, false),
+        None => VulkanBase::new_headless(window_width, window_height),
+    };
 
     // Render passes
     let render_pass_attachments = [
@@ -71,7 +227,7 @@ fn main() {
             samples: vk::SampleCountFlags::TYPE_1,
             load_op: vk::AttachmentLoadOp::CLEAR,
             store_op: vk::AttachmentStoreOp::STORE,
-            final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            final_layout: base.present_layout,
             ..Default::default()
         },
         vk::AttachmentDescription {
@@ -195,8 +351,48 @@ fn main() {
         &view_scissor,
         &instances.instances_buffer_descriptor,
         NUM_INSTANCES,
+        &base.feature_support,
     );
 
+    let techniques = render_grids.supported_techniques();
+    for technique in GRID_TECHNIQUES.iter() {
+        if !techniques.contains(technique) {
+            println!(
+                "Technique not supported by the device: {}",
+                technique.name()
+            );
+        }
+    }
+    if let Some(technique) = params.technique {
+        if !render_grids.set_technique(technique) {
+            println!(
+                "Technique not supported by the device: {}",
+                technique.name()
+            );
+            process::exit(1);
+        }
+    }
+    println!("Technique: {}", render_grids.technique.name());
+
+    let mut sweep = match &params.benchmark {
+        Some(benchmark) => {
+            let benchmark_techniques = if params.sweep.is_some() {
+                techniques.clone()
+            } else {
+                vec![render_grids.technique]
+            };
+            Some(Sweep::new(
+                benchmark_techniques,
+                benchmark.frames,
+                benchmark.warmup_frames,
+            ))
+        }
+        None => params
+            .sweep
+            .map(|frames| Sweep::new(techniques.clone(), frames, SWEEP_WARMUP_FRAMES)),
+    };
+    let mut benchmark_runs: Vec<BenchmarkRun> = Vec::new();
+
     // Submit initialization command buffer before rendering starts
     base.record_submit_commandbuffer(
         0,
@@ -251,255 +447,390 @@ fn main() {
         }
     }
 
-    // Window event loop
-    println!("Start window event loop");
-
     let mut inputs_prev: Inputs = Default::default();
     let mut inputs: Inputs = Default::default();
 
     let mut time_start = Instant::now();
     let mut frame = 0u32;
     let mut active_command_buffer = 0;
+    let mut gpu_profiler = GpuProfiler::new(&base, &GPU_PASS_NAMES);
+    let mut frame_time_prev = Instant::now();
+
+    // Benchmark orbits around the origin starting from the default camera
+    let orbit_radius = Vec3 {
+        x: camera.position.x,
+        y: 0.0,
+        z: camera.position.z,
+    }
+    .length();
+    let orbit_height = camera.position.y;
+
+    // Keyboard technique switching is disabled during a sweep
+    let sweeping = sweep.is_some();
+
+    // Renders one frame, returns false when the run is finished
+    let mut render_frame =
+        |inputs: Inputs, technique_request: Option<usize>, capture_file: Option<&String>| -> bool {
+            let cursor_delta = (
+                inputs.cursor_position.0 - inputs_prev.cursor_position.0,
+                inputs.cursor_position.1 - inputs_prev.cursor_position.1,
+            );
+
+            inputs_prev = inputs;
+
+            // Update camera based in inputs
+            let view_rot = view(
+                Vec3 {
+                    x: 0.0,
+                    y: 0.0,
+                    z: 0.0,
+                },
+                camera.direction,
+                Vec3 {
+                    x: 0.0,
+                    y: 1.0,
+                    z: 0.0,
+                },
+            );
 
-    events_loop.run_return(|event, _, control_flow| {
-        *control_flow = ControlFlow::Poll;
+            let forward_speed = inputs.wheel_delta * 5.0 + inputs.keyboard_forward as f32 * 1.5;
+            camera.position = camera.position + camera.direction * forward_speed;
 
-        match event {
-            Event::NewEvents(_) => {
-                inputs.wheel_delta = 0.0;
-            }
+            let side_speed = inputs.keyboard_side as f32 * 1.5;
+            let side_vec = Vec3 {
+                x: view_rot.r0.x,
+                y: view_rot.r1.x,
+                z: view_rot.r2.x,
+            };
+            camera.position = camera.position + side_vec * side_speed;
 
-            Event::MainEventsCleared => {
-                let cursor_delta = (
-                    inputs.cursor_position.0 - inputs_prev.cursor_position.0,
-                    inputs.cursor_position.1 - inputs_prev.cursor_position.1,
-                );
+            if inputs.is_left_clicked {
+                let rot = rot_y_axis(cursor_delta.0 as f32 * 0.0015)
+                    * rot_x_axis(cursor_delta.1 as f32 * 0.0015);
 
-                inputs_prev = inputs;
+                let rot = rot * inverse(view_rot);
 
-                // Update camera based in inputs
-                let view_rot = view(
-                    Vec3 {
-                        x: 0.0,
-                        y: 0.0,
-                        z: 0.0,
-                    },
-                    camera.direction,
-                    Vec3 {
-                        x: 0.0,
-                        y: 1.0,
-                        z: 0.0,
-                    },
-                );
+                camera.direction = Vec3 {
+                    x: 0.0,
+                    y: 0.0,
+                    z: 1.0,
+                } * rot;
 
-                let forward_speed = inputs.wheel_delta * 5.0 + inputs.keyboard_forward as f32 * 1.5;
-                camera.position = camera.position + camera.direction * forward_speed;
+                camera.direction = camera.direction.normalize();
+            }
 
-                let side_speed = inputs.keyboard_side as f32 * 1.5;
-                let side_vec = Vec3 {
-                    x: view_rot.r0.x,
-                    y: view_rot.r1.x,
-                    z: view_rot.r2.x,
-                };
-                camera.position = camera.position + side_vec * side_speed;
+            // Technique selection
+            if let Some(index) = technique_request {
+                let technique = GRID_TECHNIQUES[index];
+                if render_grids.set_technique(technique) {
+                    println!("Technique: {}", technique.name());
+                } else {
+                    println!(
+                        "Technique not supported by the device: {}",
+                        technique.name()
+                    );
+                }
+            }
+            if let Some(sweep) = &sweep {
+                match sweep.technique() {
+                    Some(technique) => {
+                        if render_grids.technique != technique {
+                            println!("Sweep: {}", technique.name());
+                            render_grids.set_technique(technique);
+                        }
+                        if params.benchmark.is_some() {
+                            let (position, direction) = orbit_camera(
+                                orbit_radius,
+                                orbit_height,
+                                sweep.frame(),
+                                sweep.total_frames(),
+                            );
+                            camera.position = position;
+                            camera.direction = direction;
+                            gpu_profiler.recording = sweep.is_measuring();
+                        }
+                    }
+                    None => {
+                        sweep.print_table();
+                        return false;
+                    }
+                }
+            }
 
-                if inputs.is_left_clicked {
-                    let rot = rot_y_axis(cursor_delta.0 as f32 * 0.0015)
-                        * rot_x_axis(cursor_delta.1 as f32 * 0.0015);
+            // Render
+            let present_index = base.acquire_next_image();
+
+            // Update uniform buffer
+            let color = Vec4 {
+                x: 1.0,
+                y: 0.1,
+                z: 0.0,
+                w: 0.0,
+            };
+
+            let world_to_screen = view(
+                camera.position,
+                camera.direction,
+                Vec3 {
+                    x: 0.0,
+                    y: 1.0,
+                    z: 0.0,
+                },
+            ) * projection(
+                std::f32::consts::PI / 2.0,
+                window_width as f32 / window_height as f32,
+                1.0,
+                10000000.0,
+            );
+
+            let grid_uniforms = GridUniforms {
+                world_to_screen,
+                color,
+                center_to_edge: center_to_edge.to_4d(),
+            };
+
+            render_grids.update(&grid_uniforms);
+
+            // Setup render passs
+            let clear_values = [
+                vk::ClearValue {
+                    color: vk::ClearColorValue {
+                        float32: [0.0, 0.0, 0.0, 0.0],
+                    },
+                },
+                vk::ClearValue {
+                    depth_stencil: vk::ClearDepthStencilValue {
+                        depth: 0.0,
+                        stencil: 0,
+                    },
+                },
+            ];
 
-                    let rot = rot * inverse(view_rot);
+            let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+                .render_pass(render_pass)
+                .framebuffer(framebuffers[present_index as usize])
+                .render_area(vk::Rect2D {
+                    offset: vk::Offset2D { x: 0, y: 0 },
+                    extent: base.surface_resolution,
+                })
+                .clear_values(&clear_values);
+
+            // Submit main command buffer
+            let frame_slot = active_command_buffer;
+            active_command_buffer = base.record_submit_commandbuffer(
+                active_command_buffer,
+                base.present_queue,
+                &[vk::PipelineStageFlags::BOTTOM_OF_PIPE],
+                &[base.present_complete_semaphore],
+                &[base.rendering_complete_semaphore],
+                |device, command_buffer| {
+                    gpu_profiler.begin_frame(device, &command_buffer, frame_slot);
+
+                    // Draw/setup (before main render pass)
+                    render_grids.gpu_draw(device, &command_buffer);
+
+                    // Render pass
+                    unsafe {
+                        device.cmd_begin_render_pass(
+                            command_buffer,
+                            &render_pass_begin_info,
+                            vk::SubpassContents::INLINE,
+                        );
+                        device.cmd_set_viewport(command_buffer, 0, &[view_scissor.viewport]);
+                        device.cmd_set_scissor(command_buffer, 0, &[view_scissor.scissor]);
+                    }
 
-                    camera.direction = Vec3 {
-                        x: 0.0,
-                        y: 0.0,
-                        z: 1.0,
-                    } * rot;
+                    // Draw (main render pass)
+                    gpu_profiler.begin_pass(device, &command_buffer, GPU_PASS_GRIDS);
+                    render_grids.gpu_draw_main_render_pass(device, &command_buffer);
+                    gpu_profiler.end_pass(device, &command_buffer, GPU_PASS_GRIDS);
 
-                    camera.direction = camera.direction.normalize();
+                    unsafe {
+                        device.cmd_end_render_pass(command_buffer);
+                    }
+                },
+            );
+
+            if let Some(capture_file) = capture_file {
+                match base.read_present_image(present_index) {
+                    Some(mut image) => {
+                        for pixel in image.pixels_mut() {
+                            pixel[3] = 255;
+                        }
+                        println!("Capture: {}", capture_file);
+                        image.save(capture_file).unwrap_or_else(|err| {
+                            println!("Capture store failed: {}", err);
+                            process::exit(1);
+                        });
+                    }
+                    None => println!("Capture not supported by the swapchain"),
                 }
+            }
 
-                // Render
-                let (present_index, _) = unsafe {
-                    base.swapchain_loader.acquire_next_image(
-                        base.swapchain,
-                        std::u64::MAX,
-                        base.present_complete_semaphore,
-                        vk::Fence::null(),
-                    )
+            // Present frame
+            base.present(present_index);
+
+            let frame_time_now = Instant::now();
+            if let Some(sweep) = &mut sweep {
+                let frame_time = (frame_time_now - frame_time_prev).as_secs_f32() * 1000.0;
+                if sweep.record_frame(frame_time) && params.benchmark.is_some() {
+                    // Technique done, collect its GPU results before switching
+                    unsafe { base.device.device_wait_idle() }.unwrap();
+                    gpu_profiler.flush(&base.device);
+                    let gpu_samples = gpu_profiler.take_samples();
+                    let result = sweep.results.last().unwrap();
+                    benchmark_runs.push(BenchmarkRun::new(
+                        result.technique.name(),
+                        &result.frame_times_ms,
+                        &gpu_profiler.pass_names,
+                        &gpu_samples,
+                    ));
                 }
-                .unwrap();
+            }
+            frame_time_prev = frame_time_now;
 
-                // Update uniform buffer
-                let color = Vec4 {
-                    x: 1.0,
-                    y: 0.1,
-                    z: 0.0,
-                    w: 0.0,
-                };
-
-                let world_to_screen = view(
-                    camera.position,
-                    camera.direction,
-                    Vec3 {
-                        x: 0.0,
-                        y: 1.0,
-                        z: 0.0,
-                    },
-                ) * projection(
-                    std::f32::consts::PI / 2.0,
-                    window_width as f32 / window_height as f32,
-                    1.0,
-                    10000000.0,
-                );
+            // Output performance info every 60 frames
+            frame += 1;
+            if frame.is_multiple_of(60) {
+                let time_now = Instant::now();
+                let interval = (time_now - time_start).as_millis();
+                println!("Average frame time: {} ms", interval as f32 / 60.0f32);
+                gpu_profiler.report();
 
-                let grid_uniforms = GridUniforms {
-                    world_to_screen,
-                    color,
-                    center_to_edge: center_to_edge.to_4d(),
-                };
+                time_start = time_now;
+            }
 
-                render_grids.update(&grid_uniforms);
+            true
+        };
 
-                // Setup render passs
-                let clear_values = [
-                    vk::ClearValue {
-                        color: vk::ClearColorValue {
-                            float32: [0.0, 0.0, 0.0, 0.0],
-                        },
-                    },
-                    vk::ClearValue {
-                        depth_stencil: vk::ClearDepthStencilValue {
-                            depth: 0.0,
-                            stencil: 0,
-                        },
-                    },
-                ];
-
-                let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
-                    .render_pass(render_pass)
-                    .framebuffer(framebuffers[present_index as usize])
-                    .render_area(vk::Rect2D {
-                        offset: vk::Offset2D { x: 0, y: 0 },
-                        extent: base.surface_resolution,
-                    })
-                    .clear_values(&clear_values);
-
-                // Submit main command buffer
-                active_command_buffer = base.record_submit_commandbuffer(
-                    active_command_buffer,
-                    base.present_queue,
-                    &[vk::PipelineStageFlags::BOTTOM_OF_PIPE],
-                    &[base.present_complete_semaphore],
-                    &[base.rendering_complete_semaphore],
-                    |device, command_buffer| {
-                        // Draw/setup (before main render pass)
-                        render_grids.gpu_draw(device, &command_buffer);
-
-                        // Render pass
-                        unsafe {
-                            device.cmd_begin_render_pass(
-                                command_buffer,
-                                &render_pass_begin_info,
-                                vk::SubpassContents::INLINE,
-                            );
-                            device.cmd_set_viewport(command_buffer, 0, &[view_scissor.viewport]);
-                            device.cmd_set_scissor(command_buffer, 0, &[view_scissor.scissor]);
-                        }
+    if let Some(frames) = params.headless {
+        println!("Start headless rendering");
+        let mut headless_frames = 0;
+        loop {
+            // The sweep ends the run on its own
+            let last_frame = !sweeping && headless_frames + 1 == frames;
+            let capture_file = params.screenshot.as_ref().filter(|_| last_frame);
+            if !render_frame(Default::default(), None, capture_file) {
+                break;
+            }
+            headless_frames += 1;
+            if last_frame {
+                break;
+            }
+        }
+        println!("End headless rendering: {} frames", headless_frames);
+    }
 
-                        // Draw (main render pass)
-                        render_grids.gpu_draw_main_render_pass(device, &command_buffer);
+    let mut technique_request: Option<usize> = None;
+    if let Some((events_loop, _)) = &mut window {
+        println!("Start window event loop");
+        events_loop.run_return(|event, _, control_flow| {
+            *control_flow = ControlFlow::Poll;
 
-                        unsafe {
-                            device.cmd_end_render_pass(command_buffer);
-                        }
-                    },
-                );
+            match event {
+                Event::NewEvents(_) => {
+                    inputs.wheel_delta = 0.0;
+                }
 
-                // Present frame
-                let present_info = vk::PresentInfoKHR {
-                    wait_semaphore_count: 1,
-                    p_wait_semaphores: &base.rendering_complete_semaphore,
-                    swapchain_count: 1,
-                    p_swapchains: &base.swapchain,
-                    p_image_indices: &present_index,
-                    ..Default::default()
-                };
-
-                unsafe {
-                    base.swapchain_loader
-                        .queue_present(base.present_queue, &present_info)
+                Event::MainEventsCleared => {
+                    let running = render_frame(inputs, technique_request.take(), None);
+                    if !running {
+                        *control_flow = ControlFlow::Exit;
+                    }
                 }
-                .unwrap();
 
-                // Output performance info every 60 frames
-                frame += 1;
-                if (frame % 60) == 0 {
-                    let time_now = Instant::now();
-                    let interval = (time_now - time_start).as_millis();
-                    println!("Average frame time: {} ms", interval as f32 / 60.0f32);
+                Event::WindowEvent { event, .. } => match event {
+                    WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
 
-                    time_start = time_now;
-                }
-            }
+                    // TODO: Handle swapchain resize
+                    WindowEvent::Resized { .. } => {}
 
-            Event::WindowEvent { event, .. } => match event {
-                WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+                    // Keyboard
+                    WindowEvent::KeyboardInput { input, .. } => {
+                        let pressed = input.state == ElementState::Pressed;
 
-                // TODO: Handle swapchain resize
-                WindowEvent::Resized { .. } => {}
+                        if input.virtual_keycode == Some(VirtualKeyCode::W) {
+                            inputs.keyboard_forward = if pressed { 1 } else { 0 };
+                        }
 
-                // Keyboard
-                WindowEvent::KeyboardInput { input, .. } => {
-                    let pressed = input.state == ElementState::Pressed;
+                        if input.virtual_keycode == Some(VirtualKeyCode::S) {
+                            inputs.keyboard_forward = if pressed { -1 } else { 0 };
+                        }
 
-                    if input.virtual_keycode == Some(VirtualKeyCode::W) {
-                        inputs.keyboard_forward = if pressed { 1 } else { 0 };
-                    }
+                        if input.virtual_keycode == Some(VirtualKeyCode::D) {
+                            inputs.keyboard_side = if pressed { 1 } else { 0 };
+                        }
 
-                    if input.virtual_keycode == Some(VirtualKeyCode::S) {
-                        inputs.keyboard_forward = if pressed { -1 } else { 0 };
-                    }
+                        if input.virtual_keycode == Some(VirtualKeyCode::A) {
+                            inputs.keyboard_side = if pressed { -1 } else { 0 };
+                        }
 
-                    if input.virtual_keycode == Some(VirtualKeyCode::D) {
-                        inputs.keyboard_side = if pressed { 1 } else { 0 };
+                        let technique_keys = [
+                            VirtualKeyCode::Key1,
+                            VirtualKeyCode::Key2,
+                            VirtualKeyCode::Key3,
+                            VirtualKeyCode::Key4,
+                            VirtualKeyCode::Key5,
+                            VirtualKeyCode::Key6,
+                        ];
+                        if let Some(index) = technique_keys
+                            .iter()
+                            .position(|&key| input.virtual_keycode == Some(key))
+                        {
+                            if pressed && !sweeping {
+                                technique_request = Some(index);
+                            }
+                        }
                     }
 
-                    if input.virtual_keycode == Some(VirtualKeyCode::A) {
-                        inputs.keyboard_side = if pressed { -1 } else { 0 };
+                    // Mouse
+                    WindowEvent::MouseInput {
+                        button: MouseButton::Left,
+                        state,
+                        ..
+                    } => {
+                        inputs.is_left_clicked = state == ElementState::Pressed;
                     }
-                }
+                    WindowEvent::CursorMoved { position, .. } => {
+                        let position: (i32, i32) = position.into();
+                        inputs.cursor_position = position;
+                    }
+                    WindowEvent::MouseWheel {
+                        delta: MouseScrollDelta::LineDelta(_, v_lines),
+                        ..
+                    } => {
+                        inputs.wheel_delta += v_lines;
+                    }
+                    _ => (),
+                },
 
-                // Mouse
-                WindowEvent::MouseInput {
-                    button: MouseButton::Left,
-                    state,
-                    ..
-                } => {
-                    inputs.is_left_clicked = state == ElementState::Pressed;
-                }
-                WindowEvent::CursorMoved { position, .. } => {
-                    let position: (i32, i32) = position.into();
-                    inputs.cursor_position = position;
-                }
-                WindowEvent::MouseWheel {
-                    delta: MouseScrollDelta::LineDelta(_, v_lines),
-                    ..
-                } => {
-                    inputs.wheel_delta += v_lines;
-                }
                 _ => (),
-            },
-
-            Event::LoopDestroyed => unsafe { base.device.device_wait_idle() }.unwrap(),
-            _ => (),
-        }
-    });
-
-    println!("End window event loop");
+            }
+        });
+        println!("End window event loop");
+    }
 
     unsafe { base.device.device_wait_idle() }.unwrap();
 
+    if let Some(benchmark) = &params.benchmark {
+        let report = BenchmarkReport {
+            application: "vbufferbench".to_string(),
+            device: DeviceInfo::new(&base),
+            width: window_width,
+            height: window_height,
+            num_instances: NUM_INSTANCES,
+            warmup_frames: benchmark.warmup_frames,
+            frames: benchmark.frames,
+            runs: benchmark_runs,
+        };
+        match report.store(&benchmark.report_file) {
+            Ok(()) => println!("Benchmark report: {}", benchmark.report_file),
+            Err(err) => println!("Benchmark report store failed: {}", err),
+        }
+    }
+
     // Cleanup
+    gpu_profiler.destroy(&base.device);
     instances.destroy(&base.device, &mut base.allocator);
     render_grids.destroy(&base.device, &mut base.allocator);
     unsafe {
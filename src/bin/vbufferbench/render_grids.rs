@@ -1,4 +1,5 @@
-enum GridTechnique {
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GridTechnique {
     Color,
     PrimId,
     NonIndexed,
@@ -7,7 +8,58 @@ enum GridTechnique {
     MeshShader,
 }
 
-const GRID_TECHNIQUE: GridTechnique = GridTechnique::LeadingVertex;
+pub const GRID_TECHNIQUES: [GridTechnique; 6] = [
+    GridTechnique::Color,
+    GridTechnique::PrimId,
+    GridTechnique::NonIndexed,
+    GridTechnique::LeadingVertex,
+    GridTechnique::GetAttributeAtVertex,
+    GridTechnique::MeshShader,
+];
+
+pub const DEFAULT_GRID_TECHNIQUE: GridTechnique = GridTechnique::LeadingVertex;
+
+impl GridTechnique {
+    pub fn name(self) -> &'static str {
+        match self {
+            GridTechnique::Color => "Color",
+            GridTechnique::PrimId => "PrimId",
+            GridTechnique::NonIndexed => "NonIndexed",
+            GridTechnique::LeadingVertex => "LeadingVertex",
+            GridTechnique::GetAttributeAtVertex => "GetAttributeAtVertex",
+            GridTechnique::MeshShader => "MeshShader",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<GridTechnique> {
+        GRID_TECHNIQUES
+            .iter()
+            .copied()
+            .find(|technique| technique.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn is_supported(self, support: &DeviceFeatureSupport) -> bool {
+        match self {
+            GridTechnique::GetAttributeAtVertex => support.fragment_shader_barycentric,
+            GridTechnique::MeshShader => support.mesh_shader,
+            _ => true,
+        }
+    }
+
+    fn geom_shader_stage(self) -> vk::ShaderStageFlags {
+        match self {
+            GridTechnique::MeshShader => vk::ShaderStageFlags::MESH_NV,
+            _ => vk::ShaderStageFlags::VERTEX,
+        }
+    }
+}
+
+pub struct GridPipeline {
+    pub technique: GridTechnique,
+    pub graphic_pipeline: vk::Pipeline,
+    pub vertex_shader_module: vk::ShaderModule,
+    pub fragment_shader_module: vk::ShaderModule,
+}
 
 use ash::Instance;
 use std::default::Default;
@@ -22,8 +74,12 @@ use gpu_allocator::vulkan::*;
 use gpu_allocator::MemoryLocation;
 
 use crate::minivector::*;
+use crate::vulkan_base::*;
 use crate::vulkan_helpers::*;
 
+const GRID_DIM: usize = 7;
+const NUM_GRID_INDICES: usize = GRID_DIM * GRID_DIM * 2 * 3;
+
 #[derive(Clone, Copy)]
 pub struct GridUniforms {
     pub world_to_screen: Mat4x4,
@@ -35,17 +91,99 @@ pub struct RenderGrids {
     pub pipeline_layout: vk::PipelineLayout,
     pub index_buffer: VkBuffer,
     pub index_buffer_gpu: VkBuffer,
+    // LeadingVertex uses a wider vertex grid (separate leading vertex per triangle)
+    pub index_buffer_leading_vertex: VkBuffer,
+    pub index_buffer_leading_vertex_gpu: VkBuffer,
     pub uniform_buffer: VkBuffer,
     pub uniform_buffer_gpu: VkBuffer,
     pub desc_set_layout: vk::DescriptorSetLayout,
-    pub graphic_pipeline: vk::Pipeline,
+    // Supported techniques only
+    pub pipelines: Vec<GridPipeline>,
+    pub technique: GridTechnique,
     pub descriptor_sets: Vec<vk::DescriptorSet>,
-    pub vertex_shader_module: vk::ShaderModule,
-    pub fragment_shader_module: vk::ShaderModule,
     pub mesh_shader: ash::extensions::nv::MeshShader,
     pub num_instances: usize,
 }
 
+fn grid_index_buffer_data(num_instances: usize, leading_vertex: bool) -> Vec<u32> {
+    let grid_stride = if leading_vertex {
+        (GRID_DIM + 1) * 2
+    } else {
+        GRID_DIM + 1
+    };
+    let instance_stride = if leading_vertex {
+        GRID_DIM * (GRID_DIM + 1) * 2
+    } else {
+        (GRID_DIM + 1) * (GRID_DIM + 1)
+    };
+
+    let mut grid_indices: [u32; NUM_GRID_INDICES] = [0; NUM_GRID_INDICES];
+    for y in 0..GRID_DIM {
+        for x in 0..GRID_DIM {
+            let grid = x + y * GRID_DIM;
+            let vertex = (x + y * grid_stride) as u32;
+
+            // Upper left triangle
+            grid_indices[grid * 6] = vertex;
+            grid_indices[grid * 6 + 1] = 1 + vertex;
+            grid_indices[grid * 6 + 2] = (GRID_DIM + 1) as u32 + vertex;
+
+            // Lower right triangle
+            grid_indices[grid * 6 + 3] = (GRID_DIM + 1) as u32 + vertex;
+            grid_indices[grid * 6 + 4] = 1 + vertex;
+            grid_indices[grid * 6 + 5] = 1 + (GRID_DIM + 1) as u32 + vertex;
+        }
+    }
+
+    let num_indices = num_instances * NUM_GRID_INDICES;
+
+    (0..num_indices)
+        .map(|i| {
+            let grid = i / NUM_GRID_INDICES;
+            let grid_local = i % NUM_GRID_INDICES;
+            grid_indices[grid_local] + grid as u32 * instance_stride as u32
+        })
+        .collect()
+}
+
+// CPU upload buffer and GPU index buffer
+fn create_index_buffers(
+    device: &Device,
+    allocator: &mut Allocator,
+    index_buffer_data: &[u32],
+) -> (VkBuffer, VkBuffer) {
+    let index_buffer_info = vk::BufferCreateInfo {
+        size: std::mem::size_of_val(index_buffer_data) as u64,
+        usage: vk::BufferUsageFlags::TRANSFER_SRC,
+        sharing_mode: vk::SharingMode::EXCLUSIVE,
+        ..Default::default()
+    };
+
+    let index_buffer = VkBuffer::new(
+        device,
+        allocator,
+        &index_buffer_info,
+        MemoryLocation::CpuToGpu,
+    );
+    index_buffer.copy_from_slice(index_buffer_data, 0);
+
+    let index_buffer_gpu_info = vk::BufferCreateInfo {
+        size: std::mem::size_of_val(index_buffer_data) as u64,
+        usage: vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::INDEX_BUFFER,
+        sharing_mode: vk::SharingMode::EXCLUSIVE,
+        ..Default::default()
+    };
+
+    let index_buffer_gpu = VkBuffer::new(
+        device,
+        allocator,
+        &index_buffer_gpu_info,
+        MemoryLocation::GpuOnly,
+    );
+
+    (index_buffer, index_buffer_gpu)
+}
+
 impl RenderGrids {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
@@ -57,78 +195,19 @@ impl RenderGrids {
         view_scissor: &VkViewScissor,
         instances_buffer_descriptor: &vk::DescriptorBufferInfo,
         num_instances: usize,
+        feature_support: &DeviceFeatureSupport,
     ) -> RenderGrids {
         let mesh_shader = ash::extensions::nv::MeshShader::new(instance, device);
 
-        const GRID_DIM: usize = 7;
-        const NUM_GRID_INDICES: usize = GRID_DIM * GRID_DIM * 2 * 3;
-
-        let grid_stride = if let GridTechnique::LeadingVertex = GRID_TECHNIQUE {
-            (GRID_DIM + 1) * 2
-        } else {
-            GRID_DIM + 1
-        };
-        let instance_stride = if let GridTechnique::LeadingVertex = GRID_TECHNIQUE {
-            GRID_DIM * (GRID_DIM + 1) * 2
-        } else {
-            (GRID_DIM + 1) * (GRID_DIM + 1)
-        };
-
-        let mut grid_indices: [u32; NUM_GRID_INDICES] = [0; NUM_GRID_INDICES];
-        for y in 0..GRID_DIM {
-            for x in 0..GRID_DIM {
-                let grid = x + y * GRID_DIM;
-                let vertex = (x + y * grid_stride) as u32;
-
-                // Upper left triangle
-                grid_indices[grid * 6] = vertex;
-                grid_indices[grid * 6 + 1] = 1 + vertex;
-                grid_indices[grid * 6 + 2] = (GRID_DIM + 1) as u32 + vertex;
-
-                // Lower right triangle
-                grid_indices[grid * 6 + 3] = (GRID_DIM + 1) as u32 + vertex;
-                grid_indices[grid * 6 + 4] = 1 + vertex;
-                grid_indices[grid * 6 + 5] = 1 + (GRID_DIM + 1) as u32 + vertex;
-            }
-        }
-
-        let num_indices = num_instances * NUM_GRID_INDICES;
-
-        let index_buffer_data: Vec<u32> = (0..num_indices)
-            .map(|i| {
-                let grid = i / NUM_GRID_INDICES;
-                let grid_local = i % NUM_GRID_INDICES;
-                grid_indices[grid_local] + grid as u32 * instance_stride as u32
-            })
-            .collect();
-
-        let index_buffer_info = vk::BufferCreateInfo {
-            size: std::mem::size_of_val(&index_buffer_data[..]) as u64,
-            usage: vk::BufferUsageFlags::TRANSFER_SRC,
-            sharing_mode: vk::SharingMode::EXCLUSIVE,
-            ..Default::default()
-        };
-
-        let index_buffer = VkBuffer::new(
+        let (index_buffer, index_buffer_gpu) = create_index_buffers(
             device,
             allocator,
-            &index_buffer_info,
-            MemoryLocation::CpuToGpu,
+            &grid_index_buffer_data(num_instances, false),
         );
-        index_buffer.copy_from_slice(&index_buffer_data[..], 0);
-
-        let index_buffer_gpu_info = vk::BufferCreateInfo {
-            size: std::mem::size_of_val(&index_buffer_data[..]) as u64,
-            usage: vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::INDEX_BUFFER,
-            sharing_mode: vk::SharingMode::EXCLUSIVE,
-            ..Default::default()
-        };
-
-        let index_buffer_gpu = VkBuffer::new(
+        let (index_buffer_leading_vertex, index_buffer_leading_vertex_gpu) = create_index_buffers(
             device,
             allocator,
-            &index_buffer_gpu_info,
-            MemoryLocation::GpuOnly,
+            &grid_index_buffer_data(num_instances, true),
         );
 
         let uniform_buffer_info = vk::BufferCreateInfo {
@@ -159,12 +238,18 @@ impl RenderGrids {
             MemoryLocation::GpuOnly,
         );
 
-        let geom_shader_stage: vk::ShaderStageFlags =
-            if let GridTechnique::MeshShader = GRID_TECHNIQUE {
-                vk::ShaderStageFlags::MESH_NV
-            } else {
-                vk::ShaderStageFlags::VERTEX
-            };
+        let techniques: Vec<GridTechnique> = GRID_TECHNIQUES
+            .iter()
+            .copied()
+            .filter(|technique| technique.is_supported(feature_support))
+            .collect();
+
+        // Descriptor set layout shared by all technique pipelines
+        let geom_shader_stage = techniques
+            .iter()
+            .fold(vk::ShaderStageFlags::empty(), |stages, technique| {
+                stages | technique.geom_shader_stage()
+            });
 
         let desc_layout_bindings = [
             vk::DescriptorSetLayoutBinding {
@@ -244,227 +329,75 @@ impl RenderGrids {
         let pipeline_layout =
             unsafe { device.create_pipeline_layout(&layout_create_info, None) }.unwrap();
 
-        let mut vertex_spv_file = Cursor::new(match GRID_TECHNIQUE {
-            GridTechnique::Color => &include_bytes!("../../../shader/vbuffer_vert.spv")[..],
-            GridTechnique::PrimId => &include_bytes!("../../../shader/vbuffer_vert.spv")[..],
-            GridTechnique::NonIndexed => {
-                &include_bytes!("../../../shader/vbuffer_nonindexed_vert.spv")[..]
-            }
-            GridTechnique::LeadingVertex => {
-                &include_bytes!("../../../shader/vbuffer_leadingvertex_vert.spv")[..]
-            }
-            GridTechnique::GetAttributeAtVertex => {
-                &include_bytes!("../../../shader/vbuffer_getattributeatvertex_vert.spv")[..]
-            }
-            GridTechnique::MeshShader => {
-                &include_bytes!("../../../shader/vbuffer_meshshader_mesh.spv")[..]
-            }
-        });
-
-        let mut frag_spv_file = Cursor::new(match GRID_TECHNIQUE {
-            GridTechnique::Color => &include_bytes!("../../../shader/vbuffer_color_frag.spv")[..],
-            GridTechnique::PrimId => &include_bytes!("../../../shader/vbuffer_primid_frag.spv")[..],
-            GridTechnique::NonIndexed => {
-                &include_bytes!("../../../shader/vbuffer_nonindexed_frag.spv")[..]
-            }
-            GridTechnique::LeadingVertex => {
-                &include_bytes!("../../../shader/vbuffer_leadingvertex_frag.spv")[..]
-            }
-            GridTechnique::GetAttributeAtVertex => {
-                &include_bytes!("../../../shader/vbuffer_getattributeatvertex_frag.spv")[..]
-            }
-            GridTechnique::MeshShader => {
-                &include_bytes!("../../../shader/vbuffer_meshshader_frag.spv")[..]
-            }
-        });
-
-        let vertex_code =
-            read_spv(&mut vertex_spv_file).expect("Failed to read vertex shader spv file");
-        let vertex_shader_info = vk::ShaderModuleCreateInfo::builder().code(&vertex_code);
-
-        let frag_code =
-            read_spv(&mut frag_spv_file).expect("Failed to read fragment shader spv file");
-        let frag_shader_info = vk::ShaderModuleCreateInfo::builder().code(&frag_code);
-
-        let vertex_shader_module =
-            unsafe { device.create_shader_module(&vertex_shader_info, None) }
-                .expect("Vertex shader module error");
-
-        let fragment_shader_module =
-            unsafe { device.create_shader_module(&frag_shader_info, None) }
-                .expect("Fragment shader module error");
-
-        let shader_entry_name = CString::new("main").unwrap();
-        let shader_stage_create_infos = [
-            vk::PipelineShaderStageCreateInfo {
-                module: vertex_shader_module,
-                p_name: shader_entry_name.as_ptr(),
-                stage: geom_shader_stage,
-                ..Default::default()
-            },
-            vk::PipelineShaderStageCreateInfo {
-                module: fragment_shader_module,
-                p_name: shader_entry_name.as_ptr(),
-                stage: vk::ShaderStageFlags::FRAGMENT,
-                ..Default::default()
-            },
-        ];
-
-        let vertex_input_state_info = vk::PipelineVertexInputStateCreateInfo::default();
-
-        let vertex_input_assembly_state_info = vk::PipelineInputAssemblyStateCreateInfo {
-            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
-            ..Default::default()
-        };
-
-        let scissors = &[view_scissor.scissor];
-        let viewports = &[view_scissor.viewport];
-        let viewport_state_info = vk::PipelineViewportStateCreateInfo::builder()
-            .scissors(scissors)
-            .viewports(viewports);
-
-        let rasterization_info = vk::PipelineRasterizationStateCreateInfo {
-            cull_mode: vk::CullModeFlags::NONE,
-            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
-            line_width: 1.0,
-            polygon_mode: vk::PolygonMode::FILL,
-            ..Default::default()
-        };
-
-        let multisample_state_info = vk::PipelineMultisampleStateCreateInfo::builder()
-            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+        let pipelines = techniques
+            .iter()
+            .map(|&technique| {
+                create_pipeline(
+                    device,
+                    technique,
+                    pipeline_layout,
+                    render_pass,
+                    view_scissor,
+                )
+            })
+            .collect();
 
-        let noop_stencil_state = vk::StencilOpState {
-            fail_op: vk::StencilOp::KEEP,
-            pass_op: vk::StencilOp::KEEP,
-            depth_fail_op: vk::StencilOp::KEEP,
-            compare_op: vk::CompareOp::ALWAYS,
-            ..Default::default()
-        };
-        let depth_state_info = vk::PipelineDepthStencilStateCreateInfo {
-            depth_test_enable: 1,
-            depth_write_enable: 1,
-            depth_compare_op: vk::CompareOp::GREATER_OR_EQUAL,
-            front: noop_stencil_state,
-            back: noop_stencil_state,
-            max_depth_bounds: 1.0,
-            ..Default::default()
+        let technique = if DEFAULT_GRID_TECHNIQUE.is_supported(feature_support) {
+            DEFAULT_GRID_TECHNIQUE
+        } else {
+            techniques[0]
         };
 
-        let color_blend_attachment_states = [vk::PipelineColorBlendAttachmentState {
-            blend_enable: 0,
-            src_color_blend_factor: vk::BlendFactor::SRC_COLOR,
-            dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_DST_COLOR,
-            color_blend_op: vk::BlendOp::ADD,
-            src_alpha_blend_factor: vk::BlendFactor::ZERO,
-            dst_alpha_blend_factor: vk::BlendFactor::ZERO,
-            alpha_blend_op: vk::BlendOp::ADD,
-            color_write_mask: vk::ColorComponentFlags::RGBA,
-        }];
-        let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
-            .logic_op(vk::LogicOp::CLEAR)
-            .attachments(&color_blend_attachment_states);
-
-        let dynamic_state = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
-        let dynamic_state_info =
-            vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_state);
-
-        let graphic_pipeline_infos = vk::GraphicsPipelineCreateInfo::builder()
-            .stages(&shader_stage_create_infos)
-            .vertex_input_state(&vertex_input_state_info)
-            .input_assembly_state(&vertex_input_assembly_state_info)
-            .viewport_state(&viewport_state_info)
-            .rasterization_state(&rasterization_info)
-            .multisample_state(&multisample_state_info)
-            .depth_stencil_state(&depth_state_info)
-            .color_blend_state(&color_blend_state)
-            .dynamic_state(&dynamic_state_info)
-            .layout(pipeline_layout)
-            .render_pass(*render_pass);
-
-        let graphics_pipelines = unsafe {
-            device.create_graphics_pipelines(
-                vk::PipelineCache::null(),
-                &[graphic_pipeline_infos.build()],
-                None,
-            )
-        }
-        .unwrap();
-
-        let graphic_pipeline = graphics_pipelines[0];
-
         RenderGrids {
             pipeline_layout,
             index_buffer,
             index_buffer_gpu,
+            index_buffer_leading_vertex,
+            index_buffer_leading_vertex_gpu,
             uniform_buffer,
             uniform_buffer_gpu,
             desc_set_layout,
-            graphic_pipeline,
+            pipelines,
+            technique,
             descriptor_sets,
-            vertex_shader_module,
-            fragment_shader_module,
             mesh_shader,
             num_instances,
         }
     }
 
+    pub fn supported_techniques(&self) -> Vec<GridTechnique> {
+        self.pipelines
+            .iter()
+            .map(|pipeline| pipeline.technique)
+            .collect()
+    }
+
+    // False if the device doesn't support the technique
+    pub fn set_technique(&mut self, technique: GridTechnique) -> bool {
+        let supported = self.pipelines.iter().any(|p| p.technique == technique);
+        if supported {
+            self.technique = technique;
+        }
+        supported
+    }
+
     pub fn update(&self, uniforms: &GridUniforms) {
         self.uniform_buffer.copy_from_slice(&[*uniforms], 0);
     }
 
     pub fn gpu_setup(&self, device: &Device, command_buffer: &vk::CommandBuffer) {
-        let buffer_copy_regions = vk::BufferCopy {
-            src_offset: 0,
-            dst_offset: 0,
-            size: self.index_buffer.size,
-        };
-
-        let buffer_barrier = vk::BufferMemoryBarrier {
-            dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
-            buffer: self.index_buffer_gpu.buffer,
-            offset: 0,
-            size: buffer_copy_regions.size,
-            ..Default::default()
-        };
-
-        let buffer_barrier_end = vk::BufferMemoryBarrier {
-            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
-            dst_access_mask: vk::AccessFlags::INDEX_READ,
-            buffer: self.index_buffer_gpu.buffer,
-            offset: 0,
-            size: buffer_copy_regions.size,
-            ..Default::default()
-        };
-
-        unsafe {
-            device.cmd_pipeline_barrier(
-                *command_buffer,
-                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
-                vk::PipelineStageFlags::TRANSFER,
-                vk::DependencyFlags::empty(),
-                &[],
-                &[buffer_barrier],
-                &[],
-            );
-
-            device.cmd_copy_buffer(
-                *command_buffer,
-                self.index_buffer.buffer,
-                self.index_buffer_gpu.buffer,
-                &[buffer_copy_regions],
-            );
-
-            device.cmd_pipeline_barrier(
-                *command_buffer,
-                vk::PipelineStageFlags::TRANSFER,
-                vk::PipelineStageFlags::VERTEX_INPUT,
-                vk::DependencyFlags::empty(),
-                &[],
-                &[buffer_barrier_end],
-                &[],
-            );
-        };
+        upload_index_buffer(
+            device,
+            command_buffer,
+            &self.index_buffer,
+            &self.index_buffer_gpu,
+        );
+        upload_index_buffer(
+            device,
+            command_buffer,
+            &self.index_buffer_leading_vertex,
+            &self.index_buffer_leading_vertex_gpu,
+        );
     }
 
     pub fn gpu_draw(&self, device: &Device, command_buffer: &vk::CommandBuffer) {
@@ -532,23 +465,33 @@ impl RenderGrids {
                 &[],
             );
 
+            let pipeline = self
+                .pipelines
+                .iter()
+                .find(|pipeline| pipeline.technique == self.technique)
+                .unwrap();
             device.cmd_bind_pipeline(
                 *command_buffer,
                 vk::PipelineBindPoint::GRAPHICS,
-                self.graphic_pipeline,
+                pipeline.graphic_pipeline,
             );
 
+            let index_buffer_gpu = if self.technique == GridTechnique::LeadingVertex {
+                &self.index_buffer_leading_vertex_gpu
+            } else {
+                &self.index_buffer_gpu
+            };
             device.cmd_bind_index_buffer(
                 *command_buffer,
-                self.index_buffer_gpu.buffer,
+                index_buffer_gpu.buffer,
                 0,
                 vk::IndexType::UINT32,
             );
 
-            match GRID_TECHNIQUE {
+            match self.technique {
                 GridTechnique::NonIndexed => device.cmd_draw(
                     *command_buffer,
-                    self.index_buffer_gpu.size as u32 / std::mem::size_of::<u32>() as u32,
+                    index_buffer_gpu.size as u32 / std::mem::size_of::<u32>() as u32,
                     1,
                     0,
                     0,
@@ -560,7 +503,7 @@ impl RenderGrids {
                 ),
                 _ => device.cmd_draw_indexed(
                     *command_buffer,
-                    self.index_buffer_gpu.size as u32 / std::mem::size_of::<u32>() as u32,
+                    index_buffer_gpu.size as u32 / std::mem::size_of::<u32>() as u32,
                     1,
                     0,
                     0,
@@ -572,15 +515,238 @@ impl RenderGrids {
 
     pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
         unsafe {
-            device.destroy_pipeline(self.graphic_pipeline, None);
+            for pipeline in self.pipelines.iter() {
+                device.destroy_pipeline(pipeline.graphic_pipeline, None);
+                device.destroy_shader_module(pipeline.vertex_shader_module, None);
+                device.destroy_shader_module(pipeline.fragment_shader_module, None);
+            }
             device.destroy_pipeline_layout(self.pipeline_layout, None);
-            device.destroy_shader_module(self.vertex_shader_module, None);
-            device.destroy_shader_module(self.fragment_shader_module, None);
             self.index_buffer.destroy(device, allocator);
             self.index_buffer_gpu.destroy(device, allocator);
+            self.index_buffer_leading_vertex.destroy(device, allocator);
+            self.index_buffer_leading_vertex_gpu
+                .destroy(device, allocator);
             self.uniform_buffer.destroy(device, allocator);
             self.uniform_buffer_gpu.destroy(device, allocator);
             device.destroy_descriptor_set_layout(self.desc_set_layout, None);
         }
     }
 }
+
+fn create_pipeline(
+    device: &Device,
+    technique: GridTechnique,
+    pipeline_layout: vk::PipelineLayout,
+    render_pass: &vk::RenderPass,
+    view_scissor: &VkViewScissor,
+) -> GridPipeline {
+    let mut vertex_spv_file = Cursor::new(match technique {
+        GridTechnique::Color => &include_bytes!("../../../shader/vbuffer_vert.spv")[..],
+        GridTechnique::PrimId => &include_bytes!("../../../shader/vbuffer_vert.spv")[..],
+        GridTechnique::NonIndexed => {
+            &include_bytes!("../../../shader/vbuffer_nonindexed_vert.spv")[..]
+        }
+        GridTechnique::LeadingVertex => {
+            &include_bytes!("../../../shader/vbuffer_leadingvertex_vert.spv")[..]
+        }
+        GridTechnique::GetAttributeAtVertex => {
+            &include_bytes!("../../../shader/vbuffer_getattributeatvertex_vert.spv")[..]
+        }
+        GridTechnique::MeshShader => {
+            &include_bytes!("../../../shader/vbuffer_meshshader_mesh.spv")[..]
+        }
+    });
+
+    let mut frag_spv_file = Cursor::new(match technique {
+        GridTechnique::Color => &include_bytes!("../../../shader/vbuffer_color_frag.spv")[..],
+        GridTechnique::PrimId => &include_bytes!("../../../shader/vbuffer_primid_frag.spv")[..],
+        GridTechnique::NonIndexed => {
+            &include_bytes!("../../../shader/vbuffer_nonindexed_frag.spv")[..]
+        }
+        GridTechnique::LeadingVertex => {
+            &include_bytes!("../../../shader/vbuffer_leadingvertex_frag.spv")[..]
+        }
+        GridTechnique::GetAttributeAtVertex => {
+            &include_bytes!("../../../shader/vbuffer_getattributeatvertex_frag.spv")[..]
+        }
+        GridTechnique::MeshShader => {
+            &include_bytes!("../../../shader/vbuffer_meshshader_frag.spv")[..]
+        }
+    });
+
+    let vertex_code =
+        read_spv(&mut vertex_spv_file).expect("Failed to read vertex shader spv file");
+    let vertex_shader_info = vk::ShaderModuleCreateInfo::builder().code(&vertex_code);
+
+    let frag_code = read_spv(&mut frag_spv_file).expect("Failed to read fragment shader spv file");
+    let frag_shader_info = vk::ShaderModuleCreateInfo::builder().code(&frag_code);
+
+    let vertex_shader_module = unsafe { device.create_shader_module(&vertex_shader_info, None) }
+        .expect("Vertex shader module error");
+
+    let fragment_shader_module = unsafe { device.create_shader_module(&frag_shader_info, None) }
+        .expect("Fragment shader module error");
+
+    let shader_entry_name = CString::new("main").unwrap();
+    let shader_stage_create_infos = [
+        vk::PipelineShaderStageCreateInfo {
+            module: vertex_shader_module,
+            p_name: shader_entry_name.as_ptr(),
+            stage: technique.geom_shader_stage(),
+            ..Default::default()
+        },
+        vk::PipelineShaderStageCreateInfo {
+            module: fragment_shader_module,
+            p_name: shader_entry_name.as_ptr(),
+            stage: vk::ShaderStageFlags::FRAGMENT,
+            ..Default::default()
+        },
+    ];
+
+    let vertex_input_state_info = vk::PipelineVertexInputStateCreateInfo::default();
+
+    let vertex_input_assembly_state_info = vk::PipelineInputAssemblyStateCreateInfo {
+        topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+        ..Default::default()
+    };
+
+    let scissors = &[view_scissor.scissor];
+    let viewports = &[view_scissor.viewport];
+    let viewport_state_info = vk::PipelineViewportStateCreateInfo::builder()
+        .scissors(scissors)
+        .viewports(viewports);
+
+    let rasterization_info = vk::PipelineRasterizationStateCreateInfo {
+        cull_mode: vk::CullModeFlags::NONE,
+        front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+        line_width: 1.0,
+        polygon_mode: vk::PolygonMode::FILL,
+        ..Default::default()
+    };
+
+    let multisample_state_info = vk::PipelineMultisampleStateCreateInfo::builder()
+        .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+
+    let noop_stencil_state = vk::StencilOpState {
+        fail_op: vk::StencilOp::KEEP,
+        pass_op: vk::StencilOp::KEEP,
+        depth_fail_op: vk::StencilOp::KEEP,
+        compare_op: vk::CompareOp::ALWAYS,
+        ..Default::default()
+    };
+    let depth_state_info = vk::PipelineDepthStencilStateCreateInfo {
+        depth_test_enable: 1,
+        depth_write_enable: 1,
+        depth_compare_op: vk::CompareOp::GREATER_OR_EQUAL,
+        front: noop_stencil_state,
+        back: noop_stencil_state,
+        max_depth_bounds: 1.0,
+        ..Default::default()
+    };
+
+    let color_blend_attachment_states = [vk::PipelineColorBlendAttachmentState {
+        blend_enable: 0,
+        src_color_blend_factor: vk::BlendFactor::SRC_COLOR,
+        dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_DST_COLOR,
+        color_blend_op: vk::BlendOp::ADD,
+        src_alpha_blend_factor: vk::BlendFactor::ZERO,
+        dst_alpha_blend_factor: vk::BlendFactor::ZERO,
+        alpha_blend_op: vk::BlendOp::ADD,
+        color_write_mask: vk::ColorComponentFlags::RGBA,
+    }];
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op(vk::LogicOp::CLEAR)
+        .attachments(&color_blend_attachment_states);
+
+    let dynamic_state = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+    let dynamic_state_info =
+        vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_state);
+
+    let graphic_pipeline_infos = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(&shader_stage_create_infos)
+        .vertex_input_state(&vertex_input_state_info)
+        .input_assembly_state(&vertex_input_assembly_state_info)
+        .viewport_state(&viewport_state_info)
+        .rasterization_state(&rasterization_info)
+        .multisample_state(&multisample_state_info)
+        .depth_stencil_state(&depth_state_info)
+        .color_blend_state(&color_blend_state)
+        .dynamic_state(&dynamic_state_info)
+        .layout(pipeline_layout)
+        .render_pass(*render_pass);
+
+    let graphics_pipelines = unsafe {
+        device.create_graphics_pipelines(
+            vk::PipelineCache::null(),
+            &[graphic_pipeline_infos.build()],
+            None,
+        )
+    }
+    .unwrap();
+
+    GridPipeline {
+        technique,
+        graphic_pipeline: graphics_pipelines[0],
+        vertex_shader_module,
+        fragment_shader_module,
+    }
+}
+
+fn upload_index_buffer(
+    device: &Device,
+    command_buffer: &vk::CommandBuffer,
+    index_buffer: &VkBuffer,
+    index_buffer_gpu: &VkBuffer,
+) {
+    let buffer_copy_regions = vk::BufferCopy {
+        src_offset: 0,
+        dst_offset: 0,
+        size: index_buffer.size,
+    };
+
+    let buffer_barrier = vk::BufferMemoryBarrier {
+        dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+        buffer: index_buffer_gpu.buffer,
+        offset: 0,
+        size: buffer_copy_regions.size,
+        ..Default::default()
+    };
+
+    let buffer_barrier_end = vk::BufferMemoryBarrier {
+        src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+        dst_access_mask: vk::AccessFlags::INDEX_READ,
+        buffer: index_buffer_gpu.buffer,
+        offset: 0,
+        size: buffer_copy_regions.size,
+        ..Default::default()
+    };
+
+    unsafe {
+        device.cmd_pipeline_barrier(
+            *command_buffer,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[buffer_barrier],
+            &[],
+        );
+
+        device.cmd_copy_buffer(
+            *command_buffer,
+            index_buffer.buffer,
+            index_buffer_gpu.buffer,
+            &[buffer_copy_regions],
+        );
+
+        device.cmd_pipeline_barrier(
+            *command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::VERTEX_INPUT,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[buffer_barrier_end],
+            &[],
+        );
+    };
+}
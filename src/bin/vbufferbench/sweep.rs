@@ -0,0 +1,124 @@
+use crate::render_grids::*;
+
+pub const DEFAULT_SWEEP_FRAMES: u32 = 300;
+
+// Frames skipped after switching the technique (pipeline warmup)
+pub const SWEEP_WARMUP_FRAMES: u32 = 10;
+
+pub struct SweepResult {
+    pub technique: GridTechnique,
+    pub frames: u32,
+    pub average_ms: f32,
+    pub min_ms: f32,
+    pub max_ms: f32,
+    pub frame_times_ms: Vec<f64>,
+}
+
+// Runs each technique for a fixed number of frames
+pub struct Sweep {
+    techniques: Vec<GridTechnique>,
+    frames_per_technique: u32,
+    warmup_frames: u32,
+    current: usize,
+    frame: u32,
+    frame_times: Vec<f32>,
+    pub results: Vec<SweepResult>,
+}
+
+impl Sweep {
+    pub fn new(
+        techniques: Vec<GridTechnique>,
+        frames_per_technique: u32,
+        warmup_frames: u32,
+    ) -> Sweep {
+        Sweep {
+            techniques,
+            frames_per_technique,
+            warmup_frames,
+            current: 0,
+            frame: 0,
+            frame_times: Vec::new(),
+            results: Vec::new(),
+        }
+    }
+
+    // None when the sweep is done
+    pub fn technique(&self) -> Option<GridTechnique> {
+        self.techniques.get(self.current).copied()
+    }
+
+    // Frame index within the current technique (warmup included)
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    pub fn total_frames(&self) -> u32 {
+        self.warmup_frames + self.frames_per_technique
+    }
+
+    pub fn is_measuring(&self) -> bool {
+        self.frame >= self.warmup_frames
+    }
+
+    // Returns true when the current technique finished
+    pub fn record_frame(&mut self, frame_time_ms: f32) -> bool {
+        let technique = match self.technique() {
+            Some(technique) => technique,
+            None => return false,
+        };
+
+        self.frame += 1;
+        if self.frame <= self.warmup_frames {
+            return false;
+        }
+        self.frame_times.push(frame_time_ms);
+
+        if self.frame_times.len() as u32 == self.frames_per_technique {
+            let sum: f32 = self.frame_times.iter().sum();
+            let result = SweepResult {
+                technique,
+                frames: self.frame_times.len() as u32,
+                average_ms: sum / self.frame_times.len() as f32,
+                min_ms: self.frame_times.iter().copied().fold(f32::MAX, f32::min),
+                max_ms: self.frame_times.iter().copied().fold(0.0, f32::max),
+                frame_times_ms: self.frame_times.iter().map(|&t| t as f64).collect(),
+            };
+            println!(
+                "Sweep: {} average frame time: {:.3} ms",
+                technique.name(),
+                result.average_ms
+            );
+            self.results.push(result);
+
+            self.current += 1;
+            self.frame = 0;
+            self.frame_times.clear();
+            return true;
+        }
+        false
+    }
+
+    pub fn print_table(&self) {
+        let fastest = self
+            .results
+            .iter()
+            .map(|result| result.average_ms)
+            .fold(f32::MAX, f32::min);
+
+        println!(
+            "{:<22}{:>8}{:>12}{:>12}{:>12}{:>10}",
+            "Technique", "Frames", "Avg (ms)", "Min (ms)", "Max (ms)", "Relative"
+        );
+        for result in self.results.iter() {
+            println!(
+                "{:<22}{:>8}{:>12.3}{:>12.3}{:>12.3}{:>9.2}x",
+                result.technique.name(),
+                result.frames,
+                result.average_ms,
+                result.min_ms,
+                result.max_ms,
+                result.average_ms / fastest
+            );
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum SdfError {
+    Io(io::Error),
+    // Read past the end of data: offset, bytes needed, data length
+    Truncated {
+        offset: usize,
+        needed: usize,
+        len: usize,
+    },
+    // Voxel count of the dimensions does not fit in memory
+    DimensionOverflow((u32, u32, u32)),
+    // Voxel payload size (bytes) does not match the dimensions
+    SizeMismatch {
+        dim: (u32, u32, u32),
+        expected: u64,
+        actual: u64,
+    },
+    Compress(String),
+    Decompress(String),
+    BadMagic,
+    UnsupportedVersion(u32),
+    UnknownEncoding(u32),
+    UnknownVoxelType(u32),
+    // Encoding does not support the voxel type (encoding, voxel type)
+    UnsupportedVoxelType(u32, u32),
+    UnknownPredictor(u32),
+    // Quantized brick bit count out of range
+    InvalidBrick(u32),
+    ChecksumMismatch {
+        expected: u32,
+        actual: u32,
+    },
+    UnknownFormat,
+    // Mesh to SDF: no triangles to bound the volume
+    EmptyMesh,
+    // Tilemap file contents are inconsistent, see sdf_tilemap.rs
+    InvalidTilemap(&'static str),
+}
+
+impl fmt::Display for SdfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SdfError::Io(err) => write!(f, "I/O error: {}", err),
+            SdfError::Truncated {
+                offset,
+                needed,
+                len,
+            } => write!(
+                f,
+                "Truncated data: reading {} bytes at offset {}, data length {}",
+                needed, offset, len
+            ),
+            SdfError::DimensionOverflow(dim) => {
+                write!(f, "SDF dimensions {:?} overflow the voxel count", dim)
+            }
+            SdfError::SizeMismatch {
+                dim,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "SDF payload size {} does not match dimensions {:?} (expected {})",
+                actual, dim, expected
+            ),
+            SdfError::Compress(err) => write!(f, "SDF compression failed: {}", err),
+            SdfError::Decompress(err) => write!(f, "SDF decompression failed: {}", err),
+            SdfError::BadMagic => write!(f, "Not a versioned SDF file (bad magic)"),
+            SdfError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported SDF file version {}", version)
+            }
+            SdfError::UnknownEncoding(encoding) => write!(f, "Unknown SDF encoding {}", encoding),
+            SdfError::UnknownVoxelType(voxel_type) => {
+                write!(f, "Unknown SDF voxel type {}", voxel_type)
+            }
+            SdfError::UnsupportedVoxelType(encoding, voxel_type) => write!(
+                f,
+                "SDF encoding {} does not support voxel type {}",
+                encoding, voxel_type
+            ),
+            SdfError::UnknownPredictor(predictor) => {
+                write!(f, "Unknown SDF predictor {}", predictor)
+            }
+            SdfError::InvalidBrick(bits) => {
+                write!(f, "Invalid quantized SDF brick ({} bits per voxel)", bits)
+            }
+            SdfError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "SDF payload checksum mismatch: {:08x}, expected {:08x}",
+                actual, expected
+            ),
+            SdfError::UnknownFormat => write!(f, "Unknown SDF file format"),
+            SdfError::EmptyMesh => write!(f, "Mesh has no triangles"),
+            SdfError::InvalidTilemap(err) => write!(f, "Invalid tilemap: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SdfError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SdfError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SdfError {
+    fn from(err: io::Error) -> SdfError {
+        SdfError::Io(err)
+    }
+}
+
+impl From<SdfError> for io::Error {
+    fn from(err: SdfError) -> io::Error {
+        match err {
+            SdfError::Io(err) => err,
+            err => io::Error::new(io::ErrorKind::InvalidData, err),
+        }
+    }
+}
+
+pub type SdfResult<T> = Result<T, SdfError>;
@@ -0,0 +1,227 @@
+use ash::{vk, Device};
+
+use crate::vulkan_base::VulkanBase;
+
+#[derive(Clone, Copy)]
+pub struct PassTiming {
+    pub frames: u32,
+    pub total_ms: f64,
+    pub min_ms: f64,
+    pub max_ms: f64,
+}
+
+impl Default for PassTiming {
+    fn default() -> Self {
+        PassTiming {
+            frames: 0,
+            total_ms: 0.0,
+            min_ms: f64::MAX,
+            max_ms: 0.0,
+        }
+    }
+}
+
+impl PassTiming {
+    pub fn average_ms(&self) -> f64 {
+        if self.frames > 0 {
+            self.total_ms / self.frames as f64
+        } else {
+            0.0
+        }
+    }
+}
+
+// Timestamp queries bracketing named passes. One query range per command buffer: results
+// of a range are read back when its command buffer is recorded again (fence already waited).
+pub struct GpuProfiler {
+    pub query_pool: vk::QueryPool,
+    pub pass_names: Vec<String>,
+    pub timings: Vec<PassTiming>,
+    // Per pass samples of recorded frames (benchmark percentiles)
+    pub samples: Vec<Vec<f64>>,
+    pub recording: bool,
+    timestamp_period_ns: f64,
+    timestamp_mask: u64,
+    // Passes written per frame slot (not all passes run every frame)
+    written: Vec<Vec<bool>>,
+    // Recording state when the slot was recorded (results arrive frames later)
+    slot_recording: Vec<bool>,
+    frame_slot: usize,
+    enabled: bool,
+}
+
+impl GpuProfiler {
+    pub fn new(base: &VulkanBase, pass_names: &[&str]) -> GpuProfiler {
+        let num_frames = base.command_buffer_pool.command_buffers.len();
+        let num_passes = pass_names.len();
+
+        let properties = unsafe { base.instance.get_physical_device_properties(base.pdevice) };
+        let queue_families = unsafe {
+            base.instance
+                .get_physical_device_queue_family_properties(base.pdevice)
+        };
+        let valid_bits = queue_families[base.queue_family_index as usize].timestamp_valid_bits;
+
+        let enabled = valid_bits > 0 && properties.limits.timestamp_period > 0.0;
+        if !enabled {
+            println!("GPU timestamps not supported by the queue, profiling disabled");
+        }
+
+        let timestamp_mask = if valid_bits >= 64 {
+            u64::MAX
+        } else {
+            (1u64 << valid_bits) - 1
+        };
+
+        let query_pool_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::TIMESTAMP)
+            .query_count((num_frames * num_passes * 2) as u32);
+
+        let query_pool = unsafe { base.device.create_query_pool(&query_pool_info, None) }.unwrap();
+
+        GpuProfiler {
+            query_pool,
+            pass_names: pass_names.iter().map(|name| name.to_string()).collect(),
+            timings: vec![PassTiming::default(); num_passes],
+            samples: vec![Vec::new(); num_passes],
+            recording: false,
+            timestamp_period_ns: properties.limits.timestamp_period as f64,
+            timestamp_mask,
+            written: vec![vec![false; num_passes]; num_frames],
+            slot_recording: vec![false; num_frames],
+            frame_slot: 0,
+            enabled,
+        }
+    }
+
+    fn query_index(&self, frame_slot: usize, pass: usize) -> u32 {
+        ((frame_slot * self.pass_names.len() + pass) * 2) as u32
+    }
+
+    // Call first in the command buffer, frame_slot = active command buffer index
+    pub fn begin_frame(
+        &mut self,
+        device: &Device,
+        command_buffer: &vk::CommandBuffer,
+        frame_slot: usize,
+    ) {
+        if !self.enabled {
+            return;
+        }
+        self.frame_slot = frame_slot;
+
+        // Collect results of the previous frame recorded into this slot
+        self.collect(device, frame_slot);
+        self.slot_recording[frame_slot] = self.recording;
+
+        unsafe {
+            device.cmd_reset_query_pool(
+                *command_buffer,
+                self.query_pool,
+                self.query_index(frame_slot, 0),
+                (self.pass_names.len() * 2) as u32,
+            );
+        }
+    }
+
+    fn collect(&mut self, device: &Device, frame_slot: usize) {
+        for pass in 0..self.pass_names.len() {
+            if !self.written[frame_slot][pass] {
+                continue;
+            }
+            let mut timestamps = [0u64; 2];
+            let result = unsafe {
+                device.get_query_pool_results(
+                    self.query_pool,
+                    self.query_index(frame_slot, pass),
+                    2,
+                    &mut timestamps,
+                    vk::QueryResultFlags::TYPE_64,
+                )
+            };
+            if result.is_ok() {
+                let ticks = timestamps[1].wrapping_sub(timestamps[0]) & self.timestamp_mask;
+                let ms = ticks as f64 * self.timestamp_period_ns / 1_000_000.0;
+                let timing = &mut self.timings[pass];
+                timing.frames += 1;
+                timing.total_ms += ms;
+                timing.min_ms = timing.min_ms.min(ms);
+                timing.max_ms = timing.max_ms.max(ms);
+                if self.slot_recording[frame_slot] {
+                    self.samples[pass].push(ms);
+                }
+            }
+            self.written[frame_slot][pass] = false;
+        }
+    }
+
+    // Collects all pending results. Call after device_wait_idle.
+    pub fn flush(&mut self, device: &Device) {
+        if !self.enabled {
+            return;
+        }
+        for frame_slot in 0..self.slot_recording.len() {
+            self.collect(device, frame_slot);
+        }
+    }
+
+    pub fn take_samples(&mut self) -> Vec<Vec<f64>> {
+        let num_passes = self.pass_names.len();
+        std::mem::replace(&mut self.samples, vec![Vec::new(); num_passes])
+    }
+
+    pub fn begin_pass(&self, device: &Device, command_buffer: &vk::CommandBuffer, pass: usize) {
+        if !self.enabled {
+            return;
+        }
+        unsafe {
+            device.cmd_write_timestamp(
+                *command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                self.query_pool,
+                self.query_index(self.frame_slot, pass),
+            );
+        }
+    }
+
+    pub fn end_pass(&mut self, device: &Device, command_buffer: &vk::CommandBuffer, pass: usize) {
+        if !self.enabled {
+            return;
+        }
+        unsafe {
+            device.cmd_write_timestamp(
+                *command_buffer,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                self.query_pool,
+                self.query_index(self.frame_slot, pass) + 1,
+            );
+        }
+        self.written[self.frame_slot][pass] = true;
+    }
+
+    // Prints min/avg/max per pass since the last report and resets the timings
+    pub fn report(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        for (name, timing) in self.pass_names.iter().zip(self.timings.iter_mut()) {
+            if timing.frames == 0 {
+                continue;
+            }
+            println!(
+                "GPU {}: avg {:.3} ms, min {:.3} ms, max {:.3} ms",
+                name,
+                timing.average_ms(),
+                timing.min_ms,
+                timing.max_ms
+            );
+            *timing = PassTiming::default();
+        }
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        unsafe {
+            device.destroy_query_pool(self.query_pool, None);
+        }
+    }
+}
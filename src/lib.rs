@@ -1,5 +1,20 @@
+pub mod benchmark;
+pub mod error;
+pub mod gpu_profiler;
+pub mod mesh;
+pub mod mesh_to_sdf;
 pub mod minivector;
+pub mod quantized_sdf;
 pub mod sdf;
+pub mod sdf_csg;
+pub mod sdf_edit;
+pub mod sdf_mips;
+pub mod sdf_sampling;
+pub mod sdf_stream;
+pub mod sdf_tilemap;
+pub mod sdf_to_mesh;
+pub mod sdf_tracer;
+pub mod sdf_voxel;
 pub mod serialization;
 pub mod sparse_sdf;
 pub mod vulkan_base;
@@ -0,0 +1,500 @@
+use crate::minivector::*;
+use crate::serialization::*;
+use std::fmt::Write;
+use std::io;
+
+// Named range of triangles (OBJ group or object)
+#[derive(Clone, Debug)]
+pub struct MeshGroup {
+    pub name: String,
+    pub first_triangle: u32,
+    pub triangle_count: u32,
+}
+
+// Indexed triangle list. Normals are optional (empty or one per position).
+pub struct Mesh {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub indices: Vec<u32>,
+    pub groups: Vec<MeshGroup>,
+}
+
+impl Mesh {
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    pub fn triangle(&self, t: usize) -> (Vec3, Vec3, Vec3) {
+        (
+            self.positions[self.indices[t * 3] as usize],
+            self.positions[self.indices[t * 3 + 1] as usize],
+            self.positions[self.indices[t * 3 + 2] as usize],
+        )
+    }
+
+    // Axis aligned bounding box (min, max) of the referenced vertices
+    pub fn bounds(&self) -> Option<(Vec3, Vec3)> {
+        let mut indices = self.indices.iter();
+        let first = self.positions[*indices.next()? as usize];
+
+        let bounds = indices.fold((first, first), |(min, max), &i| {
+            let p = self.positions[i as usize];
+            (
+                Vec3 {
+                    x: min.x.min(p.x),
+                    y: min.y.min(p.y),
+                    z: min.z.min(p.z),
+                },
+                Vec3 {
+                    x: max.x.max(p.x),
+                    y: max.y.max(p.y),
+                    z: max.z.max(p.z),
+                },
+            )
+        });
+
+        Some(bounds)
+    }
+}
+
+fn invalid_data(line_number: usize, message: String) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("OBJ line {}: {}", line_number, message),
+    )
+}
+
+// Face vertex index: 1-based, negative = relative to the last vertex
+fn parse_index(token: &str, vertex_count: usize) -> Result<u32, String> {
+    let position = token.split('/').next().unwrap_or("");
+    let index: i64 = position
+        .parse()
+        .map_err(|_| format!("Invalid face vertex '{}'", token))?;
+
+    let resolved = match index {
+        0 => return Err("Face index 0 is not valid (indices start from 1)".to_string()),
+        i if i > 0 => i - 1,
+        i => vertex_count as i64 + i,
+    };
+
+    if resolved < 0 || resolved >= vertex_count as i64 {
+        return Err(format!(
+            "Face index {} out of range (vertices = {})",
+            index, vertex_count
+        ));
+    }
+
+    Ok(resolved as u32)
+}
+
+// Ear clipping triangulation of a (possibly concave) planar polygon
+fn triangulate_polygon(positions: &[Vec3], face: &[u32], indices: &mut Vec<u32>) {
+    if face.len() == 3 {
+        indices.extend_from_slice(face);
+        return;
+    }
+
+    // Newell normal selects the projection plane
+    let mut normal = Vec3::from_scalar(0.0);
+    for i in 0..face.len() {
+        let a = positions[face[i] as usize];
+        let b = positions[face[(i + 1) % face.len()] as usize];
+        normal = normal + (a - b).cross(a + b) * 0.5;
+    }
+
+    let (u, v) = if normal.x.abs() >= normal.y.abs() && normal.x.abs() >= normal.z.abs() {
+        (1, 2)
+    } else if normal.y.abs() >= normal.z.abs() {
+        (2, 0)
+    } else {
+        (0, 1)
+    };
+    let flip = match u {
+        1 => normal.x < 0.0,
+        2 => normal.y < 0.0,
+        _ => normal.z < 0.0,
+    };
+
+    let project = |i: u32| {
+        let p = positions[i as usize];
+        let c = [p.x, p.y, p.z];
+        if flip {
+            (c[v], c[u])
+        } else {
+            (c[u], c[v])
+        }
+    };
+    let cross = |a: (f32, f32), b: (f32, f32), c: (f32, f32)| {
+        (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0)
+    };
+
+    let mut remaining: Vec<u32> = face.to_vec();
+    while remaining.len() > 3 {
+        let n = remaining.len();
+        let ear = (0..n).find(|&i| {
+            let a = project(remaining[(i + n - 1) % n]);
+            let b = project(remaining[i]);
+            let c = project(remaining[(i + 1) % n]);
+            if cross(a, b, c) <= 0.0 {
+                // Reflex or degenerate corner
+                return false;
+            }
+            // No other vertex inside the ear
+            (0..n)
+                .filter(|&j| j != i && j != (i + n - 1) % n && j != (i + 1) % n)
+                .all(|j| {
+                    let p = project(remaining[j]);
+                    cross(a, b, p) < 0.0 || cross(b, c, p) < 0.0 || cross(c, a, p) < 0.0
+                })
+        });
+
+        // Degenerate or self intersecting polygon: fall back to a fan
+        let ear = match ear {
+            Some(ear) => ear,
+            None => break,
+        };
+
+        indices.extend_from_slice(&[
+            remaining[(ear + n - 1) % n],
+            remaining[ear],
+            remaining[(ear + 1) % n],
+        ]);
+        remaining.remove(ear);
+    }
+
+    for i in 2..remaining.len() {
+        indices.extend_from_slice(&[remaining[0], remaining[i - 1], remaining[i]]);
+    }
+}
+
+fn close_group(groups: &mut Vec<MeshGroup>, name: &str, start: u32, end: u32) {
+    if end > start {
+        groups.push(MeshGroup {
+            name: name.to_string(),
+            first_triangle: start,
+            triangle_count: end - start,
+        });
+    }
+}
+
+// Wavefront OBJ parser. Reads vertex positions, faces (polygons are triangulated), groups
+// and objects. Texture coordinates, normals and materials are ignored.
+pub fn parse_obj(text: &str) -> io::Result<Mesh> {
+    let mut positions = Vec::new();
+    let mut indices = Vec::new();
+    let mut groups: Vec<MeshGroup> = Vec::new();
+    let mut group_name = String::from("default");
+    let mut group_start = 0u32;
+
+    let mut line_number = 0;
+    let mut lines = text.lines();
+    while let Some(line) = lines.next() {
+        line_number += 1;
+        let first_line_number = line_number;
+
+        // Backslash joins the next line
+        let mut line = line.to_string();
+        while line.ends_with('\\') {
+            line.pop();
+            line.push(' ');
+            match lines.next() {
+                Some(next) => {
+                    line_number += 1;
+                    line.push_str(next);
+                }
+                None => break,
+            }
+        }
+
+        let line = match line.find('#') {
+            Some(comment) => &line[..comment],
+            None => &line[..],
+        };
+
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+
+        match keyword {
+            "v" => {
+                let coords: Vec<&str> = tokens.collect();
+                if coords.len() < 3 {
+                    return Err(invalid_data(
+                        first_line_number,
+                        format!("Vertex needs 3 coordinates, found {}", coords.len()),
+                    ));
+                }
+                let mut v = [0.0f32; 3];
+                for (c, token) in v.iter_mut().zip(coords.iter()) {
+                    *c = token.parse().map_err(|_| {
+                        invalid_data(first_line_number, format!("Invalid coordinate '{}'", token))
+                    })?;
+                }
+                positions.push(Vec3 {
+                    x: v[0],
+                    y: v[1],
+                    z: v[2],
+                });
+            }
+            "f" => {
+                let face = tokens
+                    .map(|token| parse_index(token, positions.len()))
+                    .collect::<Result<Vec<u32>, String>>()
+                    .map_err(|message| invalid_data(first_line_number, message))?;
+
+                if face.len() < 3 {
+                    return Err(invalid_data(
+                        first_line_number,
+                        format!("Face needs at least 3 vertices, found {}", face.len()),
+                    ));
+                }
+
+                triangulate_polygon(&positions, &face, &mut indices);
+            }
+            "g" | "o" => {
+                let end = (indices.len() / 3) as u32;
+                close_group(&mut groups, &group_name, group_start, end);
+
+                let name: Vec<&str> = tokens.collect();
+                group_name = if name.is_empty() {
+                    String::from("default")
+                } else {
+                    name.join(" ")
+                };
+                group_start = end;
+            }
+            _ => (),
+        }
+    }
+
+    let end = (indices.len() / 3) as u32;
+    close_group(&mut groups, &group_name, group_start, end);
+
+    Ok(Mesh {
+        positions,
+        normals: Vec::new(),
+        indices,
+        groups,
+    })
+}
+
+pub fn load_obj(filename: &str) -> io::Result<Mesh> {
+    let text = std::fs::read_to_string(filename)?;
+    let mesh = parse_obj(&text)
+        .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", filename, err)))?;
+
+    println!(
+        "Loaded OBJ: vertices = {}, triangles = {}, groups = {}",
+        mesh.positions.len(),
+        mesh.triangle_count(),
+        mesh.groups.len()
+    );
+
+    Ok(mesh)
+}
+
+pub fn store_obj(filename: &str, mesh: &Mesh) -> io::Result<()> {
+    let has_normals = mesh.normals.len() == mesh.positions.len();
+
+    let mut text = String::new();
+    for p in mesh.positions.iter() {
+        writeln!(text, "v {} {} {}", p.x, p.y, p.z).unwrap();
+    }
+    if has_normals {
+        for n in mesh.normals.iter() {
+            writeln!(text, "vn {} {} {}", n.x, n.y, n.z).unwrap();
+        }
+    }
+
+    let write_faces = |text: &mut String, first_triangle: usize, triangle_count: usize| {
+        for t in first_triangle..first_triangle + triangle_count {
+            let i = &mesh.indices[t * 3..t * 3 + 3];
+            if has_normals {
+                writeln!(
+                    text,
+                    "f {0}//{0} {1}//{1} {2}//{2}",
+                    i[0] + 1,
+                    i[1] + 1,
+                    i[2] + 1
+                )
+                .unwrap();
+            } else {
+                writeln!(text, "f {} {} {}", i[0] + 1, i[1] + 1, i[2] + 1).unwrap();
+            }
+        }
+    };
+
+    if mesh.groups.is_empty() {
+        write_faces(&mut text, 0, mesh.triangle_count());
+    } else {
+        for group in mesh.groups.iter() {
+            writeln!(text, "g {}", group.name).unwrap();
+            write_faces(
+                &mut text,
+                group.first_triangle as usize,
+                group.triangle_count as usize,
+            );
+        }
+    }
+
+    std::fs::write(filename, text)
+}
+
+// Binary little endian PLY
+pub fn store_ply(filename: &str, mesh: &Mesh) -> io::Result<()> {
+    let has_normals = mesh.normals.len() == mesh.positions.len();
+
+    let mut header = String::new();
+    writeln!(header, "ply").unwrap();
+    writeln!(header, "format binary_little_endian 1.0").unwrap();
+    writeln!(header, "element vertex {}", mesh.positions.len()).unwrap();
+    writeln!(header, "property float x").unwrap();
+    writeln!(header, "property float y").unwrap();
+    writeln!(header, "property float z").unwrap();
+    if has_normals {
+        writeln!(header, "property float nx").unwrap();
+        writeln!(header, "property float ny").unwrap();
+        writeln!(header, "property float nz").unwrap();
+    }
+    writeln!(header, "element face {}", mesh.triangle_count()).unwrap();
+    writeln!(header, "property list uchar uint vertex_indices").unwrap();
+    writeln!(header, "end_header").unwrap();
+
+    let mut storer = StorerVec::new();
+    storer.store_array_u8(header.as_bytes());
+
+    for (i, p) in mesh.positions.iter().enumerate() {
+        storer.store_array_f32(&[p.x, p.y, p.z]);
+        if has_normals {
+            let n = mesh.normals[i];
+            storer.store_array_f32(&[n.x, n.y, n.z]);
+        }
+    }
+
+    for triangle in mesh.indices.chunks(3) {
+        storer.store_u8(3);
+        for &i in triangle {
+            storer.store_u32(i);
+        }
+    }
+
+    std::fs::write(filename, storer.v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUAD: &str = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n";
+
+    fn signed_area_z(mesh: &Mesh, t: usize) -> f32 {
+        let (a, b, c) = mesh.triangle(t);
+        (b - a).cross(c - a).z * 0.5
+    }
+
+    #[test]
+    fn absolute_and_relative_indices() {
+        let text = format!("{}f 1 2 3\nf -4 -2 -1\nv 5 5 5\nf -1 -2 -3\n", QUAD);
+        let mesh = parse_obj(&text).unwrap();
+        assert_eq!(mesh.positions.len(), 5);
+        assert_eq!(mesh.triangle_count(), 3);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3, 4, 3, 2]);
+    }
+
+    #[test]
+    fn index_forms() {
+        let text = format!(
+            "{}vt 0 0\nvn 0 0 1\nf 1/1 2/1 3/1\nf 1/1/1 3/1/1 4/1/1\nf 2//1 3//1 4//1\n",
+            QUAD
+        );
+        let mesh = parse_obj(&text).unwrap();
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3, 1, 2, 3]);
+    }
+
+    #[test]
+    fn polygon_triangulation() {
+        // Convex quad
+        let mesh = parse_obj(&format!("{}f 1 2 3 4\n", QUAD)).unwrap();
+        assert_eq!(mesh.triangle_count(), 2);
+
+        // Concave quad, the reflex vertex (2, 1) is last. A fan from the first vertex would
+        // produce a flipped triangle outside the polygon.
+        let text = "v 4 0 0\nv 2 3 0\nv 0 0 0\nv 2 1 0\nf 1 2 3 4\n";
+        let mesh = parse_obj(text).unwrap();
+        assert_eq!(mesh.triangle_count(), 2);
+        let mut area = 0.0;
+        for t in 0..mesh.triangle_count() {
+            let a = signed_area_z(&mesh, t);
+            assert!(a > 0.0, "triangle {} area {}", t, a);
+            area += a;
+        }
+        assert!((area - 4.0).abs() < 1e-5);
+
+        // Concave L-shaped hexagon, clockwise
+        let text = "v 0 0 0\nv 0 2 0\nv 1 2 0\nv 1 1 0\nv 2 1 0\nv 2 0 0\nf 1 2 3 4 5 6\n";
+        let mesh = parse_obj(text).unwrap();
+        assert_eq!(mesh.triangle_count(), 4);
+        let mut area = 0.0;
+        for t in 0..mesh.triangle_count() {
+            let a = signed_area_z(&mesh, t);
+            assert!(a < 0.0, "triangle {} area {}", t, a);
+            area += a;
+        }
+        assert!((area + 3.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn group_ranges() {
+        let text = format!(
+            "{}f 1 2 3\ng first\nf 1 2 3\nf 1 3 4\ng empty\no second part\nf 1 2 3 4\ng\nf 2 3 4\n",
+            QUAD
+        );
+        let mesh = parse_obj(&text).unwrap();
+        assert_eq!(mesh.triangle_count(), 6);
+
+        let groups: Vec<(&str, u32, u32)> = mesh
+            .groups
+            .iter()
+            .map(|g| (g.name.as_str(), g.first_triangle, g.triangle_count))
+            .collect();
+        assert_eq!(
+            groups,
+            vec![
+                ("default", 0, 1),
+                ("first", 1, 2),
+                ("second part", 3, 2),
+                ("default", 5, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn comments_and_line_continuation() {
+        let text = format!("# header\n{}f 1 2 \\\n 3 4 # quad\n", QUAD);
+        let mesh = parse_obj(&text).unwrap();
+        assert_eq!(mesh.triangle_count(), 2);
+        assert!(mesh.bounds().is_some());
+    }
+
+    #[test]
+    fn malformed_lines() {
+        let error = |text: &str| {
+            let err = parse_obj(text).err().expect(text);
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            err.to_string()
+        };
+
+        assert!(error("v 1 2\n").starts_with("OBJ line 1:"));
+        assert!(error("v 1 x 3\n").contains("'x'"));
+        assert!(error(&format!("{}f 1 2\n", QUAD)).starts_with("OBJ line 5:"));
+        assert!(error(&format!("{}f 0 1 2\n", QUAD)).contains("index 0"));
+        assert!(error(&format!("{}f 1 2 5\n", QUAD)).contains("out of range"));
+        assert!(error(&format!("{}f 1 2 -5\n", QUAD)).contains("out of range"));
+        assert!(error(&format!("{}f a b c\n", QUAD)).contains("'a'"));
+
+        // Line numbers count continued lines
+        assert!(error("v 0 0 \\\n0\nf 1 1\n").starts_with("OBJ line 3:"));
+    }
+}
@@ -0,0 +1,473 @@
+// Mesh to SDF conversion. Port of the level set generator of Christopher Batty's SDFGen:
+// https://github.com/christopherbatty/SDFGen (and the 16 bit fork: https://github.com/sebbbi/SDFGen)
+// Exact distances are computed near the triangles and propagated with fast sweeping.
+// Sign is determined by ray intersection parity. Parity is evaluated along all three axes
+// and majority voted, making the result robust against small holes in the mesh.
+
+use crate::error::*;
+use crate::mesh::*;
+use crate::minivector::*;
+use crate::sdf::*;
+
+// Voxels (per axis) around each triangle that get exact distances
+const EXACT_BAND: i32 = 1;
+
+fn point_segment_distance(x0: Vec3, x1: Vec3, x2: Vec3) -> f32 {
+    let dx = x2 - x1;
+    let m2 = dx.dot(dx) as f64;
+
+    // Find parameter value of closest point on segment
+    let s12 = if m2 > 0.0 {
+        ((x2 - x0).dot(dx) as f64 / m2).clamp(0.0, 1.0) as f32
+    } else {
+        0.0
+    };
+
+    (x0 - (x1 * s12 + x2 * (1.0 - s12))).length()
+}
+
+fn point_triangle_distance(x0: Vec3, x1: Vec3, x2: Vec3, x3: Vec3) -> f32 {
+    // Barycentric coordinates of closest point on infinite plane
+    let x13 = x1 - x3;
+    let x23 = x2 - x3;
+    let x03 = x0 - x3;
+    let m13 = x13.dot(x13);
+    let m23 = x23.dot(x23);
+    let d = x13.dot(x23);
+    let invdet = 1.0 / (m13 * m23 - d * d).max(1e-30);
+    let a = x13.dot(x03);
+    let b = x23.dot(x03);
+
+    let w23 = invdet * (m23 * a - d * b);
+    let w31 = invdet * (m13 * b - d * a);
+    let w12 = 1.0 - w23 - w31;
+
+    if w23 >= 0.0 && w31 >= 0.0 && w12 >= 0.0 {
+        // Inside the triangle
+        (x0 - (x1 * w23 + x2 * w31 + x3 * w12)).length()
+    } else if w23 > 0.0 {
+        // Clamp to one of the edges. This rules out edge 2-3.
+        point_segment_distance(x0, x1, x2).min(point_segment_distance(x0, x1, x3))
+    } else if w31 > 0.0 {
+        // Rules out edge 1-3
+        point_segment_distance(x0, x1, x2).min(point_segment_distance(x0, x2, x3))
+    } else {
+        // w12 must be > 0, ruling out edge 1-2
+        point_segment_distance(x0, x1, x3).min(point_segment_distance(x0, x2, x3))
+    }
+}
+
+// Twice the signed area of triangle (0,0)-(x1,y1)-(x2,y2), with an SOS-determined sign
+// (-1, +1, or 0 only if it's a truly degenerate triangle)
+fn orientation(x1: f64, y1: f64, x2: f64, y2: f64) -> (i32, f64) {
+    let twice_signed_area = y1 * x2 - x1 * y2;
+    let sign = if twice_signed_area > 0.0 {
+        1
+    } else if twice_signed_area < 0.0 {
+        -1
+    } else if y2 > y1 {
+        1
+    } else if y2 < y1 {
+        -1
+    } else if x1 > x2 {
+        1
+    } else if x1 < x2 {
+        -1
+    } else {
+        0
+    };
+    (sign, twice_signed_area)
+}
+
+// Robust test of (x0,y0) in the triangle (x1,y1)-(x2,y2)-(x3,y3). Returns barycentrics.
+#[allow(clippy::too_many_arguments)]
+fn point_in_triangle_2d(
+    x0: f64,
+    y0: f64,
+    x1: f64,
+    y1: f64,
+    x2: f64,
+    y2: f64,
+    x3: f64,
+    y3: f64,
+) -> Option<(f64, f64, f64)> {
+    let (x1, x2, x3) = (x1 - x0, x2 - x0, x3 - x0);
+    let (y1, y2, y3) = (y1 - y0, y2 - y0, y3 - y0);
+
+    let (sign_a, a) = orientation(x2, y2, x3, y3);
+    if sign_a == 0 {
+        return None;
+    }
+    let (sign_b, b) = orientation(x3, y3, x1, y1);
+    if sign_b != sign_a {
+        return None;
+    }
+    let (sign_c, c) = orientation(x1, y1, x2, y2);
+    if sign_c != sign_a {
+        return None;
+    }
+
+    // SOS signs match and are nonzero: a, b and c can't all be zero
+    let sum = a + b + c;
+    Some((a / sum, b / sum, c / sum))
+}
+
+struct Grid {
+    dim: [i32; 3],
+    origin: Vec3,
+    dx: f32,
+}
+
+impl Grid {
+    fn index(&self, p: [i32; 3]) -> usize {
+        let (dim_x, dim_y) = (self.dim[0] as usize, self.dim[1] as usize);
+        p[0] as usize + p[1] as usize * dim_x + p[2] as usize * dim_x * dim_y
+    }
+
+    fn position(&self, p: [i32; 3]) -> Vec3 {
+        Vec3 {
+            x: p[0] as f32 * self.dx + self.origin.x,
+            y: p[1] as f32 * self.dx + self.origin.y,
+            z: p[2] as f32 * self.dx + self.origin.z,
+        }
+    }
+}
+
+fn sweep(mesh: &Mesh, grid: &Grid, phi: &mut [f32], closest_tri: &mut [i32], direction: [i32; 3]) {
+    let range = |axis: usize| {
+        if direction[axis] > 0 {
+            (1, grid.dim[axis])
+        } else {
+            (grid.dim[axis] - 2, -1)
+        }
+    };
+    let (i0, i1) = range(0);
+    let (j0, j1) = range(1);
+    let (k0, k1) = range(2);
+    let (di, dj, dk) = (direction[0], direction[1], direction[2]);
+
+    let neighbors = [
+        [di, 0, 0],
+        [0, dj, 0],
+        [di, dj, 0],
+        [0, 0, dk],
+        [di, 0, dk],
+        [0, dj, dk],
+        [di, dj, dk],
+    ];
+
+    let mut k = k0;
+    while k != k1 {
+        let mut j = j0;
+        while j != j1 {
+            let mut i = i0;
+            while i != i1 {
+                let p = [i, j, k];
+                let index = grid.index(p);
+                let gx = grid.position(p);
+                for n in neighbors.iter() {
+                    let neighbor = grid.index([i - n[0], j - n[1], k - n[2]]);
+                    let t = closest_tri[neighbor];
+                    if t >= 0 {
+                        let (x1, x2, x3) = mesh.triangle(t as usize);
+                        let d = point_triangle_distance(gx, x1, x2, x3);
+                        if d < phi[index] {
+                            phi[index] = d;
+                            closest_tri[index] = t;
+                        }
+                    }
+                }
+                i += di;
+            }
+            j += dj;
+        }
+        k += dk;
+    }
+}
+
+// Voxel size (dx) and padding (in voxels) match the SDFGen command line arguments. Fails if
+// the mesh has no triangles (vertices or lines only).
+pub fn mesh_to_sdf(mesh: &Mesh, dx: f32, padding: u32) -> SdfResult<Sdf> {
+    let (bounds_min, bounds_max) = mesh.bounds().ok_or(SdfError::EmptyMesh)?;
+
+    // Add padding around the box
+    let box_min = bounds_min - Vec3::from_scalar(padding as f32 * dx);
+    let box_max = bounds_max + Vec3::from_scalar(padding as f32 * dx);
+    let size = (box_max - box_min) * (1.0 / dx);
+
+    // Grid coordinates are i32, the voxel count must fit in usize
+    let axis_dim = |s: f32| (s as u64).max(1);
+    let dim64 = [axis_dim(size.x), axis_dim(size.y), axis_dim(size.z)];
+    let dim = (
+        dim64[0].min(u32::MAX as u64) as u32,
+        dim64[1].min(u32::MAX as u64) as u32,
+        dim64[2].min(u32::MAX as u64) as u32,
+    );
+    if dim64.iter().any(|&d| d > i32::MAX as u64) {
+        return Err(SdfError::DimensionOverflow(dim));
+    }
+    let count_voxels = voxel_count(dim)?;
+
+    let grid = Grid {
+        dim: [dim.0 as i32, dim.1 as i32, dim.2 as i32],
+        origin: box_min,
+        dx,
+    };
+
+    println!(
+        "Mesh to SDF: triangles = {}, dim = {:?}",
+        mesh.triangle_count(),
+        grid.dim
+    );
+
+    // Upper bound on distance
+    let mut phi = vec![(dim.0 as f32 + dim.1 as f32 + dim.2 as f32) * dx; count_voxels];
+    let mut closest_tri = vec![-1i32; count_voxels];
+
+    // intersection_count[axis][p] = triangle intersections in (p-1,p] along the axis
+    let mut intersection_count = vec![vec![0u16; count_voxels]; 3];
+
+    // Initialize distances near the mesh and count ray intersections
+    for t in 0..mesh.triangle_count() {
+        let (x1, x2, x3) = mesh.triangle(t);
+
+        // Grid coordinates to high precision
+        let to_grid = |p: Vec3| {
+            [
+                (p.x as f64 - grid.origin.x as f64) / dx as f64,
+                (p.y as f64 - grid.origin.y as f64) / dx as f64,
+                (p.z as f64 - grid.origin.z as f64) / dx as f64,
+            ]
+        };
+        let f = [to_grid(x1), to_grid(x2), to_grid(x3)];
+
+        let f_min = |axis: usize| f[0][axis].min(f[1][axis]).min(f[2][axis]);
+        let f_max = |axis: usize| f[0][axis].max(f[1][axis]).max(f[2][axis]);
+
+        // Exact distances nearby
+        let band = |axis: usize| {
+            (
+                (f_min(axis) as i32 - EXACT_BAND).clamp(0, grid.dim[axis] - 1),
+                (f_max(axis) as i32 + EXACT_BAND + 1).clamp(0, grid.dim[axis] - 1),
+            )
+        };
+        let (i0, i1) = band(0);
+        let (j0, j1) = band(1);
+        let (k0, k1) = band(2);
+
+        for k in k0..=k1 {
+            for j in j0..=j1 {
+                for i in i0..=i1 {
+                    let p = [i, j, k];
+                    let index = grid.index(p);
+                    let d = point_triangle_distance(grid.position(p), x1, x2, x3);
+                    if d < phi[index] {
+                        phi[index] = d;
+                        closest_tri[index] = t as i32;
+                    }
+                }
+            }
+        }
+
+        // Intersection counts along each axis
+        for (axis, counts) in intersection_count.iter_mut().enumerate() {
+            let u = (axis + 1) % 3;
+            let v = (axis + 2) % 3;
+
+            let u0 = (f_min(u).ceil() as i32).clamp(0, grid.dim[u] - 1);
+            let u1 = (f_max(u).floor() as i32).clamp(0, grid.dim[u] - 1);
+            let v0 = (f_min(v).ceil() as i32).clamp(0, grid.dim[v] - 1);
+            let v1 = (f_max(v).floor() as i32).clamp(0, grid.dim[v] - 1);
+
+            for pv in v0..=v1 {
+                for pu in u0..=u1 {
+                    if let Some((a, b, c)) = point_in_triangle_2d(
+                        pu as f64, pv as f64, f[0][u], f[0][v], f[1][u], f[1][v], f[2][u], f[2][v],
+                    ) {
+                        // Intersection is in (interval-1, interval]
+                        let fa = a * f[0][axis] + b * f[1][axis] + c * f[2][axis];
+                        let interval = fa.ceil() as i32;
+
+                        let mut p = [0; 3];
+                        p[u] = pu;
+                        p[v] = pv;
+                        if interval < 0 {
+                            // The first interval includes everything to the negative direction
+                            p[axis] = 0;
+                        } else if interval < grid.dim[axis] {
+                            p[axis] = interval;
+                        } else {
+                            // Ignore intersections beyond the positive side of the grid
+                            continue;
+                        }
+                        counts[grid.index(p)] += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    // Fill in the rest of the distances with fast sweeping
+    for _ in 0..2 {
+        for direction in [
+            [1, 1, 1],
+            [-1, -1, -1],
+            [1, 1, -1],
+            [-1, -1, 1],
+            [1, -1, 1],
+            [-1, 1, -1],
+            [1, -1, -1],
+            [-1, 1, 1],
+        ] {
+            sweep(mesh, &grid, &mut phi, &mut closest_tri, direction);
+        }
+    }
+
+    // Inside/outside from intersection count parity. Majority vote of the three axes.
+    let mut inside_votes = vec![0u8; count_voxels];
+    for (axis, counts) in intersection_count.iter().enumerate() {
+        let u = (axis + 1) % 3;
+        let v = (axis + 2) % 3;
+        for pv in 0..grid.dim[v] {
+            for pu in 0..grid.dim[u] {
+                let mut total_count = 0u32;
+                for pa in 0..grid.dim[axis] {
+                    let mut p = [0; 3];
+                    p[axis] = pa;
+                    p[u] = pu;
+                    p[v] = pv;
+                    let index = grid.index(p);
+                    total_count += counts[index] as u32;
+                    if total_count % 2 == 1 {
+                        inside_votes[index] += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    let header = SdfHeader {
+        dim,
+        box_min: (box_min.x, box_min.y, box_min.z),
+        dx,
+    };
+
+    let voxels = phi
+        .iter()
+        .zip(inside_votes.iter())
+        .map(|(&d, &votes)| {
+            let d = if votes >= 2 { -d } else { d };
+            header.encode_distance(d)
+        })
+        .collect();
+
+    Ok(Sdf { header, voxels })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HALF_SIZE: f32 = 0.5;
+
+    // Axis aligned cube centered at the origin, outward facing triangles
+    fn cube_mesh() -> Mesh {
+        let mut positions = Vec::new();
+        for i in 0..8 {
+            let corner = |bit: i32| if i & bit != 0 { HALF_SIZE } else { -HALF_SIZE };
+            positions.push(Vec3 {
+                x: corner(1),
+                y: corner(2),
+                z: corner(4),
+            });
+        }
+        let quads = [
+            [0, 4, 6, 2],
+            [1, 3, 7, 5],
+            [0, 1, 5, 4],
+            [2, 6, 7, 3],
+            [0, 2, 3, 1],
+            [4, 5, 7, 6],
+        ];
+        let mut indices = Vec::new();
+        for q in quads.iter() {
+            indices.extend_from_slice(&[q[0], q[1], q[2], q[0], q[2], q[3]]);
+        }
+        Mesh {
+            positions,
+            normals: Vec::new(),
+            indices,
+            groups: Vec::new(),
+        }
+    }
+
+    fn cube_distance(p: Vec3) -> f32 {
+        let q = Vec3 {
+            x: p.x.abs() - HALF_SIZE,
+            y: p.y.abs() - HALF_SIZE,
+            z: p.z.abs() - HALF_SIZE,
+        };
+        let outside = Vec3 {
+            x: q.x.max(0.0),
+            y: q.y.max(0.0),
+            z: q.z.max(0.0),
+        };
+        outside.length() + q.x.max(q.y).max(q.z).min(0.0)
+    }
+
+    #[test]
+    fn cube_matches_analytic_distance() {
+        let dx = 0.05;
+        let sdf = mesh_to_sdf(&cube_mesh(), dx, 4).unwrap();
+        assert_eq!(sdf.header.dim, (28, 28, 28));
+
+        let (mut inside, mut outside) = (0, 0);
+        let mut voxels = sdf.voxels.iter();
+        for z in 0..sdf.header.dim.2 {
+            for y in 0..sdf.header.dim.1 {
+                for x in 0..sdf.header.dim.0 {
+                    let p = sdf.voxel_position(x, y, z);
+                    let expected = cube_distance(p);
+                    let d = sdf.to_world_distance(*voxels.next().unwrap());
+                    assert!(
+                        (d - expected).abs() <= dx,
+                        "{:?}: distance {} expected {}",
+                        p,
+                        d,
+                        expected
+                    );
+                    if expected.abs() > dx * 0.5 {
+                        assert_eq!(d < 0.0, expected < 0.0, "{:?}: sign of {}", p, d);
+                    }
+                    if expected < 0.0 {
+                        inside += 1;
+                    } else {
+                        outside += 1;
+                    }
+                }
+            }
+        }
+        assert!(inside > 0 && outside > 0);
+    }
+
+    #[test]
+    fn empty_and_oversized_meshes() {
+        let mut mesh = cube_mesh();
+        mesh.indices.clear();
+        assert!(matches!(
+            mesh_to_sdf(&mesh, 0.1, 1),
+            Err(SdfError::EmptyMesh)
+        ));
+
+        // Axis above i32 range, then a voxel count overflow. Both fail before allocating.
+        let mesh = cube_mesh();
+        assert!(matches!(
+            mesh_to_sdf(&mesh, 1e-10, 0),
+            Err(SdfError::DimensionOverflow(_))
+        ));
+        assert!(matches!(
+            mesh_to_sdf(&mesh, 5e-7, 0),
+            Err(SdfError::DimensionOverflow(_))
+        ));
+    }
+}
@@ -0,0 +1,379 @@
+// Lossy narrow band SDF encoding. Distances are clamped to a band around the zero level and
+// quantized with a step that bounds the error. The volume is split to bricks of 8^3 voxels,
+// each brick stores its quantized values relative to the brick minimum with the smallest
+// bit count covering the range: bricks fully outside the band cost no bits, the default
+// band (127 steps) fits in 8 bits.
+//
+// Payload (deflated): step u32, band u32, then slabs of QUANTIZED_BRICK_SIZE z slices. Each
+// slab stores its bricks in y, x order: bits u8, min i32, bit packed values (LSB first).
+
+use crate::error::*;
+use crate::sdf::*;
+use crate::serialization::*;
+
+pub const QUANTIZED_BRICK_SIZE: u32 = 8;
+
+// Default band in quantization steps, quantized values fit in 8 bits
+pub const QUANTIZED_DEFAULT_BAND: u32 = 127;
+
+// Brick header: bits u8, min i32
+pub const QUANTIZED_BRICK_HEADER_SIZE: usize = 5;
+
+// Quantized values are in [-65535, 65535], 17 bits covers any brick range
+const QUANTIZED_MAX_BITS: u32 = 17;
+
+// World units per voxel value step (see SdfHeader::decode_distance)
+fn value_size(header: &SdfHeader) -> f32 {
+    header.diagonal_length() * 2.0 / 65535.0
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SdfQuantization {
+    // Step in voxel value units. Odd, the error is at most step / 2 (rounded down).
+    pub step: u32,
+    // Largest stored |quantized value|, distances outside the band are clamped
+    pub band: u32,
+}
+
+impl SdfQuantization {
+    // Max error and band in world units. No band = QUANTIZED_DEFAULT_BAND steps.
+    pub fn new(header: &SdfHeader, max_error: f32, band: Option<f32>) -> SdfQuantization {
+        let value_size = value_size(header);
+
+        let half_step = (max_error / value_size).floor().clamp(0.0, 32767.0) as u32;
+        let step = half_step * 2 + 1;
+
+        let band = match band {
+            Some(band) => (band / (step as f32 * value_size)).floor().max(1.0) as u32,
+            None => QUANTIZED_DEFAULT_BAND,
+        };
+
+        SdfQuantization {
+            step,
+            // Wider than the value range is meaningless
+            band: band.min(65535 / step + 1),
+        }
+    }
+
+    pub fn max_error(&self, header: &SdfHeader) -> f32 {
+        (self.step / 2) as f32 * value_size(header)
+    }
+
+    pub fn band_width(&self, header: &SdfHeader) -> f32 {
+        (self.band * self.step) as f32 * value_size(header)
+    }
+
+    fn quantize_unclamped(&self, v: u16) -> i32 {
+        let d = v as i32 - SDF_LEVEL_ZERO as i32;
+        let step = self.step as i32;
+        let half = step / 2;
+        if d >= 0 {
+            (d + half) / step
+        } else {
+            -((half - d) / step)
+        }
+    }
+
+    pub fn quantize(&self, v: u16) -> i32 {
+        let band = self.band as i32;
+        self.quantize_unclamped(v).clamp(-band, band)
+    }
+
+    pub fn dequantize(&self, q: i64) -> u16 {
+        (SDF_LEVEL_ZERO as i64 + q * self.step as i64).clamp(0, 65535) as u16
+    }
+}
+
+// Voxel range (start, size) of brick b along an axis
+pub fn quantized_brick_extent(dim: u32, b: u32) -> (u32, u32) {
+    let start = b * QUANTIZED_BRICK_SIZE;
+    (start, QUANTIZED_BRICK_SIZE.min(dim - start))
+}
+
+pub fn quantized_brick_bytes(bits: u32, count: usize) -> usize {
+    (count * bits as usize).div_ceil(8)
+}
+
+// Encodes a slab of up to QUANTIZED_BRICK_SIZE z slices. Bricks in y, x order, each brick
+// voxels in z, y, x order (clipped to the volume).
+pub fn encode_quantized_slab(
+    dim: (u32, u32, u32),
+    quantization: &SdfQuantization,
+    slab: &[u16],
+    storer: &mut StorerVec,
+) {
+    let x_dim = dim.0 as usize;
+    let slice_size = dim.0 as usize * dim.1 as usize;
+    let depth = slab.len() / slice_size.max(1);
+
+    let mut values = Vec::new();
+    for by in 0..dim.1.div_ceil(QUANTIZED_BRICK_SIZE) {
+        let (y0, h) = quantized_brick_extent(dim.1, by);
+        for bx in 0..dim.0.div_ceil(QUANTIZED_BRICK_SIZE) {
+            let (x0, w) = quantized_brick_extent(dim.0, bx);
+
+            values.clear();
+            for z in 0..depth {
+                for y in y0..y0 + h {
+                    let row = z * slice_size + y as usize * x_dim;
+                    for x in x0..x0 + w {
+                        values.push(quantization.quantize(slab[row + x as usize]));
+                    }
+                }
+            }
+
+            let min = *values.iter().min().unwrap();
+            let max = *values.iter().max().unwrap();
+            let bits = 32 - ((max - min) as u32).leading_zeros();
+
+            storer.store_u8(bits as u8);
+            storer.store_u32(min as u32);
+
+            // LSB first bit packing
+            let mut acc = 0u64;
+            let mut acc_bits = 0;
+            for &v in values.iter() {
+                acc |= ((v - min) as u64) << acc_bits;
+                acc_bits += bits;
+                while acc_bits >= 8 {
+                    storer.store_u8(acc as u8);
+                    acc >>= 8;
+                    acc_bits -= 8;
+                }
+            }
+            if acc_bits > 0 {
+                storer.store_u8(acc as u8);
+            }
+        }
+    }
+}
+
+// Parses a brick header: (bits, min)
+pub fn parse_quantized_brick_header(bytes: &[u8]) -> SdfResult<(u32, i32)> {
+    let mut loader = Loader::new();
+    let bits = loader.load_u8(bytes)? as u32;
+    let min = loader.load_u32(bytes)? as i32;
+
+    if bits > QUANTIZED_MAX_BITS {
+        return Err(SdfError::InvalidBrick(bits));
+    }
+
+    Ok((bits, min))
+}
+
+// Decodes count voxels of a brick, appends them to out
+pub fn decode_quantized_brick(
+    quantization: &SdfQuantization,
+    bits: u32,
+    min: i32,
+    bytes: &[u8],
+    count: usize,
+    out: &mut Vec<u16>,
+) {
+    let mask = (1u64 << bits) - 1;
+    let mut acc = 0u64;
+    let mut acc_bits = 0;
+    let mut bytes = bytes.iter();
+    for _ in 0..count {
+        while acc_bits < bits {
+            acc |= (*bytes.next().unwrap() as u64) << acc_bits;
+            acc_bits += 8;
+        }
+        let v = acc & mask;
+        acc >>= bits;
+        acc_bits -= bits;
+
+        out.push(quantization.dequantize(min as i64 + v as i64));
+    }
+}
+
+// Quantizes and decodes in memory, same result as a stored and loaded quantized SDF
+pub fn quantize_sdf(sdf: &Sdf, quantization: &SdfQuantization) -> Sdf {
+    let voxels = sdf
+        .voxels
+        .iter()
+        .map(|&v| quantization.dequantize(quantization.quantize(v) as i64))
+        .collect();
+
+    Sdf {
+        header: sdf.header,
+        voxels,
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct QuantizationReport {
+    // Voxels inside the band, and clamped voxels outside of it
+    pub band_voxels: usize,
+    pub clamped_voxels: usize,
+    // Errors in world units. Inside the band the error is bounded by the quantization step.
+    pub max_error_band: f32,
+    pub rms_error_band: f32,
+    pub max_error: f32,
+}
+
+pub fn quantization_report(
+    original: &Sdf,
+    decoded: &Sdf,
+    quantization: &SdfQuantization,
+) -> QuantizationReport {
+    assert_eq!(original.voxels.len(), decoded.voxels.len());
+
+    let value_size = value_size(&original.header);
+    let band = quantization.band as i32;
+
+    let mut report = QuantizationReport {
+        band_voxels: 0,
+        clamped_voxels: 0,
+        max_error_band: 0.0,
+        rms_error_band: 0.0,
+        max_error: 0.0,
+    };
+    let mut sum_squares = 0.0f64;
+
+    for (&a, &b) in original.voxels.iter().zip(decoded.voxels.iter()) {
+        let error = (a as i32 - b as i32).abs() as f32 * value_size;
+        report.max_error = report.max_error.max(error);
+
+        if quantization.quantize_unclamped(a).abs() > band {
+            report.clamped_voxels += 1;
+        } else {
+            report.band_voxels += 1;
+            report.max_error_band = report.max_error_band.max(error);
+            sum_squares += error as f64 * error as f64;
+        }
+    }
+
+    if report.band_voxels > 0 {
+        report.rms_error_band = (sum_squares / report.band_voxels as f64).sqrt() as f32;
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sdf_sampling::*;
+
+    fn sphere_sdf() -> Sdf {
+        let header = SdfHeader {
+            dim: (20, 18, 17),
+            box_min: (-0.5, -0.45, -0.4),
+            dx: 0.05,
+        };
+        sample_sdf(header, |p| p.length() - 0.3).unwrap()
+    }
+
+    #[test]
+    fn quantization_parameters() {
+        let header = sphere_sdf().header;
+        let value_size = value_size(&header);
+
+        for &max_error in [0.0, value_size * 0.5, 0.001, 0.01, 0.05].iter() {
+            let q = SdfQuantization::new(&header, max_error, None);
+            assert_eq!(q.step % 2, 1);
+            assert_eq!(q.band, QUANTIZED_DEFAULT_BAND.min(65535 / q.step + 1));
+            assert!(q.max_error(&header) <= max_error, "{}", max_error);
+            // The next odd step would exceed the requested error
+            assert!((q.step / 2 + 1) as f32 * value_size > max_error);
+        }
+        assert_eq!(SdfQuantization::new(&header, 0.0, None).step, 1);
+
+        let q = SdfQuantization::new(&header, 0.002, Some(0.1));
+        assert!(q.band_width(&header) <= 0.1);
+        assert!(q.band_width(&header) > 0.1 - q.step as f32 * value_size);
+
+        // At least one step, at most the whole value range
+        assert_eq!(SdfQuantization::new(&header, 0.002, Some(0.0)).band, 1);
+        let q = SdfQuantization::new(&header, 0.002, Some(1000.0));
+        assert_eq!(q.band, 65535 / q.step + 1);
+    }
+
+    #[test]
+    fn quantized_error_bounds() {
+        let sdf = sphere_sdf();
+        let max_error = 0.002;
+        let band = 0.12;
+        let q = SdfQuantization::new(&sdf.header, max_error, Some(band));
+        let decoded = quantize_sdf(&sdf, &q);
+        let report = quantization_report(&sdf, &decoded, &q);
+
+        assert!(report.max_error_band <= max_error);
+        assert!(report.max_error_band <= q.max_error(&sdf.header) + 1e-7);
+        assert!(report.rms_error_band <= report.max_error_band);
+        assert!(report.max_error > max_error);
+
+        // Clamped: |v - zero| > band * step + step / 2, the sign is kept
+        let limit = (q.band * q.step + q.step / 2) as i32;
+        let clamped = sdf
+            .voxels
+            .iter()
+            .filter(|&&v| (v as i32 - SDF_LEVEL_ZERO as i32).abs() > limit)
+            .count();
+        assert!(clamped > 0 && report.band_voxels > 0);
+        assert_eq!(report.clamped_voxels, clamped);
+        assert_eq!(report.band_voxels + report.clamped_voxels, sdf.voxels.len());
+
+        // Decoded zero level is half a value off (32768 = +value_size / 2)
+        let band_width = q.band_width(&sdf.header);
+        let value_size = value_size(&sdf.header);
+        for (&a, &b) in sdf.voxels.iter().zip(decoded.voxels.iter()) {
+            let (da, db) = (sdf.header.decode_distance(a), sdf.header.decode_distance(b));
+            if da.abs() > band_width + max_error {
+                assert_eq!(da < 0.0, db < 0.0);
+                assert!(
+                    (db.abs() - band_width).abs() <= value_size,
+                    "{} -> {}",
+                    da,
+                    db
+                );
+            }
+        }
+    }
+
+    // Encodes a single brick slab, returns (bits, decoded voxels, encoded size)
+    fn brick_round_trip(
+        dim: (u32, u32, u32),
+        q: &SdfQuantization,
+        voxels: &[u16],
+    ) -> (u32, Vec<u16>, usize) {
+        let mut storer = StorerVec::new();
+        encode_quantized_slab(dim, q, voxels, &mut storer);
+
+        let (bits, min) = parse_quantized_brick_header(&storer.v).unwrap();
+        let bytes = &storer.v[QUANTIZED_BRICK_HEADER_SIZE..];
+        assert_eq!(bytes.len(), quantized_brick_bytes(bits, voxels.len()));
+
+        let mut decoded = Vec::new();
+        decode_quantized_brick(q, bits, min, bytes, voxels.len(), &mut decoded);
+        (bits, decoded, storer.v.len())
+    }
+
+    #[test]
+    fn brick_encoding() {
+        let header = sphere_sdf().header;
+        let q = SdfQuantization::new(&header, 0.002, None);
+        let quantized = |v: u16| q.dequantize(q.quantize(v) as i64);
+
+        // Constant bricks cost only the header, inside and outside the band
+        let dim = (5, 8, 3);
+        for &v in [SDF_LEVEL_ZERO + 100, SDF_LEVEL_ZERO - 3, 65535, 0].iter() {
+            let voxels = vec![v; 5 * 8 * 3];
+            let (bits, decoded, size) = brick_round_trip(dim, &q, &voxels);
+            assert_eq!(bits, 0);
+            assert_eq!(size, QUANTIZED_BRICK_HEADER_SIZE);
+            assert!(decoded.iter().all(|&d| d == quantized(v)));
+        }
+
+        // Full range gradient brick
+        let dim = (8, 8, 8);
+        let voxels: Vec<u16> = (0..512u32).map(|i| (i * 128) as u16).collect();
+        let (bits, decoded, _) = brick_round_trip(dim, &q, &voxels);
+        assert_eq!(bits, 32 - (2 * q.band).leading_zeros());
+        let expected: Vec<u16> = voxels.iter().map(|&v| quantized(v)).collect();
+        assert_eq!(decoded, expected);
+
+        assert!(parse_quantized_brick_header(&[QUANTIZED_MAX_BITS as u8 + 1, 0, 0, 0, 0]).is_err());
+    }
+}
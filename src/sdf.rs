@@ -1,9 +1,14 @@
-extern crate miniz_oxide;
-use miniz_oxide::deflate::compress_to_vec;
-use miniz_oxide::inflate::decompress_to_vec;
-
+use crate::error::*;
+use crate::quantized_sdf::*;
+use crate::sdf_mips::*;
+use crate::sdf_stream::*;
+use crate::sdf_voxel::*;
 use crate::serialization::*;
-use std::io;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+
+// Voxel value of the surface (distance = 0)
+pub const SDF_LEVEL_ZERO: u16 = 32768;
 
 #[derive(Clone, Debug, Copy)]
 pub struct SdfHeader {
@@ -12,120 +17,528 @@ pub struct SdfHeader {
     pub dx: f32,
 }
 
-pub struct Sdf {
+impl SdfHeader {
+    // Distances are stored relative to the volume diagonal length (matches main.frag)
+    pub fn diagonal_length(&self) -> f32 {
+        let x = self.dx * self.dim.0 as f32;
+        let y = self.dx * self.dim.1 as f32;
+        let z = self.dx * self.dim.2 as f32;
+        (x * x + y * y + z * z).sqrt()
+    }
+
+    pub fn encode_distance(&self, d: f32) -> u16 {
+        let s = d / self.diagonal_length() * 0.5 + 0.5;
+        (s * 65535.0).round().clamp(0.0, 65535.0) as u16
+    }
+
+    pub fn decode_distance(&self, v: u16) -> f32 {
+        let s = v as f32 / 65535.0 * 2.0 - 1.0;
+        s * self.diagonal_length()
+    }
+}
+
+// Voxel storage: u16 UNORM by default, see sdf_voxel.rs
+pub struct Sdf<T = u16> {
     pub header: SdfHeader,
-    pub voxels: Vec<u16>,
+    pub voxels: Vec<T>,
 }
 
-pub fn load_sdf_zlib(filename: &str) -> io::Result<Sdf> {
-    let bytes = std::fs::read(filename)?;
-    let bytes = decompress_to_vec(&bytes[..]).expect("Failed to decompress!");
+impl<T: SdfVoxel> Sdf<T> {
+    pub fn voxel_type(&self) -> VoxelType {
+        T::VOXEL_TYPE
+    }
 
-    let mut loader = Loader::new();
-    let header = SdfHeader {
-        dim: (
-            loader.load_u32(&bytes),
-            loader.load_u32(&bytes),
-            loader.load_u32(&bytes),
-        ),
-        box_min: (
-            loader.load_f32(&bytes),
-            loader.load_f32(&bytes),
-            loader.load_f32(&bytes),
-        ),
-        dx: loader.load_f32(&bytes),
-    };
+    pub fn to_world_distance(&self, v: T) -> f32 {
+        v.to_world_distance(&self.header)
+    }
 
-    let count_voxels = header.dim.0 * header.dim.1 * header.dim.2;
-    let voxels = loader.load_array_u16(&bytes, count_voxels as usize);
+    // Through the world space distance. Reversible when VoxelType::converts_losslessly_to is
+    // true.
+    pub fn convert<U: SdfVoxel>(&self) -> Sdf<U> {
+        let voxels = self
+            .voxels
+            .iter()
+            .map(|&v| U::from_distance(v.to_distance(&self.header), &self.header))
+            .collect();
+
+        Sdf {
+            header: self.header,
+            voxels,
+        }
+    }
+}
 
-    println!("Loaded SDF: {:?}", header);
+// Voxel count of the dimensions. Fails if the payload size of the largest voxel type does
+// not fit in usize.
+pub fn voxel_count(dim: (u32, u32, u32)) -> SdfResult<usize> {
+    (dim.0 as usize)
+        .checked_mul(dim.1 as usize)
+        .and_then(|v| v.checked_mul(dim.2 as usize))
+        .filter(|v| v.checked_mul(std::mem::size_of::<f32>()).is_some())
+        .ok_or(SdfError::DimensionOverflow(dim))
+}
 
-    let sdf = Sdf { header, voxels };
-    let sdf = decompress_postprocess_sdf(&sdf);
+fn check_voxel_count<T: SdfVoxel>(sdf: &Sdf<T>) -> SdfResult<usize> {
+    let count_voxels = voxel_count(sdf.header.dim)?;
+    if sdf.voxels.len() != count_voxels {
+        let voxel_size = T::VOXEL_TYPE.size();
+        return Err(SdfError::SizeMismatch {
+            dim: sdf.header.dim,
+            expected: (count_voxels * voxel_size) as u64,
+            actual: (sdf.voxels.len() * voxel_size) as u64,
+        });
+    }
+    Ok(count_voxels)
+}
+
+pub fn load_sdf_zlib(filename: &str) -> SdfResult<Sdf> {
+    let file = BufReader::new(File::open(filename)?);
+    let sdf = SdfReader::new_legacy(file, true)?.read_sdf()?;
+
+    println!("Loaded SDF: {:?}", sdf.header);
 
     Ok(sdf)
 }
 
-pub fn load_sdf(filename: &str) -> io::Result<Sdf> {
-    let bytes = std::fs::read(filename)?;
+pub fn parse_sdf_zlib(bytes: &[u8]) -> SdfResult<Sdf> {
+    let sdf = SdfReader::new_legacy(bytes, true)?.read_sdf()?;
 
-    let mut loader = Loader::new();
-    let header = SdfHeader {
+    println!("Loaded SDF: {:?}", sdf.header);
+
+    Ok(sdf)
+}
+
+pub fn load_sdf(filename: &str) -> SdfResult<Sdf> {
+    let file = BufReader::new(File::open(filename)?);
+    let sdf = SdfReader::new_legacy(file, false)?.read_sdf()?;
+
+    println!("Loaded SDF: {:?}", sdf.header);
+
+    Ok(sdf)
+}
+
+pub fn parse_sdf(bytes: &[u8]) -> SdfResult<Sdf> {
+    let mut reader = SdfReader::new_legacy(bytes, false)?;
+    let sdf = reader.read_sdf()?;
+
+    let trailing = reader.into_inner().len();
+    if trailing > 0 {
+        let expected = (sdf.voxels.len() * std::mem::size_of::<u16>()) as u64;
+        return Err(SdfError::SizeMismatch {
+            dim: sdf.header.dim,
+            expected,
+            actual: expected + trailing as u64,
+        });
+    }
+
+    println!("Loaded SDF: {:?}", sdf.header);
+
+    Ok(sdf)
+}
+
+pub fn load_sdf_header(loader: &mut Loader, bytes: &[u8]) -> SdfResult<SdfHeader> {
+    Ok(SdfHeader {
         dim: (
-            loader.load_u32(&bytes),
-            loader.load_u32(&bytes),
-            loader.load_u32(&bytes),
+            loader.load_u32(bytes)?,
+            loader.load_u32(bytes)?,
+            loader.load_u32(bytes)?,
         ),
         box_min: (
-            loader.load_f32(&bytes),
-            loader.load_f32(&bytes),
-            loader.load_f32(&bytes),
+            loader.load_f32(bytes)?,
+            loader.load_f32(bytes)?,
+            loader.load_f32(bytes)?,
         ),
-        dx: loader.load_f32(&bytes),
-    };
-
-    let count_voxels = header.dim.0 * header.dim.1 * header.dim.2;
-    let voxels = loader.load_array_u16(&bytes, count_voxels as usize);
+        dx: loader.load_f32(bytes)?,
+    })
+}
 
-    println!("Loaded SDF: {:?}", header);
+pub fn store_sdf_header(storer: &mut StorerVec, header: &SdfHeader) {
+    storer.store_u32(header.dim.0);
+    storer.store_u32(header.dim.1);
+    storer.store_u32(header.dim.2);
+    storer.store_f32(header.box_min.0);
+    storer.store_f32(header.box_min.1);
+    storer.store_f32(header.box_min.2);
+    storer.store_f32(header.dx);
+}
 
-    let sdf = Sdf { header, voxels };
+// Streams the volume to the writer slice by slice
+fn write_sdf_slices<W: Write + Seek, T: SdfVoxel>(
+    mut writer: SdfWriter<W>,
+    sdf: &Sdf<T>,
+) -> SdfResult<W> {
+    check_voxel_count(sdf)?;
+
+    let slice_size = writer.slice_size();
+    if slice_size > 0 {
+        for slice in sdf.voxels.chunks(slice_size) {
+            writer.write_slice(slice)?;
+        }
+    }
 
-    Ok(sdf)
+    writer.finish()
 }
 
-pub fn store_sdf_zlib(filename: &str, sdf: &Sdf) -> io::Result<()> {
-    println!("Store SDF: preprocess");
-    let sdf = compress_preprocess_sdf(sdf);
+pub fn store_sdf_zlib(filename: &str, sdf: &Sdf) -> SdfResult<()> {
+    check_voxel_count(sdf)?;
 
-    let byte_count =
-        sdf.voxels.len() as usize * std::mem::size_of::<u16>() + std::mem::size_of::<SdfHeader>();
-    let mut bytes = vec![0u8; byte_count];
+    let file = BufWriter::new(File::create(filename)?);
+    let writer = SdfWriter::new_legacy(file, sdf.header, true)?;
+    let mut file = write_sdf_slices(writer, sdf)?;
 
-    let mut storer = Storer::new();
+    println!("Store SDF: write {} bytes", file.stream_position()?);
 
-    storer.store_u32(&mut bytes[..], sdf.header.dim.0);
-    storer.store_u32(&mut bytes[..], sdf.header.dim.1);
-    storer.store_u32(&mut bytes[..], sdf.header.dim.2);
-    storer.store_f32(&mut bytes[..], sdf.header.box_min.0);
-    storer.store_f32(&mut bytes[..], sdf.header.box_min.1);
-    storer.store_f32(&mut bytes[..], sdf.header.box_min.2);
-    storer.store_f32(&mut bytes[..], sdf.header.dx);
+    Ok(())
+}
 
-    storer.store_array_u16(&mut bytes[..], &sdf.voxels[..]);
+pub fn store_sdf(filename: &str, sdf: &Sdf) -> SdfResult<()> {
+    check_voxel_count(sdf)?;
 
-    println!("Store SDF: zlib");
-    let bytes = compress_to_vec(&bytes[..], 5);
-    println!("Store SDF: write {} bytes", bytes.len());
-    std::fs::write(filename, bytes)?;
+    let file = BufWriter::new(File::create(filename)?);
+    let writer = SdfWriter::new_legacy(file, sdf.header, false)?;
+    write_sdf_slices(writer, sdf)?;
 
     Ok(())
 }
 
-pub fn store_sdf(filename: &str, sdf: &Sdf) -> io::Result<()> {
-    let byte_count =
-        sdf.voxels.len() as usize * std::mem::size_of::<u16>() + std::mem::size_of::<SdfHeader>();
-    let mut bytes = vec![0u8; byte_count];
+// Versioned SDF file (container) layout, little endian:
+// magic "RSDF", version u32, encoding u32, voxel type u32, predictor u32 (version 2+),
+// dim 3 x u32, box_min 3 x f32, dx f32, payload length u64, payload crc32 u32, payload
+pub const SDF_FILE_MAGIC: &[u8; 4] = b"RSDF";
+pub const SDF_FILE_VERSION: u32 = 2;
+pub const SDF_FILE_HEADER_SIZE: usize = 60;
+
+// Size of the legacy (headerless) SDF header: dim 3 x u32, box_min 3 x f32, dx f32
+pub const SDF_LEGACY_HEADER_SIZE: usize = 28;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SdfEncoding {
+    Raw,
+    GradientZlib,
+    Zlib,
+    // Lossy narrow band, see quantized_sdf.rs
+    QuantizedZlib,
+}
+
+impl SdfEncoding {
+    pub fn from_u32(v: u32) -> Option<SdfEncoding> {
+        match v {
+            0 => Some(SdfEncoding::Raw),
+            1 => Some(SdfEncoding::GradientZlib),
+            2 => Some(SdfEncoding::Zlib),
+            3 => Some(SdfEncoding::QuantizedZlib),
+            _ => None,
+        }
+    }
+
+    pub fn to_u32(self) -> u32 {
+        match self {
+            SdfEncoding::Raw => 0,
+            SdfEncoding::GradientZlib => 1,
+            SdfEncoding::Zlib => 2,
+            SdfEncoding::QuantizedZlib => 3,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VoxelType {
+    U16Unorm,
+    U8Unorm,
+    // World space distances
+    F16,
+    F32,
+}
+
+impl VoxelType {
+    pub fn from_u32(v: u32) -> Option<VoxelType> {
+        match v {
+            0 => Some(VoxelType::U16Unorm),
+            1 => Some(VoxelType::U8Unorm),
+            2 => Some(VoxelType::F16),
+            3 => Some(VoxelType::F32),
+            _ => None,
+        }
+    }
+
+    pub fn to_u32(self) -> u32 {
+        match self {
+            VoxelType::U16Unorm => 0,
+            VoxelType::U8Unorm => 1,
+            VoxelType::F16 => 2,
+            VoxelType::F32 => 3,
+        }
+    }
+
+    // Bytes per voxel
+    pub fn size(self) -> usize {
+        match self {
+            VoxelType::U8Unorm => 1,
+            VoxelType::U16Unorm | VoxelType::F16 => 2,
+            VoxelType::F32 => 4,
+        }
+    }
+
+    // Sdf::convert from this type to the other one and back restores the voxels exactly. The
+    // converted distances match to_world_distance (f32), UNORM to F32 is not a bit-exact copy
+    // of the f64 to_distance.
+    pub fn converts_losslessly_to(self, other: VoxelType) -> bool {
+        match (self, other) {
+            (a, b) if a == b => true,
+            (VoxelType::U8Unorm, VoxelType::U16Unorm) => true,
+            (VoxelType::U8Unorm, VoxelType::F32) => true,
+            (VoxelType::U16Unorm, VoxelType::F32) => true,
+            (VoxelType::F16, VoxelType::F32) => true,
+            _ => false,
+        }
+    }
+
+    // Predictive and quantized encodings are u16 only
+    pub fn supports_encoding(self, encoding: SdfEncoding) -> bool {
+        match encoding {
+            SdfEncoding::Raw | SdfEncoding::Zlib => true,
+            SdfEncoding::GradientZlib | SdfEncoding::QuantizedZlib => self == VoxelType::U16Unorm,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Copy)]
+pub struct SdfFileHeader {
+    pub version: u32,
+    pub encoding: SdfEncoding,
+    pub voxel_type: VoxelType,
+    // Version 1 files: Planar
+    pub predictor: SdfPredictor,
+    pub header: SdfHeader,
+    pub payload_length: u64,
+    pub crc: u32,
+}
+
+pub fn is_sdf_file(bytes: &[u8]) -> bool {
+    bytes.len() >= SDF_FILE_MAGIC.len() && &bytes[..SDF_FILE_MAGIC.len()] == SDF_FILE_MAGIC
+}
+
+// Header size of the given file version
+pub fn sdf_file_header_size(version: u32) -> usize {
+    if version >= 2 {
+        SDF_FILE_HEADER_SIZE
+    } else {
+        SDF_FILE_HEADER_SIZE - 4
+    }
+}
+
+pub fn parse_sdf_file_header(bytes: &[u8]) -> SdfResult<SdfFileHeader> {
+    if !is_sdf_file(bytes) {
+        return Err(SdfError::BadMagic);
+    }
+
+    let mut loader = Loader::new();
+    loader.offset = SDF_FILE_MAGIC.len();
+
+    let version = loader.load_u32(bytes)?;
+    if version == 0 || version > SDF_FILE_VERSION {
+        return Err(SdfError::UnsupportedVersion(version));
+    }
+
+    let encoding = loader.load_u32(bytes)?;
+    let encoding = SdfEncoding::from_u32(encoding).ok_or(SdfError::UnknownEncoding(encoding))?;
+
+    let voxel_type = loader.load_u32(bytes)?;
+    let voxel_type =
+        VoxelType::from_u32(voxel_type).ok_or(SdfError::UnknownVoxelType(voxel_type))?;
+    if !voxel_type.supports_encoding(encoding) {
+        return Err(SdfError::UnsupportedVoxelType(
+            encoding.to_u32(),
+            voxel_type.to_u32(),
+        ));
+    }
+
+    let predictor = if version >= 2 {
+        let predictor = loader.load_u32(bytes)?;
+        SdfPredictor::from_u32(predictor).ok_or(SdfError::UnknownPredictor(predictor))?
+    } else {
+        SdfPredictor::Planar
+    };
+
+    let header = load_sdf_header(&mut loader, bytes)?;
 
-    let mut storer = Storer::new();
+    let payload_length = loader.load_u64(bytes)?;
+    let crc = loader.load_u32(bytes)?;
 
-    storer.store_u32(&mut bytes[..], sdf.header.dim.0);
-    storer.store_u32(&mut bytes[..], sdf.header.dim.1);
-    storer.store_u32(&mut bytes[..], sdf.header.dim.2);
-    storer.store_f32(&mut bytes[..], sdf.header.box_min.0);
-    storer.store_f32(&mut bytes[..], sdf.header.box_min.1);
-    storer.store_f32(&mut bytes[..], sdf.header.box_min.2);
-    storer.store_f32(&mut bytes[..], sdf.header.dx);
+    Ok(SdfFileHeader {
+        version,
+        encoding,
+        voxel_type,
+        predictor,
+        header,
+        payload_length,
+        crc,
+    })
+}
 
-    storer.store_array_u16(&mut bytes[..], &sdf.voxels[..]);
+pub fn store_sdf_file_header(storer: &mut StorerVec, file_header: &SdfFileHeader) {
+    storer.store_array_u8(&SDF_FILE_MAGIC[..]);
+    storer.store_u32(file_header.version);
+    storer.store_u32(file_header.encoding.to_u32());
+    storer.store_u32(file_header.voxel_type.to_u32());
+    if file_header.version >= 2 {
+        storer.store_u32(file_header.predictor.to_u32());
+    }
+    store_sdf_header(storer, &file_header.header);
+    storer.store_u64(file_header.payload_length);
+    storer.store_u32(file_header.crc);
+}
 
-    std::fs::write(filename, bytes)?;
+// Predictor is used by GradientZlib encoding only. GradientZlib requires u16 voxels.
+pub fn store_sdf_file<T: SdfVoxel>(
+    filename: &str,
+    sdf: &Sdf<T>,
+    encoding: SdfEncoding,
+    predictor: SdfPredictor,
+) -> SdfResult<()> {
+    check_voxel_count(sdf)?;
+
+    let file = BufWriter::new(File::create(filename)?);
+    let writer = SdfWriter::with_voxel_type(file, sdf.header, T::VOXEL_TYPE, encoding, predictor)?;
+    let mut file = write_sdf_slices(writer, sdf)?;
+
+    println!(
+        "Store SDF: {:?}, {:?}, {:?}, write {} bytes",
+        T::VOXEL_TYPE,
+        encoding,
+        predictor,
+        file.stream_position()?
+    );
 
     Ok(())
 }
 
-#[derive(Debug)]
+// Lossy narrow band encoding, see quantized_sdf.rs
+pub fn store_sdf_quantized(
+    filename: &str,
+    sdf: &Sdf,
+    quantization: &SdfQuantization,
+) -> SdfResult<()> {
+    check_voxel_count(sdf)?;
+
+    let file = BufWriter::new(File::create(filename)?);
+    let writer = SdfWriter::new_quantized(file, sdf.header, quantization)?;
+    let mut file = write_sdf_slices(writer, sdf)?;
+
+    println!(
+        "Store SDF: {:?}, {:?}, write {} bytes",
+        SdfEncoding::QuantizedZlib,
+        quantization,
+        file.stream_position()?
+    );
+
+    Ok(())
+}
+
+// Voxels are converted to u16, see load_sdf_file_as
+pub fn parse_sdf_file(bytes: &[u8]) -> SdfResult<Sdf> {
+    let mut reader = SdfReader::new(bytes)?;
+    let sdf = reader.read_sdf_as()?;
+
+    println!(
+        "Loaded SDF: {:?}, {:?}, {:?}",
+        reader.voxel_type(),
+        reader.encoding(),
+        sdf.header
+    );
+
+    Ok(sdf)
+}
+
+pub fn load_sdf_file(filename: &str) -> SdfResult<Sdf> {
+    load_sdf_file_as(filename)
+}
+
+// Voxels are converted to T if the file stores another voxel type
+pub fn load_sdf_file_as<T: SdfVoxel>(filename: &str) -> SdfResult<Sdf<T>> {
+    let file = BufReader::new(File::open(filename)?);
+    let mut reader = SdfReader::new(file)?;
+    let sdf = reader.read_sdf_as()?;
+
+    println!(
+        "Loaded SDF: {:?}, {:?}, {:?}",
+        reader.voxel_type(),
+        reader.encoding(),
+        sdf.header
+    );
+
+    Ok(sdf)
+}
+
+// Legacy raw: file size matches the header dimensions exactly
+fn is_legacy_raw_sdf(header_bytes: &[u8], file_size: u64) -> bool {
+    let header = match load_sdf_header(&mut Loader::new(), header_bytes) {
+        Ok(header) => header,
+        Err(_) => return false,
+    };
+
+    match voxel_count(header.dim) {
+        Ok(count_voxels) => {
+            (count_voxels as u64 * std::mem::size_of::<u16>() as u64)
+                .checked_add(SDF_LEGACY_HEADER_SIZE as u64)
+                == Some(file_size)
+        }
+        Err(_) => false,
+    }
+}
+
+// Deflate can't expand data by more than this (~1032:1)
+const DEFLATE_MAX_RATIO: u64 = 1040;
+
+// Loads versioned SDF files and legacy headerless files (raw and grad+zlib)
+pub fn load_any_sdf(filename: &str) -> SdfResult<Sdf> {
+    let mut file = File::open(filename)?;
+    let file_size = file.metadata()?.len();
+
+    let mut header_bytes = Vec::new();
+    (&mut file)
+        .take(SDF_LEGACY_HEADER_SIZE as u64)
+        .read_to_end(&mut header_bytes)?;
+    file.seek(SeekFrom::Start(0))?;
+    let file = BufReader::new(file);
+
+    if is_sdf_file(&header_bytes) {
+        let mut reader = SdfReader::new(file)?;
+        let sdf = reader.read_sdf_as()?;
+        println!(
+            "Loaded SDF: {:?}, {:?}, {:?}",
+            reader.voxel_type(),
+            reader.encoding(),
+            sdf.header
+        );
+        return Ok(sdf);
+    }
+
+    if is_legacy_raw_sdf(&header_bytes, file_size) {
+        let sdf = SdfReader::new_legacy(file, false)?.read_sdf()?;
+        println!("Loaded SDF: {:?}", sdf.header);
+        return Ok(sdf);
+    }
+
+    // Legacy grad+zlib: deflate stream of a legacy raw file. The decompressed size must be
+    // plausible for the file size, garbage must not trigger a huge allocation.
+    let mut reader = match SdfReader::new_legacy(file, true) {
+        Ok(reader) => reader,
+        Err(_) => return Err(SdfError::UnknownFormat),
+    };
+    let raw_size = voxel_count(reader.header().dim)? as u64 * std::mem::size_of::<u16>() as u64;
+    if raw_size > file_size.saturating_mul(DEFLATE_MAX_RATIO) {
+        return Err(SdfError::UnknownFormat);
+    }
+
+    let sdf = reader.read_sdf()?;
+    println!("Loaded SDF: {:?}", sdf.header);
+
+    Ok(sdf)
+}
+
+// Source axis of an output axis, negative = reversed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum AxisFlip {
     PositiveX,
     NegativeX,
@@ -135,43 +548,135 @@ pub enum AxisFlip {
     NegativeZ,
 }
 
-pub fn orient_sdf(sdf: &Sdf, x_orient: AxisFlip, y_orient: AxisFlip, z_orient: AxisFlip) -> Sdf {
-    let stride_x = 1i32;
-    let stride_y = (sdf.header.dim.0) as i32;
-    let stride_z = (sdf.header.dim.0 * sdf.header.dim.1) as i32;
+impl AxisFlip {
+    pub fn from_axis(axis: usize, negative: bool) -> AxisFlip {
+        match (axis, negative) {
+            (0, false) => AxisFlip::PositiveX,
+            (0, true) => AxisFlip::NegativeX,
+            (1, false) => AxisFlip::PositiveY,
+            (1, true) => AxisFlip::NegativeY,
+            (2, false) => AxisFlip::PositiveZ,
+            _ => AxisFlip::NegativeZ,
+        }
+    }
 
-    let orientation = |orient| match orient {
-        AxisFlip::PositiveX => (sdf.header.dim.0 as i32, 0, 1, stride_x),
-        AxisFlip::NegativeX => (
-            sdf.header.dim.0 as i32,
-            sdf.header.dim.0 as i32 - 1,
-            -1,
-            stride_x,
-        ),
-        AxisFlip::PositiveY => (sdf.header.dim.1 as i32, 0, 1, stride_y),
-        AxisFlip::NegativeY => (
-            sdf.header.dim.1 as i32,
-            sdf.header.dim.1 as i32 - 1,
-            -1,
-            stride_y,
-        ),
-        AxisFlip::PositiveZ => (sdf.header.dim.2 as i32, 0, 1, stride_z),
-        AxisFlip::NegativeZ => (
-            sdf.header.dim.2 as i32,
-            sdf.header.dim.2 as i32 - 1,
-            -1,
-            stride_z,
-        ),
+    // x, y, z = positive axis, X, Y, Z = negated axis
+    pub fn from_char(c: char) -> Option<AxisFlip> {
+        let axis = match c.to_ascii_lowercase() {
+            'x' => 0,
+            'y' => 1,
+            'z' => 2,
+            _ => return None,
+        };
+        Some(AxisFlip::from_axis(axis, c.is_ascii_uppercase()))
+    }
+
+    pub fn axis(self) -> usize {
+        match self {
+            AxisFlip::PositiveX | AxisFlip::NegativeX => 0,
+            AxisFlip::PositiveY | AxisFlip::NegativeY => 1,
+            AxisFlip::PositiveZ | AxisFlip::NegativeZ => 2,
+        }
+    }
+
+    pub fn is_negative(self) -> bool {
+        matches!(
+            self,
+            AxisFlip::NegativeX | AxisFlip::NegativeY | AxisFlip::NegativeZ
+        )
+    }
+
+    pub fn negate(self) -> AxisFlip {
+        AxisFlip::from_axis(self.axis(), !self.is_negative())
+    }
+}
+
+// Output x, y, z axes
+pub type SdfOrientation = [AxisFlip; 3];
+
+pub const IDENTITY_ORIENTATION: SdfOrientation = [
+    AxisFlip::PositiveX,
+    AxisFlip::PositiveY,
+    AxisFlip::PositiveZ,
+];
+
+// Each source axis used exactly once
+pub fn is_permutation(orientation: &SdfOrientation) -> bool {
+    let mut used = [false; 3];
+    for flip in orientation.iter() {
+        used[flip.axis()] = true;
+    }
+    used.iter().all(|&used| used)
+}
+
+// Orientation of a counterclockwise rotation around an axis (right handed coordinates)
+pub fn rotation_orientation(axis: usize, quarter_turns: i32) -> SdfOrientation {
+    let mut orientation = IDENTITY_ORIENTATION;
+    // Rotation of the other two axes: (u, v) -> (-v, u)
+    let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+    for _ in 0..quarter_turns.rem_euclid(4) {
+        let (old_u, old_v) = (orientation[u], orientation[v]);
+        orientation[u] = old_v.negate();
+        orientation[v] = old_u;
+    }
+    orientation
+}
+
+// Applies second after first
+pub fn combine_orientations(first: &SdfOrientation, second: &SdfOrientation) -> SdfOrientation {
+    let mut orientation = IDENTITY_ORIENTATION;
+    for (combined, flip) in orientation.iter_mut().zip(second.iter()) {
+        let source = first[flip.axis()];
+        *combined = if flip.is_negative() {
+            source.negate()
+        } else {
+            source
+        };
+    }
+    orientation
+}
+
+// Permutes and flips the voxel axes. Distances are unchanged (rigid transform), box_min is
+// transformed to the new axes: a negated axis is mirrored around the origin.
+pub fn orient_sdf<T: SdfVoxel>(
+    sdf: &Sdf<T>,
+    x_orient: AxisFlip,
+    y_orient: AxisFlip,
+    z_orient: AxisFlip,
+) -> Sdf<T> {
+    let dims = [
+        sdf.header.dim.0 as i32,
+        sdf.header.dim.1 as i32,
+        sdf.header.dim.2 as i32,
+    ];
+    let source_box_min = [
+        sdf.header.box_min.0,
+        sdf.header.box_min.1,
+        sdf.header.box_min.2,
+    ];
+    let strides = [1, dims[0], dims[0] * dims[1]];
+    let dx = sdf.header.dx;
+
+    // (dim, start, step, stride, box_min)
+    let orientation = |orient: AxisFlip| {
+        let axis = orient.axis();
+        let dim = dims[axis];
+        if orient.is_negative() {
+            let box_max = source_box_min[axis] + (dim - 1).max(0) as f32 * dx;
+            (dim, dim - 1, -1, strides[axis], -box_max)
+        } else {
+            (dim, 0, 1, strides[axis], source_box_min[axis])
+        }
     };
 
-    let (x_dim, x_start, x_step, x_stride): (i32, i32, i32, i32) = orientation(x_orient);
-    let (y_dim, y_start, y_step, y_stride) = orientation(y_orient);
-    let (z_dim, z_start, z_step, z_stride) = orientation(z_orient);
+    let (x_dim, x_start, x_step, x_stride, x_min) = orientation(x_orient);
+    let (y_dim, y_start, y_step, y_stride, y_min) = orientation(y_orient);
+    let (z_dim, z_start, z_step, z_stride, z_min) = orientation(z_orient);
 
     let stride_y = x_dim;
     let stride_z = x_dim * y_dim;
 
-    let mut voxels = vec![0; sdf.voxels.len()];
+    let mut voxels = vec![T::default(); sdf.voxels.len()];
     for z in 0..z_dim {
         for y in 0..y_dim {
             for x in 0..x_dim {
@@ -186,56 +691,16 @@ pub fn orient_sdf(sdf: &Sdf, x_orient: AxisFlip, y_orient: AxisFlip, z_orient: A
 
     let header = SdfHeader {
         dim: (x_dim as u32, y_dim as u32, z_dim as u32),
-        box_min: (
-            0.0, 0.0, 0.0, // Not used
-        ),
-        dx: sdf.header.dx,
+        box_min: (x_min, y_min, z_min),
+        dx,
     };
 
     Sdf { header, voxels }
 }
 
+// Box filtered mip, see sdf_mips.rs for the other filters and whole mip chains
 pub fn downsample_2x_sdf(sdf: &Sdf) -> Sdf {
-    let x_dim = sdf.header.dim.0 / 2;
-    let y_dim = sdf.header.dim.1 / 2;
-    let z_dim = sdf.header.dim.2 / 2;
-
-    let stride_y = (sdf.header.dim.0) as u32;
-    let stride_z = (sdf.header.dim.0 * sdf.header.dim.1) as u32;
-
-    let stride_write_y = x_dim as u32;
-    let stride_write_z = (x_dim * y_dim) as u32;
-
-    let mut voxels = vec![0; (x_dim * y_dim * z_dim) as usize];
-    for z in 0..z_dim {
-        for y in 0..y_dim {
-            for x in 0..x_dim {
-                let write_addr = x + y * stride_write_y + z * stride_write_z;
-                let read_addr_base = x * 2 + y * stride_y * 2 + z * stride_z * 2;
-
-                let sum = sdf.voxels[(read_addr_base) as usize] as u32
-                    + sdf.voxels[(read_addr_base + 1) as usize] as u32
-                    + sdf.voxels[(read_addr_base + stride_y) as usize] as u32
-                    + sdf.voxels[(read_addr_base + 1 + stride_y) as usize] as u32
-                    + sdf.voxels[(read_addr_base + stride_z) as usize] as u32
-                    + sdf.voxels[(read_addr_base + 1 + stride_z) as usize] as u32
-                    + sdf.voxels[(read_addr_base + stride_y + stride_z) as usize] as u32
-                    + sdf.voxels[(read_addr_base + 1 + stride_y + stride_z) as usize] as u32;
-
-                voxels[write_addr as usize] = (sum / 8) as u16;
-            }
-        }
-    }
-
-    let header = SdfHeader {
-        dim: (x_dim as u32, y_dim as u32, z_dim as u32),
-        box_min: (
-            0.0, 0.0, 0.0, // Not used
-        ),
-        dx: sdf.header.dx * 2.0,
-    };
-
-    Sdf { header, voxels }
+    downsample_sdf(sdf, MipFilter::Box)
 }
 
 // https://gist.github.com/mfuerstenau/ba870a29e16536fdbaba
@@ -250,41 +715,201 @@ pub fn abs_diff_inv(v: u32) -> i32 {
     (v >> 1) as i32 ^ -((v & 1) as i32)
 }
 
-pub fn compress_preprocess_sdf(sdf: &Sdf) -> Sdf {
-    let x_dim = sdf.header.dim.0 as usize;
-    let y_dim = sdf.header.dim.1 as usize;
-    let z_dim = sdf.header.dim.2 as usize;
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SdfPredictor {
+    // Linear estimate inside the slice, x=0, y=0 and z=0 slices stored as is (legacy files)
+    Planar,
+    // 1d estimate on the first scanline, 2d on the first slice, 3d (Lorenzo) elsewhere
+    Gradient,
+    // Like Gradient, but interior voxels use the local gradient direction with the unit
+    // length of the eikonal equation |grad d| = 1
+    Eikonal,
+}
 
-    let stride_y = (sdf.header.dim.0) as usize;
-    let stride_z = (sdf.header.dim.0 * sdf.header.dim.1) as usize;
+impl SdfPredictor {
+    pub fn from_u32(v: u32) -> Option<SdfPredictor> {
+        match v {
+            0 => Some(SdfPredictor::Planar),
+            1 => Some(SdfPredictor::Gradient),
+            2 => Some(SdfPredictor::Eikonal),
+            _ => None,
+        }
+    }
 
-    let mut voxels = sdf.voxels.clone();
+    pub fn to_u32(self) -> u32 {
+        match self {
+            SdfPredictor::Planar => 0,
+            SdfPredictor::Gradient => 1,
+            SdfPredictor::Eikonal => 2,
+        }
+    }
+}
 
-    // NOTE: Storing x=0, y=0, z=0 slices as is
-    // TODO: 1d gradient estimate for the first y scanline
-    // TODO: 2d gradient estimate for the first z slice
+// Voxel value change of a one voxel step along the distance gradient. Distances are stored
+// relative to the diagonal (see SdfHeader::encode_distance), so only the dimensions matter.
+fn eikonal_gradient_length(dim: (u32, u32, u32)) -> f32 {
+    let x = dim.0 as f32;
+    let y = dim.1 as f32;
+    let z = dim.2 as f32;
+    65535.0 * 0.5 / (x * x + y * y + z * z).sqrt()
+}
 
-    for z in 1..z_dim {
-        for y in 1..y_dim {
-            for x in 1..x_dim {
-                let addr_base = x + y * stride_y + z * stride_z;
+// Estimate of voxel (x, y) from the causal neighborhood: voxels before it in the slice and
+// the previous slice (None for z=0). None = stored as is.
+fn predict_voxel(
+    predictor: SdfPredictor,
+    x_dim: usize,
+    gradient_length: f32,
+    slice: &[u16],
+    prev: Option<&[u16]>,
+    x: usize,
+    y: usize,
+) -> Option<i32> {
+    let addr = x + y * x_dim;
+    let p = |dx: usize, dy: usize, dz: usize| -> i32 {
+        let addr = addr - dx - dy * x_dim;
+        if dz == 0 {
+            slice[addr] as i32
+        } else {
+            prev.unwrap()[addr] as i32
+        }
+    };
 
-                let dx = sdf.voxels[addr_base - stride_y] as i32
-                    - sdf.voxels[addr_base - stride_y - 1] as i32;
-                //let dy = sdf.voxels[addr_base - 1] as i32 - sdf.voxels[addr_base - stride_y - 1] as i32;
-                //let dz = sdf.voxels[addr_base - 1] as i32 - sdf.voxels[addr_base - stride_z - 1] as i32;
+    let has_x = x > 0;
+    let has_y = y > 0;
+    let has_z = prev.is_some();
 
-                // TODO: Use eikonal equation instead of this simple linear estimate
-                let estimate = sdf.voxels[addr_base - 1] as i32 + dx;
+    if predictor == SdfPredictor::Planar {
+        if !has_x || !has_y || !has_z {
+            return None;
+        }
+        let dx = p(0, 1, 0) - p(1, 1, 0);
+        return Some(p(1, 0, 0) + dx);
+    }
 
-                let v = sdf.voxels[addr_base] as i32;
-                voxels[addr_base] = abs_diff(v - estimate) as u16;
+    let estimate = match (has_x, has_y, has_z) {
+        (true, true, true) => {
+            let lorenzo =
+                p(1, 0, 0) + p(0, 1, 0) + p(0, 0, 1) - p(1, 1, 0) - p(1, 0, 1) - p(0, 1, 1)
+                    + p(1, 1, 1);
+
+            if predictor == SdfPredictor::Eikonal {
+                // Gradient from the known edges of the 2x2x2 cube ending at this voxel
+                let gx = (p(0, 1, 1) - p(1, 1, 1))
+                    + (p(0, 0, 1) - p(1, 0, 1))
+                    + (p(0, 1, 0) - p(1, 1, 0));
+                let gy = (p(1, 0, 1) - p(1, 1, 1))
+                    + (p(0, 0, 1) - p(0, 1, 1))
+                    + (p(1, 0, 0) - p(1, 1, 0));
+                let gz = (p(1, 1, 0) - p(1, 1, 1))
+                    + (p(0, 1, 0) - p(0, 1, 1))
+                    + (p(1, 0, 0) - p(1, 0, 1));
+                let (gx, gy, gz) = (gx as f32, gy as f32, gz as f32);
+                let length = (gx * gx + gy * gy + gz * gz).sqrt();
+
+                if length > 0.0 {
+                    let scale = gradient_length / length;
+                    let sum = p(1, 0, 0) as f32
+                        + p(0, 1, 0) as f32
+                        + p(0, 0, 1) as f32
+                        + (gx + gy + gz) * scale;
+                    (sum / 3.0).round() as i32
+                } else {
+                    lorenzo
+                }
+            } else {
+                lorenzo
             }
         }
+        (true, true, false) => p(1, 0, 0) + p(0, 1, 0) - p(1, 1, 0),
+        (true, false, true) => p(1, 0, 0) + p(0, 0, 1) - p(1, 0, 1),
+        (false, true, true) => p(0, 1, 0) + p(0, 0, 1) - p(0, 1, 1),
+        (true, false, false) if x > 1 => 2 * p(1, 0, 0) - p(2, 0, 0),
+        (true, false, false) => p(1, 0, 0),
+        (false, true, false) if y > 1 => 2 * p(0, 1, 0) - p(0, 2, 0),
+        (false, true, false) => p(0, 1, 0),
+        (false, false, true) => p(0, 0, 1),
+        (false, false, false) => SDF_LEVEL_ZERO as i32,
+    };
+
+    Some(estimate)
+}
+
+// Residuals wrap around (mod 2^16), so every residual fits in u16 whatever the estimate
+fn encode_residual(v: u16, estimate: i32) -> u16 {
+    abs_diff((v as i32 - estimate) as i16 as i32) as u16
+}
+
+fn decode_residual(r: u16, estimate: i32) -> u16 {
+    (estimate + abs_diff_inv(r as u32)) as u16
+}
+
+// Predictor for one z slice (dim.0 * dim.1 voxels). prev is the previous slice (None for
+// z=0), the streaming load and store process the volume slice by slice.
+pub fn compress_preprocess_slice(
+    dim: (u32, u32, u32),
+    predictor: SdfPredictor,
+    prev: Option<&[u16]>,
+    slice: &[u16],
+    out: &mut [u16],
+) {
+    let x_dim = dim.0 as usize;
+    let y_dim = dim.1 as usize;
+    let gradient_length = eikonal_gradient_length(dim);
+
+    for y in 0..y_dim {
+        for x in 0..x_dim {
+            let addr = x + y * x_dim;
+            out[addr] = match predict_voxel(predictor, x_dim, gradient_length, slice, prev, x, y) {
+                Some(estimate) => encode_residual(slice[addr], estimate),
+                None => slice[addr],
+            };
+        }
+    }
+}
+
+// Inverse of compress_preprocess_slice, in place. prev is the decoded previous slice.
+pub fn decompress_postprocess_slice(
+    dim: (u32, u32, u32),
+    predictor: SdfPredictor,
+    prev: Option<&[u16]>,
+    slice: &mut [u16],
+) {
+    let x_dim = dim.0 as usize;
+    let y_dim = dim.1 as usize;
+    let gradient_length = eikonal_gradient_length(dim);
+
+    for y in 0..y_dim {
+        for x in 0..x_dim {
+            let addr = x + y * x_dim;
+            if let Some(estimate) =
+                predict_voxel(predictor, x_dim, gradient_length, slice, prev, x, y)
+            {
+                slice[addr] = decode_residual(slice[addr], estimate);
+            }
+        }
+    }
+}
+
+pub fn compress_preprocess_sdf(sdf: &Sdf, predictor: SdfPredictor) -> Sdf {
+    let dim = sdf.header.dim;
+    let slice_size = dim.0 as usize * dim.1 as usize;
+
+    let mut voxels = vec![0u16; sdf.voxels.len()];
+    if slice_size > 0 {
+        let mut prev = None;
+        for (slice, out) in sdf
+            .voxels
+            .chunks(slice_size)
+            .zip(voxels.chunks_mut(slice_size))
+        {
+            compress_preprocess_slice(dim, predictor, prev, slice, out);
+            prev = Some(slice);
+        }
     }
 
     let header = SdfHeader {
-        dim: (x_dim as u32, y_dim as u32, z_dim as u32),
+        dim,
         box_min: (
             0.0, 0.0, 0.0, // Not used
         ),
@@ -294,41 +919,25 @@ pub fn compress_preprocess_sdf(sdf: &Sdf) -> Sdf {
     Sdf { header, voxels }
 }
 
-pub fn decompress_postprocess_sdf(sdf: &Sdf) -> Sdf {
-    let x_dim = sdf.header.dim.0 as usize;
-    let y_dim = sdf.header.dim.1 as usize;
-    let z_dim = sdf.header.dim.2 as usize;
-
-    let stride_y = (sdf.header.dim.0) as usize;
-    let stride_z = (sdf.header.dim.0 * sdf.header.dim.1) as usize;
+pub fn decompress_postprocess_sdf(sdf: &Sdf, predictor: SdfPredictor) -> Sdf {
+    let dim = sdf.header.dim;
+    let slice_size = dim.0 as usize * dim.1 as usize;
 
     let mut voxels = sdf.voxels.clone();
-
-    // NOTE: Storing x=0, y=0, z=0 slices as is
-    // TODO: 1d gradient estimate for the first y scanline
-    // TODO: 2d gradient estimate for the first z slice
-
-    for z in 1..z_dim {
-        for y in 1..y_dim {
-            for x in 1..x_dim {
-                let addr_base = x + y * stride_y + z * stride_z;
-
-                let dx =
-                    voxels[addr_base - stride_y] as i32 - voxels[addr_base - stride_y - 1] as i32;
-                //let dy = voxels[addr_base - 1] as i32 - voxels[addr_base - stride_y - 1] as i32;
-                //let dz = voxels[addr_base - 1] as i32 - voxels[addr_base - stride_z - 1] as i32;
-
-                // TODO: Use eikonal equation instead of this simple linear estimate
-                let estimate = voxels[addr_base - 1] as i32 + dx;
-
-                let v = voxels[addr_base] as u32;
-                voxels[addr_base] = (estimate + abs_diff_inv(v)) as u16;
-            }
+    if slice_size > 0 {
+        for z in 0..dim.2 as usize {
+            let (decoded, rest) = voxels.split_at_mut(z * slice_size);
+            let prev = if z > 0 {
+                Some(&decoded[(z - 1) * slice_size..])
+            } else {
+                None
+            };
+            decompress_postprocess_slice(dim, predictor, prev, &mut rest[..slice_size]);
         }
     }
 
     let header = SdfHeader {
-        dim: (x_dim as u32, y_dim as u32, z_dim as u32),
+        dim,
         box_min: (
             0.0, 0.0, 0.0, // Not used
         ),
@@ -337,3 +946,367 @@ pub fn decompress_postprocess_sdf(sdf: &Sdf) -> Sdf {
 
     Sdf { header, voxels }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sdf_sampling::*;
+    use miniz_oxide::deflate::compress_to_vec;
+    use std::io::Cursor;
+
+    // Partial quantized bricks on every axis
+    fn test_sdf() -> Sdf {
+        let header = SdfHeader {
+            dim: (11, 9, 10),
+            box_min: (-1.0, -1.0, -1.0),
+            dx: 0.2,
+        };
+        sample_sdf(header, |p| p.length() - 0.7).unwrap()
+    }
+
+    fn written_bytes<T: SdfVoxel>(writer: SdfWriter<Cursor<Vec<u8>>>, sdf: &Sdf<T>) -> Vec<u8> {
+        write_sdf_slices(writer, sdf).unwrap().into_inner()
+    }
+
+    fn legacy_bytes(sdf: &Sdf, compressed: bool) -> Vec<u8> {
+        let writer = SdfWriter::new_legacy(Cursor::new(Vec::new()), sdf.header, compressed);
+        written_bytes(writer.unwrap(), sdf)
+    }
+
+    fn versioned_bytes<T: SdfVoxel>(sdf: &Sdf, encoding: SdfEncoding) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for &predictor in &[
+            SdfPredictor::Planar,
+            SdfPredictor::Gradient,
+            SdfPredictor::Eikonal,
+        ] {
+            let writer = SdfWriter::with_voxel_type(
+                Cursor::new(Vec::new()),
+                sdf.header,
+                T::VOXEL_TYPE,
+                encoding,
+                predictor,
+            );
+            bytes = written_bytes(writer.unwrap(), &sdf.convert::<T>());
+            if encoding != SdfEncoding::GradientZlib {
+                break;
+            }
+        }
+        bytes
+    }
+
+    // (name, bytes) of every versioned encoding and voxel type
+    fn versioned_files(sdf: &Sdf) -> Vec<(String, Vec<u8>)> {
+        let mut files = Vec::new();
+        for &encoding in &[SdfEncoding::Raw, SdfEncoding::Zlib] {
+            files.push((
+                format!("{:?} u8", encoding),
+                versioned_bytes::<u8>(sdf, encoding),
+            ));
+            files.push((
+                format!("{:?} u16", encoding),
+                versioned_bytes::<u16>(sdf, encoding),
+            ));
+            files.push((
+                format!("{:?} f16", encoding),
+                versioned_bytes::<F16>(sdf, encoding),
+            ));
+            files.push((
+                format!("{:?} f32", encoding),
+                versioned_bytes::<f32>(sdf, encoding),
+            ));
+        }
+        for &predictor in &[
+            SdfPredictor::Planar,
+            SdfPredictor::Gradient,
+            SdfPredictor::Eikonal,
+        ] {
+            let writer = SdfWriter::new(
+                Cursor::new(Vec::new()),
+                sdf.header,
+                SdfEncoding::GradientZlib,
+                predictor,
+            );
+            files.push((
+                format!("GradientZlib {:?}", predictor),
+                written_bytes(writer.unwrap(), sdf),
+            ));
+        }
+        let quantization = SdfQuantization::new(&sdf.header, 0.01, Some(0.3));
+        let writer = SdfWriter::new_quantized(Cursor::new(Vec::new()), sdf.header, &quantization);
+        files.push((
+            "QuantizedZlib".to_string(),
+            written_bytes(writer.unwrap(), sdf),
+        ));
+        files
+    }
+
+    fn is_truncation(err: &SdfError) -> bool {
+        matches!(err, SdfError::Truncated { .. } | SdfError::Decompress(_))
+    }
+
+    fn temp_file(name: &str, bytes: &[u8]) -> String {
+        let path = std::env::temp_dir().join(format!("rust_test_{}_{}", std::process::id(), name));
+        std::fs::write(&path, bytes).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn round_trip() {
+        let sdf = test_sdf();
+        assert_eq!(
+            parse_sdf(&legacy_bytes(&sdf, false)).unwrap().voxels,
+            sdf.voxels
+        );
+        assert_eq!(
+            parse_sdf_zlib(&legacy_bytes(&sdf, true)).unwrap().voxels,
+            sdf.voxels
+        );
+        for (name, bytes) in versioned_files(&sdf) {
+            let loaded = parse_sdf_file(&bytes).unwrap_or_else(|err| panic!("{}: {}", name, err));
+            assert_eq!(loaded.header.dim, sdf.header.dim, "{}", name);
+            if name.contains("u16") || name.starts_with("Gradient") {
+                assert_eq!(loaded.voxels, sdf.voxels, "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_legacy() {
+        let sdf = test_sdf();
+        let bytes = legacy_bytes(&sdf, false);
+        for len in 0..bytes.len() {
+            match parse_sdf(&bytes[..len]) {
+                Err(SdfError::Truncated { .. }) => (),
+                result => panic!("length {}: {:?}", len, result.err()),
+            }
+        }
+        let bytes = legacy_bytes(&sdf, true);
+        for len in 0..bytes.len() {
+            match parse_sdf_zlib(&bytes[..len]) {
+                Err(err) if is_truncation(&err) => (),
+                result => panic!("length {}: {:?}", len, result.err()),
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_versioned() {
+        let sdf = test_sdf();
+        for (name, bytes) in versioned_files(&sdf) {
+            for len in 0..bytes.len() {
+                match parse_sdf_file(&bytes[..len]) {
+                    // Cut in the magic
+                    Err(SdfError::BadMagic) if len < SDF_FILE_MAGIC.len() => (),
+                    Err(err) if is_truncation(&err) => (),
+                    result => panic!("{}, length {}: {:?}", name, len, result.err()),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn trailing_data() {
+        let sdf = test_sdf();
+        let mut bytes = legacy_bytes(&sdf, false);
+        bytes.push(0);
+        assert!(matches!(
+            parse_sdf(&bytes),
+            Err(SdfError::SizeMismatch { .. })
+        ));
+        for (name, mut bytes) in versioned_files(&sdf) {
+            // Past the payload length: ignored
+            bytes.push(0);
+            assert!(parse_sdf_file(&bytes).is_ok(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn bit_flips() {
+        let sdf = test_sdf();
+
+        // No checksum: anything goes, but no panics
+        for &compressed in &[false, true] {
+            let bytes = legacy_bytes(&sdf, compressed);
+            for i in 0..bytes.len() {
+                let mut bytes = bytes.clone();
+                bytes[i] ^= 1 << (i % 8);
+                let _ = SdfReader::new_legacy(&bytes[..], compressed)
+                    .and_then(|mut r| r.read_sdf::<u16>());
+            }
+        }
+
+        // Payload, payload length and crc are checksummed. Header flips may load, the
+        // dimensions are validated against the payload.
+        for (name, bytes) in versioned_files(&sdf) {
+            for i in 0..bytes.len() {
+                // All bits of the header fields, a single bit of the payload
+                let flips: &[u8] = if i < SDF_FILE_HEADER_SIZE {
+                    &[0xff, 0x80, 0x01]
+                } else {
+                    &[1 << (i % 8)]
+                };
+                for &flip in flips {
+                    let mut bytes = bytes.clone();
+                    bytes[i] ^= flip;
+                    let result = parse_sdf_file(&bytes);
+                    if i >= SDF_FILE_PAYLOAD_INFO_OFFSET {
+                        assert!(result.is_err(), "{}, byte {}", name, i);
+                    }
+                }
+            }
+        }
+    }
+
+    // Payload length and crc offset, see the file layout
+    const SDF_FILE_PAYLOAD_INFO_OFFSET: usize = SDF_FILE_HEADER_SIZE - 12;
+
+    #[test]
+    fn huge_dimensions() {
+        let sdf = test_sdf();
+        for (name, bytes) in versioned_files(&sdf) {
+            let mut bytes = bytes.clone();
+            // dim.0 (after magic, version, encoding, voxel type, predictor)
+            bytes[20..24].copy_from_slice(&u32::MAX.to_le_bytes());
+            bytes[24..28].copy_from_slice(&u32::MAX.to_le_bytes());
+            bytes[28..32].copy_from_slice(&u32::MAX.to_le_bytes());
+            assert!(
+                matches!(parse_sdf_file(&bytes), Err(SdfError::DimensionOverflow(_))),
+                "{}",
+                name
+            );
+        }
+
+        let mut bytes = legacy_bytes(&sdf, false);
+        bytes[0..4].copy_from_slice(&0x10000u32.to_le_bytes());
+        bytes[4..8].copy_from_slice(&0x10000u32.to_le_bytes());
+        // Reserve may fail, otherwise the voxels run out
+        assert!(matches!(
+            parse_sdf(&bytes),
+            Err(SdfError::Truncated { .. }) | Err(SdfError::DimensionOverflow(_))
+        ));
+    }
+
+    #[test]
+    fn load_any_formats() {
+        let sdf = test_sdf();
+        let files = [
+            ("legacy_raw", legacy_bytes(&sdf, false)),
+            ("legacy_zlib", legacy_bytes(&sdf, true)),
+            ("versioned", versioned_bytes::<u16>(&sdf, SdfEncoding::Zlib)),
+        ];
+        for (name, bytes) in files.iter() {
+            let filename = temp_file(name, bytes);
+            let loaded = load_any_sdf(&filename);
+            let truncated = temp_file(name, &bytes[..bytes.len() - 1]);
+            let loaded_truncated = load_any_sdf(&truncated);
+            std::fs::remove_file(&filename).unwrap();
+            assert_eq!(loaded.unwrap().voxels, sdf.voxels, "{}", name);
+            assert!(loaded_truncated.is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn load_any_deflate_ratio() {
+        let header = |dim: u32| {
+            let mut storer = StorerVec::new();
+            store_sdf_header(
+                &mut storer,
+                &SdfHeader {
+                    dim: (dim, dim, dim),
+                    box_min: (0.0, 0.0, 0.0),
+                    dx: 1.0,
+                },
+            );
+            compress_to_vec(&storer.v[..], 5)
+        };
+
+        // Small legacy zlib file claiming 2 GB of voxels: not a SDF file, nothing allocated
+        let filename = temp_file("deflate_ratio_huge", &header(1024));
+        let huge = load_any_sdf(&filename);
+        // Plausible size: loaded as legacy zlib, fails on the missing voxels
+        let filename_small = temp_file("deflate_ratio_small", &header(10));
+        let small = load_any_sdf(&filename_small);
+        // Garbage
+        let filename_garbage = temp_file("deflate_ratio_garbage", &[0xffu8; 100]);
+        let garbage = load_any_sdf(&filename_garbage);
+        for filename in [filename, filename_small, filename_garbage].iter() {
+            std::fs::remove_file(filename).unwrap();
+        }
+
+        assert!(matches!(huge, Err(SdfError::UnknownFormat)));
+        assert!(matches!(small, Err(err) if is_truncation(&err)));
+        assert!(matches!(garbage, Err(SdfError::UnknownFormat)));
+    }
+
+    // Converts to U and back: Some(max world distance difference) if every voxel is restored
+    fn convert_round_trip<T: SdfVoxel, U: SdfVoxel>(
+        header: SdfHeader,
+        voxels: &[T],
+    ) -> Option<f32> {
+        let sdf = Sdf {
+            header,
+            voxels: voxels.to_vec(),
+        };
+        let converted = sdf.convert::<U>();
+        if converted.convert::<T>().voxels != sdf.voxels {
+            return None;
+        }
+        let error = sdf
+            .voxels
+            .iter()
+            .zip(converted.voxels.iter())
+            .map(|(&a, &b)| (sdf.to_world_distance(a) - converted.to_world_distance(b)).abs())
+            .fold(0.0, f32::max);
+        Some(error)
+    }
+
+    fn check_conversions<T: SdfVoxel>(header: SdfHeader, voxels: &[T]) {
+        // Restored voxels, and the distances only differ by the f32 rounding
+        let tolerance = header.diagonal_length() * 1e-6;
+        let check = |other: VoxelType, result: Option<f32>| {
+            let lossless = T::VOXEL_TYPE.converts_losslessly_to(other);
+            let exact = result.is_some_and(|error| error <= tolerance);
+            assert_eq!(exact, lossless, "{:?} to {:?}", T::VOXEL_TYPE, other);
+        };
+        check(
+            VoxelType::U8Unorm,
+            convert_round_trip::<T, u8>(header, voxels),
+        );
+        check(
+            VoxelType::U16Unorm,
+            convert_round_trip::<T, u16>(header, voxels),
+        );
+        check(VoxelType::F16, convert_round_trip::<T, F16>(header, voxels));
+        check(VoxelType::F32, convert_round_trip::<T, f32>(header, voxels));
+    }
+
+    #[test]
+    fn lossless_conversions() {
+        let headers = [
+            SdfHeader {
+                dim: (37, 41, 43),
+                box_min: (0.0, 0.0, 0.0),
+                dx: 0.05,
+            },
+            SdfHeader {
+                dim: (1000, 3, 7),
+                box_min: (0.0, 0.0, 0.0),
+                dx: 1.7,
+            },
+        ];
+        for &header in headers.iter() {
+            let u8_voxels: Vec<u8> = (0..=255).collect();
+            check_conversions(header, &u8_voxels);
+            let u16_voxels: Vec<u16> = (0..=65535).collect();
+            check_conversions(header, &u16_voxels);
+            let f16_voxels: Vec<F16> = (0..=65535u16)
+                .map(F16)
+                .filter(|v| !v.to_f32().is_nan())
+                .collect();
+            check_conversions(header, &f16_voxels);
+            let f32_voxels: Vec<f32> = (-1000..1000).map(|i| i as f32 * 0.0123456).collect();
+            check_conversions(header, &f32_voxels);
+        }
+    }
+}
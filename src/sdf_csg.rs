@@ -0,0 +1,357 @@
+// Boolean and smooth CSG operations between two SDF volumes. The inputs are resampled
+// (world space trilinear queries, see sdf_sampling.rs) to a new grid covering the combined
+// bounding box with the finer voxel size of the two, so box_min, dx and dims may differ.
+
+use crate::error::*;
+use crate::minivector::*;
+use crate::sdf::*;
+use crate::sdf_sampling::*;
+use crate::sdf_voxel::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CsgOperation {
+    Union,
+    Intersection,
+    // a minus b
+    Subtraction,
+    // Polynomial smooth min/max, blend radius in world units
+    SmoothUnion(f32),
+    SmoothIntersection(f32),
+    SmoothSubtraction(f32),
+}
+
+// https://iquilezles.org/articles/smin/
+pub fn smooth_min(a: f32, b: f32, k: f32) -> f32 {
+    if k <= 0.0 {
+        return a.min(b);
+    }
+    let h = (k - (a - b).abs()).max(0.0) / k;
+    a.min(b) - h * h * k * 0.25
+}
+
+pub fn smooth_max(a: f32, b: f32, k: f32) -> f32 {
+    -smooth_min(-a, -b, k)
+}
+
+impl CsgOperation {
+    pub fn name(self) -> &'static str {
+        match self {
+            CsgOperation::Union => "union",
+            CsgOperation::Intersection => "intersection",
+            CsgOperation::Subtraction => "subtraction",
+            CsgOperation::SmoothUnion(_) => "smooth_union",
+            CsgOperation::SmoothIntersection(_) => "smooth_intersection",
+            CsgOperation::SmoothSubtraction(_) => "smooth_subtraction",
+        }
+    }
+
+    // Smooth operations take the blend radius
+    pub fn from_name(name: &str, k: f32) -> Option<CsgOperation> {
+        match name {
+            "union" => Some(CsgOperation::Union),
+            "intersection" => Some(CsgOperation::Intersection),
+            "subtraction" => Some(CsgOperation::Subtraction),
+            "smooth_union" => Some(CsgOperation::SmoothUnion(k)),
+            "smooth_intersection" => Some(CsgOperation::SmoothIntersection(k)),
+            "smooth_subtraction" => Some(CsgOperation::SmoothSubtraction(k)),
+            _ => None,
+        }
+    }
+
+    pub fn apply(self, a: f32, b: f32) -> f32 {
+        match self {
+            CsgOperation::Union => a.min(b),
+            CsgOperation::Intersection => a.max(b),
+            CsgOperation::Subtraction => a.max(-b),
+            CsgOperation::SmoothUnion(k) => smooth_min(a, b, k),
+            CsgOperation::SmoothIntersection(k) => smooth_max(a, b, k),
+            CsgOperation::SmoothSubtraction(k) => smooth_max(a, -b, k),
+        }
+    }
+}
+
+// Union of the boxes for unions, the first volume for subtractions and the overlap for
+// intersections (the first volume if the boxes don't overlap). Blend radius is added to
+// the smooth union box.
+fn csg_bounds<T: SdfVoxel>(a: &Sdf<T>, b: &Sdf<T>, operation: CsgOperation) -> (Vec3, Vec3) {
+    let (a_min, a_max) = (a.box_min(), a.box_max());
+    let (b_min, b_max) = (b.box_min(), b.box_max());
+
+    let min = |p: Vec3, q: Vec3| Vec3 {
+        x: p.x.min(q.x),
+        y: p.y.min(q.y),
+        z: p.z.min(q.z),
+    };
+    let max = |p: Vec3, q: Vec3| Vec3 {
+        x: p.x.max(q.x),
+        y: p.y.max(q.y),
+        z: p.z.max(q.z),
+    };
+
+    match operation {
+        CsgOperation::Union => (min(a_min, b_min), max(a_max, b_max)),
+        CsgOperation::SmoothUnion(k) => {
+            let border = Vec3::from_scalar(k.max(0.0));
+            (min(a_min, b_min) - border, max(a_max, b_max) + border)
+        }
+        CsgOperation::Subtraction | CsgOperation::SmoothSubtraction(_) => (a_min, a_max),
+        CsgOperation::Intersection | CsgOperation::SmoothIntersection(_) => {
+            let (box_min, box_max) = (max(a_min, b_min), min(a_max, b_max));
+            if box_min.x > box_max.x || box_min.y > box_max.y || box_min.z > box_max.z {
+                (a_min, a_max)
+            } else {
+                (box_min, box_max)
+            }
+        }
+    }
+}
+
+pub fn csg_sdf<T: SdfVoxel + Send + Sync>(
+    a: &Sdf<T>,
+    b: &Sdf<T>,
+    operation: CsgOperation,
+) -> SdfResult<Sdf<T>> {
+    let dx = a.header.dx.min(b.header.dx);
+    let (box_min, box_max) = csg_bounds(a, b, operation);
+
+    // Voxels are grid points, the last one at or beyond box_max
+    let extent = box_max - box_min;
+    let axis_dim = |e: f32| (e / dx - 0.001).ceil().max(0.0) as u32 + 1;
+    let header = SdfHeader {
+        dim: (axis_dim(extent.x), axis_dim(extent.y), axis_dim(extent.z)),
+        box_min: (box_min.x, box_min.y, box_min.z),
+        dx,
+    };
+
+    sample_sdf(header, |p| {
+        operation.apply(a.distance_at(p), b.distance_at(p))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CENTER_A: Vec3 = Vec3 {
+        x: -0.2,
+        y: 0.0,
+        z: 0.0,
+    };
+    const RADIUS_A: f32 = 0.5;
+    const CENTER_B: Vec3 = Vec3 {
+        x: 0.25,
+        y: 0.1,
+        z: 0.05,
+    };
+    const RADIUS_B: f32 = 0.4;
+
+    // Operation on analytic distances (a, b) and (a, b, k)
+    type Reference = fn(f32, f32) -> f32;
+    type SmoothReference = fn(f32, f32, f32) -> f32;
+
+    fn sphere_a(p: Vec3) -> f32 {
+        (p - CENTER_A).length() - RADIUS_A
+    }
+
+    fn sphere_b(p: Vec3) -> f32 {
+        (p - CENTER_B).length() - RADIUS_B
+    }
+
+    // Overlapping spheres with different voxel sizes and grid origins
+    fn spheres() -> (Sdf<f32>, Sdf<f32>) {
+        let a = SdfHeader {
+            dim: (31, 31, 31),
+            box_min: (-0.8, -0.6, -0.6),
+            dx: 0.04,
+        };
+        let b = SdfHeader {
+            dim: (19, 19, 18),
+            box_min: (-0.3, -0.42, -0.49),
+            dx: 0.06,
+        };
+        (
+            sample_sdf(a, sphere_a).unwrap(),
+            sample_sdf(b, sphere_b).unwrap(),
+        )
+    }
+
+    fn inside_box<T: SdfVoxel>(sdf: &Sdf<T>, p: Vec3) -> bool {
+        let (min, max) = (sdf.box_min(), sdf.box_max());
+        let eps = 1e-4;
+        p.x >= min.x - eps
+            && p.y >= min.y - eps
+            && p.z >= min.z - eps
+            && p.x <= max.x + eps
+            && p.y <= max.y + eps
+            && p.z <= max.z + eps
+    }
+
+    // Calls f for the result voxels inside both source volumes, returns the voxel count
+    fn for_each_shared_voxel(
+        a: &Sdf<f32>,
+        b: &Sdf<f32>,
+        result: &Sdf<f32>,
+        mut f: impl FnMut(Vec3, f32),
+    ) -> usize {
+        let dim = result.header.dim;
+        let mut count = 0;
+        for z in 0..dim.2 {
+            for y in 0..dim.1 {
+                for x in 0..dim.0 {
+                    let p = result.voxel_position(x, y, z);
+                    if inside_box(a, p) && inside_box(b, p) {
+                        let index = x + (y + z * dim.1) * dim.0;
+                        f(p, result.voxels[index as usize]);
+                        count += 1;
+                    }
+                }
+            }
+        }
+        count
+    }
+
+    #[test]
+    fn sharp_operations() {
+        let (a, b) = spheres();
+        let tolerance = b.header.dx;
+
+        let reference: [(CsgOperation, Reference); 3] = [
+            (CsgOperation::Union, |a, b| a.min(b)),
+            (CsgOperation::Intersection, |a, b| a.max(b)),
+            (CsgOperation::Subtraction, |a, b| a.max(-b)),
+        ];
+        for &(operation, expected) in reference.iter() {
+            let result = csg_sdf(&a, &b, operation).unwrap();
+            assert_eq!(result.header.dx, a.header.dx);
+
+            let (mut inside, mut outside) = (0, 0);
+            let count = for_each_shared_voxel(&a, &b, &result, |p, d| {
+                let e = expected(sphere_a(p), sphere_b(p));
+                assert!(
+                    (d - e).abs() <= tolerance,
+                    "{} {:?}: {} expected {}",
+                    operation.name(),
+                    p,
+                    d,
+                    e
+                );
+                if e < -tolerance {
+                    inside += 1;
+                } else if e > tolerance {
+                    outside += 1;
+                }
+            });
+            assert!(
+                count > 1000 && inside > 0 && outside > 0,
+                "{}",
+                operation.name()
+            );
+        }
+
+        // Result boxes: union covers both, subtraction keeps a, intersection the overlap
+        let union = csg_sdf(&a, &b, CsgOperation::Union).unwrap();
+        assert!(inside_box(&union, a.box_min()) && inside_box(&union, b.box_max()));
+        let subtraction = csg_sdf(&a, &b, CsgOperation::Subtraction).unwrap();
+        assert_eq!(subtraction.header.box_min, a.header.box_min);
+        assert_eq!(subtraction.header.dim, a.header.dim);
+        let intersection = csg_sdf(&a, &b, CsgOperation::Intersection).unwrap();
+        assert_eq!(intersection.header.box_min, (-0.3, -0.42, -0.49));
+    }
+
+    #[test]
+    fn smooth_operations() {
+        let (a, b) = spheres();
+        let tolerance = b.header.dx;
+        let k = 0.4;
+
+        let reference: [(CsgOperation, SmoothReference); 3] = [
+            (CsgOperation::SmoothUnion(k), smooth_min),
+            (CsgOperation::SmoothIntersection(k), smooth_max),
+            (CsgOperation::SmoothSubtraction(k), |a, b, k| {
+                smooth_max(a, -b, k)
+            }),
+        ];
+        for &(operation, expected) in reference.iter() {
+            let result = csg_sdf(&a, &b, operation).unwrap();
+            let mut blended = 0;
+            for_each_shared_voxel(&a, &b, &result, |p, d| {
+                let (da, db) = (sphere_a(p), sphere_b(p));
+                let e = expected(da, db, k);
+                assert!(
+                    (d - e).abs() <= tolerance,
+                    "{} {:?}: {} expected {}",
+                    operation.name(),
+                    p,
+                    d,
+                    e
+                );
+                if (e - expected(da, db, 0.0)).abs() > tolerance {
+                    blended += 1;
+                }
+            });
+            assert!(blended > 0, "{} has no blend region", operation.name());
+        }
+
+        assert_eq!(smooth_min(0.3, -0.1, 0.0), -0.1);
+        assert_eq!(smooth_min(0.3, -0.1, 0.1), -0.1);
+        assert!((smooth_min(0.1, 0.1, 0.2) - 0.05).abs() < 1e-6);
+        assert!((smooth_max(0.1, 0.1, 0.2) - 0.15).abs() < 1e-6);
+    }
+
+    #[test]
+    fn smooth_converges_to_sharp() {
+        let (a, b) = spheres();
+        let dx = a.header.dx;
+
+        let pairs = [
+            (
+                CsgOperation::Union,
+                CsgOperation::SmoothUnion as fn(f32) -> CsgOperation,
+            ),
+            (CsgOperation::Intersection, CsgOperation::SmoothIntersection),
+            (CsgOperation::Subtraction, CsgOperation::SmoothSubtraction),
+        ];
+        for &(sharp_operation, smooth_operation) in pairs.iter() {
+            let sharp = csg_sdf(&a, &b, sharp_operation).unwrap();
+            let dim = sharp.header.dim;
+
+            // Blend radii are whole voxels, so the smooth union box stays on the same grid
+            let mut prev_error = f32::MAX;
+            for &voxels in [4, 2, 1, 0].iter() {
+                let k = voxels as f32 * dx;
+                let smooth = csg_sdf(&a, &b, smooth_operation(k)).unwrap();
+                let offset = match sharp_operation {
+                    CsgOperation::Union => voxels,
+                    _ => 0,
+                };
+                let smooth_dim = smooth.header.dim;
+                assert_eq!(smooth_dim.0, dim.0 + offset * 2);
+
+                let mut error = 0.0f32;
+                for z in 0..dim.2 {
+                    for y in 0..dim.1 {
+                        for x in 0..dim.0 {
+                            let i = x + (y + z * dim.1) * dim.0;
+                            let (sx, sy, sz) = (x + offset, y + offset, z + offset);
+                            let j = sx + (sy + sz * smooth_dim.1) * smooth_dim.0;
+                            let d = smooth.voxels[j as usize] - sharp.voxels[i as usize];
+                            error = error.max(d.abs());
+                        }
+                    }
+                }
+
+                // Polynomial smooth min deviates at most k / 4
+                assert!(
+                    error <= k * 0.25 + 1e-4,
+                    "{} k {}: {}",
+                    sharp_operation.name(),
+                    k,
+                    error
+                );
+                assert!(error <= prev_error);
+                prev_error = error;
+            }
+            assert!(prev_error < 1e-4);
+        }
+    }
+}
@@ -0,0 +1,377 @@
+// Volume statistics and resizing (crop, pad and resample). UNORM distances are relative to
+// the volume diagonal, so the voxels are re-encoded when the dimensions change. box_min is
+// adjusted so world space placement is unchanged.
+
+use crate::error::*;
+use crate::minivector::*;
+use crate::sdf::*;
+use crate::sdf_sampling::*;
+use crate::sdf_voxel::*;
+
+pub struct SdfStats {
+    pub min_distance: f32,
+    pub max_distance: f32,
+    // Negative distance
+    pub inside_voxels: usize,
+    // Sign change to a 6-neighbor (the zero crossing is next to the voxel)
+    pub surface_voxels: usize,
+}
+
+fn surface_voxels<T: SdfVoxel>(sdf: &Sdf<T>) -> Vec<bool> {
+    let dim = sdf.header.dim;
+    let (dim_x, dim_y, dim_z) = (dim.0 as usize, dim.1 as usize, dim.2 as usize);
+    let inside: Vec<bool> = sdf
+        .voxels
+        .iter()
+        .map(|&v| v.to_world_distance(&sdf.header) < 0.0)
+        .collect();
+
+    let mut surface = vec![false; inside.len()];
+    let mut mark = |a: usize, b: usize| {
+        if inside[a] != inside[b] {
+            surface[a] = true;
+            surface[b] = true;
+        }
+    };
+    for z in 0..dim_z {
+        for y in 0..dim_y {
+            for x in 0..dim_x {
+                let i = x + (y + z * dim_y) * dim_x;
+                if x + 1 < dim_x {
+                    mark(i, i + 1);
+                }
+                if y + 1 < dim_y {
+                    mark(i, i + dim_x);
+                }
+                if z + 1 < dim_z {
+                    mark(i, i + dim_x * dim_y);
+                }
+            }
+        }
+    }
+    surface
+}
+
+pub fn sdf_stats<T: SdfVoxel>(sdf: &Sdf<T>) -> SdfStats {
+    let mut min_distance = f32::MAX;
+    let mut max_distance = f32::MIN;
+    let mut inside_voxels = 0;
+    for &v in sdf.voxels.iter() {
+        let d = v.to_world_distance(&sdf.header);
+        min_distance = min_distance.min(d);
+        max_distance = max_distance.max(d);
+        if d < 0.0 {
+            inside_voxels += 1;
+        }
+    }
+
+    SdfStats {
+        min_distance,
+        max_distance,
+        inside_voxels,
+        surface_voxels: surface_voxels(sdf).iter().filter(|&&s| s).count(),
+    }
+}
+
+// Inclusive voxel range: first, last
+pub type VoxelBounds = ((u32, u32, u32), (u32, u32, u32));
+
+// None if the volume has no zero crossing
+pub fn surface_bounds<T: SdfVoxel>(sdf: &Sdf<T>) -> Option<VoxelBounds> {
+    let dim = sdf.header.dim;
+    let mut bounds: Option<VoxelBounds> = None;
+    for (i, _) in surface_voxels(sdf).iter().enumerate().filter(|(_, &s)| s) {
+        let x = (i % dim.0 as usize) as u32;
+        let y = (i / dim.0 as usize % dim.1 as usize) as u32;
+        let z = (i / (dim.0 as usize * dim.1 as usize)) as u32;
+        bounds = Some(match bounds {
+            Some((min, max)) => (
+                (min.0.min(x), min.1.min(y), min.2.min(z)),
+                (max.0.max(x), max.1.max(y), max.2.max(z)),
+            ),
+            None => ((x, y, z), (x, y, z)),
+        });
+    }
+    bounds
+}
+
+// New volume with the same voxel size. origin = position of the first voxel in the source
+// voxel grid (negative = padding). Voxels outside of the source are extrapolated
+// (SdfBorder::Extend), voxels inside are copied.
+pub fn resize_sdf<T: SdfVoxel>(
+    sdf: &Sdf<T>,
+    origin: (i32, i32, i32),
+    dim: (u32, u32, u32),
+) -> SdfResult<Sdf<T>> {
+    let source = &sdf.header;
+    let header = SdfHeader {
+        dim,
+        box_min: (
+            source.box_min.0 + origin.0 as f32 * source.dx,
+            source.box_min.1 + origin.1 as f32 * source.dx,
+            source.box_min.2 + origin.2 as f32 * source.dx,
+        ),
+        dx: source.dx,
+    };
+
+    let box_min = Vec3 {
+        x: header.box_min.0,
+        y: header.box_min.1,
+        z: header.box_min.2,
+    };
+
+    let mut voxels = Vec::with_capacity(voxel_count(dim)?);
+    for z in 0..dim.2 {
+        for y in 0..dim.1 {
+            for x in 0..dim.0 {
+                let sx = x as i64 + origin.0 as i64;
+                let sy = y as i64 + origin.1 as i64;
+                let sz = z as i64 + origin.2 as i64;
+                let inside = (0..source.dim.0 as i64).contains(&sx)
+                    && (0..source.dim.1 as i64).contains(&sy)
+                    && (0..source.dim.2 as i64).contains(&sz);
+
+                let d = if inside {
+                    let index = sx + (sy + sz * source.dim.1 as i64) * source.dim.0 as i64;
+                    sdf.voxels[index as usize].to_distance(source)
+                } else {
+                    let grid = Vec3 {
+                        x: x as f32,
+                        y: y as f32,
+                        z: z as f32,
+                    };
+                    sdf.distance_at(box_min + grid * header.dx) as f64
+                };
+                voxels.push(T::from_distance(d, &header));
+            }
+        }
+    }
+
+    Ok(Sdf { header, voxels })
+}
+
+// Tight bounds of the surface plus a margin (voxels), clamped to the volume. None if the
+// volume has no surface.
+pub fn crop_sdf<T: SdfVoxel>(sdf: &Sdf<T>, margin: u32) -> Option<SdfResult<Sdf<T>>> {
+    let (min, max) = surface_bounds(sdf)?;
+    let dim = sdf.header.dim;
+    let first = (
+        min.0.saturating_sub(margin),
+        min.1.saturating_sub(margin),
+        min.2.saturating_sub(margin),
+    );
+    let last = (
+        max.0.saturating_add(margin).min(dim.0 - 1),
+        max.1.saturating_add(margin).min(dim.1 - 1),
+        max.2.saturating_add(margin).min(dim.2 - 1),
+    );
+    Some(resize_sdf(
+        sdf,
+        (first.0 as i32, first.1 as i32, first.2 as i32),
+        (
+            last.0 - first.0 + 1,
+            last.1 - first.1 + 1,
+            last.2 - first.2 + 1,
+        ),
+    ))
+}
+
+// Centered in the new dimensions. Odd padding puts the extra voxel at the end.
+pub fn pad_sdf<T: SdfVoxel>(sdf: &Sdf<T>, dim: (u32, u32, u32)) -> SdfResult<Sdf<T>> {
+    let source = sdf.header.dim;
+    let origin = |from: u32, to: u32| -((to as i64 - from as i64) / 2) as i32;
+    resize_sdf(
+        sdf,
+        (
+            origin(source.0, dim.0),
+            origin(source.1, dim.1),
+            origin(source.2, dim.2),
+        ),
+        dim,
+    )
+}
+
+// Same bounds (first voxel at box_min, the last one at or beyond the old last voxel) with a
+// new voxel size. Trilinear point sampling: downsampling by large factors aliases thin
+// features, use sdf_mips.rs for 2x steps. The output voxel type can differ (f32 keeps the
+// sampled distances exactly).
+pub fn resample_sdf<T, U>(sdf: &Sdf<T>, dx: f32) -> SdfResult<Sdf<U>>
+where
+    T: SdfVoxel + Sync,
+    U: SdfVoxel + Send,
+{
+    let extent = sdf.box_max() - sdf.box_min();
+    let axis_dim = |e: f32| (e / dx - 0.001).ceil().max(0.0) as u32 + 1;
+    let header = SdfHeader {
+        dim: (axis_dim(extent.x), axis_dim(extent.y), axis_dim(extent.z)),
+        box_min: sdf.header.box_min,
+        dx,
+    };
+    sample_sdf(header, |p| sdf.distance_at(p))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPHERE_RADIUS: f32 = 0.5;
+
+    // Plane x = box_min.x + 0.325: voxel columns 0-3 inside, 3 and 4 next to the surface
+    fn plane_sdf() -> Sdf<f32> {
+        let header = SdfHeader {
+            dim: (8, 4, 3),
+            box_min: (-0.5, 1.0, 2.0),
+            dx: 0.1,
+        };
+        sample_sdf(header, |p| p.x + 0.5 - 0.325).unwrap()
+    }
+
+    fn sphere_distance(p: Vec3) -> f32 {
+        p.length() - SPHERE_RADIUS
+    }
+
+    // Sphere with empty space around it, the volume is not centered
+    fn sphere_sdf<T: SdfVoxel + Send>() -> Sdf<T> {
+        let header = SdfHeader {
+            dim: (31, 29, 27),
+            box_min: (-0.75, -0.7, -0.6),
+            dx: 0.05,
+        };
+        sample_sdf(header, sphere_distance).unwrap()
+    }
+
+    fn for_each_voxel<T: SdfVoxel>(sdf: &Sdf<T>, mut f: impl FnMut((u32, u32, u32), Vec3, f32)) {
+        let dim = sdf.header.dim;
+        let mut voxels = sdf.voxels.iter();
+        for z in 0..dim.2 {
+            for y in 0..dim.1 {
+                for x in 0..dim.0 {
+                    let d = voxels.next().unwrap().to_world_distance(&sdf.header);
+                    f((x, y, z), sdf.voxel_position(x, y, z), d);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn plane_stats() {
+        let sdf = plane_sdf();
+        let stats = sdf_stats(&sdf);
+        assert!((stats.min_distance + 0.325).abs() < 1e-6);
+        assert!((stats.max_distance - 0.375).abs() < 1e-6);
+        assert_eq!(stats.inside_voxels, 4 * 4 * 3);
+        assert_eq!(stats.surface_voxels, 2 * 4 * 3);
+        assert_eq!(surface_bounds(&sdf), Some(((3, 0, 0), (4, 3, 2))));
+
+        let outside: Sdf<f32> = sample_sdf(sdf.header, |_| 1.0).unwrap();
+        let stats = sdf_stats(&outside);
+        assert_eq!((stats.inside_voxels, stats.surface_voxels), (0, 0));
+        assert!(surface_bounds(&outside).is_none());
+        assert!(crop_sdf(&outside, 1).is_none());
+    }
+
+    #[test]
+    fn crop_keeps_world_positions() {
+        let sdf = plane_sdf();
+        let cropped = crop_sdf(&sdf, 1).unwrap().unwrap();
+        assert_eq!(cropped.header.dim, (4, 4, 3));
+        assert_eq!(cropped.header.dx, sdf.header.dx);
+        assert!((cropped.header.box_min.0 - (-0.3)).abs() < 1e-6);
+        assert_eq!(cropped.header.box_min.1, sdf.header.box_min.1);
+        assert_eq!(cropped.header.box_min.2, sdf.header.box_min.2);
+
+        for_each_voxel(&cropped, |(x, y, z), p, d| {
+            let source = sdf.voxel_position(x + 2, y, z);
+            assert!(
+                (p - source).length() < 1e-5,
+                "{:?} moved to {:?}",
+                source,
+                p
+            );
+            let dim = sdf.header.dim;
+            let index = x + 2 + (y + z * dim.1) * dim.0;
+            assert_eq!(d, sdf.voxels[index as usize]);
+        });
+
+        // UNORM voxels are re-encoded for the smaller diagonal
+        let sdf = sphere_sdf::<u16>();
+        let cropped = crop_sdf(&sdf, 2).unwrap().unwrap();
+        assert!(cropped.header.dim.0 < sdf.header.dim.0);
+        let tolerance = sdf.header.diagonal_length() / 32768.0;
+        for_each_voxel(&cropped, |_, p, d| {
+            assert!(
+                (d - sdf.distance_at(p)).abs() <= tolerance,
+                "{:?}: {}",
+                p,
+                d
+            );
+            assert!((d - sphere_distance(p)).abs() <= tolerance * 2.0);
+        });
+    }
+
+    #[test]
+    fn pad_keeps_outside_positive() {
+        let sdf = sphere_sdf::<f32>();
+        let padded = pad_sdf(&sdf, (40, 29, 30)).unwrap();
+
+        // (40 - 31) / 2 = 4 voxels before x, odd padding puts the extra z voxel at the end
+        let dx = sdf.header.dx;
+        let box_min = padded.header.box_min;
+        assert!((box_min.0 - (sdf.header.box_min.0 - 4.0 * dx)).abs() < 1e-6);
+        assert_eq!(box_min.1, sdf.header.box_min.1);
+        assert!((box_min.2 - (sdf.header.box_min.2 - dx)).abs() < 1e-6);
+
+        let (source_min, source_max) = (sdf.box_min(), sdf.box_max());
+        let mut extrapolated = 0;
+        for_each_voxel(&padded, |_, p, d| {
+            let eps = 1e-4;
+            let inside_source = p.x >= source_min.x - eps
+                && p.y >= source_min.y - eps
+                && p.z >= source_min.z - eps
+                && p.x <= source_max.x + eps
+                && p.y <= source_max.y + eps
+                && p.z <= source_max.z + eps;
+            if inside_source {
+                assert!((d - sphere_distance(p)).abs() < 1e-5, "{:?}: {}", p, d);
+            } else {
+                // Extrapolated distances never underestimate the distance to the surface
+                assert!(d > 0.0, "{:?}: {}", p, d);
+                assert!(d >= sphere_distance(p) - 1e-5, "{:?}: {}", p, d);
+                extrapolated += 1;
+            }
+        });
+        assert!(extrapolated > 0);
+
+        // Padding to the same size is a copy
+        let same = pad_sdf(&sdf, sdf.header.dim).unwrap();
+        assert_eq!(same.header.box_min, sdf.header.box_min);
+        assert_eq!(same.voxels, sdf.voxels);
+    }
+
+    #[test]
+    fn resample_preserves_distances() {
+        let sdf = sphere_sdf::<u16>();
+        for &dx in [0.08, 0.03].iter() {
+            let resampled: Sdf<f32> = resample_sdf(&sdf, dx).unwrap();
+            assert_eq!(resampled.header.box_min, sdf.header.box_min);
+            assert_eq!(resampled.header.dx, dx);
+
+            let (old_max, new_max) = (sdf.box_max(), resampled.box_max());
+            assert!(new_max.x >= old_max.x - 1e-4 && new_max.x < old_max.x + dx);
+            assert!(new_max.y >= old_max.y - 1e-4 && new_max.y < old_max.y + dx);
+            assert!(new_max.z >= old_max.z - 1e-4 && new_max.z < old_max.z + dx);
+
+            for_each_voxel(&resampled, |_, p, d| {
+                let expected = sphere_distance(p);
+                assert!(
+                    (d - expected).abs() <= dx,
+                    "dx {} {:?}: {} expected {}",
+                    dx,
+                    p,
+                    d,
+                    expected
+                );
+            });
+        }
+    }
+}
@@ -0,0 +1,464 @@
+// Mip chain of an SDF volume. Level 0 is padded (edge voxels repeated) to dimensions that
+// halve evenly down to the last mip, so every mip voxel covers 2x2x2 voxels of the level
+// above, dx doubles per level and the dimensions match Vulkan image mips. Filters work on
+// world space distances, each level is re-encoded with its own header. All levels keep the
+// volume diagonal of level 0, so UNORM distances have the same scale in every mip, like the
+// shaders expect. box_min is moved to the first mip voxel (voxels are grid points in world
+// space queries, see sdf_sampling.rs).
+
+use crate::sdf::*;
+use crate::sdf_voxel::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MipFilter {
+    // Overlap weighted average of the footprint
+    Box,
+    // Minimum of the footprint
+    Min,
+    // Lower bound of the distance: the trilinearly filtered mip never exceeds the distance
+    // of a 1-Lipschitz source field, so sphere tracing the mip can't overshoot the surface
+    Conservative,
+}
+
+pub const DEFAULT_MIP_FILTER: MipFilter = MipFilter::Conservative;
+
+pub const MIP_FILTERS: [MipFilter; 3] = [MipFilter::Box, MipFilter::Min, MipFilter::Conservative];
+
+impl MipFilter {
+    pub fn name(self) -> &'static str {
+        match self {
+            MipFilter::Box => "box",
+            MipFilter::Min => "min",
+            MipFilter::Conservative => "conservative",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<MipFilter> {
+        MIP_FILTERS
+            .iter()
+            .copied()
+            .find(|filter| filter.name().eq_ignore_ascii_case(name))
+    }
+}
+
+pub struct SdfLevel<T = u16> {
+    pub sdf: Sdf<T>,
+    // Voxel offset of the level in the whole chain (level 0 = 0)
+    pub offset: u32,
+}
+
+// Vulkan mip dimensions
+pub fn mip_dimensions(dim: (u32, u32, u32)) -> (u32, u32, u32) {
+    ((dim.0 / 2).max(1), (dim.1 / 2).max(1), (dim.2 / 2).max(1))
+}
+
+// Level 0 dimensions for a mip chain: multiples of 2^(mips below level 0). Halving stops
+// at levels - 1 mips or before a dimension drops below 1 (like build_sdf_mips).
+pub fn padded_mip_dimensions(dim: (u32, u32, u32), levels: u32) -> (u32, u32, u32) {
+    let mut multiple = 1u32;
+    for _ in 1..levels {
+        let mip = |d: u32| d.div_ceil(multiple);
+        if mip(dim.0) < 2 || mip(dim.1) < 2 || mip(dim.2) < 2 {
+            break;
+        }
+        multiple *= 2;
+    }
+    let pad = |d: u32| d.div_ceil(multiple) * multiple;
+    (pad(dim.0), pad(dim.1), pad(dim.2))
+}
+
+// Source voxel of a padded voxel index (edge voxels repeated)
+fn padded_source_index(index: usize, padded: (u32, u32, u32), dim: (u32, u32, u32)) -> usize {
+    let padded_x = padded.0 as usize;
+    let padded_xy = padded_x * padded.1 as usize;
+    let x = (index % padded_x).min(dim.0 as usize - 1);
+    let y = (index / padded_x % padded.1 as usize).min(dim.1 as usize - 1);
+    let z = (index / padded_xy).min(dim.2 as usize - 1);
+    x + (y + z * dim.1 as usize) * dim.0 as usize
+}
+
+// Pads at the end, voxels keep their world positions
+fn pad_level<T: SdfVoxel>(sdf: Sdf<T>, padded: (u32, u32, u32)) -> Sdf<T> {
+    let dim = sdf.header.dim;
+    if padded == dim || sdf.voxels.is_empty() {
+        return sdf;
+    }
+    let header = SdfHeader {
+        dim: padded,
+        ..sdf.header
+    };
+    let count = padded.0 as usize * padded.1 as usize * padded.2 as usize;
+    // Re-encoded, the diagonal (UNORM scale) grows
+    let voxels = (0..count)
+        .map(|i| {
+            let d = sdf.voxels[padded_source_index(i, padded, dim)].to_distance(&sdf.header);
+            T::from_distance(d, &header)
+        })
+        .collect();
+    Sdf { header, voxels }
+}
+
+// Fine voxels overlapping mip voxel i: [i * ratio, (i + 1) * ratio), (index, overlap)
+fn axis_footprints(dim: u32, mip_dim: u32) -> Vec<Vec<(usize, f32)>> {
+    let ratio = dim as f64 / mip_dim as f64;
+    (0..mip_dim)
+        .map(|i| {
+            let begin = i as f64 * ratio;
+            let end = (i + 1) as f64 * ratio;
+            let first = begin.floor() as u32;
+            let last = (end.ceil() as u32).min(dim);
+            (first..last)
+                .map(|j| {
+                    let overlap = (end.min(j as f64 + 1.0) - begin.max(j as f64)) as f32;
+                    (j as usize, overlap)
+                })
+                .filter(|&(_, overlap)| overlap > 0.0)
+                .collect()
+        })
+        .collect()
+}
+
+// Distances at voxel centers. Lower bounds of the distance for the conservative filter
+// (the interpolation margin is only subtracted at encode time, so it doesn't accumulate).
+struct MipDistances {
+    dim: (u32, u32, u32),
+    box_min: (f32, f32, f32),
+    // World space voxel size per axis
+    spacing: (f32, f32, f32),
+    distances: Vec<f32>,
+}
+
+fn downsample_distances<F: Fn(usize) -> f32>(
+    distance: F,
+    dim: (u32, u32, u32),
+    box_min: (f32, f32, f32),
+    spacing: (f32, f32, f32),
+    filter: MipFilter,
+) -> MipDistances {
+    let mip_dim = mip_dimensions(dim);
+    let mip_spacing = (
+        spacing.0 * dim.0 as f32 / mip_dim.0 as f32,
+        spacing.1 * dim.1 as f32 / mip_dim.1 as f32,
+        spacing.2 * dim.2 as f32 / mip_dim.2 as f32,
+    );
+
+    let footprints_x = axis_footprints(dim.0, mip_dim.0);
+    let footprints_y = axis_footprints(dim.1, mip_dim.1);
+    let footprints_z = axis_footprints(dim.2, mip_dim.2);
+
+    // Offset between the voxel center and a fine voxel center along an axis
+    let center_offset = |i: u32, j: usize, fine: f32, mip: f32| -> f32 {
+        (j as f32 + 0.5) * fine - (i as f32 + 0.5) * mip
+    };
+
+    let stride_y = dim.0 as usize;
+    let stride_z = dim.0 as usize * dim.1 as usize;
+
+    let mut distances = Vec::with_capacity((mip_dim.0 * mip_dim.1 * mip_dim.2) as usize);
+    for z in 0..mip_dim.2 {
+        for y in 0..mip_dim.1 {
+            for x in 0..mip_dim.0 {
+                let mut sum = 0.0;
+                let mut weight_sum = 0.0;
+                let mut min = f32::MAX;
+                let mut lower_bound = f32::MIN;
+
+                for &(iz, wz) in footprints_z[z as usize].iter() {
+                    let oz = center_offset(z, iz, spacing.2, mip_spacing.2);
+                    for &(iy, wy) in footprints_y[y as usize].iter() {
+                        let oy = center_offset(y, iy, spacing.1, mip_spacing.1);
+                        for &(ix, wx) in footprints_x[x as usize].iter() {
+                            let d = distance(ix + iy * stride_y + iz * stride_z);
+                            match filter {
+                                MipFilter::Box => {
+                                    let w = wx * wy * wz;
+                                    sum += d * w;
+                                    weight_sum += w;
+                                }
+                                MipFilter::Min => min = min.min(d),
+                                MipFilter::Conservative => {
+                                    // 1-Lipschitz: d(center) >= d - |center - p|
+                                    let ox = center_offset(x, ix, spacing.0, mip_spacing.0);
+                                    let offset = (ox * ox + oy * oy + oz * oz).sqrt();
+                                    lower_bound = lower_bound.max(d - offset);
+                                }
+                            }
+                        }
+                    }
+                }
+
+                distances.push(match filter {
+                    MipFilter::Box => sum / weight_sum,
+                    MipFilter::Min => min,
+                    MipFilter::Conservative => lower_bound,
+                });
+            }
+        }
+    }
+
+    // The first mip voxel is centered on its footprint
+    let mip_box_min = (
+        box_min.0 + (mip_spacing.0 - spacing.0) * 0.5,
+        box_min.1 + (mip_spacing.1 - spacing.1) * 0.5,
+        box_min.2 + (mip_spacing.2 - spacing.2) * 0.5,
+    );
+
+    MipDistances {
+        dim: mip_dim,
+        box_min: mip_box_min,
+        spacing: mip_spacing,
+        distances,
+    }
+}
+
+// Even source dimensions: the spacing is uniform (2x the source dx)
+fn encode_level<T: SdfVoxel>(mip: &MipDistances, filter: MipFilter) -> Sdf<T> {
+    let header = SdfHeader {
+        dim: mip.dim,
+        box_min: mip.box_min,
+        dx: mip.spacing.0,
+    };
+
+    // Trilinear interpolation of a 1-Lipschitz field overshoots at most by half of the
+    // voxel diagonal (between the voxel centers)
+    let margin = match filter {
+        MipFilter::Conservative => {
+            let s = mip.spacing;
+            0.5 * (s.0 * s.0 + s.1 * s.1 + s.2 * s.2).sqrt()
+        }
+        _ => 0.0,
+    };
+
+    let voxels = mip
+        .distances
+        .iter()
+        .map(|&d| T::from_distance((d - margin) as f64, &header))
+        .collect();
+
+    Sdf { header, voxels }
+}
+
+fn level_spacing(header: &SdfHeader) -> (f32, f32, f32) {
+    (header.dx, header.dx, header.dx)
+}
+
+// Single mip level below sdf. Odd dimensions are padded by one voxel (edge voxels repeated),
+// the mip has (dim + 1) / 2 voxels per axis and dx is doubled.
+pub fn downsample_sdf<T: SdfVoxel>(sdf: &Sdf<T>, filter: MipFilter) -> Sdf<T> {
+    let header = &sdf.header;
+    let dim = header.dim;
+    if sdf.voxels.is_empty() {
+        return Sdf {
+            header: *header,
+            voxels: Vec::new(),
+        };
+    }
+    let padded = (
+        dim.0.div_ceil(2) * 2,
+        dim.1.div_ceil(2) * 2,
+        dim.2.div_ceil(2) * 2,
+    );
+    let mip = downsample_distances(
+        |i| sdf.voxels[padded_source_index(i, padded, dim)].to_world_distance(header),
+        padded,
+        header.box_min,
+        level_spacing(header),
+        filter,
+    );
+    encode_level(&mip, filter)
+}
+
+// Level 0 (sdf) and up to levels - 1 mips. Stops early when a dimension drops below 2.
+pub fn build_sdf_mips<T: SdfVoxel>(
+    sdf: Sdf<T>,
+    levels: u32,
+    filter: MipFilter,
+) -> Vec<SdfLevel<T>> {
+    let padded = padded_mip_dimensions(sdf.header.dim, levels);
+    let sdf = pad_level(sdf, padded);
+    let header = sdf.header;
+
+    let mut total_voxels = header.dim.0 * header.dim.1 * header.dim.2;
+    let mut sdf_levels = vec![SdfLevel { sdf, offset: 0 }];
+
+    // Distances of the previous level (conservative bounds without the margin)
+    let mut prev: Option<MipDistances> = None;
+    while (sdf_levels.len() as u32) < levels {
+        let dim = match &prev {
+            Some(prev) => prev.dim,
+            None => header.dim,
+        };
+        if dim.0 < 2 || dim.1 < 2 || dim.2 < 2 {
+            break;
+        }
+
+        let mip = match &prev {
+            Some(prev) => downsample_distances(
+                |i| prev.distances[i],
+                prev.dim,
+                prev.box_min,
+                prev.spacing,
+                filter,
+            ),
+            None => {
+                let level_0 = &sdf_levels[0].sdf;
+                downsample_distances(
+                    |i| level_0.voxels[i].to_world_distance(&header),
+                    header.dim,
+                    header.box_min,
+                    level_spacing(&header),
+                    filter,
+                )
+            }
+        };
+
+        let sdf = encode_level(&mip, filter);
+        let offset = total_voxels;
+        total_voxels += mip.dim.0 * mip.dim.1 * mip.dim.2;
+        sdf_levels.push(SdfLevel { sdf, offset });
+        prev = Some(mip);
+    }
+
+    sdf_levels
+}
+
+// Voxel count of the whole chain
+pub fn sdf_mips_total_voxels<T>(sdf_levels: &[SdfLevel<T>]) -> u32 {
+    sdf_levels.last().map_or(0, |level| {
+        let dim = level.sdf.header.dim;
+        level.offset + dim.0 * dim.1 * dim.2
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::minivector::*;
+    use crate::sdf_sampling::*;
+
+    const SPHERE_RADIUS: f32 = 0.6;
+
+    // Odd dimensions, sphere at the origin
+    fn sphere_sdf<T: SdfVoxel + Send>() -> Sdf<T> {
+        let header = SdfHeader {
+            dim: (37, 41, 43),
+            box_min: (-0.9, -1.0, -1.05),
+            dx: 0.05,
+        };
+        sample_sdf(header, |p| p.length() - SPHERE_RADIUS).unwrap()
+    }
+
+    #[test]
+    fn padded_dimensions() {
+        assert_eq!(padded_mip_dimensions((37, 41, 43), 6), (64, 64, 64));
+        assert_eq!(padded_mip_dimensions((37, 41, 43), 3), (40, 44, 44));
+        assert_eq!(padded_mip_dimensions((64, 32, 16), 6), (64, 32, 16));
+        assert_eq!(padded_mip_dimensions((5, 100, 100), 6), (8, 104, 104));
+        assert_eq!(padded_mip_dimensions((7, 9, 1), 6), (7, 9, 1));
+        assert_eq!(padded_mip_dimensions((0, 0, 0), 6), (0, 0, 0));
+    }
+
+    #[test]
+    fn uniform_spacing() {
+        let sdf = sphere_sdf::<u16>();
+        let header = sdf.header;
+        let levels = build_sdf_mips(sdf, 4, MipFilter::Box);
+        assert_eq!(levels.len(), 4);
+
+        let level_0 = levels[0].sdf.header;
+        assert_eq!(level_0.dim, (40, 48, 48));
+        assert_eq!(level_0.box_min, header.box_min);
+        assert_eq!(level_0.dx, header.dx);
+
+        let mut offset = 0;
+        for (i, level) in levels.iter().enumerate() {
+            let h = level.sdf.header;
+            let scale = (1 << i) as f32;
+            assert_eq!(
+                h.dim,
+                (level_0.dim.0 >> i, level_0.dim.1 >> i, level_0.dim.2 >> i)
+            );
+            assert!((h.dx - level_0.dx * scale).abs() < 1e-6);
+            assert!((h.diagonal_length() - level_0.diagonal_length()).abs() < 1e-4);
+
+            // First voxel at the center of its level 0 footprint
+            let center = |min: f32| min + (scale - 1.0) * 0.5 * level_0.dx;
+            assert!((h.box_min.0 - center(level_0.box_min.0)).abs() < 1e-5);
+            assert!((h.box_min.1 - center(level_0.box_min.1)).abs() < 1e-5);
+            assert!((h.box_min.2 - center(level_0.box_min.2)).abs() < 1e-5);
+
+            assert_eq!(level.offset, offset);
+            offset += h.dim.0 * h.dim.1 * h.dim.2;
+        }
+        assert_eq!(sdf_mips_total_voxels(&levels), offset);
+
+        // Padding repeats the edge voxels
+        let original = sphere_sdf::<u16>();
+        let padded = &levels[0].sdf;
+        let p = original.voxel_position(36, 40, 42);
+        let d = original.distance_at(p);
+        assert!((padded.distance_at(padded.voxel_position(39, 47, 47)) - d).abs() < 1e-3);
+        assert!((padded.distance_at(p) - d).abs() < 1e-3);
+    }
+
+    #[test]
+    fn downsample_odd_dimensions() {
+        let sdf = sphere_sdf::<f32>();
+        let mip = downsample_sdf(&sdf, MipFilter::Box);
+        assert_eq!(mip.header.dim, (19, 21, 22));
+        assert_eq!(mip.header.dx, sdf.header.dx * 2.0);
+        assert_eq!(mip.voxels.len(), 19 * 21 * 22);
+
+        // Box filtered voxels are close to the sphere away from its center. The last voxels
+        // cover padding (all axes are odd).
+        for z in 0..21 {
+            for y in 0..20 {
+                for x in 0..18 {
+                    let p = mip.voxel_position(x, y, z);
+                    if p.length() < 0.3 {
+                        continue;
+                    }
+                    let d = mip.distance_at(p);
+                    assert!((d - (p.length() - SPHERE_RADIUS)).abs() < 0.02, "{:?}", p);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn conservative_never_overshoots() {
+        let sdf = sphere_sdf::<f32>();
+        let box_min = sdf.box_min();
+        let box_max = sdf.box_max();
+        let levels = build_sdf_mips(sdf, 5, MipFilter::Conservative);
+        assert_eq!(levels.len(), 5);
+
+        // Trilinear samples between the voxel centers of every mip, inside the source volume
+        let steps = 29;
+        for level in levels.iter().skip(1) {
+            let mip = &level.sdf;
+            for z in 0..=steps {
+                for y in 0..=steps {
+                    for x in 0..=steps {
+                        let t = Vec3 {
+                            x: x as f32,
+                            y: y as f32,
+                            z: z as f32,
+                        } * (1.0 / steps as f32);
+                        let p = box_min + (box_max - box_min) * t;
+                        let d = mip.distance_at(p);
+                        let exact = p.length() - SPHERE_RADIUS;
+                        assert!(
+                            d <= exact + 1e-4,
+                            "level dim {:?}, {:?}: {} > {}",
+                            mip.header.dim,
+                            p,
+                            d,
+                            exact
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,351 @@
+// World space queries of a SDF volume. Voxels are grid points: voxel (x, y, z) is located at
+// box_min + (x, y, z) * dx (SDFGen convention, see mesh_to_sdf). Distances are trilinearly
+// interpolated and decoded to world units.
+
+use crate::error::*;
+use crate::minivector::*;
+use crate::sdf::*;
+use crate::sdf_voxel::*;
+use std::thread;
+
+// Queries outside of the volume bounds
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SdfBorder {
+    // Distance of the nearest point inside the volume
+    Clamp,
+    // Clamp plus the distance to the volume bounds. Grows away from the volume, keeps ray
+    // marching from outside of the volume working.
+    Extend,
+}
+
+impl<T: SdfVoxel> Sdf<T> {
+    pub fn box_min(&self) -> Vec3 {
+        Vec3 {
+            x: self.header.box_min.0,
+            y: self.header.box_min.1,
+            z: self.header.box_min.2,
+        }
+    }
+
+    // Position of the last voxel
+    pub fn box_max(&self) -> Vec3 {
+        let dim = self.header.dim;
+        let extent = Vec3 {
+            x: dim.0.saturating_sub(1) as f32,
+            y: dim.1.saturating_sub(1) as f32,
+            z: dim.2.saturating_sub(1) as f32,
+        };
+        self.box_min() + extent * self.header.dx
+    }
+
+    pub fn voxel_position(&self, x: u32, y: u32, z: u32) -> Vec3 {
+        let grid = Vec3 {
+            x: x as f32,
+            y: y as f32,
+            z: z as f32,
+        };
+        self.box_min() + grid * self.header.dx
+    }
+
+    fn voxel_distance(&self, x: usize, y: usize, z: usize) -> f32 {
+        let dim = self.header.dim;
+        let index = x + (y + z * dim.1 as usize) * dim.0 as usize;
+        self.voxels[index].to_world_distance(&self.header)
+    }
+
+    // Trilinear distance. Empty volumes return infinity.
+    pub fn distance_at_border(&self, world_pos: Vec3, border: SdfBorder) -> f32 {
+        let dim = self.header.dim;
+        if self.voxels.is_empty() {
+            return f32::INFINITY;
+        }
+
+        let box_min = self.box_min();
+        let box_max = self.box_max();
+        let clamped = Vec3 {
+            x: world_pos.x.clamp(box_min.x, box_max.x),
+            y: world_pos.y.clamp(box_min.y, box_max.y),
+            z: world_pos.z.clamp(box_min.z, box_max.z),
+        };
+        let grid = (clamped - box_min) * (1.0 / self.header.dx);
+
+        // Cell origin and fraction. The last voxel of an axis uses the previous cell.
+        let cell = |g: f32, dim: u32| -> (usize, usize, f32) {
+            let i0 = (g.floor() as u32).min(dim.saturating_sub(2));
+            let i1 = (i0 + 1).min(dim - 1);
+            (i0 as usize, i1 as usize, (g - i0 as f32).clamp(0.0, 1.0))
+        };
+        let (x0, x1, fx) = cell(grid.x, dim.0);
+        let (y0, y1, fy) = cell(grid.y, dim.1);
+        let (z0, z1, fz) = cell(grid.z, dim.2);
+
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let d00 = lerp(
+            self.voxel_distance(x0, y0, z0),
+            self.voxel_distance(x1, y0, z0),
+            fx,
+        );
+        let d10 = lerp(
+            self.voxel_distance(x0, y1, z0),
+            self.voxel_distance(x1, y1, z0),
+            fx,
+        );
+        let d01 = lerp(
+            self.voxel_distance(x0, y0, z1),
+            self.voxel_distance(x1, y0, z1),
+            fx,
+        );
+        let d11 = lerp(
+            self.voxel_distance(x0, y1, z1),
+            self.voxel_distance(x1, y1, z1),
+            fx,
+        );
+        let d = lerp(lerp(d00, d10, fy), lerp(d01, d11, fy), fz);
+
+        match border {
+            SdfBorder::Clamp => d,
+            SdfBorder::Extend => d + (world_pos - clamped).length(),
+        }
+    }
+
+    // Extended border, see SdfBorder
+    pub fn distance_at(&self, world_pos: Vec3) -> f32 {
+        self.distance_at_border(world_pos, SdfBorder::Extend)
+    }
+
+    // Central differences, half a voxel apart (matches main.frag)
+    pub fn gradient_at(&self, world_pos: Vec3) -> Vec3 {
+        let e = self.header.dx * 0.5;
+        let d = |x: f32, y: f32, z: f32| self.distance_at(world_pos + Vec3 { x, y, z });
+        Vec3 {
+            x: d(e, 0.0, 0.0) - d(-e, 0.0, 0.0),
+            y: d(0.0, e, 0.0) - d(0.0, -e, 0.0),
+            z: d(0.0, 0.0, e) - d(0.0, 0.0, -e),
+        } * (0.5 / e)
+    }
+
+    // Zero vector if the gradient vanishes
+    pub fn normal_at(&self, world_pos: Vec3) -> Vec3 {
+        let gradient = self.gradient_at(world_pos);
+        if gradient.length() > 0.0 {
+            gradient.normalize()
+        } else {
+            Vec3::from_scalar(0.0)
+        }
+    }
+}
+
+// New volume from a world space distance function evaluated at the voxel positions of the
+// header. Slices are split over the threads.
+pub fn sample_sdf<T, F>(header: SdfHeader, distance: F) -> SdfResult<Sdf<T>>
+where
+    T: SdfVoxel + Send,
+    F: Fn(Vec3) -> f32 + Sync,
+{
+    let count = voxel_count(header.dim)?;
+    let mut sdf = Sdf {
+        header,
+        voxels: vec![T::default(); count],
+    };
+    if count == 0 {
+        return Ok(sdf);
+    }
+
+    let box_min = sdf.box_min();
+    let slice_size = header.dim.0 as usize * header.dim.1 as usize;
+    let sample_slice = |z: u32, slice: &mut [T]| {
+        for y in 0..header.dim.1 {
+            for x in 0..header.dim.0 {
+                let grid = Vec3 {
+                    x: x as f32,
+                    y: y as f32,
+                    z: z as f32,
+                };
+                let d = distance(box_min + grid * header.dx);
+                slice[x as usize + y as usize * header.dim.0 as usize] =
+                    T::from_distance(d as f64, &header);
+            }
+        }
+    };
+
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let mut slices: Vec<(u32, &mut [T])> = sdf
+        .voxels
+        .chunks_mut(slice_size)
+        .enumerate()
+        .map(|(z, slice)| (z as u32, slice))
+        .collect();
+    let chunk = slices.len().div_ceil(threads).max(1);
+    thread::scope(|scope| {
+        for slices in slices.chunks_mut(chunk) {
+            let sample_slice = &sample_slice;
+            scope.spawn(move || {
+                for (z, slice) in slices.iter_mut() {
+                    sample_slice(*z, slice);
+                }
+            });
+        }
+    });
+
+    Ok(sdf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RADIUS: f32 = 0.6;
+
+    // 41^3 voxels over [-1, 1]^3, dx = 0.05
+    fn sphere_sdf<T: SdfVoxel + Send>() -> Sdf<T> {
+        let header = SdfHeader {
+            dim: (41, 41, 41),
+            box_min: (-1.0, -1.0, -1.0),
+            dx: 0.05,
+        };
+        sample_sdf(header, |p| p.length() - RADIUS).unwrap()
+    }
+
+    // Points inside the volume, off the voxel grid
+    fn test_points() -> Vec<Vec3> {
+        let mut points = Vec::new();
+        for z in 0..9 {
+            for y in 0..9 {
+                for x in 0..9 {
+                    let c = |i: i32| -0.93 + i as f32 * 0.231;
+                    points.push(Vec3 {
+                        x: c(x),
+                        y: c(y) + 0.013,
+                        z: c(z) - 0.007,
+                    });
+                }
+            }
+        }
+        points
+    }
+
+    #[test]
+    fn voxel_positions() {
+        let sdf = sphere_sdf::<f32>();
+        assert!((sdf.box_max() - Vec3::from_scalar(1.0)).length() < 1e-5);
+        for &(x, y, z) in &[(0, 0, 0), (40, 40, 40), (20, 3, 37), (7, 20, 20)] {
+            let p = sdf.voxel_position(x, y, z);
+            let index = (x + (y + z * 41) * 41) as usize;
+            assert_eq!(sdf.voxels[index], p.length() - RADIUS);
+            assert!((sdf.distance_at(p) - sdf.voxels[index]).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn distance_matches_sphere() {
+        // Trilinear interpolation error of the sphere distance is ~dx^2 / |p|, the distance
+        // is not smooth at the center
+        let check = |sdf_distance: &dyn Fn(Vec3) -> f32, tolerance: f32| {
+            for p in test_points().into_iter().filter(|p| p.length() > 0.2) {
+                let expected = p.length() - RADIUS;
+                let d = sdf_distance(p);
+                assert!(
+                    (d - expected).abs() < tolerance,
+                    "{:?}: {} {}",
+                    p,
+                    d,
+                    expected
+                );
+            }
+        };
+        let sdf = sphere_sdf::<f32>();
+        check(&|p| sdf.distance_at(p), 0.005);
+        let sdf = sphere_sdf::<u16>();
+        check(&|p| sdf.distance_at(p), 0.005);
+        let sdf = sphere_sdf::<u8>();
+        check(&|p| sdf.distance_at(p), 0.02);
+    }
+
+    #[test]
+    fn border_modes() {
+        let sdf = sphere_sdf::<f32>();
+        let outside = Vec3 {
+            x: 3.0,
+            y: 0.0,
+            z: 0.0,
+        };
+        // Nearest point inside the volume is (1, 0, 0)
+        let clamped = 1.0 - RADIUS;
+        assert!((sdf.distance_at_border(outside, SdfBorder::Clamp) - clamped).abs() < 1e-5);
+        assert!(
+            (sdf.distance_at_border(outside, SdfBorder::Extend) - (clamped + 2.0)).abs() < 1e-5
+        );
+
+        // Extend is a lower bound of the true distance outside of the volume
+        let corner = Vec3::from_scalar(-2.0);
+        let d = sdf.distance_at_border(corner, SdfBorder::Extend);
+        assert!(d <= corner.length() - RADIUS + 1e-4);
+        assert!(d > sdf.distance_at_border(corner, SdfBorder::Clamp));
+
+        // Inside the volume both modes agree
+        for p in test_points() {
+            assert_eq!(
+                sdf.distance_at_border(p, SdfBorder::Clamp),
+                sdf.distance_at_border(p, SdfBorder::Extend)
+            );
+        }
+    }
+
+    #[test]
+    fn gradient_and_normal() {
+        let sdf = sphere_sdf::<f32>();
+        for p in test_points() {
+            // The gradient vanishes at the center and the distance is not smooth there
+            if p.length() < 0.2 {
+                continue;
+            }
+            let expected = p.normalize();
+            let gradient = sdf.gradient_at(p);
+            assert!(
+                (gradient.length() - 1.0).abs() < 0.05,
+                "{:?}: {:?}",
+                p,
+                gradient
+            );
+
+            let normal = sdf.normal_at(p);
+            assert!((normal.length() - 1.0).abs() < 1e-5);
+            assert!(normal.dot(expected) > 0.995, "{:?}: {:?}", p, normal);
+        }
+
+        // Constant field: no gradient
+        let constant: Sdf<f32> = sample_sdf(sdf.header, |_| 0.5).unwrap();
+        let p = Vec3::from_scalar(0.1);
+        assert_eq!(constant.gradient_at(p).length(), 0.0);
+        assert_eq!(constant.normal_at(p).length(), 0.0);
+    }
+
+    #[test]
+    fn empty_volume() {
+        let header = SdfHeader {
+            dim: (0, 4, 4),
+            box_min: (0.0, 0.0, 0.0),
+            dx: 1.0,
+        };
+        let sdf: Sdf<u16> = sample_sdf(header, |p| p.length()).unwrap();
+        assert!(sdf.voxels.is_empty());
+        assert_eq!(sdf.distance_at(Vec3::from_scalar(0.0)), f32::INFINITY);
+    }
+
+    #[test]
+    fn single_voxel_axis() {
+        // Flat volume: the missing cell on the z axis clamps to the only slice
+        let header = SdfHeader {
+            dim: (5, 5, 1),
+            box_min: (-1.0, -1.0, 0.0),
+            dx: 0.5,
+        };
+        let sdf: Sdf<f32> = sample_sdf(header, |p| p.x).unwrap();
+        let p = Vec3 {
+            x: 0.25,
+            y: 0.3,
+            z: 0.0,
+        };
+        assert!((sdf.distance_at_border(p, SdfBorder::Clamp) - 0.25).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,1135 @@
+// Streaming SDF load and store over Read/Write. The volume is processed one z slice at a
+// time (including the gradient predictor and deflate), so peak memory stays near one copy
+// of the voxels, or a single slice when the caller consumes the slices directly.
+
+use crate::error::*;
+use crate::quantized_sdf::*;
+use crate::sdf::*;
+use crate::sdf_voxel::*;
+use crate::serialization::*;
+use miniz_oxide::deflate::core::{
+    compress, create_comp_flags_from_zip_params, CompressorOxide, TDEFLFlush, TDEFLStatus,
+};
+use miniz_oxide::inflate::stream::{inflate, InflateState};
+use miniz_oxide::{DataFormat, MZError, MZFlush, MZStatus};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+const STREAM_BUFFER_SIZE: usize = 64 * 1024;
+const COMPRESSION_LEVEL: i32 = 5;
+
+// Payload length (u64) and crc (u32) are the last fields of the versioned file header
+const SDF_FILE_PAYLOAD_INFO_OFFSET: u64 = SDF_FILE_HEADER_SIZE as u64 - 12;
+
+// Like read_exact, but reports a short read as truncated data at the given offset
+fn read_exact_at<R: Read>(reader: &mut R, buf: &mut [u8], offset: u64) -> SdfResult<()> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => {
+                return Err(SdfError::Truncated {
+                    offset: offset as usize,
+                    needed: buf.len(),
+                    len: offset as usize + filled,
+                })
+            }
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(())
+}
+
+// Payload bytes read from the file. Limited to the payload length (if known) and checksummed.
+struct PayloadSource<R: Read> {
+    reader: R,
+    offset: u64,
+    remaining: Option<u64>,
+    crc: Crc32,
+}
+
+impl<R: Read> Read for PayloadSource<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = match self.remaining {
+            Some(remaining) => (buf.len() as u64).min(remaining) as usize,
+            None => buf.len(),
+        };
+        let n = self.reader.read(&mut buf[..len])?;
+        self.crc.update(&buf[..n]);
+        self.offset += n as u64;
+        if let Some(remaining) = self.remaining.as_mut() {
+            *remaining -= n as u64;
+        }
+        Ok(n)
+    }
+}
+
+enum PayloadReader<R: Read> {
+    Raw(PayloadSource<R>),
+    Inflate {
+        source: PayloadSource<R>,
+        state: Box<InflateState>,
+        input: Vec<u8>,
+        input_pos: usize,
+        input_len: usize,
+        output_offset: u64,
+        stream_end: bool,
+    },
+}
+
+impl<R: Read> PayloadReader<R> {
+    fn new_inflate(source: PayloadSource<R>) -> PayloadReader<R> {
+        PayloadReader::Inflate {
+            source,
+            state: InflateState::new_boxed(DataFormat::Raw),
+            input: vec![0u8; STREAM_BUFFER_SIZE],
+            input_pos: 0,
+            input_len: 0,
+            output_offset: 0,
+            stream_end: false,
+        }
+    }
+
+    fn source(&mut self) -> &mut PayloadSource<R> {
+        match self {
+            PayloadReader::Raw(source) => source,
+            PayloadReader::Inflate { source, .. } => source,
+        }
+    }
+
+    fn into_source(self) -> PayloadSource<R> {
+        match self {
+            PayloadReader::Raw(source) => source,
+            PayloadReader::Inflate { source, .. } => source,
+        }
+    }
+
+    // Inflates into output until it is full or the deflate stream ends. Returns bytes written.
+    fn inflate_some(&mut self, output: &mut [u8]) -> SdfResult<usize> {
+        match self {
+            PayloadReader::Raw(_) => unreachable!(),
+            PayloadReader::Inflate {
+                source,
+                state,
+                input,
+                input_pos,
+                input_len,
+                output_offset,
+                stream_end,
+            } => {
+                let mut written = 0;
+                while written < output.len() && !*stream_end {
+                    if *input_pos == *input_len {
+                        *input_len = source.read(&mut input[..])?;
+                        *input_pos = 0;
+                    }
+
+                    let result = inflate(
+                        state,
+                        &input[*input_pos..*input_len],
+                        &mut output[written..],
+                        MZFlush::None,
+                    );
+                    *input_pos += result.bytes_consumed;
+                    written += result.bytes_written;
+
+                    match result.status {
+                        Ok(MZStatus::StreamEnd) => *stream_end = true,
+                        Ok(_) => (),
+                        // No progress: needs more input, unless the input is exhausted
+                        Err(MZError::Buf) if *input_len == 0 => {
+                            return Err(SdfError::Decompress(
+                                "Unexpected end of deflate stream".to_string(),
+                            ))
+                        }
+                        Err(MZError::Buf) => (),
+                        Err(err) => return Err(SdfError::Decompress(format!("{:?}", err))),
+                    }
+                }
+                *output_offset += written as u64;
+                Ok(written)
+            }
+        }
+    }
+
+    fn read_exact(&mut self, output: &mut [u8]) -> SdfResult<()> {
+        match self {
+            PayloadReader::Raw(source) => {
+                let offset = source.offset;
+                read_exact_at(source, output, offset)
+            }
+            PayloadReader::Inflate { output_offset, .. } => {
+                let offset = *output_offset;
+                let written = self.inflate_some(output)?;
+                if written < output.len() {
+                    return Err(SdfError::Truncated {
+                        offset: offset as usize,
+                        needed: output.len(),
+                        len: offset as usize + written,
+                    });
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // Bytes left in the payload after the last slice (deflate stream must end too)
+    fn remaining_output(&mut self) -> SdfResult<u64> {
+        let mut remaining = 0;
+        if let PayloadReader::Inflate { .. } = self {
+            let mut scratch = vec![0u8; STREAM_BUFFER_SIZE];
+            loop {
+                let written = self.inflate_some(&mut scratch[..])?;
+                remaining += written as u64;
+                if written < scratch.len() {
+                    break;
+                }
+            }
+        }
+        Ok(remaining)
+    }
+}
+
+pub struct SdfReader<R: Read> {
+    header: SdfHeader,
+    voxel_type: VoxelType,
+    encoding: SdfEncoding,
+    predictor: SdfPredictor,
+    expected_crc: Option<u32>,
+    payload: PayloadReader<R>,
+    quantization: Option<SdfQuantization>,
+    chunk_bytes: Vec<u8>,
+    prev_slice: Vec<u16>,
+    // Decoded bricks of the current quantized slab
+    slab: Vec<u16>,
+    z: u32,
+    verified: bool,
+}
+
+impl<R: Read> SdfReader<R> {
+    // Versioned SDF file
+    pub fn new(mut reader: R) -> SdfResult<SdfReader<R>> {
+        // Magic and version first, the header size depends on the version
+        let mut bytes = [0u8; SDF_FILE_HEADER_SIZE];
+        read_exact_at(&mut reader, &mut bytes[..8], 0)?;
+        if !is_sdf_file(&bytes) {
+            return Err(SdfError::BadMagic);
+        }
+        let version = Loader { offset: 4 }.load_u32(&bytes)?;
+        let header_size = sdf_file_header_size(version).min(SDF_FILE_HEADER_SIZE);
+        read_exact_at(&mut reader, &mut bytes[8..header_size], 8)?;
+        let file_header = parse_sdf_file_header(&bytes[..header_size])?;
+        let header = file_header.header;
+
+        let count_voxels = voxel_count(header.dim)?;
+        let expected_size = (count_voxels * file_header.voxel_type.size()) as u64;
+
+        let source = PayloadSource {
+            reader,
+            offset: header_size as u64,
+            remaining: Some(file_header.payload_length),
+            crc: Crc32::new(),
+        };
+        let payload = match file_header.encoding {
+            SdfEncoding::Raw => {
+                if file_header.payload_length != expected_size {
+                    return Err(SdfError::SizeMismatch {
+                        dim: header.dim,
+                        expected: expected_size,
+                        actual: file_header.payload_length,
+                    });
+                }
+                PayloadReader::Raw(source)
+            }
+            SdfEncoding::Zlib | SdfEncoding::GradientZlib | SdfEncoding::QuantizedZlib => {
+                PayloadReader::new_inflate(source)
+            }
+        };
+
+        let mut reader = SdfReader::with_payload(
+            header,
+            file_header.voxel_type,
+            file_header.encoding,
+            file_header.predictor,
+            Some(file_header.crc),
+            payload,
+        );
+
+        if file_header.encoding == SdfEncoding::QuantizedZlib {
+            let bytes = &mut reader.chunk_bytes[..8];
+            reader.payload.read_exact(bytes)?;
+            let mut loader = Loader::new();
+            let step = loader.load_u32(bytes)?;
+            let band = loader.load_u32(bytes)?;
+            if step % 2 == 0 || step > 65535 || band == 0 {
+                return Err(SdfError::Decompress(format!(
+                    "Invalid quantization: step = {}, band = {}",
+                    step, band
+                )));
+            }
+            reader.quantization = Some(SdfQuantization { step, band });
+        }
+
+        Ok(reader)
+    }
+
+    // Legacy headerless file: SDFGen raw, or grad+zlib when compressed
+    pub fn new_legacy(reader: R, compressed: bool) -> SdfResult<SdfReader<R>> {
+        let source = PayloadSource {
+            reader,
+            offset: 0,
+            remaining: None,
+            crc: Crc32::new(),
+        };
+        let mut payload = if compressed {
+            PayloadReader::new_inflate(source)
+        } else {
+            PayloadReader::Raw(source)
+        };
+
+        let mut bytes = [0u8; SDF_LEGACY_HEADER_SIZE];
+        payload.read_exact(&mut bytes[..])?;
+        let header = load_sdf_header(&mut Loader::new(), &bytes)?;
+        voxel_count(header.dim)?;
+
+        let encoding = if compressed {
+            SdfEncoding::GradientZlib
+        } else {
+            SdfEncoding::Raw
+        };
+
+        Ok(SdfReader::with_payload(
+            header,
+            VoxelType::U16Unorm,
+            encoding,
+            SdfPredictor::Planar,
+            None,
+            payload,
+        ))
+    }
+
+    fn with_payload(
+        header: SdfHeader,
+        voxel_type: VoxelType,
+        encoding: SdfEncoding,
+        predictor: SdfPredictor,
+        expected_crc: Option<u32>,
+        payload: PayloadReader<R>,
+    ) -> SdfReader<R> {
+        SdfReader {
+            header,
+            voxel_type,
+            encoding,
+            predictor,
+            expected_crc,
+            payload,
+            quantization: None,
+            chunk_bytes: vec![0u8; STREAM_BUFFER_SIZE],
+            prev_slice: Vec::new(),
+            slab: Vec::new(),
+            z: 0,
+            verified: false,
+        }
+    }
+
+    pub fn header(&self) -> &SdfHeader {
+        &self.header
+    }
+
+    pub fn voxel_type(&self) -> VoxelType {
+        self.voxel_type
+    }
+
+    pub fn encoding(&self) -> SdfEncoding {
+        self.encoding
+    }
+
+    pub fn predictor(&self) -> SdfPredictor {
+        self.predictor
+    }
+
+    // QuantizedZlib encoding only
+    pub fn quantization(&self) -> Option<SdfQuantization> {
+        self.quantization
+    }
+
+    // Voxels per z slice
+    pub fn slice_size(&self) -> usize {
+        self.header.dim.0 as usize * self.header.dim.1 as usize
+    }
+
+    // Reads voxels in bounded chunks. Memory is only committed as the data arrives, a
+    // truncated file with huge dimensions fails early.
+    fn read_voxels<T: SdfVoxel>(
+        &mut self,
+        count: usize,
+        mut out: impl FnMut(&[T]),
+    ) -> SdfResult<()> {
+        let voxel_size = T::VOXEL_TYPE.size();
+        let chunk_size = STREAM_BUFFER_SIZE / voxel_size;
+        let mut chunk_voxels = Vec::with_capacity(count.min(chunk_size));
+
+        let mut remaining = count;
+        while remaining > 0 {
+            let n = remaining.min(chunk_size);
+            let bytes = &mut self.chunk_bytes[..n * voxel_size];
+            self.payload.read_exact(bytes)?;
+            chunk_voxels.clear();
+            chunk_voxels.extend(bytes.chunks_exact(voxel_size).map(T::load));
+            out(&chunk_voxels[..]);
+            remaining -= n;
+        }
+        Ok(())
+    }
+
+    // Decodes the bricks of the slab starting at the current z slice
+    fn read_quantized_slab(&mut self, quantization: &SdfQuantization) -> SdfResult<()> {
+        let dim = self.header.dim;
+        let (_, depth) = quantized_brick_extent(dim.2, self.z / QUANTIZED_BRICK_SIZE);
+
+        self.slab.clear();
+        for by in 0..dim.1.div_ceil(QUANTIZED_BRICK_SIZE) {
+            let (_, h) = quantized_brick_extent(dim.1, by);
+            for bx in 0..dim.0.div_ceil(QUANTIZED_BRICK_SIZE) {
+                let (_, w) = quantized_brick_extent(dim.0, bx);
+                let count = (w * h * depth) as usize;
+
+                let bytes = &mut self.chunk_bytes[..QUANTIZED_BRICK_HEADER_SIZE];
+                self.payload.read_exact(bytes)?;
+                let (bits, min) = parse_quantized_brick_header(bytes)?;
+
+                let bytes = &mut self.chunk_bytes[..quantized_brick_bytes(bits, count)];
+                self.payload.read_exact(bytes)?;
+                decode_quantized_brick(quantization, bits, min, bytes, count, &mut self.slab);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Gathers the current z slice from the slab bricks, row by row
+    fn read_quantized_slice<T: SdfVoxel>(
+        &mut self,
+        quantization: &SdfQuantization,
+        mut out: impl FnMut(&[T]),
+    ) -> SdfResult<()> {
+        if self.z.is_multiple_of(QUANTIZED_BRICK_SIZE) {
+            self.read_quantized_slab(quantization)?;
+        }
+
+        let dim = self.header.dim;
+        let (_, depth) = quantized_brick_extent(dim.2, self.z / QUANTIZED_BRICK_SIZE);
+        let z = self.z % QUANTIZED_BRICK_SIZE;
+
+        for y in 0..dim.1 {
+            let by = y / QUANTIZED_BRICK_SIZE;
+            let (y0, h) = quantized_brick_extent(dim.1, by);
+
+            // Brick rows above are full height
+            let mut offset = (y0 * depth * dim.0) as usize;
+            for bx in 0..dim.0.div_ceil(QUANTIZED_BRICK_SIZE) {
+                let (_, w) = quantized_brick_extent(dim.0, bx);
+                let start = offset + ((z * h + y - y0) * w) as usize;
+                out(T::from_u16_slice(&self.slab[start..start + w as usize]).unwrap());
+                offset += (w * h * depth) as usize;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_slice_voxels<T: SdfVoxel>(&mut self, out: impl FnMut(&[T])) -> SdfResult<()> {
+        match self.quantization {
+            Some(quantization) => self.read_quantized_slice(&quantization, out),
+            None => self.read_voxels(self.slice_size(), out),
+        }
+    }
+
+    fn end_slice<T: SdfVoxel>(&mut self, voxels: &mut [T]) -> SdfResult<()> {
+        if self.encoding == SdfEncoding::GradientZlib {
+            let voxels = T::as_u16_slice_mut(voxels).unwrap();
+            let prev = if self.z > 0 {
+                Some(&self.prev_slice[..])
+            } else {
+                None
+            };
+            decompress_postprocess_slice(self.header.dim, self.predictor, prev, voxels);
+
+            // Copied only once the slice has arrived, see read_voxels
+            self.prev_slice.clear();
+            self.prev_slice.extend_from_slice(voxels);
+        }
+
+        self.z += 1;
+        if self.z == self.header.dim.2 {
+            self.verify()?;
+        }
+
+        Ok(())
+    }
+
+    // Reads the next z slice. The payload length and checksum are verified after the last one.
+    pub fn read_slice<T: SdfVoxel>(&mut self, voxels: &mut [T]) -> SdfResult<()> {
+        assert_eq!(T::VOXEL_TYPE, self.voxel_type, "Voxel type mismatch");
+        assert_eq!(voxels.len(), self.slice_size());
+        assert!(self.z < self.header.dim.2, "All slices already read");
+
+        let mut offset = 0;
+        self.read_slice_voxels(|chunk| {
+            voxels[offset..offset + chunk.len()].copy_from_slice(chunk);
+            offset += chunk.len();
+        })?;
+
+        self.end_slice(voxels)
+    }
+
+    // Reads the remaining slices into a new volume
+    pub fn read_sdf<T: SdfVoxel>(&mut self) -> SdfResult<Sdf<T>> {
+        assert_eq!(T::VOXEL_TYPE, self.voxel_type, "Voxel type mismatch");
+
+        let dim = self.header.dim;
+        let slice_size = self.slice_size();
+        let remaining_slices = (dim.2 - self.z) as usize;
+
+        // Reserve only, pages get touched when the data arrives
+        let mut voxels = Vec::new();
+        voxels
+            .try_reserve_exact(slice_size * remaining_slices)
+            .map_err(|_| SdfError::DimensionOverflow(dim))?;
+
+        // Empty slices, nothing to read
+        if slice_size == 0 {
+            self.z = dim.2;
+        }
+
+        while self.z < dim.2 {
+            let start = voxels.len();
+            self.read_slice_voxels(|chunk| voxels.extend_from_slice(chunk))?;
+            self.end_slice(&mut voxels[start..])?;
+        }
+        self.verify()?;
+
+        Ok(Sdf {
+            header: self.header,
+            voxels,
+        })
+    }
+
+    // Reads the remaining slices, converting the voxels to T if the stored type differs
+    pub fn read_sdf_as<T: SdfVoxel>(&mut self) -> SdfResult<Sdf<T>> {
+        if T::VOXEL_TYPE == self.voxel_type {
+            return self.read_sdf();
+        }
+
+        Ok(match self.voxel_type {
+            VoxelType::U16Unorm => self.read_sdf::<u16>()?.convert(),
+            VoxelType::U8Unorm => self.read_sdf::<u8>()?.convert(),
+            VoxelType::F16 => self.read_sdf::<F16>()?.convert(),
+            VoxelType::F32 => self.read_sdf::<f32>()?.convert(),
+        })
+    }
+
+    fn verify(&mut self) -> SdfResult<()> {
+        if self.verified {
+            return Ok(());
+        }
+        self.verified = true;
+
+        let remaining = self.payload.remaining_output()?;
+        if remaining > 0 {
+            let expected = (voxel_count(self.header.dim)? * self.voxel_type.size()) as u64;
+            return Err(SdfError::SizeMismatch {
+                dim: self.header.dim,
+                expected,
+                actual: expected + remaining,
+            });
+        }
+
+        if let Some(expected_crc) = self.expected_crc {
+            // Rest of the payload (deflate reads ahead in blocks) must be checksummed too
+            let source = self.payload.source();
+            io::copy(source, &mut io::sink())?;
+            if let Some(remaining) = source.remaining.filter(|&remaining| remaining > 0) {
+                return Err(SdfError::Truncated {
+                    offset: source.offset as usize,
+                    needed: remaining as usize,
+                    len: source.offset as usize,
+                });
+            }
+
+            let crc = source.crc.finish();
+            if crc != expected_crc {
+                return Err(SdfError::ChecksumMismatch {
+                    expected: expected_crc,
+                    actual: crc,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    // NOTE: Compressed payloads read ahead, the inner reader position is past the deflate data
+    pub fn into_inner(self) -> R {
+        self.payload.into_source().reader
+    }
+}
+
+// Payload bytes written to the file, counted and checksummed
+struct PayloadSink<W: Write> {
+    writer: W,
+    length: u64,
+    crc: Crc32,
+}
+
+impl<W: Write> PayloadSink<W> {
+    fn write_all(&mut self, bytes: &[u8]) -> SdfResult<()> {
+        self.writer.write_all(bytes)?;
+        self.crc.update(bytes);
+        self.length += bytes.len() as u64;
+        Ok(())
+    }
+}
+
+enum PayloadWriter<W: Write> {
+    Raw(PayloadSink<W>),
+    Deflate {
+        sink: PayloadSink<W>,
+        compressor: Box<CompressorOxide>,
+        output: Vec<u8>,
+    },
+}
+
+impl<W: Write> PayloadWriter<W> {
+    fn new(sink: PayloadSink<W>, compressed: bool) -> PayloadWriter<W> {
+        if compressed {
+            // Raw deflate, same as compress_to_vec
+            let flags = create_comp_flags_from_zip_params(COMPRESSION_LEVEL, 0, 0);
+            PayloadWriter::Deflate {
+                sink,
+                compressor: Box::new(CompressorOxide::new(flags)),
+                output: vec![0u8; STREAM_BUFFER_SIZE],
+            }
+        } else {
+            PayloadWriter::Raw(sink)
+        }
+    }
+
+    fn deflate(&mut self, mut input: &[u8], flush: TDEFLFlush) -> SdfResult<()> {
+        match self {
+            PayloadWriter::Raw(sink) => sink.write_all(input),
+            PayloadWriter::Deflate {
+                sink,
+                compressor,
+                output,
+            } => loop {
+                let (status, bytes_in, bytes_out) =
+                    compress(compressor, input, &mut output[..], flush);
+                sink.write_all(&output[..bytes_out])?;
+                input = &input[bytes_in..];
+
+                match status {
+                    TDEFLStatus::Done => return Ok(()),
+                    TDEFLStatus::Okay => {
+                        // Output space left over: all input consumed into the compressor
+                        if flush != TDEFLFlush::Finish
+                            && input.is_empty()
+                            && bytes_out < output.len()
+                        {
+                            return Ok(());
+                        }
+                    }
+                    status => return Err(SdfError::Compress(format!("{:?}", status))),
+                }
+            },
+        }
+    }
+
+    fn write_all(&mut self, bytes: &[u8]) -> SdfResult<()> {
+        self.deflate(bytes, TDEFLFlush::None)
+    }
+
+    fn finish(mut self) -> SdfResult<PayloadSink<W>> {
+        self.deflate(&[], TDEFLFlush::Finish)?;
+        Ok(match self {
+            PayloadWriter::Raw(sink) => sink,
+            PayloadWriter::Deflate { sink, .. } => sink,
+        })
+    }
+}
+
+// Seek is needed to fill in the payload length and checksum of the versioned file header
+pub struct SdfWriter<W: Write + Seek> {
+    header: SdfHeader,
+    voxel_type: VoxelType,
+    encoding: SdfEncoding,
+    predictor: SdfPredictor,
+    container_start: Option<u64>,
+    payload: PayloadWriter<W>,
+    quantization: Option<SdfQuantization>,
+    // Slices of the current quantized slab
+    slab: Vec<u16>,
+    slice_predicted: Vec<u16>,
+    prev_slice: Vec<u16>,
+    slice_bytes: StorerVec,
+    z: u32,
+}
+
+impl<W: Write + Seek> SdfWriter<W> {
+    // Versioned SDF file. Predictor is used by GradientZlib encoding only.
+    pub fn new(
+        writer: W,
+        header: SdfHeader,
+        encoding: SdfEncoding,
+        predictor: SdfPredictor,
+    ) -> SdfResult<SdfWriter<W>> {
+        SdfWriter::with_voxel_type(writer, header, VoxelType::U16Unorm, encoding, predictor)
+    }
+
+    // Versioned SDF file of the given voxel type, see VoxelType::supports_encoding
+    pub fn with_voxel_type(
+        writer: W,
+        header: SdfHeader,
+        voxel_type: VoxelType,
+        encoding: SdfEncoding,
+        predictor: SdfPredictor,
+    ) -> SdfResult<SdfWriter<W>> {
+        assert!(
+            encoding != SdfEncoding::QuantizedZlib,
+            "Use new_quantized for QuantizedZlib encoding"
+        );
+        SdfWriter::new_container(writer, header, voxel_type, encoding, predictor, None)
+    }
+
+    // Versioned SDF file, lossy QuantizedZlib encoding
+    pub fn new_quantized(
+        writer: W,
+        header: SdfHeader,
+        quantization: &SdfQuantization,
+    ) -> SdfResult<SdfWriter<W>> {
+        SdfWriter::new_container(
+            writer,
+            header,
+            VoxelType::U16Unorm,
+            SdfEncoding::QuantizedZlib,
+            SdfPredictor::Planar,
+            Some(*quantization),
+        )
+    }
+
+    fn new_container(
+        mut writer: W,
+        header: SdfHeader,
+        voxel_type: VoxelType,
+        encoding: SdfEncoding,
+        predictor: SdfPredictor,
+        quantization: Option<SdfQuantization>,
+    ) -> SdfResult<SdfWriter<W>> {
+        voxel_count(header.dim)?;
+        if !voxel_type.supports_encoding(encoding) {
+            return Err(SdfError::UnsupportedVoxelType(
+                encoding.to_u32(),
+                voxel_type.to_u32(),
+            ));
+        }
+
+        let container_start = writer.stream_position()?;
+
+        // Payload length and crc are written by finish
+        let file_header = SdfFileHeader {
+            version: SDF_FILE_VERSION,
+            encoding,
+            voxel_type,
+            predictor,
+            header,
+            payload_length: 0,
+            crc: 0,
+        };
+        let mut storer = StorerVec::new();
+        store_sdf_file_header(&mut storer, &file_header);
+        writer.write_all(&storer.v[..])?;
+
+        let sink = PayloadSink {
+            writer,
+            length: 0,
+            crc: Crc32::new(),
+        };
+        let compressed = encoding != SdfEncoding::Raw;
+
+        let mut writer = SdfWriter::with_payload(
+            header,
+            voxel_type,
+            encoding,
+            predictor,
+            Some(container_start),
+            PayloadWriter::new(sink, compressed),
+        );
+
+        if let Some(quantization) = quantization {
+            let mut storer = StorerVec::new();
+            storer.store_u32(quantization.step);
+            storer.store_u32(quantization.band);
+            writer.payload.write_all(&storer.v[..])?;
+            writer.quantization = Some(quantization);
+        }
+
+        Ok(writer)
+    }
+
+    // Legacy headerless file: SDFGen raw, or grad+zlib when compressed
+    pub fn new_legacy(writer: W, header: SdfHeader, compressed: bool) -> SdfResult<SdfWriter<W>> {
+        voxel_count(header.dim)?;
+
+        let sink = PayloadSink {
+            writer,
+            length: 0,
+            crc: Crc32::new(),
+        };
+        let mut payload = PayloadWriter::new(sink, compressed);
+
+        let mut storer = StorerVec::new();
+        store_sdf_header(&mut storer, &header);
+        payload.write_all(&storer.v[..])?;
+
+        let encoding = if compressed {
+            SdfEncoding::GradientZlib
+        } else {
+            SdfEncoding::Raw
+        };
+
+        Ok(SdfWriter::with_payload(
+            header,
+            VoxelType::U16Unorm,
+            encoding,
+            SdfPredictor::Planar,
+            None,
+            payload,
+        ))
+    }
+
+    fn with_payload(
+        header: SdfHeader,
+        voxel_type: VoxelType,
+        encoding: SdfEncoding,
+        predictor: SdfPredictor,
+        container_start: Option<u64>,
+        payload: PayloadWriter<W>,
+    ) -> SdfWriter<W> {
+        let slice_size = header.dim.0 as usize * header.dim.1 as usize;
+        SdfWriter {
+            header,
+            voxel_type,
+            encoding,
+            predictor,
+            container_start,
+            payload,
+            quantization: None,
+            slab: Vec::new(),
+            slice_predicted: vec![0u16; slice_size],
+            prev_slice: Vec::new(),
+            slice_bytes: StorerVec::new(),
+            z: 0,
+        }
+    }
+
+    // Voxels per z slice
+    pub fn slice_size(&self) -> usize {
+        self.header.dim.0 as usize * self.header.dim.1 as usize
+    }
+
+    pub fn write_slice<T: SdfVoxel>(&mut self, voxels: &[T]) -> SdfResult<()> {
+        assert_eq!(T::VOXEL_TYPE, self.voxel_type, "Voxel type mismatch");
+        assert_eq!(voxels.len(), self.slice_size());
+        assert!(self.z < self.header.dim.2, "All slices already written");
+
+        if let Some(quantization) = self.quantization {
+            let voxels = T::as_u16_slice(voxels).unwrap();
+            self.slab.extend_from_slice(voxels);
+            self.z += 1;
+
+            if self.z.is_multiple_of(QUANTIZED_BRICK_SIZE) || self.z == self.header.dim.2 {
+                self.slice_bytes.v.clear();
+                encode_quantized_slab(
+                    self.header.dim,
+                    &quantization,
+                    &self.slab,
+                    &mut self.slice_bytes,
+                );
+                self.payload.write_all(&self.slice_bytes.v[..])?;
+                self.slab.clear();
+            }
+            return Ok(());
+        }
+
+        self.slice_bytes.v.clear();
+        if self.encoding == SdfEncoding::GradientZlib {
+            let voxels = T::as_u16_slice(voxels).unwrap();
+            let prev = if self.z > 0 {
+                Some(&self.prev_slice[..])
+            } else {
+                None
+            };
+            compress_preprocess_slice(
+                self.header.dim,
+                self.predictor,
+                prev,
+                voxels,
+                &mut self.slice_predicted,
+            );
+
+            self.prev_slice.clear();
+            self.prev_slice.extend_from_slice(voxels);
+            self.slice_bytes.store_array_u16(&self.slice_predicted);
+        } else {
+            for &v in voxels {
+                v.store(&mut self.slice_bytes);
+            }
+        }
+        self.payload.write_all(&self.slice_bytes.v[..])?;
+
+        self.z += 1;
+        Ok(())
+    }
+
+    // Flushes the payload and completes the file header. Returns the writer, positioned at
+    // the end of the written data.
+    pub fn finish(self) -> SdfResult<W> {
+        if self.z != self.header.dim.2 {
+            let slice_bytes = (self.slice_size() * self.voxel_type.size()) as u64;
+            return Err(SdfError::SizeMismatch {
+                dim: self.header.dim,
+                expected: slice_bytes * self.header.dim.2 as u64,
+                actual: slice_bytes * self.z as u64,
+            });
+        }
+
+        let PayloadSink {
+            mut writer,
+            length,
+            crc,
+        } = self.payload.finish()?;
+
+        if let Some(container_start) = self.container_start {
+            let end = writer.stream_position()?;
+            writer.seek(SeekFrom::Start(
+                container_start + SDF_FILE_PAYLOAD_INFO_OFFSET,
+            ))?;
+            writer.write_all(&length.to_le_bytes()[..])?;
+            writer.write_all(&crc.finish().to_le_bytes()[..])?;
+            writer.seek(SeekFrom::Start(end))?;
+        }
+
+        writer.flush()?;
+        Ok(writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn test_sdf() -> Sdf {
+        let header = SdfHeader {
+            dim: (9, 10, 11),
+            box_min: (0.0, 0.0, 0.0),
+            dx: 0.1,
+        };
+        let voxels = (0..9 * 10 * 11)
+            .map(|i: u32| header.encode_distance((i % 17) as f32 * 0.05 - 0.4))
+            .collect();
+        Sdf { header, voxels }
+    }
+
+    fn write_sdf(mut writer: SdfWriter<Cursor<Vec<u8>>>, sdf: &Sdf) -> Vec<u8> {
+        for slice in sdf.voxels.chunks(writer.slice_size()) {
+            writer.write_slice(slice).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    // (name, legacy compressed flag (None = versioned), bytes)
+    fn test_files(sdf: &Sdf) -> Vec<(&'static str, Option<bool>, Vec<u8>)> {
+        let cursor = || Cursor::new(Vec::new());
+        let versioned = |encoding| {
+            SdfWriter::new(cursor(), sdf.header, encoding, SdfPredictor::Eikonal).unwrap()
+        };
+        let quantization = SdfQuantization::new(&sdf.header, 0.002, None);
+        vec![
+            (
+                "legacy raw",
+                Some(false),
+                write_sdf(
+                    SdfWriter::new_legacy(cursor(), sdf.header, false).unwrap(),
+                    sdf,
+                ),
+            ),
+            (
+                "legacy zlib",
+                Some(true),
+                write_sdf(
+                    SdfWriter::new_legacy(cursor(), sdf.header, true).unwrap(),
+                    sdf,
+                ),
+            ),
+            ("raw", None, write_sdf(versioned(SdfEncoding::Raw), sdf)),
+            ("zlib", None, write_sdf(versioned(SdfEncoding::Zlib), sdf)),
+            (
+                "gradient",
+                None,
+                write_sdf(versioned(SdfEncoding::GradientZlib), sdf),
+            ),
+            (
+                "quantized",
+                None,
+                write_sdf(
+                    SdfWriter::new_quantized(cursor(), sdf.header, &quantization).unwrap(),
+                    sdf,
+                ),
+            ),
+        ]
+    }
+
+    fn open<R: Read>(reader: R, legacy: Option<bool>) -> SdfResult<SdfReader<R>> {
+        match legacy {
+            Some(compressed) => SdfReader::new_legacy(reader, compressed),
+            None => SdfReader::new(reader),
+        }
+    }
+
+    // Reads slice by slice. Returns the slices read before the error.
+    fn read_slices<R: Read>(reader: R, legacy: Option<bool>) -> (usize, SdfResult<Vec<u16>>) {
+        let mut reader = match open(reader, legacy) {
+            Ok(reader) => reader,
+            Err(err) => return (0, Err(err)),
+        };
+        // Corrupted dimensions: the caller sized buffers would be huge, read the volume instead
+        if reader.header().dim != (9, 10, 11) {
+            return (0, reader.read_sdf().map(|sdf| sdf.voxels));
+        }
+        let mut voxels = vec![0u16; reader.slice_size() * reader.header().dim.2 as usize];
+        let slice_size = reader.slice_size();
+        for (z, slice) in voxels.chunks_mut(slice_size).enumerate() {
+            if let Err(err) = reader.read_slice(slice) {
+                return (z, Err(err));
+            }
+        }
+        (voxels.len() / slice_size, Ok(voxels))
+    }
+
+    #[test]
+    fn slice_round_trip() {
+        let sdf = test_sdf();
+        for (name, legacy, bytes) in test_files(&sdf) {
+            let (slices, voxels) = read_slices(&bytes[..], legacy);
+            assert_eq!(slices, 11, "{}", name);
+            let voxels = voxels.unwrap();
+            if name == "quantized" {
+                let quantization = SdfQuantization::new(&sdf.header, 0.002, None);
+                assert_eq!(voxels, quantize_sdf(&sdf, &quantization).voxels);
+            } else {
+                assert_eq!(voxels, sdf.voxels, "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_slices() {
+        let sdf = test_sdf();
+        for (name, legacy, bytes) in test_files(&sdf) {
+            let mut last_slices = 0;
+            for len in 0..bytes.len() {
+                let (slices, result) = read_slices(&bytes[..len], legacy);
+                match result {
+                    Err(SdfError::Truncated { .. }) | Err(SdfError::Decompress(_)) => (),
+                    result => panic!("{}, length {}: {:?}", name, len, result.err()),
+                }
+                // Slices before the cut are read
+                assert!(slices >= last_slices, "{}, length {}", name, len);
+                last_slices = slices;
+            }
+            // Uncompressed slices are complete before the end of the file
+            if legacy == Some(false) || name == "raw" {
+                assert_eq!(last_slices, 10, "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn bit_flips() {
+        let sdf = test_sdf();
+        for (name, legacy, bytes) in test_files(&sdf) {
+            let payload_start = match legacy {
+                Some(_) => bytes.len(),
+                None => SDF_FILE_HEADER_SIZE,
+            };
+            for i in 0..bytes.len() {
+                let mut bytes = bytes.clone();
+                bytes[i] ^= 1 << (i % 8);
+                let (_, result) = read_slices(&bytes[..], legacy);
+                if i >= payload_start {
+                    assert!(result.is_err(), "{}, byte {}", name, i);
+                }
+            }
+        }
+    }
+
+    // Fails with an I/O error after the given number of bytes
+    struct FailingReader<'a> {
+        bytes: &'a [u8],
+        remaining: usize,
+    }
+
+    impl Read for FailingReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.remaining == 0 {
+                return Err(io::Error::other("read failed"));
+            }
+            let n = buf.len().min(self.remaining).min(self.bytes.len());
+            buf[..n].copy_from_slice(&self.bytes[..n]);
+            self.bytes = &self.bytes[n..];
+            self.remaining -= n;
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn read_errors() {
+        let sdf = test_sdf();
+        for (name, legacy, bytes) in test_files(&sdf) {
+            for &fail_at in &[0, 10, SDF_FILE_HEADER_SIZE + 1, bytes.len() / 2] {
+                let reader = FailingReader {
+                    bytes: &bytes[..],
+                    remaining: fail_at,
+                };
+                let (_, result) = read_slices(reader, legacy);
+                assert!(
+                    matches!(result, Err(SdfError::Io(_))),
+                    "{}, fail at {}: {:?}",
+                    name,
+                    fail_at,
+                    result.err()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn missing_slices() {
+        let sdf = test_sdf();
+        let mut writer = SdfWriter::new(
+            Cursor::new(Vec::new()),
+            sdf.header,
+            SdfEncoding::Zlib,
+            SdfPredictor::Planar,
+        )
+        .unwrap();
+        writer
+            .write_slice(&sdf.voxels[..writer.slice_size()])
+            .unwrap();
+        assert!(matches!(
+            writer.finish(),
+            Err(SdfError::SizeMismatch { .. })
+        ));
+    }
+}
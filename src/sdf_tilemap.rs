@@ -0,0 +1,571 @@
+// Edge tiles of a SDF mip chain (sdf2tilemap output). Each level is split into tiles of
+// tile_size_payload voxels per axis. A tile stores tile_size_outer voxels per axis, the
+// border duplicates the neighbor's first voxels for filtering. Only tiles crossing the zero
+// level are stored.
+//
+// File layout, little endian:
+// magic "RTMP", version u32, level 0 SdfHeader (dim, box_min, dx), tile size outer u32,
+// tile size payload u32, level count u32,
+// per level: SdfHeader (mip dim, box_min, dx), tile grid 3 x u32, first tile u32, tile count u32,
+// packed tile coordinates u32 per tile, tile voxels (u16, outer^3 per tile).
+// Tiles are sorted by level and packed coordinate, tile lookup is a binary search.
+
+use std::fs;
+use std::ops::Range;
+
+use crate::error::*;
+use crate::minivector::*;
+use crate::sdf::*;
+use crate::sdf_mips::*;
+use crate::serialization::*;
+
+pub const TILEMAP_MAGIC: &[u8; 4] = b"RTMP";
+pub const TILEMAP_VERSION: u32 = 1;
+
+// Bits per axis of a packed tile coordinate
+pub const TILE_COORD_BITS: u32 = 10;
+pub const MAX_TILE_GRID: u32 = 1 << TILE_COORD_BITS;
+
+// x in the lowest bits: sorted packed coordinates are in z, y, x order
+pub fn pack_tile_coord(coord: (u32, u32, u32)) -> u32 {
+    coord.0 | (coord.1 << TILE_COORD_BITS) | (coord.2 << (TILE_COORD_BITS * 2))
+}
+
+pub fn unpack_tile_coord(packed: u32) -> (u32, u32, u32) {
+    let mask = MAX_TILE_GRID - 1;
+    (
+        packed & mask,
+        (packed >> TILE_COORD_BITS) & mask,
+        (packed >> (TILE_COORD_BITS * 2)) & mask,
+    )
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TileMapLevel {
+    // Mip dimensions and world placement
+    pub header: SdfHeader,
+    // Tiles per axis (stored or not)
+    pub tile_grid: (u32, u32, u32),
+    // Index of the first tile of the level in the whole map
+    pub first_tile: u32,
+    pub tile_count: u32,
+}
+
+pub struct TileMap {
+    pub header: SdfHeader,
+    pub tile_size_outer: u32,
+    pub tile_size_payload: u32,
+    pub levels: Vec<TileMapLevel>,
+    // Packed, see pack_tile_coord
+    pub tile_coords: Vec<u32>,
+    pub voxels: Vec<u16>,
+}
+
+impl TileMap {
+    pub fn tile_voxel_count(&self) -> usize {
+        (self.tile_size_outer as usize).pow(3)
+    }
+
+    pub fn tile_count(&self) -> usize {
+        self.tile_coords.len()
+    }
+
+    pub fn level_tiles(&self, level: usize) -> Range<usize> {
+        let level = &self.levels[level];
+        let first = level.first_tile as usize;
+        first..first + level.tile_count as usize
+    }
+
+    pub fn tile_coord(&self, tile: usize) -> (u32, u32, u32) {
+        unpack_tile_coord(self.tile_coords[tile])
+    }
+
+    // tile_size_outer^3 voxels, x fastest
+    pub fn tile_voxels(&self, tile: usize) -> &[u16] {
+        let count = self.tile_voxel_count();
+        &self.voxels[tile * count..(tile + 1) * count]
+    }
+
+    // None if the tile has no surface (not stored) or is outside of the grid
+    pub fn find_tile(&self, level: usize, coord: (u32, u32, u32)) -> Option<usize> {
+        let grid = self.levels[level].tile_grid;
+        if coord.0 >= grid.0 || coord.1 >= grid.1 || coord.2 >= grid.2 {
+            return None;
+        }
+        let tiles = self.level_tiles(level);
+        let first = tiles.start;
+        self.tile_coords[tiles]
+            .binary_search(&pack_tile_coord(coord))
+            .ok()
+            .map(|i| first + i)
+    }
+
+    // Tile grid coordinate of a world space position, None outside of the grid
+    pub fn tile_coord_at(&self, level: usize, world_pos: Vec3) -> Option<(u32, u32, u32)> {
+        let level = &self.levels[level];
+        let header = &level.header;
+        let tile_size = header.dx * self.tile_size_payload as f32;
+        let tile = |p: f32, min: f32, grid: u32| {
+            let t = ((p - min) / tile_size).floor();
+            if t >= 0.0 && t < grid as f32 {
+                Some(t as u32)
+            } else {
+                None
+            }
+        };
+        Some((
+            tile(world_pos.x, header.box_min.0, level.tile_grid.0)?,
+            tile(world_pos.y, header.box_min.1, level.tile_grid.1)?,
+            tile(world_pos.z, header.box_min.2, level.tile_grid.2)?,
+        ))
+    }
+
+    // Stored tile containing a world space position
+    pub fn tile_at(&self, level: usize, world_pos: Vec3) -> Option<usize> {
+        let coord = self.tile_coord_at(level, world_pos)?;
+        self.find_tile(level, coord)
+    }
+
+    // World position of the first voxel of a tile
+    pub fn tile_position(&self, level: usize, coord: (u32, u32, u32)) -> Vec3 {
+        let header = &self.levels[level].header;
+        let tile_size = header.dx * self.tile_size_payload as f32;
+        Vec3 {
+            x: header.box_min.0 + coord.0 as f32 * tile_size,
+            y: header.box_min.1 + coord.1 as f32 * tile_size,
+            z: header.box_min.2 + coord.2 as f32 * tile_size,
+        }
+    }
+}
+
+// Tiles whose outer voxels fit in the level
+fn tile_grid(
+    dim: (u32, u32, u32),
+    tile_size_outer: u32,
+    tile_size_payload: u32,
+) -> (u32, u32, u32) {
+    let border = tile_size_outer - tile_size_payload;
+    let tiles = |d: u32| d.saturating_sub(border) / tile_size_payload;
+    (tiles(dim.0), tiles(dim.1), tiles(dim.2))
+}
+
+// Edge tiles: outer voxels contain both inside and outside distances
+pub fn build_tilemap(
+    sdf_levels: &[SdfLevel],
+    tile_size_outer: u32,
+    tile_size_payload: u32,
+) -> SdfResult<TileMap> {
+    if tile_size_payload == 0 || tile_size_payload > tile_size_outer {
+        return Err(SdfError::InvalidTilemap(
+            "tile payload must be between 1 and the outer size",
+        ));
+    }
+
+    let mut levels = Vec::with_capacity(sdf_levels.len());
+    let mut tile_coords = Vec::new();
+    let mut voxels = Vec::new();
+
+    for level in sdf_levels.iter() {
+        let sdf = &level.sdf;
+        let dim = sdf.header.dim;
+        let grid = tile_grid(dim, tile_size_outer, tile_size_payload);
+        if grid.0 > MAX_TILE_GRID || grid.1 > MAX_TILE_GRID || grid.2 > MAX_TILE_GRID {
+            return Err(SdfError::InvalidTilemap("more than 1024 tiles per axis"));
+        }
+
+        let stride_y = dim.0 as usize;
+        let stride_z = dim.0 as usize * dim.1 as usize;
+        let first_tile = tile_coords.len() as u32;
+
+        for z in 0..grid.2 {
+            for y in 0..grid.1 {
+                for x in 0..grid.0 {
+                    let tile_offset = tile_size_payload as usize
+                        * (z as usize * stride_z + y as usize * stride_y + x as usize);
+                    let outer = 0..tile_size_outer as usize;
+                    let tile_voxel = |ix: usize, iy: usize, iz: usize| {
+                        sdf.voxels[tile_offset + iz * stride_z + iy * stride_y + ix]
+                    };
+
+                    let mut has_inside = false;
+                    let mut has_outside = false;
+                    for iz in outer.clone() {
+                        for iy in outer.clone() {
+                            for ix in outer.clone() {
+                                let d = tile_voxel(ix, iy, iz);
+                                has_inside |= d < SDF_LEVEL_ZERO;
+                                has_outside |= d > SDF_LEVEL_ZERO;
+                            }
+                        }
+                    }
+
+                    if has_inside && has_outside {
+                        tile_coords.push(pack_tile_coord((x, y, z)));
+                        for iz in outer.clone() {
+                            for iy in outer.clone() {
+                                for ix in outer.clone() {
+                                    voxels.push(tile_voxel(ix, iy, iz));
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        levels.push(TileMapLevel {
+            header: sdf.header,
+            tile_grid: grid,
+            first_tile,
+            tile_count: tile_coords.len() as u32 - first_tile,
+        });
+    }
+
+    Ok(TileMap {
+        header: sdf_levels.first().map_or(
+            SdfHeader {
+                dim: (0, 0, 0),
+                box_min: (0.0, 0.0, 0.0),
+                dx: 0.0,
+            },
+            |level| level.sdf.header,
+        ),
+        tile_size_outer,
+        tile_size_payload,
+        levels,
+        tile_coords,
+        voxels,
+    })
+}
+
+pub fn store_tilemap_bytes(tilemap: &TileMap) -> Vec<u8> {
+    let mut storer = StorerVec::new();
+    storer.store_array_u8(&TILEMAP_MAGIC[..]);
+    storer.store_u32(TILEMAP_VERSION);
+    store_sdf_header(&mut storer, &tilemap.header);
+    storer.store_u32(tilemap.tile_size_outer);
+    storer.store_u32(tilemap.tile_size_payload);
+    storer.store_u32(tilemap.levels.len() as u32);
+    for level in tilemap.levels.iter() {
+        store_sdf_header(&mut storer, &level.header);
+        storer.store_u32(level.tile_grid.0);
+        storer.store_u32(level.tile_grid.1);
+        storer.store_u32(level.tile_grid.2);
+        storer.store_u32(level.first_tile);
+        storer.store_u32(level.tile_count);
+    }
+    for &coord in tilemap.tile_coords.iter() {
+        storer.store_u32(coord);
+    }
+    storer.store_array_u16(&tilemap.voxels);
+    storer.v
+}
+
+pub fn store_tilemap(filename: &str, tilemap: &TileMap) -> SdfResult<()> {
+    let bytes = store_tilemap_bytes(tilemap);
+    fs::write(filename, &bytes)?;
+
+    println!("Store tilemap: write {} bytes", bytes.len());
+
+    Ok(())
+}
+
+pub fn parse_tilemap(bytes: &[u8]) -> SdfResult<TileMap> {
+    if bytes.len() < TILEMAP_MAGIC.len() || &bytes[..TILEMAP_MAGIC.len()] != TILEMAP_MAGIC {
+        return Err(SdfError::InvalidTilemap("bad magic"));
+    }
+
+    let mut loader = Loader::new();
+    loader.offset = TILEMAP_MAGIC.len();
+
+    let version = loader.load_u32(bytes)?;
+    if version == 0 || version > TILEMAP_VERSION {
+        return Err(SdfError::UnsupportedVersion(version));
+    }
+
+    let header = load_sdf_header(&mut loader, bytes)?;
+    let tile_size_outer = loader.load_u32(bytes)?;
+    let tile_size_payload = loader.load_u32(bytes)?;
+    if tile_size_payload == 0 || tile_size_payload > tile_size_outer {
+        return Err(SdfError::InvalidTilemap(
+            "tile payload must be between 1 and the outer size",
+        ));
+    }
+    let tile_voxel_count = (tile_size_outer as usize)
+        .checked_pow(3)
+        .ok_or(SdfError::InvalidTilemap("tile size too large"))?;
+
+    // Not preallocated: the counts are validated by reading the data
+    let level_count = loader.load_u32(bytes)?;
+    let mut levels = Vec::new();
+    let mut tile_count = 0u64;
+    for _ in 0..level_count {
+        let level = TileMapLevel {
+            header: load_sdf_header(&mut loader, bytes)?,
+            tile_grid: (
+                loader.load_u32(bytes)?,
+                loader.load_u32(bytes)?,
+                loader.load_u32(bytes)?,
+            ),
+            first_tile: loader.load_u32(bytes)?,
+            tile_count: loader.load_u32(bytes)?,
+        };
+        let grid = level.tile_grid;
+        if grid.0 > MAX_TILE_GRID || grid.1 > MAX_TILE_GRID || grid.2 > MAX_TILE_GRID {
+            return Err(SdfError::InvalidTilemap("more than 1024 tiles per axis"));
+        }
+        if level.first_tile as u64 != tile_count {
+            return Err(SdfError::InvalidTilemap(
+                "level tile offsets are not contiguous",
+            ));
+        }
+        tile_count += level.tile_count as u64;
+        levels.push(level);
+    }
+
+    let tile_count = tile_count as usize;
+    let mut tile_coords = Vec::new();
+    for _ in 0..tile_count {
+        tile_coords.push(loader.load_u32(bytes)?);
+    }
+    let voxel_count = tile_count
+        .checked_mul(tile_voxel_count)
+        .ok_or(SdfError::InvalidTilemap("tile count too large"))?;
+    let voxels = loader.load_array_u16(bytes, voxel_count)?;
+
+    let tilemap = TileMap {
+        header,
+        tile_size_outer,
+        tile_size_payload,
+        levels,
+        tile_coords,
+        voxels,
+    };
+
+    // Tile coordinates inside of the grid and sorted (binary search)
+    for (i, level) in tilemap.levels.iter().enumerate() {
+        let grid = level.tile_grid;
+        let coords = &tilemap.tile_coords[tilemap.level_tiles(i)];
+        let inside = coords.iter().all(|&c| {
+            let (x, y, z) = unpack_tile_coord(c);
+            pack_tile_coord((x, y, z)) == c && x < grid.0 && y < grid.1 && z < grid.2
+        });
+        if !inside || coords.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err(SdfError::InvalidTilemap("invalid tile coordinates"));
+        }
+    }
+
+    if loader.offset != bytes.len() {
+        return Err(SdfError::InvalidTilemap("trailing data"));
+    }
+
+    Ok(tilemap)
+}
+
+pub fn load_tilemap(filename: &str) -> SdfResult<TileMap> {
+    let tilemap = parse_tilemap(&fs::read(filename)?)?;
+
+    println!(
+        "Loaded tilemap: {:?}, tile size = {}, payload = {}, levels = {}, tiles = {}",
+        tilemap.header,
+        tilemap.tile_size_outer,
+        tilemap.tile_size_payload,
+        tilemap.levels.len(),
+        tilemap.tile_count()
+    );
+
+    Ok(tilemap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sdf_sampling::*;
+
+    const TILE_SIZE_OUTER: u32 = 8;
+    const TILE_SIZE_PAYLOAD: u32 = 7;
+
+    fn test_tilemap() -> (Vec<SdfLevel>, TileMap) {
+        let header = SdfHeader {
+            dim: (64, 57, 50),
+            box_min: (-1.6, -1.4, -1.2),
+            dx: 0.05,
+        };
+        let sdf: Sdf = sample_sdf(header, |p| p.length() - 0.9).unwrap();
+        let levels = build_sdf_mips(sdf, 3, MipFilter::Conservative);
+        let tilemap = build_tilemap(&levels, TILE_SIZE_OUTER, TILE_SIZE_PAYLOAD).unwrap();
+        (levels, tilemap)
+    }
+
+    // Outer voxels of a tile, x fastest
+    fn level_tile_voxels(sdf: &Sdf, coord: (u32, u32, u32)) -> Vec<u16> {
+        let dim = sdf.header.dim;
+        let mut voxels = Vec::new();
+        for z in 0..TILE_SIZE_OUTER {
+            for y in 0..TILE_SIZE_OUTER {
+                for x in 0..TILE_SIZE_OUTER {
+                    let vx = coord.0 * TILE_SIZE_PAYLOAD + x;
+                    let vy = coord.1 * TILE_SIZE_PAYLOAD + y;
+                    let vz = coord.2 * TILE_SIZE_PAYLOAD + z;
+                    voxels.push(sdf.voxels[(vx + (vy + vz * dim.1) * dim.0) as usize]);
+                }
+            }
+        }
+        voxels
+    }
+
+    fn assert_same_header(a: &SdfHeader, b: &SdfHeader) {
+        assert_eq!(a.dim, b.dim);
+        assert_eq!(a.box_min, b.box_min);
+        assert_eq!(a.dx, b.dx);
+    }
+
+    #[test]
+    fn pack_coords() {
+        for &coord in [(0, 0, 0), (1, 2, 3), (1023, 0, 512), (1023, 1023, 1023)].iter() {
+            assert_eq!(unpack_tile_coord(pack_tile_coord(coord)), coord);
+        }
+        // z, y, x order
+        assert!(pack_tile_coord((1023, 1023, 0)) < pack_tile_coord((0, 0, 1)));
+        assert!(pack_tile_coord((1023, 0, 0)) < pack_tile_coord((0, 1, 0)));
+    }
+
+    #[test]
+    fn round_trip() {
+        let (_, tilemap) = test_tilemap();
+        assert!(tilemap.tile_count() > 0);
+        let loaded = parse_tilemap(&store_tilemap_bytes(&tilemap)).unwrap();
+
+        assert_same_header(&loaded.header, &tilemap.header);
+        assert_eq!(loaded.tile_size_outer, TILE_SIZE_OUTER);
+        assert_eq!(loaded.tile_size_payload, TILE_SIZE_PAYLOAD);
+        assert_eq!(loaded.levels.len(), tilemap.levels.len());
+        for (a, b) in loaded.levels.iter().zip(tilemap.levels.iter()) {
+            assert_same_header(&a.header, &b.header);
+            assert_eq!(a.tile_grid, b.tile_grid);
+            assert_eq!(a.first_tile, b.first_tile);
+            assert_eq!(a.tile_count, b.tile_count);
+        }
+        assert_eq!(loaded.tile_coords, tilemap.tile_coords);
+        assert!(loaded.voxels == tilemap.voxels);
+    }
+
+    #[test]
+    fn lookup() {
+        let (levels, built) = test_tilemap();
+        let tilemap = parse_tilemap(&store_tilemap_bytes(&built)).unwrap();
+
+        for (level, sdf_level) in levels.iter().enumerate() {
+            let sdf = &sdf_level.sdf;
+            let grid = tilemap.levels[level].tile_grid;
+            let tile_size = sdf.header.dx * TILE_SIZE_PAYLOAD as f32;
+            let mut stored = 0;
+            for z in 0..grid.2 {
+                for y in 0..grid.1 {
+                    for x in 0..grid.0 {
+                        let coord = (x, y, z);
+                        let voxels = level_tile_voxels(sdf, coord);
+                        let edge = voxels.iter().any(|&v| v < SDF_LEVEL_ZERO)
+                            && voxels.iter().any(|&v| v > SDF_LEVEL_ZERO);
+
+                        let tile = tilemap.find_tile(level, coord);
+                        assert_eq!(tile.is_some(), edge, "level {} {:?}", level, coord);
+
+                        // Center of the tile payload
+                        let center = tilemap.tile_position(level, coord)
+                            + Vec3::from_scalar(tile_size * 0.5);
+                        assert_eq!(tilemap.tile_coord_at(level, center), Some(coord));
+                        assert_eq!(tilemap.tile_at(level, center), tile);
+
+                        if let Some(tile) = tile {
+                            assert!(tilemap.level_tiles(level).contains(&tile));
+                            assert_eq!(tilemap.tile_coord(tile), coord);
+                            assert!(tilemap.tile_voxels(tile) == &voxels[..]);
+                            stored += 1;
+                        }
+                    }
+                }
+            }
+            assert_eq!(stored, tilemap.levels[level].tile_count);
+
+            // Outside of the grid
+            assert_eq!(tilemap.find_tile(level, (grid.0, 0, 0)), None);
+            assert_eq!(tilemap.find_tile(level, (0, 0, grid.2)), None);
+            let before = tilemap.tile_position(level, (0, 0, 0)) - Vec3::from_scalar(0.01);
+            assert_eq!(tilemap.tile_at(level, before), None);
+            let after = tilemap.tile_position(level, grid) + Vec3::from_scalar(0.01);
+            assert_eq!(tilemap.tile_at(level, after), None);
+        }
+    }
+
+    #[test]
+    fn corrupted_offsets() {
+        let (_, tilemap) = test_tilemap();
+        let corrupted = |edit: &dyn Fn(&mut TileMap)| {
+            let mut tilemap = TileMap {
+                levels: tilemap.levels.clone(),
+                tile_coords: tilemap.tile_coords.clone(),
+                voxels: tilemap.voxels.clone(),
+                ..tilemap
+            };
+            edit(&mut tilemap);
+            parse_tilemap(&store_tilemap_bytes(&tilemap))
+        };
+
+        assert!(corrupted(&|t| t.levels[1].first_tile += 1).is_err());
+        assert!(corrupted(&|t| t.levels[1].first_tile -= 1).is_err());
+        assert!(corrupted(&|t| t.levels[0].tile_count += 1).is_err());
+        assert!(corrupted(&|t| t.levels[2].tile_count = u32::MAX).is_err());
+        assert!(corrupted(&|t| t.levels[0].tile_grid.0 = MAX_TILE_GRID + 1).is_err());
+        assert!(corrupted(&|t| t.tile_size_payload = t.tile_size_outer + 1).is_err());
+        assert!(corrupted(&|t| t.tile_size_payload = 0).is_err());
+        assert!(corrupted(&|t| {
+            t.voxels.pop();
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn invalid_coordinates() {
+        let (_, tilemap) = test_tilemap();
+        let count = tilemap.levels[0].tile_count as usize;
+        assert!(count >= 2);
+        let corrupted = |edit: &dyn Fn(&mut Vec<u32>)| {
+            let mut tile_coords = tilemap.tile_coords.clone();
+            edit(&mut tile_coords);
+            let tilemap = TileMap {
+                levels: tilemap.levels.clone(),
+                tile_coords,
+                voxels: tilemap.voxels.clone(),
+                ..tilemap
+            };
+            parse_tilemap(&store_tilemap_bytes(&tilemap))
+        };
+
+        // Unsorted, duplicate, outside of the grid, bits above the packed coordinate
+        assert!(corrupted(&|c| c.swap(0, 1)).is_err());
+        assert!(corrupted(&|c| c[1] = c[0]).is_err());
+        assert!(corrupted(&|c| c[count - 1] = pack_tile_coord((1023, 1023, 1023))).is_err());
+        assert!(corrupted(&|c| c[count - 1] |= 1 << 31).is_err());
+        assert!(corrupted(&|_| ()).is_ok());
+    }
+
+    #[test]
+    fn truncated() {
+        let (_, tilemap) = test_tilemap();
+        let bytes = store_tilemap_bytes(&tilemap);
+        for len in (0..bytes.len()).step_by(97) {
+            assert!(parse_tilemap(&bytes[..len]).is_err(), "length {}", len);
+        }
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(parse_tilemap(&trailing).is_err());
+
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'X';
+        assert!(parse_tilemap(&bad_magic).is_err());
+        let mut bad_version = bytes;
+        bad_version[4] = 2;
+        assert!(parse_tilemap(&bad_version).is_err());
+    }
+}
@@ -0,0 +1,270 @@
+// Iso-surface extraction from a SDF volume using surface nets (dual contouring with
+// mass point vertices). Each voxel cell crossing the iso-level gets one vertex, placed at
+// the average of the edge crossings. Each crossing edge emits a quad connecting the four
+// cells sharing the edge. No lookup tables needed and the output is watertight.
+
+use crate::mesh::*;
+use crate::minivector::*;
+use crate::sdf::*;
+
+pub fn sdf_to_mesh(sdf: &Sdf, level: u16) -> Mesh {
+    let dim = [
+        sdf.header.dim.0 as i32,
+        sdf.header.dim.1 as i32,
+        sdf.header.dim.2 as i32,
+    ];
+
+    let level = level as f32;
+    let stride = [1, dim[0] as usize, dim[0] as usize * dim[1] as usize];
+
+    let voxel = |p: [i32; 3]| -> f32 {
+        let x = p[0].clamp(0, dim[0] - 1);
+        let y = p[1].clamp(0, dim[1] - 1);
+        let z = p[2].clamp(0, dim[2] - 1);
+        sdf.voxels[x as usize * stride[0] + y as usize * stride[1] + z as usize * stride[2]] as f32
+    };
+
+    // Gradient (central differences) points towards increasing distance
+    let gradient = |p: [i32; 3]| -> Vec3 {
+        Vec3 {
+            x: voxel([p[0] + 1, p[1], p[2]]) - voxel([p[0] - 1, p[1], p[2]]),
+            y: voxel([p[0], p[1] + 1, p[2]]) - voxel([p[0], p[1] - 1, p[2]]),
+            z: voxel([p[0], p[1], p[2] + 1]) - voxel([p[0], p[1], p[2] - 1]),
+        }
+    };
+
+    let box_min = Vec3 {
+        x: sdf.header.box_min.0,
+        y: sdf.header.box_min.1,
+        z: sdf.header.box_min.2,
+    };
+    let dx = sdf.header.dx;
+
+    let cell_dim = [dim[0] - 1, dim[1] - 1, dim[2] - 1];
+    if cell_dim.iter().any(|&d| d <= 0) {
+        return Mesh {
+            positions: Vec::new(),
+            normals: Vec::new(),
+            indices: Vec::new(),
+            groups: Vec::new(),
+        };
+    }
+    let cell_stride = [
+        1,
+        cell_dim[0] as usize,
+        cell_dim[0] as usize * cell_dim[1] as usize,
+    ];
+    let cell_index = |c: [i32; 3]| {
+        c[0] as usize * cell_stride[0]
+            + c[1] as usize * cell_stride[1]
+            + c[2] as usize * cell_stride[2]
+    };
+
+    let mut cell_vertex = vec![u32::MAX; cell_stride[2] * cell_dim[2] as usize];
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+
+    // Cell corners and edges (corner pairs)
+    let corners: [[i32; 3]; 8] = [
+        [0, 0, 0],
+        [1, 0, 0],
+        [0, 1, 0],
+        [1, 1, 0],
+        [0, 0, 1],
+        [1, 0, 1],
+        [0, 1, 1],
+        [1, 1, 1],
+    ];
+    let edges: [(usize, usize); 12] = [
+        (0, 1),
+        (2, 3),
+        (4, 5),
+        (6, 7),
+        (0, 2),
+        (1, 3),
+        (4, 6),
+        (5, 7),
+        (0, 4),
+        (1, 5),
+        (2, 6),
+        (3, 7),
+    ];
+
+    // Vertices
+    for z in 0..cell_dim[2] {
+        for y in 0..cell_dim[1] {
+            for x in 0..cell_dim[0] {
+                let c = [x, y, z];
+                let mut values = [0.0f32; 8];
+                for (value, corner) in values.iter_mut().zip(corners.iter()) {
+                    *value = voxel([x + corner[0], y + corner[1], z + corner[2]]);
+                }
+
+                let inside_count = values.iter().filter(|&&v| v < level).count();
+                if inside_count == 0 || inside_count == 8 {
+                    continue;
+                }
+
+                let mut sum = Vec3::from_scalar(0.0);
+                let mut normal = Vec3::from_scalar(0.0);
+                let mut crossings = 0;
+                for &(a, b) in edges.iter() {
+                    let (va, vb) = (values[a], values[b]);
+                    if (va < level) == (vb < level) {
+                        continue;
+                    }
+
+                    let t = (level - va) / (vb - va);
+                    let ca = corners[a];
+                    let cb = corners[b];
+                    let local = Vec3 {
+                        x: ca[0] as f32 + (cb[0] - ca[0]) as f32 * t,
+                        y: ca[1] as f32 + (cb[1] - ca[1]) as f32 * t,
+                        z: ca[2] as f32 + (cb[2] - ca[2]) as f32 * t,
+                    };
+                    sum = sum + local;
+
+                    let ga = gradient([x + ca[0], y + ca[1], z + ca[2]]);
+                    let gb = gradient([x + cb[0], y + cb[1], z + cb[2]]);
+                    normal = normal + ga * (1.0 - t) + gb * t;
+                    crossings += 1;
+                }
+
+                let local = sum * (1.0 / crossings as f32);
+                let position = Vec3 {
+                    x: x as f32 + local.x,
+                    y: y as f32 + local.y,
+                    z: z as f32 + local.z,
+                };
+
+                cell_vertex[cell_index(c)] = positions.len() as u32;
+                positions.push(box_min + position * dx);
+                normals.push(if normal.length() > 0.0 {
+                    normal.normalize()
+                } else {
+                    normal
+                });
+            }
+        }
+    }
+
+    // Faces: one quad per crossing voxel edge, connecting the 4 cells around the edge
+    let mut indices = Vec::new();
+    for z in 0..dim[2] {
+        for y in 0..dim[1] {
+            for x in 0..dim[0] {
+                let p = [x, y, z];
+                let v0 = voxel(p);
+
+                for axis in 0..3 {
+                    let u = (axis + 1) % 3;
+                    let v = (axis + 2) % 3;
+
+                    let mut q = p;
+                    q[axis] += 1;
+                    if q[axis] >= dim[axis] || p[u] == 0 || p[v] == 0 {
+                        continue;
+                    }
+                    if p[u] >= cell_dim[u] || p[v] >= cell_dim[v] {
+                        continue;
+                    }
+
+                    let v1 = voxel(q);
+                    if (v0 < level) == (v1 < level) {
+                        continue;
+                    }
+
+                    // Cells around the edge, counter clockwise around +axis
+                    let cell = |du: i32, dv: i32| {
+                        let mut c = p;
+                        c[u] -= du;
+                        c[v] -= dv;
+                        cell_vertex[cell_index(c)]
+                    };
+                    let quad = [cell(1, 1), cell(0, 1), cell(0, 0), cell(1, 0)];
+
+                    // Front face points towards outside (increasing distance)
+                    let quad = if v0 < level {
+                        quad
+                    } else {
+                        [quad[0], quad[3], quad[2], quad[1]]
+                    };
+
+                    indices.extend_from_slice(&[quad[0], quad[1], quad[2]]);
+                    indices.extend_from_slice(&[quad[0], quad[2], quad[3]]);
+                }
+            }
+        }
+    }
+
+    Mesh {
+        positions,
+        normals,
+        indices,
+        groups: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sdf_sampling::*;
+    use std::collections::HashMap;
+
+    const RADIUS: f32 = 0.6;
+
+    #[test]
+    fn sphere_surface() {
+        let header = SdfHeader {
+            dim: (37, 41, 43),
+            box_min: (-0.9, -1.0, -1.05),
+            dx: 0.05,
+        };
+        let sdf: Sdf = sample_sdf(header, |p| p.length() - RADIUS).unwrap();
+        let mesh = sdf_to_mesh(&sdf, SDF_LEVEL_ZERO);
+        assert!(mesh.triangle_count() > 100);
+        assert_eq!(mesh.normals.len(), mesh.positions.len());
+
+        // Closed and consistently oriented: each directed edge is used once and its
+        // reverse once
+        let mut edges: HashMap<(u32, u32), u32> = HashMap::new();
+        for triangle in mesh.indices.chunks(3) {
+            for i in 0..3 {
+                *edges
+                    .entry((triangle[i], triangle[(i + 1) % 3]))
+                    .or_insert(0) += 1;
+            }
+        }
+        for (&(a, b), &count) in edges.iter() {
+            assert_eq!(count, 1, "edge {}-{} used {} times", a, b, count);
+            assert_eq!(edges.get(&(b, a)), Some(&1), "edge {}-{} is open", a, b);
+        }
+
+        for p in mesh.positions.iter() {
+            let error = (p.length() - RADIUS).abs();
+            assert!(error <= header.dx * 0.5, "{:?}: error {}", p, error);
+        }
+
+        for t in 0..mesh.triangle_count() {
+            let (a, b, c) = mesh.triangle(t);
+            let normal = (b - a).cross(c - a);
+            let center = (a + b + c) * (1.0 / 3.0);
+            assert!(normal.dot(center) > 0.0, "triangle {} faces inwards", t);
+        }
+        for (p, n) in mesh.positions.iter().zip(mesh.normals.iter()) {
+            assert!(n.dot(p.normalize()) > 0.9, "{:?}: normal {:?}", p, n);
+        }
+    }
+
+    #[test]
+    fn flat_volume() {
+        let header = SdfHeader {
+            dim: (8, 8, 1),
+            box_min: (0.0, 0.0, 0.0),
+            dx: 0.1,
+        };
+        let sdf: Sdf = sample_sdf(header, |p| p.x - 0.35).unwrap();
+        let mesh = sdf_to_mesh(&sdf, SDF_LEVEL_ZERO);
+        assert_eq!(mesh.triangle_count(), 0);
+    }
+}
@@ -0,0 +1,290 @@
+// CPU reference sphere tracer. Mirrors the rendersdf cube renderer (main.vert + main.frag)
+// for a single volume instance: the ray starts at the front face of the instance cube,
+// marches in texture space with the same step threshold and LOD, and outputs the normal
+// as the color. Used as a golden image regression test on machines without a GPU.
+
+use crate::minivector::*;
+use crate::sdf::*;
+use crate::sdf_mips::*;
+use std::thread;
+
+// main.frag
+pub const TRACE_STEP_THRESHOLD: f32 = 0.00025;
+const TRACE_MAX_STEPS: u32 = 1024;
+
+// rendersdf: SDF_LEVELS and the projection near plane
+pub const TRACE_DEFAULT_LEVELS: u32 = 6;
+const TRACE_ZNEAR: f32 = 1.0;
+
+#[derive(Clone, Copy, Debug)]
+pub struct TraceCamera {
+    pub position: Vec3,
+    pub direction: Vec3,
+    // Vertical field of view (radians), rendersdf uses PI / 2
+    pub fovy: f32,
+}
+
+pub struct SdfTracer {
+    levels: Vec<Sdf>,
+    // Instance position (cube center) in world space
+    center: Vec3,
+    center_to_edge: Vec3,
+    volume_scale: Vec3,
+    texel_scale: Vec3,
+}
+
+impl SdfTracer {
+    // Mip levels are built like rendersdf (build_sdf_mips)
+    pub fn new(sdf: &Sdf, levels: u32, filter: MipFilter, center: Vec3) -> SdfTracer {
+        let level_0 = Sdf {
+            header: sdf.header,
+            voxels: sdf.voxels.clone(),
+        };
+        let mips: Vec<Sdf> = build_sdf_mips(level_0, levels, filter)
+            .into_iter()
+            .map(|level| level.sdf)
+            .collect();
+
+        // Uniforms of rendersdf main.rs (padded level 0)
+        let header = mips[0].header;
+        let diagonal = Vec3 {
+            x: header.dx * header.dim.0 as f32,
+            y: header.dx * header.dim.1 as f32,
+            z: header.dx * header.dim.2 as f32,
+        };
+        let texels = Vec3 {
+            x: header.dim.0 as f32,
+            y: header.dim.1 as f32,
+            z: header.dim.2 as f32,
+        };
+
+        SdfTracer {
+            levels: mips,
+            center,
+            center_to_edge: diagonal * 0.5,
+            volume_scale: Vec3::from_scalar(diagonal.length()) / diagonal,
+            texel_scale: Vec3::from_scalar(1.0) / texels,
+        }
+    }
+
+    // Trilinear texture fetch (LINEAR filter, REPEAT addressing, NEAREST mip), UNORM [0, 1]
+    fn sample(&self, uvw: Vec3, lod: f32) -> f32 {
+        // Vulkan nearest mip selection: ceil(lod + 0.5) - 1
+        let max_level = (self.levels.len() - 1) as f32;
+        let lod = lod.clamp(0.0, max_level);
+        let level = ((lod + 0.5).ceil() - 1.0).clamp(0.0, max_level) as usize;
+        let sdf = &self.levels[level];
+        let dim = sdf.header.dim;
+
+        let axis = |u: f32, n: u32| -> (usize, usize, f32) {
+            let t = u * n as f32 - 0.5;
+            let i = t.floor();
+            let i0 = (i as i64).rem_euclid(n as i64) as usize;
+            let i1 = (i as i64 + 1).rem_euclid(n as i64) as usize;
+            (i0, i1, t - i)
+        };
+        let (x0, x1, fx) = axis(uvw.x, dim.0);
+        let (y0, y1, fy) = axis(uvw.y, dim.1);
+        let (z0, z1, fz) = axis(uvw.z, dim.2);
+
+        let texel = |x: usize, y: usize, z: usize| -> f32 {
+            let index = x + (y + z * dim.1 as usize) * dim.0 as usize;
+            sdf.voxels[index] as f32 / 65535.0
+        };
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+
+        let d00 = lerp(texel(x0, y0, z0), texel(x1, y0, z0), fx);
+        let d10 = lerp(texel(x0, y1, z0), texel(x1, y1, z0), fx);
+        let d01 = lerp(texel(x0, y0, z1), texel(x1, y0, z1), fx);
+        let d11 = lerp(texel(x0, y1, z1), texel(x1, y1, z1), fx);
+        lerp(lerp(d00, d10, fy), lerp(d01, d11, fy), fz)
+    }
+
+    // main.frag normal(): central differences half a texel apart
+    fn normal(&self, uvw: Vec3, lod: f32) -> Vec3 {
+        let e = self.texel_scale * 0.5;
+        let s = |x: f32, y: f32, z: f32| self.sample(uvw + Vec3 { x, y, z }, lod);
+        Vec3 {
+            x: s(e.x, 0.0, 0.0) - s(-e.x, 0.0, 0.0),
+            y: s(0.0, e.y, 0.0) - s(0.0, -e.y, 0.0),
+            z: s(0.0, 0.0, e.z) - s(0.0, 0.0, -e.z),
+        }
+        .normalize()
+    }
+
+    // Fragment of the instance cube front face hit by the ray. None = no fragment.
+    fn trace(&self, local_camera_pos: Vec3, ray: Vec3) -> Option<Vec3> {
+        let c2e = self.center_to_edge;
+
+        // Cube front face (slab test)
+        let slab = |o: f32, d: f32, e: f32| -> (f32, f32) {
+            let t0 = (-e - o) / d;
+            let t1 = (e - o) / d;
+            (t0.min(t1), t0.max(t1))
+        };
+        let (x0, x1) = slab(local_camera_pos.x, ray.x, c2e.x);
+        let (y0, y1) = slab(local_camera_pos.y, ray.y, c2e.y);
+        let (z0, z1) = slab(local_camera_pos.z, ray.z, c2e.z);
+        let t_near = x0.max(y0).max(z0);
+        let t_far = x1.min(y1).min(z1);
+
+        // Ray depth is in view space units (ray forward component is 1), near plane clips
+        if !(t_near <= t_far && t_near >= TRACE_ZNEAR) {
+            return None;
+        }
+        let local_pos = local_camera_pos + ray * t_near;
+
+        // main.vert
+        let lod = 0.5 * local_camera_pos.dot(local_camera_pos).log2() - 6.0;
+        let texel_scale_lod = self.texel_scale * lod.clamp(0.0, 5.0).exp2();
+        let face_uvw = Vec3 {
+            x: (local_pos.x / c2e.x * 0.5 + 0.5).clamp(0.0, 1.0),
+            y: (local_pos.y / c2e.y * 0.5 + 0.5).clamp(0.0, 1.0),
+            z: (local_pos.z / c2e.z * 0.5 + 0.5).clamp(0.0, 1.0),
+        };
+        let ray_pos = face_uvw * (Vec3::from_scalar(1.0) - texel_scale_lod) + texel_scale_lod * 0.5;
+
+        // main.frag
+        let ray_dir = (local_pos - local_camera_pos).normalize() * self.volume_scale;
+        let outside = |uvw: Vec3| {
+            (uvw.x - 0.5).abs() > 0.5 || (uvw.y - 0.5).abs() > 0.5 || (uvw.z - 0.5).abs() > 0.5
+        };
+
+        // First step without the outside check
+        let s = self.sample(ray_pos, lod) * 2.0 - 1.0;
+        let mut d = s;
+        if s > TRACE_STEP_THRESHOLD {
+            for _ in 0..TRACE_MAX_STEPS {
+                let uvw = ray_pos + ray_dir * d;
+                if outside(uvw) {
+                    return None;
+                }
+                let s = self.sample(uvw, lod) * 2.0 - 1.0;
+                d += s;
+                if s < TRACE_STEP_THRESHOLD {
+                    break;
+                }
+            }
+        }
+
+        Some(self.normal(ray_pos + ray_dir * d, lod))
+    }
+
+    // RGBA8 image. Background is the rendersdf clear color (transparent black), the normal is
+    // written to a UNORM target (negative components clamp to zero).
+    pub fn render(&self, camera: &TraceCamera, width: u32, height: u32) -> Vec<u8> {
+        let up = Vec3 {
+            x: 0.0,
+            y: 1.0,
+            z: 0.0,
+        };
+        let view = view(camera.position, camera.direction, up);
+        let proj = projection(camera.fovy, width as f32 / height as f32, TRACE_ZNEAR, 1e7);
+
+        let right = Vec3 {
+            x: view.r0.x,
+            y: view.r1.x,
+            z: view.r2.x,
+        };
+        let up = Vec3 {
+            x: view.r0.y,
+            y: view.r1.y,
+            z: view.r2.y,
+        };
+        let forward = Vec3 {
+            x: view.r0.z,
+            y: view.r1.z,
+            z: view.r2.z,
+        };
+        let local_camera_pos = camera.position - self.center;
+
+        let render_row = |y: u32, row: &mut [u8]| {
+            // Vulkan NDC: y down, projection flips view space y
+            let ndc_y = (y as f32 + 0.5) / height as f32 * 2.0 - 1.0;
+            for x in 0..width {
+                let ndc_x = (x as f32 + 0.5) / width as f32 * 2.0 - 1.0;
+                let ray = right * (ndc_x / proj.r0.x) + up * (ndc_y / proj.r1.y) + forward;
+
+                let color = match self.trace(local_camera_pos, ray) {
+                    Some(n) => [n.x, n.y, n.z, 1.0],
+                    None => [0.0; 4],
+                };
+                let pixel = &mut row[x as usize * 4..x as usize * 4 + 4];
+                for (p, c) in pixel.iter_mut().zip(color.iter()) {
+                    *p = (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+                }
+            }
+        };
+
+        let row_size = width as usize * 4;
+        let mut pixels = vec![0u8; row_size * height as usize];
+        if row_size == 0 {
+            return pixels;
+        }
+
+        // Rows interleaved over the threads
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        let mut rows: Vec<(u32, &mut [u8])> = pixels
+            .chunks_mut(row_size)
+            .enumerate()
+            .map(|(y, row)| (y as u32, row))
+            .collect();
+        let chunk = rows.len().div_ceil(threads).max(1);
+        thread::scope(|scope| {
+            for rows in rows.chunks_mut(chunk) {
+                let render_row = &render_row;
+                scope.spawn(move || {
+                    for (y, row) in rows.iter_mut() {
+                        render_row(*y, row);
+                    }
+                });
+            }
+        });
+
+        pixels
+    }
+}
+
+pub fn store_png(filename: &str, width: u32, height: u32, pixels: &[u8]) -> image::ImageResult<()> {
+    image::save_buffer(filename, pixels, width, height, image::ColorType::Rgba8)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ImageDiff {
+    pub max_difference: u8,
+    // Pixels with any channel differing more than the tolerance
+    pub different_pixels: usize,
+}
+
+// Compares an RGBA8 image with a golden image file. None if the image sizes differ.
+pub fn compare_png(
+    filename: &str,
+    width: u32,
+    height: u32,
+    pixels: &[u8],
+    tolerance: u8,
+) -> image::ImageResult<Option<ImageDiff>> {
+    let golden = image::open(filename)?.to_rgba8();
+    if golden.dimensions() != (width, height) {
+        return Ok(None);
+    }
+
+    let mut diff = ImageDiff {
+        max_difference: 0,
+        different_pixels: 0,
+    };
+    for (a, b) in pixels.chunks_exact(4).zip(golden.as_raw().chunks_exact(4)) {
+        let d = a
+            .iter()
+            .zip(b.iter())
+            .map(|(&a, &b)| (a as i32 - b as i32).unsigned_abs() as u8)
+            .max()
+            .unwrap();
+        diff.max_difference = diff.max_difference.max(d);
+        if d > tolerance {
+            diff.different_pixels += 1;
+        }
+    }
+
+    Ok(Some(diff))
+}
@@ -0,0 +1,303 @@
+// Voxel storage types of Sdf. UNORM types store distances relative to the volume diagonal
+// (0.5 = surface, see SdfHeader::encode_distance), float types store world space distances.
+
+use crate::sdf::*;
+use crate::serialization::*;
+use std::convert::TryInto;
+
+// IEEE 754 half precision float (bits). Same layout as u16: voxel slices are uploaded and
+// stored as raw bytes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct F16(pub u16);
+
+impl F16 {
+    // Round to nearest even, out of range values become infinity
+    pub fn from_f32(v: f32) -> F16 {
+        let x = v.to_bits();
+        let sign = ((x >> 16) & 0x8000) as u16;
+        let exp = ((x >> 23) & 0xff) as i32;
+        let man = x & 0x7fffff;
+
+        // Infinity and NaN (keep NaN a NaN)
+        if exp == 0xff {
+            let nan = if man != 0 {
+                0x200 | (man >> 13) as u16
+            } else {
+                0
+            };
+            return F16(sign | 0x7c00 | nan);
+        }
+
+        let e = exp - 127 + 15;
+        if e >= 0x1f {
+            return F16(sign | 0x7c00);
+        }
+
+        let (half, man, shift) = if e <= 0 {
+            // Subnormal (or zero), implicit leading one shifted into the mantissa
+            if e < -10 {
+                return F16(sign);
+            }
+            let man = man | 0x800000;
+            let shift = (14 - e) as u32;
+            (man >> shift, man, shift)
+        } else {
+            (((e as u32) << 10) | (man >> 13), man, 13)
+        };
+
+        // Mantissa carry rounds up to the next exponent (or infinity)
+        let rem = man & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        let round = rem > halfway || (rem == halfway && half & 1 == 1);
+        F16(sign | (half + round as u32) as u16)
+    }
+
+    pub fn to_f32(self) -> f32 {
+        let sign = ((self.0 & 0x8000) as u32) << 16;
+        let exp = ((self.0 >> 10) & 0x1f) as u32;
+        let man = (self.0 & 0x3ff) as u32;
+
+        match exp {
+            0 => {
+                // Subnormal: man * 2^-24
+                let v = man as f32 * (1.0 / 16777216.0);
+                if sign != 0 {
+                    -v
+                } else {
+                    v
+                }
+            }
+            0x1f => f32::from_bits(sign | 0x7f800000 | (man << 13)),
+            _ => f32::from_bits(sign | ((exp + 112) << 23) | (man << 13)),
+        }
+    }
+}
+
+pub trait SdfVoxel: Copy + Default + PartialEq + std::fmt::Debug {
+    const VOXEL_TYPE: VoxelType;
+
+    // World space distance. f64 keeps the conversions between the types exact where possible.
+    fn to_distance(self, header: &SdfHeader) -> f64;
+    fn from_distance(d: f64, header: &SdfHeader) -> Self;
+
+    fn to_world_distance(self, header: &SdfHeader) -> f32 {
+        self.to_distance(header) as f32
+    }
+
+    // Little endian, VOXEL_TYPE.size() bytes
+    fn load(bytes: &[u8]) -> Self;
+    fn store(self, storer: &mut StorerVec);
+
+    // Predictive and quantized encodings work on u16 voxels only
+    fn as_u16_slice(_voxels: &[Self]) -> Option<&[u16]> {
+        None
+    }
+
+    fn as_u16_slice_mut(_voxels: &mut [Self]) -> Option<&mut [u16]> {
+        None
+    }
+
+    fn from_u16_slice(_voxels: &[u16]) -> Option<&[Self]> {
+        None
+    }
+}
+
+fn unorm_to_distance(v: f64, max: f64, header: &SdfHeader) -> f64 {
+    (v / max * 2.0 - 1.0) * header.diagonal_length() as f64
+}
+
+fn unorm_from_distance(d: f64, max: f64, header: &SdfHeader) -> f64 {
+    let s = d / header.diagonal_length() as f64 * 0.5 + 0.5;
+    (s * max).round().clamp(0.0, max)
+}
+
+impl SdfVoxel for u8 {
+    const VOXEL_TYPE: VoxelType = VoxelType::U8Unorm;
+
+    fn to_distance(self, header: &SdfHeader) -> f64 {
+        unorm_to_distance(self as f64, 255.0, header)
+    }
+
+    fn from_distance(d: f64, header: &SdfHeader) -> u8 {
+        unorm_from_distance(d, 255.0, header) as u8
+    }
+
+    fn load(bytes: &[u8]) -> u8 {
+        bytes[0]
+    }
+
+    fn store(self, storer: &mut StorerVec) {
+        storer.store_u8(self);
+    }
+}
+
+impl SdfVoxel for u16 {
+    const VOXEL_TYPE: VoxelType = VoxelType::U16Unorm;
+
+    fn to_distance(self, header: &SdfHeader) -> f64 {
+        unorm_to_distance(self as f64, 65535.0, header)
+    }
+
+    fn from_distance(d: f64, header: &SdfHeader) -> u16 {
+        unorm_from_distance(d, 65535.0, header) as u16
+    }
+
+    fn load(bytes: &[u8]) -> u16 {
+        u16::from_le_bytes(bytes[..2].try_into().unwrap())
+    }
+
+    fn store(self, storer: &mut StorerVec) {
+        storer.store_u16(self);
+    }
+
+    fn as_u16_slice(voxels: &[u16]) -> Option<&[u16]> {
+        Some(voxels)
+    }
+
+    fn as_u16_slice_mut(voxels: &mut [u16]) -> Option<&mut [u16]> {
+        Some(voxels)
+    }
+
+    fn from_u16_slice(voxels: &[u16]) -> Option<&[u16]> {
+        Some(voxels)
+    }
+}
+
+impl SdfVoxel for F16 {
+    const VOXEL_TYPE: VoxelType = VoxelType::F16;
+
+    fn to_distance(self, _header: &SdfHeader) -> f64 {
+        self.to_f32() as f64
+    }
+
+    fn from_distance(d: f64, _header: &SdfHeader) -> F16 {
+        F16::from_f32(d as f32)
+    }
+
+    fn load(bytes: &[u8]) -> F16 {
+        F16(u16::from_le_bytes(bytes[..2].try_into().unwrap()))
+    }
+
+    fn store(self, storer: &mut StorerVec) {
+        storer.store_u16(self.0);
+    }
+}
+
+impl SdfVoxel for f32 {
+    const VOXEL_TYPE: VoxelType = VoxelType::F32;
+
+    fn to_distance(self, _header: &SdfHeader) -> f64 {
+        self as f64
+    }
+
+    fn from_distance(d: f64, _header: &SdfHeader) -> f32 {
+        d as f32
+    }
+
+    fn load(bytes: &[u8]) -> f32 {
+        f32::from_le_bytes(bytes[..4].try_into().unwrap())
+    }
+
+    fn store(self, storer: &mut StorerVec) {
+        storer.store_f32(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_nan(h: F16) -> bool {
+        h.0 & 0x7c00 == 0x7c00 && h.0 & 0x3ff != 0
+    }
+
+    // Reference value of a finite half
+    fn half_value(h: F16) -> f64 {
+        let sign = if h.0 & 0x8000 != 0 { -1.0 } else { 1.0 };
+        let exp = ((h.0 >> 10) & 0x1f) as i32;
+        let man = (h.0 & 0x3ff) as f64;
+        match exp {
+            0 => sign * man * 2f64.powi(-24),
+            _ => sign * (1.0 + man / 1024.0) * 2f64.powi(exp - 15),
+        }
+    }
+
+    #[test]
+    fn f16_round_trip() {
+        for bits in 0..=0xffffu16 {
+            let h = F16(bits);
+            let v = h.to_f32();
+            if is_nan(h) {
+                assert!(v.is_nan(), "{:04x}", bits);
+                assert!(is_nan(F16::from_f32(v)), "{:04x}", bits);
+            } else if bits & 0x7fff == 0x7c00 {
+                assert!(v.is_infinite() && (v < 0.0) == (bits & 0x8000 != 0));
+                assert_eq!(F16::from_f32(v), h);
+            } else {
+                assert_eq!(v as f64, half_value(h), "{:04x}", bits);
+                assert_eq!(F16::from_f32(v), h, "{:04x} = {}", bits, v);
+            }
+        }
+    }
+
+    #[test]
+    fn f16_ties_to_even() {
+        // Consecutive positive finite halves (and the largest one to infinity). The midpoint
+        // is exact in f32.
+        for bits in 0..0x7c00u16 {
+            let (a, b) = (F16(bits).to_f32(), F16(bits + 1).to_f32());
+            let b = if bits == 0x7bff { 65536.0 } else { b };
+            let mid = (a + b) * 0.5;
+            let even = if bits & 1 == 0 { bits } else { bits + 1 };
+            assert_eq!(F16::from_f32(mid).0, even, "{} - {}", a, b);
+            assert_eq!(F16::from_f32(-mid).0, even | 0x8000);
+
+            // Next f32 values around the midpoint round to the nearest half
+            let below = f32::from_bits(mid.to_bits() - 1);
+            let above = f32::from_bits(mid.to_bits() + 1);
+            assert_eq!(F16::from_f32(below).0, bits, "{}", below);
+            assert_eq!(F16::from_f32(above).0, bits + 1, "{}", above);
+        }
+    }
+
+    #[test]
+    fn f16_special_values() {
+        // Subnormals
+        let tiny = 2f32.powi(-24);
+        assert_eq!(F16::from_f32(tiny), F16(1));
+        assert_eq!(F16::from_f32(tiny * 1023.0), F16(0x3ff));
+        assert_eq!(F16::from_f32(tiny * 1024.0), F16(0x400));
+        assert_eq!(F16::from_f32(tiny * 0.5), F16(0));
+        assert_eq!(F16::from_f32(tiny * 0.75), F16(1));
+        assert_eq!(F16::from_f32(tiny * 1.5), F16(2));
+        assert_eq!(F16::from_f32(-tiny * 2.5), F16(0x8002));
+        assert_eq!(F16::from_f32(f32::MIN_POSITIVE), F16(0));
+        assert_eq!(F16(0x3ff).to_f32(), tiny * 1023.0);
+
+        // Overflow to infinity
+        assert_eq!(F16::from_f32(65504.0), F16(0x7bff));
+        assert_eq!(F16::from_f32(65519.0), F16(0x7bff));
+        assert_eq!(F16::from_f32(65520.0), F16(0x7c00));
+        assert_eq!(F16::from_f32(1e10), F16(0x7c00));
+        assert_eq!(F16::from_f32(-f32::MAX), F16(0xfc00));
+        assert_eq!(F16::from_f32(f32::INFINITY), F16(0x7c00));
+        assert_eq!(F16::from_f32(f32::NEG_INFINITY), F16(0xfc00));
+        assert_eq!(F16(0x7c00).to_f32(), f32::INFINITY);
+        assert_eq!(F16(0xfc00).to_f32(), f32::NEG_INFINITY);
+
+        // NaN stays NaN, even when the payload bits are below the half mantissa
+        assert!(is_nan(F16::from_f32(f32::NAN)));
+        assert!(is_nan(F16::from_f32(f32::from_bits(0x7f800001))));
+        assert!(is_nan(F16::from_f32(f32::from_bits(0xff800001))));
+        assert!(F16(0x7e00).to_f32().is_nan());
+
+        // Signed zeros
+        assert_eq!(F16::from_f32(0.0), F16(0));
+        assert_eq!(F16::from_f32(-0.0), F16(0x8000));
+        assert_eq!(F16::from_f32(-1e-30), F16(0x8000));
+        let zero = F16(0x8000).to_f32();
+        assert!(zero == 0.0 && zero.is_sign_negative());
+        assert!(F16(0).to_f32().is_sign_positive());
+    }
+}
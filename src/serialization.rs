@@ -1,3 +1,4 @@
+use crate::error::*;
 use std::convert::TryInto;
 
 pub struct Loader {
@@ -9,44 +10,69 @@ impl Loader {
         Loader { offset: 0 }
     }
 
-    pub fn load_u16(&mut self, bytes: &[u8]) -> u16 {
-        let out = u16::from_le_bytes(bytes[self.offset..self.offset + 2].try_into().unwrap());
-        self.offset += 2;
-        out
+    fn take<'a>(&mut self, bytes: &'a [u8], count: usize) -> SdfResult<&'a [u8]> {
+        let end = self
+            .offset
+            .checked_add(count)
+            .filter(|&end| end <= bytes.len())
+            .ok_or(SdfError::Truncated {
+                offset: self.offset,
+                needed: count,
+                len: bytes.len(),
+            })?;
+        let out = &bytes[self.offset..end];
+        self.offset = end;
+        Ok(out)
     }
 
-    pub fn load_u32(&mut self, bytes: &[u8]) -> u32 {
-        let out = u32::from_le_bytes(bytes[self.offset..self.offset + 4].try_into().unwrap());
-        self.offset += 4;
-        out
+    pub fn load_u8(&mut self, bytes: &[u8]) -> SdfResult<u8> {
+        Ok(self.take(bytes, 1)?[0])
     }
 
-    pub fn load_f32(&mut self, bytes: &[u8]) -> f32 {
-        let out = f32::from_le_bytes(bytes[self.offset..self.offset + 4].try_into().unwrap());
-        self.offset += 4;
-        out
-    }
-
-    pub fn load_array_u16(&mut self, bytes: &[u8], count: usize) -> Vec<u16> {
-        (0..count)
-            .map(|_| {
-                let out =
-                    u16::from_le_bytes(bytes[self.offset..self.offset + 2].try_into().unwrap());
-                self.offset += 2;
-                out
-            })
-            .collect()
-    }
-
-    pub fn load_array_f32(&mut self, bytes: &[u8], count: usize) -> Vec<f32> {
-        (0..count)
-            .map(|_| {
-                let out =
-                    f32::from_le_bytes(bytes[self.offset..self.offset + 4].try_into().unwrap());
-                self.offset += 4;
-                out
-            })
-            .collect()
+    pub fn load_u16(&mut self, bytes: &[u8]) -> SdfResult<u16> {
+        Ok(u16::from_le_bytes(self.take(bytes, 2)?.try_into().unwrap()))
+    }
+
+    pub fn load_u32(&mut self, bytes: &[u8]) -> SdfResult<u32> {
+        Ok(u32::from_le_bytes(self.take(bytes, 4)?.try_into().unwrap()))
+    }
+
+    pub fn load_u64(&mut self, bytes: &[u8]) -> SdfResult<u64> {
+        Ok(u64::from_le_bytes(self.take(bytes, 8)?.try_into().unwrap()))
+    }
+
+    pub fn load_f32(&mut self, bytes: &[u8]) -> SdfResult<f32> {
+        Ok(f32::from_le_bytes(self.take(bytes, 4)?.try_into().unwrap()))
+    }
+
+    pub fn load_array_u8(&mut self, bytes: &[u8], count: usize) -> SdfResult<Vec<u8>> {
+        Ok(self.take(bytes, count)?.to_vec())
+    }
+
+    pub fn load_array_u16(&mut self, bytes: &[u8], count: usize) -> SdfResult<Vec<u16>> {
+        let size = count.checked_mul(2).ok_or(SdfError::Truncated {
+            offset: self.offset,
+            needed: usize::MAX,
+            len: bytes.len(),
+        })?;
+        Ok(self
+            .take(bytes, size)?
+            .chunks_exact(2)
+            .map(|v| u16::from_le_bytes(v.try_into().unwrap()))
+            .collect())
+    }
+
+    pub fn load_array_f32(&mut self, bytes: &[u8], count: usize) -> SdfResult<Vec<f32>> {
+        let size = count.checked_mul(4).ok_or(SdfError::Truncated {
+            offset: self.offset,
+            needed: usize::MAX,
+            len: bytes.len(),
+        })?;
+        Ok(self
+            .take(bytes, size)?
+            .chunks_exact(4)
+            .map(|v| f32::from_le_bytes(v.try_into().unwrap()))
+            .collect())
     }
 }
 
@@ -80,6 +106,11 @@ impl Storer {
         self.offset += 4;
     }
 
+    pub fn store_u64(&mut self, bytes: &mut [u8], v: u64) {
+        bytes[self.offset..self.offset + 8].copy_from_slice(&v.to_le_bytes()[..]);
+        self.offset += 8;
+    }
+
     pub fn store_f32(&mut self, bytes: &mut [u8], v: f32) {
         bytes[self.offset..self.offset + 4].copy_from_slice(&v.to_le_bytes()[..]);
         self.offset += 4;
@@ -132,6 +163,10 @@ impl StorerVec {
         self.v.extend_from_slice(&v.to_le_bytes()[..]);
     }
 
+    pub fn store_u64(&mut self, v: u64) {
+        self.v.extend_from_slice(&v.to_le_bytes()[..]);
+    }
+
     pub fn store_f32(&mut self, v: f32) {
         self.v.extend_from_slice(&v.to_le_bytes()[..]);
     }
@@ -158,3 +193,144 @@ impl Default for StorerVec {
         StorerVec::new()
     }
 }
+
+// CRC-32 (IEEE 802.3, same as zip/png)
+pub struct Crc32 {
+    table: [u32; 256],
+    crc: u32,
+}
+
+impl Crc32 {
+    pub fn new() -> Crc32 {
+        let mut table = [0u32; 256];
+        for (i, entry) in table.iter_mut().enumerate() {
+            let mut c = i as u32;
+            for _ in 0..8 {
+                c = if c & 1 != 0 {
+                    0xEDB88320 ^ (c >> 1)
+                } else {
+                    c >> 1
+                };
+            }
+            *entry = c;
+        }
+        Crc32 {
+            table,
+            crc: 0xFFFFFFFF,
+        }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.crc = self.table[((self.crc ^ b as u32) & 0xFF) as usize] ^ (self.crc >> 8);
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        self.crc ^ 0xFFFFFFFF
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Crc32::new()
+    }
+}
+
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(bytes);
+    crc.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_bytes() -> Vec<u8> {
+        let mut storer = StorerVec::new();
+        storer.store_u8(7);
+        storer.store_u16(0x1234);
+        storer.store_u32(0xdeadbeef);
+        storer.store_u64(0x0123_4567_89ab_cdef);
+        storer.store_f32(-1.5);
+        storer.store_array_u16(&[1, 2, 3]);
+        storer.store_array_f32(&[0.25, 4.0]);
+        storer.store_array_u8(&[9, 8]);
+        storer.v
+    }
+
+    fn load_all(bytes: &[u8]) -> SdfResult<()> {
+        let mut loader = Loader::new();
+        assert_eq!(loader.load_u8(bytes)?, 7);
+        assert_eq!(loader.load_u16(bytes)?, 0x1234);
+        assert_eq!(loader.load_u32(bytes)?, 0xdeadbeef);
+        assert_eq!(loader.load_u64(bytes)?, 0x0123_4567_89ab_cdef);
+        assert_eq!(loader.load_f32(bytes)?, -1.5);
+        assert_eq!(loader.load_array_u16(bytes, 3)?, vec![1, 2, 3]);
+        assert_eq!(loader.load_array_f32(bytes, 2)?, vec![0.25, 4.0]);
+        assert_eq!(loader.load_array_u8(bytes, 2)?, vec![9, 8]);
+        assert_eq!(loader.offset, bytes.len());
+        Ok(())
+    }
+
+    #[test]
+    fn round_trip() {
+        load_all(&test_bytes()).unwrap();
+    }
+
+    #[test]
+    fn truncated() {
+        let bytes = test_bytes();
+        for len in 0..bytes.len() {
+            match load_all(&bytes[..len]) {
+                Err(SdfError::Truncated {
+                    offset,
+                    needed,
+                    len: data_len,
+                }) => {
+                    assert_eq!(data_len, len);
+                    assert!(offset <= len && offset + needed > len);
+                }
+                result => panic!("length {}: {:?}", len, result),
+            }
+        }
+    }
+
+    #[test]
+    fn huge_counts() {
+        let bytes = test_bytes();
+        let mut loader = Loader { offset: 3 };
+        assert!(matches!(
+            loader.load_array_u16(&bytes, usize::MAX),
+            Err(SdfError::Truncated { .. })
+        ));
+        assert!(matches!(
+            loader.load_array_f32(&bytes, usize::MAX / 2),
+            Err(SdfError::Truncated { .. })
+        ));
+        assert!(matches!(
+            loader.load_array_u8(&bytes, usize::MAX),
+            Err(SdfError::Truncated { .. })
+        ));
+        // Failed loads don't advance
+        assert_eq!(loader.offset, 3);
+
+        let mut loader = Loader { offset: usize::MAX };
+        assert!(matches!(
+            loader.load_u32(&bytes),
+            Err(SdfError::Truncated { .. })
+        ));
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(crc32(&[]), 0);
+
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), 0xcbf43926);
+    }
+}
@@ -1,7 +1,308 @@
+use crate::sdf::*;
+
+pub const BRICK_SIZE_PAYLOAD: u32 = 7;
+pub const BRICK_BORDER: u32 = 1;
+
+#[derive(Clone, Debug, Copy)]
+pub struct OctreeNode {
+    // Bit i is set when child i is stored (interior node or brick)
+    pub child_mask: u8,
+    // Child index into nodes, or into bricks for level 1 nodes
+    pub children: [u32; 8],
+    // Conservative distance for children without surface (not stored)
+    pub empty: [u16; 8],
+}
+
+impl OctreeNode {
+    fn new() -> OctreeNode {
+        OctreeNode {
+            child_mask: 0,
+            children: [0; 8],
+            empty: [u16::MAX; 8],
+        }
+    }
+}
+
+enum OctreeChild {
+    Empty(u16),
+    Node(u32),
+    Brick(u32),
+}
+
+// Sparse octree of distance field bricks. Only bricks crossing the zero level are stored.
+// Each brick stores payload + border voxels per axis (border duplicates the neighbor's first
+// voxels) to allow hardware trilinear filtering inside a brick. Node level 0 is a brick,
+// level n node covers 2^n bricks per axis. Root is always an interior node (level >= 1).
 pub struct SparseSdf {
-    pub data: Vec<u8>,
+    pub header: SdfHeader,
+    pub brick_size_payload: u32,
+    pub levels: u32,
+    pub nodes: Vec<OctreeNode>,
+    pub brick_voxels: Vec<u16>,
 }
 
 impl SparseSdf {
-    pub fn add_node() {}
+    pub fn new(sdf: &Sdf, brick_size_payload: u32) -> SparseSdf {
+        assert!(brick_size_payload > 0);
+
+        let dim = sdf.header.dim;
+        let bricks = (
+            dim.0.div_ceil(brick_size_payload),
+            dim.1.div_ceil(brick_size_payload),
+            dim.2.div_ceil(brick_size_payload),
+        );
+        let max_bricks = bricks.0.max(bricks.1).max(bricks.2);
+
+        let mut levels = 1;
+        while (1 << levels) < max_bricks {
+            levels += 1;
+        }
+
+        let mut sparse = SparseSdf {
+            header: sdf.header,
+            brick_size_payload,
+            levels,
+            nodes: Vec::new(),
+            brick_voxels: Vec::new(),
+        };
+
+        // Root node is always at index 0. Root is never collapsed and it is pushed last.
+        sparse.nodes.push(OctreeNode::new());
+        sparse.build_node(sdf, levels, (0, 0, 0));
+        sparse.nodes[0] = sparse.nodes.pop().unwrap();
+
+        sparse
+    }
+
+    pub fn brick_size_outer(&self) -> u32 {
+        self.brick_size_payload + BRICK_BORDER
+    }
+
+    pub fn brick_count(&self) -> usize {
+        let outer = self.brick_size_outer() as usize;
+        self.brick_voxels.len() / (outer * outer * outer)
+    }
+
+    pub fn memory_bytes(&self) -> usize {
+        self.nodes.len() * std::mem::size_of::<OctreeNode>()
+            + self.brick_voxels.len() * std::mem::size_of::<u16>()
+    }
+
+    // Position in bricks, at the given node level
+    fn build_node(&mut self, sdf: &Sdf, level: u32, position: (u32, u32, u32)) -> OctreeChild {
+        if level == 0 {
+            return self.build_brick(sdf, position);
+        }
+
+        let mut node = OctreeNode::new();
+        for i in 0..8u32 {
+            let child_position = (
+                position.0 * 2 + (i & 1),
+                position.1 * 2 + ((i >> 1) & 1),
+                position.2 * 2 + ((i >> 2) & 1),
+            );
+            match self.build_node(sdf, level - 1, child_position) {
+                OctreeChild::Empty(value) => node.empty[i as usize] = value,
+                OctreeChild::Node(index) | OctreeChild::Brick(index) => {
+                    node.child_mask |= 1 << i;
+                    node.children[i as usize] = index;
+                }
+            }
+        }
+
+        // Collapse fully empty subtrees (root is kept)
+        if node.child_mask == 0 && level != self.levels {
+            let value = node
+                .empty
+                .iter()
+                .copied()
+                .min_by_key(|&v| (v as i32 - SDF_LEVEL_ZERO as i32).abs())
+                .unwrap();
+            return OctreeChild::Empty(value);
+        }
+
+        self.nodes.push(node);
+        OctreeChild::Node(self.nodes.len() as u32 - 1)
+    }
+
+    fn build_brick(&mut self, sdf: &Sdf, position: (u32, u32, u32)) -> OctreeChild {
+        let dim = sdf.header.dim;
+        let payload = self.brick_size_payload;
+        let outer = self.brick_size_outer();
+
+        let base = (
+            position.0 * payload,
+            position.1 * payload,
+            position.2 * payload,
+        );
+        if base.0 >= dim.0 || base.1 >= dim.1 || base.2 >= dim.2 {
+            // Outside of the volume, never sampled
+            return OctreeChild::Empty(u16::MAX);
+        }
+
+        let stride_y = dim.0 as usize;
+        let stride_z = dim.0 as usize * dim.1 as usize;
+
+        // Border voxels are clamped to the volume edge
+        let mut voxels = Vec::with_capacity((outer * outer * outer) as usize);
+        for z in 0..outer {
+            for y in 0..outer {
+                for x in 0..outer {
+                    let vx = (base.0 + x).min(dim.0 - 1) as usize;
+                    let vy = (base.1 + y).min(dim.1 - 1) as usize;
+                    let vz = (base.2 + z).min(dim.2 - 1) as usize;
+                    voxels.push(sdf.voxels[vx + vy * stride_y + vz * stride_z]);
+                }
+            }
+        }
+
+        // Test edge: contains both positive and negative voxels
+        let has_inside = voxels.iter().any(|&d| d < SDF_LEVEL_ZERO);
+        let has_outside = voxels.iter().any(|&d| d > SDF_LEVEL_ZERO);
+
+        if has_inside && has_outside {
+            let index = self.brick_count() as u32;
+            self.brick_voxels.extend_from_slice(&voxels);
+            OctreeChild::Brick(index)
+        } else {
+            // Closest voxel to the surface is a conservative distance for the whole brick
+            let value = voxels
+                .iter()
+                .copied()
+                .min_by_key(|&v| (v as i32 - SDF_LEVEL_ZERO as i32).abs())
+                .unwrap();
+            OctreeChild::Empty(value)
+        }
+    }
+
+    pub fn sample(&self, x: u32, y: u32, z: u32) -> u16 {
+        let dim = self.header.dim;
+        assert!(x < dim.0 && y < dim.1 && z < dim.2);
+
+        let payload = self.brick_size_payload;
+        let brick = (x / payload, y / payload, z / payload);
+
+        let mut node = &self.nodes[0];
+        for level in (0..self.levels).rev() {
+            let child = ((brick.0 >> level) & 1)
+                | (((brick.1 >> level) & 1) << 1)
+                | (((brick.2 >> level) & 1) << 2);
+
+            if node.child_mask & (1 << child) == 0 {
+                return node.empty[child as usize];
+            }
+
+            let index = node.children[child as usize] as usize;
+            if level == 0 {
+                let outer = self.brick_size_outer() as usize;
+                let local_x = (x % payload) as usize;
+                let local_y = (y % payload) as usize;
+                let local_z = (z % payload) as usize;
+                let brick_offset = index * outer * outer * outer;
+                return self.brick_voxels
+                    [brick_offset + local_x + local_y * outer + local_z * outer * outer];
+            }
+            node = &self.nodes[index];
+        }
+
+        unreachable!()
+    }
+
+    pub fn to_dense(&self) -> Sdf {
+        let dim = self.header.dim;
+        let mut voxels = Vec::with_capacity(dim.0 as usize * dim.1 as usize * dim.2 as usize);
+        for z in 0..dim.2 {
+            for y in 0..dim.1 {
+                for x in 0..dim.0 {
+                    voxels.push(self.sample(x, y, z));
+                }
+            }
+        }
+
+        Sdf {
+            header: self.header,
+            voxels,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sdf_sampling::*;
+
+    fn sphere_sdf(dim: (u32, u32, u32), radius: f32) -> Sdf {
+        let header = SdfHeader {
+            dim,
+            box_min: (-1.0, -1.0, -1.0),
+            dx: 2.0 / (dim.0 - 1) as f32,
+        };
+        sample_sdf(header, |p| p.length() - radius).unwrap()
+    }
+
+    fn level_distance(v: u16) -> i32 {
+        (v as i32 - SDF_LEVEL_ZERO as i32).abs()
+    }
+
+    #[test]
+    fn to_dense_round_trip() {
+        for &(dim, radius, payload) in &[
+            ((33, 33, 33), 0.6, 7),
+            ((40, 29, 35), 0.5, 7),
+            ((24, 24, 24), 0.8, 3),
+        ] {
+            let sdf = sphere_sdf(dim, radius);
+            let sparse = SparseSdf::new(&sdf, payload);
+            assert!(sparse.brick_count() > 0);
+            let dense = sparse.to_dense();
+            assert_eq!(dense.header.dim, sdf.header.dim);
+            assert_eq!(dense.voxels.len(), sdf.voxels.len());
+
+            let (dim_x, dim_y, dim_z) = (dim.0 as usize, dim.1 as usize, dim.2 as usize);
+            let mut band_voxels = 0;
+            for z in 0..dim_z {
+                for y in 0..dim_y {
+                    for x in 0..dim_x {
+                        let i = x + (y + z * dim_y) * dim_x;
+                        let v = sdf.voxels[i];
+                        // Empty regions store a conservative (closer to the surface) value
+                        assert!(level_distance(dense.voxels[i]) <= level_distance(v));
+
+                        // Sign change to the next voxel: inside an edge brick (the border
+                        // covers the next voxel), must be exact
+                        let inside = v < SDF_LEVEL_ZERO;
+                        let mut neighbors = vec![];
+                        if x + 1 < dim_x {
+                            neighbors.push(i + 1);
+                        }
+                        if y + 1 < dim_y {
+                            neighbors.push(i + dim_x);
+                        }
+                        if z + 1 < dim_z {
+                            neighbors.push(i + dim_x * dim_y);
+                        }
+                        if neighbors
+                            .iter()
+                            .any(|&n| (sdf.voxels[n] < SDF_LEVEL_ZERO) != inside)
+                        {
+                            assert_eq!(dense.voxels[i], v, "voxel {:?}", (x, y, z));
+                            band_voxels += 1;
+                        }
+                    }
+                }
+            }
+            assert!(band_voxels > 0);
+        }
+    }
+
+    #[test]
+    fn empty_volume_has_no_bricks() {
+        let sdf = sphere_sdf((20, 20, 20), 4.0);
+        let sparse = SparseSdf::new(&sdf, 7);
+        assert_eq!(sparse.brick_count(), 0);
+        let dense = sparse.to_dense();
+        for (&a, &b) in dense.voxels.iter().zip(sdf.voxels.iter()) {
+            assert!(level_distance(a) <= level_distance(b));
+        }
+    }
 }
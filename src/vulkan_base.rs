@@ -13,6 +13,7 @@ use gpu_allocator::MemoryLocation;
 use ash::extensions::{
     ext::DebugUtils,
     khr::{Surface, Swapchain},
+    nv::MeshShader,
 };
 
 use winit::window::Window;
@@ -34,7 +35,7 @@ unsafe extern "system" fn vulkan_debug_callback(
     _user_data: *mut std::os::raw::c_void,
 ) -> vk::Bool32 {
     let callback_data = *p_callback_data;
-    let message_id_number: i32 = callback_data.message_id_number as i32;
+    let message_id_number: i32 = callback_data.message_id_number;
 
     let message_id_name = if callback_data.p_message_id_name.is_null() {
         Cow::from("")
@@ -143,6 +144,13 @@ pub struct VulkanBase {
     pub swapchain: vk::SwapchainKHR,
     pub present_images: Vec<vk::Image>,
     pub present_image_views: Vec<vk::ImageView>,
+    // Final layout of the presented image (render pass final_layout)
+    pub present_layout: vk::ImageLayout,
+    // Present images support TRANSFER_SRC (read_present_image)
+    pub present_image_readback: bool,
+    // Headless mode color target
+    pub offscreen_image: Option<VkImage>,
+    pub feature_support: DeviceFeatureSupport,
 
     pub depth_image: VkImage,
     pub depth_image_view: vk::ImageView,
@@ -155,66 +163,292 @@ pub struct VulkanBase {
     pub allocator: ManuallyDrop<Allocator>,
 }
 
+// Instance, debug messenger and validation layer (when installed)
+unsafe fn create_instance(
+    entry: &Entry,
+    surface_extensions: &[*const i8],
+) -> (Instance, DebugUtils, vk::DebugUtilsMessengerEXT) {
+    let app_name = CString::new("VulkanTest").unwrap();
+
+    // Software implementations on CI often come without the validation layer
+    let validation_layer = CString::new("VK_LAYER_KHRONOS_validation").unwrap();
+    let has_validation_layer = entry
+        .enumerate_instance_layer_properties()
+        .unwrap_or_default()
+        .iter()
+        .any(|layer| CStr::from_ptr(layer.layer_name.as_ptr()) == validation_layer.as_c_str());
+    if !has_validation_layer {
+        println!("Vulkan validation layer not found, running without validation");
+    }
+    let layers_names_raw: Vec<*const i8> = if has_validation_layer {
+        vec![validation_layer.as_ptr()]
+    } else {
+        vec![]
+    };
+
+    let mut extension_names_raw = surface_extensions.to_vec();
+    extension_names_raw.push(DebugUtils::name().as_ptr());
+    extension_names_raw.push(
+        ::std::ffi::CStr::from_bytes_with_nul(b"VK_KHR_get_physical_device_properties2\0")
+            .expect("Wrong extension string")
+            .as_ptr(),
+    );
+
+    let appinfo = vk::ApplicationInfo::builder()
+        .application_name(&app_name)
+        .application_version(0)
+        .engine_name(&app_name)
+        .engine_version(0)
+        .api_version(vk::make_api_version(0, 1, 0, 0));
+
+    let create_info = vk::InstanceCreateInfo::builder()
+        .application_info(&appinfo)
+        .enabled_layer_names(&layers_names_raw)
+        .enabled_extension_names(&extension_names_raw);
+
+    let instance: Instance = entry
+        .create_instance(&create_info, None)
+        .expect("Instance creation error");
+
+    let debug_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
+        .message_severity(
+            vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
+                | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING,
+            //| vk::DebugUtilsMessageSeverityFlagsEXT::INFO,
+        )
+        .message_type(
+            vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
+                | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
+                | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
+        )
+        .pfn_user_callback(Some(vulkan_debug_callback));
+
+    let debug_utils_loader = DebugUtils::new(entry, &instance);
+    let debug_call_back = debug_utils_loader
+        .create_debug_utils_messenger(&debug_info, None)
+        .unwrap();
+
+    (instance, debug_utils_loader, debug_call_back)
+}
+
+// Optional device extensions, enabled when supported
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DeviceFeatureSupport {
+    pub mesh_shader: bool,
+    pub fragment_shader_barycentric: bool,
+}
+
+unsafe fn create_device(
+    instance: &Instance,
+    pdevice: vk::PhysicalDevice,
+    queue_family_index: u32,
+    device_extension_names: &[&CStr],
+) -> (Device, DeviceFeatureSupport) {
+    let supported_extensions = instance
+        .enumerate_device_extension_properties(pdevice)
+        .unwrap_or_default();
+    let is_supported = |name: &CStr| {
+        supported_extensions
+            .iter()
+            .any(|ext| CStr::from_ptr(ext.extension_name.as_ptr()) == name)
+    };
+
+    let support = DeviceFeatureSupport {
+        mesh_shader: is_supported(MeshShader::name()),
+        fragment_shader_barycentric: is_supported(vk::NvFragmentShaderBarycentricFn::name()),
+    };
+
+    let mut device_extension_names_raw: Vec<*const i8> = device_extension_names
+        .iter()
+        .map(|raw_name| raw_name.as_ptr())
+        .collect();
+    if support.mesh_shader {
+        device_extension_names_raw.push(MeshShader::name().as_ptr());
+    }
+    if support.fragment_shader_barycentric {
+        device_extension_names_raw.push(vk::NvFragmentShaderBarycentricFn::name().as_ptr());
+    }
+
+    let features = vk::PhysicalDeviceFeatures {
+        shader_clip_distance: 1,
+        //geometry_shader: 1,
+        ..Default::default()
+    };
+
+    let mut mesh_shader = vk::PhysicalDeviceMeshShaderFeaturesNV::builder()
+        .mesh_shader(true)
+        .task_shader(true)
+        .build();
+    let mut fragment_shader_barycentric = vk::PhysicalDeviceFragmentShaderBarycentricFeaturesNV {
+        fragment_shader_barycentric: 1,
+        ..Default::default()
+    };
+
+    let priorities = [1.0];
+
+    let queue_info = [vk::DeviceQueueCreateInfo::builder()
+        .queue_family_index(queue_family_index)
+        .queue_priorities(&priorities)
+        .build()];
+
+    let mut device_create_info = vk::DeviceCreateInfo::builder()
+        .queue_create_infos(&queue_info)
+        .enabled_extension_names(&device_extension_names_raw)
+        .enabled_features(&features);
+    if support.mesh_shader {
+        device_create_info = device_create_info.push_next(&mut mesh_shader);
+    }
+    if support.fragment_shader_barycentric {
+        device_create_info = device_create_info.push_next(&mut fragment_shader_barycentric);
+    }
+
+    let device = instance
+        .create_device(pdevice, &device_create_info, None)
+        .unwrap();
+
+    (device, support)
+}
+
+fn create_allocator(
+    instance: &Instance,
+    device: &Device,
+    pdevice: vk::PhysicalDevice,
+) -> Allocator {
+    Allocator::new(&AllocatorCreateDesc {
+        instance: instance.clone(),
+        device: device.clone(),
+        physical_device: pdevice,
+        debug_settings: Default::default(),
+        buffer_device_address: false,
+    })
+    .unwrap()
+}
+
+fn create_image_view(
+    device: &Device,
+    image: vk::Image,
+    format: vk::Format,
+    aspect_mask: vk::ImageAspectFlags,
+) -> vk::ImageView {
+    let create_view_info = vk::ImageViewCreateInfo::builder()
+        .view_type(vk::ImageViewType::TYPE_2D)
+        .format(format)
+        .components(vk::ComponentMapping {
+            r: vk::ComponentSwizzle::R,
+            g: vk::ComponentSwizzle::G,
+            b: vk::ComponentSwizzle::B,
+            a: vk::ComponentSwizzle::A,
+        })
+        .subresource_range(vk::ImageSubresourceRange {
+            aspect_mask,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        })
+        .image(image);
+    unsafe { device.create_image_view(&create_view_info, None) }.unwrap()
+}
+
+fn create_target_image(
+    device: &Device,
+    allocator: &mut Allocator,
+    resolution: vk::Extent2D,
+    format: vk::Format,
+    usage: vk::ImageUsageFlags,
+) -> VkImage {
+    let image_create_info = vk::ImageCreateInfo::builder()
+        .image_type(vk::ImageType::TYPE_2D)
+        .format(format)
+        .extent(vk::Extent3D {
+            width: resolution.width,
+            height: resolution.height,
+            depth: 1,
+        })
+        .mip_levels(1)
+        .array_layers(1)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .usage(usage)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+    VkImage::new(
+        device,
+        allocator,
+        &image_create_info,
+        MemoryLocation::GpuOnly,
+    )
+}
+
+// 32 bit swapchain formats read_present_image can convert to RGBA8
+fn is_readback_format(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::R8G8B8A8_UNORM
+            | vk::Format::R8G8B8A8_SRGB
+            | vk::Format::B8G8R8A8_UNORM
+            | vk::Format::B8G8R8A8_SRGB
+            | vk::Format::A8B8G8R8_UNORM_PACK32
+            | vk::Format::A8B8G8R8_SRGB_PACK32
+            | vk::Format::A2B10G10R10_UNORM_PACK32
+            | vk::Format::A2R10G10B10_UNORM_PACK32
+    )
+}
+
+// Converts readback pixels to RGBA8 in place. sRGB formats keep their encoded bytes (PNG is sRGB).
+fn convert_readback_pixels(format: vk::Format, pixels: &mut [u8]) {
+    // RGBA8 and ABGR8 packed have the same byte order in memory
+    if matches!(
+        format,
+        vk::Format::R8G8B8A8_UNORM
+            | vk::Format::R8G8B8A8_SRGB
+            | vk::Format::A8B8G8R8_UNORM_PACK32
+            | vk::Format::A8B8G8R8_SRGB_PACK32
+    ) {
+        return;
+    }
+    for pixel in pixels.chunks_exact_mut(4) {
+        let packed = u32::from_le_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]);
+        let unorm10 = |shift: u32| (((packed >> shift) & 0x3ff) * 255 + 511) / 1023;
+        let rgba = match format {
+            vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => {
+                [pixel[2], pixel[1], pixel[0], pixel[3]]
+            }
+            vk::Format::A2B10G10R10_UNORM_PACK32 => [
+                unorm10(0) as u8,
+                unorm10(10) as u8,
+                unorm10(20) as u8,
+                ((packed >> 30) * 85) as u8,
+            ],
+            vk::Format::A2R10G10B10_UNORM_PACK32 => [
+                unorm10(20) as u8,
+                unorm10(10) as u8,
+                unorm10(0) as u8,
+                ((packed >> 30) * 85) as u8,
+            ],
+            _ => unreachable!("Unsupported readback format: {:?}", format),
+        };
+        pixel.copy_from_slice(&rgba);
+    }
+}
+
 impl VulkanBase {
-    pub fn new(window: &Window, window_width: u32, window_height: u32) -> Self {
+    // Without vsync the immediate present mode is preferred (benchmarking)
+    pub fn new(window: &Window, window_width: u32, window_height: u32, vsync: bool) -> Self {
         unsafe {
             let entry = Entry::load().unwrap();
-            let app_name = CString::new("VulkanTest").unwrap();
-
-            let layer_names = [CString::new("VK_LAYER_KHRONOS_validation").unwrap()];
-            let layers_names_raw: Vec<*const i8> = layer_names
-                .iter()
-                .map(|raw_name| raw_name.as_ptr())
-                .collect();
 
             //let extension_names_raw = ash_window::enumerate_required_extensions(window).unwrap().to_vec();
 
             let surface_extensions = ash_window::enumerate_required_extensions(window).unwrap();
-            let mut extension_names_raw = surface_extensions
+            let surface_extensions = surface_extensions
                 .iter()
                 .map(|ext| ext.as_ptr())
                 .collect::<Vec<_>>();
 
-            extension_names_raw.push(DebugUtils::name().as_ptr());
-            extension_names_raw.push(
-                ::std::ffi::CStr::from_bytes_with_nul(b"VK_KHR_get_physical_device_properties2\0")
-                    .expect("Wrong extension string")
-                    .as_ptr(),
-            );
-
-            let appinfo = vk::ApplicationInfo::builder()
-                .application_name(&app_name)
-                .application_version(0)
-                .engine_name(&app_name)
-                .engine_version(0)
-                .api_version(vk::make_api_version(0, 1, 0, 0));
-
-            let create_info = vk::InstanceCreateInfo::builder()
-                .application_info(&appinfo)
-                .enabled_layer_names(&layers_names_raw)
-                .enabled_extension_names(&extension_names_raw);
-
-            let instance: Instance = entry
-                .create_instance(&create_info, None)
-                .expect("Instance creation error");
-
-            let debug_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
-                .message_severity(
-                    vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
-                        | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING,
-                    //| vk::DebugUtilsMessageSeverityFlagsEXT::INFO,
-                )
-                .message_type(
-                    vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
-                        | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
-                        | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
-                )
-                .pfn_user_callback(Some(vulkan_debug_callback));
+            let (instance, debug_utils_loader, debug_call_back) =
+                create_instance(&entry, &surface_extensions);
 
-            let debug_utils_loader = DebugUtils::new(&entry, &instance);
-            let debug_call_back = debug_utils_loader
-                .create_debug_utils_messenger(&debug_info, None)
-                .unwrap();
             let surface = ash_window::create_surface(&entry, &instance, window, None).unwrap();
             let pdevices = instance
                 .enumerate_physical_devices()
@@ -250,40 +484,8 @@ impl VulkanBase {
                 .expect("Couldn't find suitable device.");
             let queue_family_index = queue_family_index as u32;
 
-            let device_extension_names = [
-                Swapchain::name(), /*, &CString::new("VK_NV_mesh_shader").unwrap()*/
-            ];
-            let device_extension_names_raw: Vec<*const i8> = device_extension_names
-                .iter()
-                .map(|raw_name| raw_name.as_ptr())
-                .collect();
-
-            let features = vk::PhysicalDeviceFeatures {
-                shader_clip_distance: 1,
-                //geometry_shader: 1,
-                ..Default::default()
-            };
-
-            //let mut mesh_shader = vk::PhysicalDeviceMeshShaderFeaturesNV::builder().mesh_shader(true).task_shader(true).build();
-
-            let priorities = [1.0];
-
-            let queue_info = [vk::DeviceQueueCreateInfo::builder()
-                .queue_family_index(queue_family_index)
-                .queue_priorities(&priorities)
-                .build()];
-
-            let device_create_info = vk::DeviceCreateInfo::builder()
-                //.push_next(&mut mesh_shader)
-                .queue_create_infos(&queue_info)
-                .enabled_extension_names(&device_extension_names_raw)
-                .enabled_features(&features);
-
-            let device: Device = instance
-                .create_device(pdevice, &device_create_info, None)
-                .unwrap();
-
-            let present_queue = device.get_device_queue(queue_family_index as u32, 0);
+            let (device, feature_support) =
+                create_device(&instance, pdevice, queue_family_index, &[Swapchain::name()]);
 
             let surface_formats = surface_loader
                 .get_physical_device_surface_formats(pdevice, surface)
@@ -309,7 +511,7 @@ impl VulkanBase {
                 desired_image_count = surface_capabilities.max_image_count;
             }
             let surface_resolution = match surface_capabilities.current_extent.width {
-                std::u32::MAX => vk::Extent2D {
+                u32::MAX => vk::Extent2D {
                     width: window_width,
                     height: window_height,
                 },
@@ -329,18 +531,28 @@ impl VulkanBase {
             let present_mode = present_modes
                 .iter()
                 .cloned()
-                .find(|&mode| mode == vk::PresentModeKHR::IMMEDIATE)
+                .find(|&mode| !vsync && mode == vk::PresentModeKHR::IMMEDIATE)
                 //.find(|&mode| mode == vk::PresentModeKHR::MAILBOX)
                 .unwrap_or(vk::PresentModeKHR::FIFO);
             let swapchain_loader = Swapchain::new(&instance, &device);
 
+            // Transfer source allows screenshots (read_present_image)
+            let present_image_readback = surface_capabilities
+                .supported_usage_flags
+                .contains(vk::ImageUsageFlags::TRANSFER_SRC);
+            let present_image_usage = if present_image_readback {
+                vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC
+            } else {
+                vk::ImageUsageFlags::COLOR_ATTACHMENT
+            };
+
             let swapchain_create_info = vk::SwapchainCreateInfoKHR::builder()
                 .surface(surface)
                 .min_image_count(desired_image_count)
                 .image_color_space(surface_format.color_space)
                 .image_format(surface_format.format)
                 .image_extent(surface_resolution)
-                .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+                .image_usage(present_image_usage)
                 .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
                 .pre_transform(pre_transform)
                 .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
@@ -356,148 +568,427 @@ impl VulkanBase {
             let present_image_views: Vec<vk::ImageView> = present_images
                 .iter()
                 .map(|&image| {
-                    let create_view_info = vk::ImageViewCreateInfo::builder()
-                        .view_type(vk::ImageViewType::TYPE_2D)
-                        .format(surface_format.format)
-                        .components(vk::ComponentMapping {
-                            r: vk::ComponentSwizzle::R,
-                            g: vk::ComponentSwizzle::G,
-                            b: vk::ComponentSwizzle::B,
-                            a: vk::ComponentSwizzle::A,
-                        })
-                        .subresource_range(vk::ImageSubresourceRange {
-                            aspect_mask: vk::ImageAspectFlags::COLOR,
-                            base_mip_level: 0,
-                            level_count: 1,
-                            base_array_layer: 0,
-                            layer_count: 1,
-                        })
-                        .image(image);
-                    device.create_image_view(&create_view_info, None).unwrap()
+                    create_image_view(
+                        &device,
+                        image,
+                        surface_format.format,
+                        vk::ImageAspectFlags::COLOR,
+                    )
                 })
                 .collect();
 
-            let mut allocator = Allocator::new(&AllocatorCreateDesc {
-                instance: instance.clone(),
-                device: device.clone(),
-                physical_device: pdevice,
-                debug_settings: Default::default(),
-                buffer_device_address: false,
-            })
-            .unwrap();
+            let allocator = create_allocator(&instance, &device, pdevice);
+
+            Self::new_common(
+                entry,
+                instance,
+                device,
+                surface_loader,
+                swapchain_loader,
+                debug_utils_loader,
+                debug_call_back,
+                pdevice,
+                queue_family_index,
+                surface,
+                surface_format,
+                surface_resolution,
+                swapchain,
+                present_images,
+                present_image_views,
+                present_image_readback,
+                None,
+                feature_support,
+                allocator,
+            )
+        }
+    }
 
-            let depth_image_create_info = vk::ImageCreateInfo::builder()
-                .image_type(vk::ImageType::TYPE_2D)
-                .format(vk::Format::D32_SFLOAT)
-                .extent(vk::Extent3D {
-                    width: surface_resolution.width,
-                    height: surface_resolution.height,
-                    depth: 1,
+    // No window, surface or swapchain: frames are rendered to a single offscreen color target
+    // (present_images[0]) that can be read back with read_present_image. Works with software
+    // Vulkan implementations (lavapipe) without a display.
+    pub fn new_headless(width: u32, height: u32) -> Self {
+        unsafe {
+            let entry = Entry::load().unwrap();
+
+            let (instance, debug_utils_loader, debug_call_back) = create_instance(&entry, &[]);
+
+            let pdevices = instance
+                .enumerate_physical_devices()
+                .expect("Physical device error");
+            let (pdevice, queue_family_index) = pdevices
+                .iter()
+                .flat_map(|pdevice| {
+                    instance
+                        .get_physical_device_queue_family_properties(*pdevice)
+                        .iter()
+                        .position(|info| info.queue_flags.contains(vk::QueueFlags::GRAPHICS))
+                        .map(|index| (*pdevice, index))
                 })
-                .mip_levels(1)
-                .array_layers(1)
-                .samples(vk::SampleCountFlags::TYPE_1)
-                .tiling(vk::ImageTiling::OPTIMAL)
-                .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED)
-                .sharing_mode(vk::SharingMode::EXCLUSIVE);
-
-            let depth_image = VkImage::new(
-                &device,
-                &mut allocator,
-                &depth_image_create_info,
-                MemoryLocation::GpuOnly,
-            );
+                .next()
+                .expect("Couldn't find suitable device.");
+            let queue_family_index = queue_family_index as u32;
 
-            let depth_image_view_info = vk::ImageViewCreateInfo::builder()
-                .subresource_range(
-                    vk::ImageSubresourceRange::builder()
-                        .aspect_mask(vk::ImageAspectFlags::DEPTH)
-                        .level_count(1)
-                        .layer_count(1)
-                        .build(),
-                )
-                .image(depth_image.image)
-                .format(depth_image_create_info.format)
-                .view_type(vk::ImageViewType::TYPE_2D);
+            let (device, feature_support) =
+                create_device(&instance, pdevice, queue_family_index, &[]);
 
-            let depth_image_view = device
-                .create_image_view(&depth_image_view_info, None)
-                .unwrap();
+            // Loaders are never called in headless mode (no surface and swapchain extensions)
+            let surface_loader = Surface::new(&entry, &instance);
+            let swapchain_loader = Swapchain::new(&instance, &device);
 
-            let semaphore_create_info = vk::SemaphoreCreateInfo::default();
+            let surface_format = vk::SurfaceFormatKHR {
+                format: vk::Format::R8G8B8A8_UNORM,
+                color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+            };
+            let surface_resolution = vk::Extent2D { width, height };
 
-            let present_complete_semaphore = device
-                .create_semaphore(&semaphore_create_info, None)
-                .unwrap();
-            let rendering_complete_semaphore = device
-                .create_semaphore(&semaphore_create_info, None)
-                .unwrap();
+            let mut allocator = create_allocator(&instance, &device, pdevice);
 
-            let command_buffer_pool =
-                CommandBufferPool::new(&device, queue_family_index, NUM_COMMAND_BUFFERS);
+            let offscreen_image = create_target_image(
+                &device,
+                &mut allocator,
+                surface_resolution,
+                surface_format.format,
+                vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+            );
+            let present_images = vec![offscreen_image.image];
+            let present_image_views = vec![create_image_view(
+                &device,
+                offscreen_image.image,
+                surface_format.format,
+                vk::ImageAspectFlags::COLOR,
+            )];
 
-            let vk = VulkanBase {
+            Self::new_common(
                 entry,
                 instance,
                 device,
-                queue_family_index,
-                pdevice,
                 surface_loader,
+                swapchain_loader,
+                debug_utils_loader,
+                debug_call_back,
+                pdevice,
+                queue_family_index,
+                vk::SurfaceKHR::null(),
                 surface_format,
-                present_queue,
                 surface_resolution,
-                swapchain_loader,
-                swapchain,
+                vk::SwapchainKHR::null(),
                 present_images,
                 present_image_views,
-                depth_image,
-                depth_image_view,
-                present_complete_semaphore,
-                rendering_complete_semaphore,
-                surface,
-                debug_call_back,
-                debug_utils_loader,
-                command_buffer_pool,
-                allocator: ManuallyDrop::new(allocator),
+                true,
+                Some(offscreen_image),
+                feature_support,
+                allocator,
+            )
+        }
+    }
+
+    // Depth target, semaphores and command buffers
+    #[allow(clippy::too_many_arguments)]
+    unsafe fn new_common(
+        entry: Entry,
+        instance: Instance,
+        device: Device,
+        surface_loader: Surface,
+        swapchain_loader: Swapchain,
+        debug_utils_loader: DebugUtils,
+        debug_call_back: vk::DebugUtilsMessengerEXT,
+        pdevice: vk::PhysicalDevice,
+        queue_family_index: u32,
+        surface: vk::SurfaceKHR,
+        surface_format: vk::SurfaceFormatKHR,
+        surface_resolution: vk::Extent2D,
+        swapchain: vk::SwapchainKHR,
+        present_images: Vec<vk::Image>,
+        present_image_views: Vec<vk::ImageView>,
+        present_image_readback: bool,
+        offscreen_image: Option<VkImage>,
+        feature_support: DeviceFeatureSupport,
+        mut allocator: Allocator,
+    ) -> Self {
+        let present_queue = device.get_device_queue(queue_family_index, 0);
+
+        let depth_image = create_target_image(
+            &device,
+            &mut allocator,
+            surface_resolution,
+            vk::Format::D32_SFLOAT,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        );
+        let depth_image_view = create_image_view(
+            &device,
+            depth_image.image,
+            vk::Format::D32_SFLOAT,
+            vk::ImageAspectFlags::DEPTH,
+        );
+
+        let semaphore_create_info = vk::SemaphoreCreateInfo::default();
+
+        let present_complete_semaphore = device
+            .create_semaphore(&semaphore_create_info, None)
+            .unwrap();
+        let rendering_complete_semaphore = device
+            .create_semaphore(&semaphore_create_info, None)
+            .unwrap();
+
+        let command_buffer_pool =
+            CommandBufferPool::new(&device, queue_family_index, NUM_COMMAND_BUFFERS);
+
+        // Headless render passes end in a layout the readback copy can use directly
+        let present_layout = if offscreen_image.is_some() {
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL
+        } else {
+            vk::ImageLayout::PRESENT_SRC_KHR
+        };
+
+        let vk = VulkanBase {
+            entry,
+            instance,
+            device,
+            queue_family_index,
+            pdevice,
+            surface_loader,
+            surface_format,
+            present_queue,
+            surface_resolution,
+            swapchain_loader,
+            swapchain,
+            present_images,
+            present_image_views,
+            present_layout,
+            present_image_readback,
+            offscreen_image,
+            feature_support,
+            depth_image,
+            depth_image_view,
+            present_complete_semaphore,
+            rendering_complete_semaphore,
+            surface,
+            debug_call_back,
+            debug_utils_loader,
+            command_buffer_pool,
+            allocator: ManuallyDrop::new(allocator),
+        };
+
+        vk.record_submit_commandbuffer(
+            0,
+            present_queue,
+            &[],
+            &[],
+            &[],
+            |device, setup_command_buffer| {
+                let layout_transition_barriers = vk::ImageMemoryBarrier::builder()
+                    .image(vk.depth_image.image)
+                    .dst_access_mask(
+                        vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                            | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                    )
+                    .new_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                    .old_layout(vk::ImageLayout::UNDEFINED)
+                    .subresource_range(
+                        vk::ImageSubresourceRange::builder()
+                            .aspect_mask(vk::ImageAspectFlags::DEPTH)
+                            .layer_count(1)
+                            .level_count(1)
+                            .build(),
+                    );
+
+                device.cmd_pipeline_barrier(
+                    setup_command_buffer,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[layout_transition_barriers.build()],
+                );
+            },
+        );
+
+        vk
+    }
+
+    pub fn is_headless(&self) -> bool {
+        self.offscreen_image.is_some()
+    }
+
+    // Signals present_complete_semaphore. Headless mode always renders to image 0.
+    pub fn acquire_next_image(&self) -> u32 {
+        unsafe {
+            if self.is_headless() {
+                let submit_info = vk::SubmitInfo::builder()
+                    .signal_semaphores(std::slice::from_ref(&self.present_complete_semaphore));
+                self.device
+                    .queue_submit(
+                        self.present_queue,
+                        &[submit_info.build()],
+                        vk::Fence::null(),
+                    )
+                    .expect("queue submit failed.");
+                return 0;
+            }
+
+            let (present_index, _) = self
+                .swapchain_loader
+                .acquire_next_image(
+                    self.swapchain,
+                    u64::MAX,
+                    self.present_complete_semaphore,
+                    vk::Fence::null(),
+                )
+                .unwrap();
+            present_index
+        }
+    }
+
+    // Waits for rendering_complete_semaphore
+    pub fn present(&self, present_index: u32) {
+        unsafe {
+            if self.is_headless() {
+                let wait_mask = [vk::PipelineStageFlags::ALL_COMMANDS];
+                let submit_info = vk::SubmitInfo::builder()
+                    .wait_semaphores(std::slice::from_ref(&self.rendering_complete_semaphore))
+                    .wait_dst_stage_mask(&wait_mask);
+                self.device
+                    .queue_submit(
+                        self.present_queue,
+                        &[submit_info.build()],
+                        vk::Fence::null(),
+                    )
+                    .expect("queue submit failed.");
+                return;
+            }
+
+            let present_info = vk::PresentInfoKHR {
+                wait_semaphore_count: 1,
+                p_wait_semaphores: &self.rendering_complete_semaphore,
+                swapchain_count: 1,
+                p_swapchains: &self.swapchain,
+                p_image_indices: &present_index,
+                ..Default::default()
             };
 
-            vk.record_submit_commandbuffer(
-                0,
-                present_queue,
-                &[],
-                &[],
-                &[],
-                |device, setup_command_buffer| {
-                    let layout_transition_barriers = vk::ImageMemoryBarrier::builder()
-                        .image(vk.depth_image.image)
-                        .dst_access_mask(
-                            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
-                                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
-                        )
-                        .new_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
-                        .old_layout(vk::ImageLayout::UNDEFINED)
-                        .subresource_range(
-                            vk::ImageSubresourceRange::builder()
-                                .aspect_mask(vk::ImageAspectFlags::DEPTH)
-                                .layer_count(1)
-                                .level_count(1)
-                                .build(),
-                        );
+            self.swapchain_loader
+                .queue_present(self.present_queue, &present_info)
+                .unwrap();
+        }
+    }
 
+    // Copies a rendered present image (in present_layout) to the CPU. Call before present, the
+    // swapchain owns the image afterwards. Waits for the GPU to idle. None if the swapchain
+    // doesn't support transfer source usage or has no RGBA8 conversion for its format.
+    pub fn read_present_image(&mut self, present_index: u32) -> Option<image::RgbaImage> {
+        if !self.present_image_readback {
+            return None;
+        }
+
+        let resolution = self.surface_resolution;
+        let format = self.surface_format.format;
+        if !is_readback_format(format) {
+            println!("Unsupported readback format: {:?}", format);
+            return None;
+        }
+        let layout = self.present_layout;
+        let image = self.present_images[present_index as usize];
+
+        let buffer_info = vk::BufferCreateInfo {
+            size: resolution.width as u64 * resolution.height as u64 * 4,
+            usage: vk::BufferUsageFlags::TRANSFER_DST,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            ..Default::default()
+        };
+        let mut buffer = VkBuffer::new(
+            &self.device,
+            &mut self.allocator,
+            &buffer_info,
+            MemoryLocation::GpuToCpu,
+        );
+
+        unsafe { self.device.device_wait_idle().unwrap() };
+
+        self.record_submit_commandbuffer(
+            0,
+            self.present_queue,
+            &[],
+            &[],
+            &[],
+            |device, command_buffer| {
+                let subresource_range = vk::ImageSubresourceRange::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .layer_count(1)
+                    .level_count(1)
+                    .build();
+                let barrier = |old_layout, new_layout, src_access_mask, dst_access_mask| {
+                    vk::ImageMemoryBarrier::builder()
+                        .image(image)
+                        .src_access_mask(src_access_mask)
+                        .dst_access_mask(dst_access_mask)
+                        .old_layout(old_layout)
+                        .new_layout(new_layout)
+                        .subresource_range(subresource_range)
+                        .build()
+                };
+
+                let region = vk::BufferImageCopy::builder()
+                    .image_subresource(
+                        vk::ImageSubresourceLayers::builder()
+                            .aspect_mask(vk::ImageAspectFlags::COLOR)
+                            .layer_count(1)
+                            .build(),
+                    )
+                    .image_extent(vk::Extent3D {
+                        width: resolution.width,
+                        height: resolution.height,
+                        depth: 1,
+                    });
+
+                unsafe {
                     device.cmd_pipeline_barrier(
-                        setup_command_buffer,
+                        command_buffer,
+                        vk::PipelineStageFlags::ALL_COMMANDS,
+                        vk::PipelineStageFlags::TRANSFER,
+                        vk::DependencyFlags::empty(),
+                        &[],
+                        &[],
+                        &[barrier(
+                            layout,
+                            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                            vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                            vk::AccessFlags::TRANSFER_READ,
+                        )],
+                    );
+                    device.cmd_copy_image_to_buffer(
+                        command_buffer,
+                        image,
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        buffer.buffer,
+                        &[region.build()],
+                    );
+                    device.cmd_pipeline_barrier(
+                        command_buffer,
+                        vk::PipelineStageFlags::TRANSFER,
                         vk::PipelineStageFlags::BOTTOM_OF_PIPE,
-                        vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
                         vk::DependencyFlags::empty(),
                         &[],
                         &[],
-                        &[layout_transition_barriers.build()],
+                        &[barrier(
+                            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                            layout,
+                            vk::AccessFlags::TRANSFER_READ,
+                            vk::AccessFlags::empty(),
+                        )],
                     );
-                },
-            );
+                }
+            },
+        );
 
-            vk
-        }
+        unsafe { self.device.device_wait_idle().unwrap() };
+
+        let mut pixels =
+            unsafe { std::slice::from_raw_parts(buffer.mapped_ptr, buffer.size as usize).to_vec() };
+        buffer.destroy(&self.device, &mut self.allocator);
+
+        convert_readback_pixels(format, &mut pixels);
+        image::RgbaImage::from_raw(resolution.width, resolution.height, pixels)
     }
 
     pub fn record_submit_commandbuffer<F: FnOnce(&Device, vk::CommandBuffer)>(
@@ -516,7 +1007,7 @@ impl VulkanBase {
                 self.command_buffer_pool.command_buffers[active_command_buffer].command_buffer;
 
             self.device
-                .wait_for_fences(&[submit_fence], true, std::u64::MAX)
+                .wait_for_fences(&[submit_fence], true, u64::MAX)
                 .expect("Wait for fence failed.");
 
             self.device
@@ -580,16 +1071,58 @@ impl Drop for VulkanBase {
             for &image_view in self.present_image_views.iter() {
                 self.device.destroy_image_view(image_view, None);
             }
-            self.swapchain_loader
-                .destroy_swapchain(self.swapchain, None);
+            match self.offscreen_image.as_mut() {
+                Some(image) => image.destroy(&self.device, &mut self.allocator),
+                None => self
+                    .swapchain_loader
+                    .destroy_swapchain(self.swapchain, None),
+            }
 
             ManuallyDrop::drop(&mut self.allocator);
 
             self.device.destroy_device(None);
-            self.surface_loader.destroy_surface(self.surface, None);
+            if !self.is_headless() {
+                self.surface_loader.destroy_surface(self.surface, None);
+            }
             self.debug_utils_loader
                 .destroy_debug_utils_messenger(self.debug_call_back, None);
             self.instance.destroy_instance(None);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn readback_conversions() {
+        let convert = |format, bytes: [u8; 4]| {
+            let mut pixels = bytes.to_vec();
+            convert_readback_pixels(format, &mut pixels);
+            pixels
+        };
+        assert_eq!(
+            convert(vk::Format::R8G8B8A8_SRGB, [1, 2, 3, 4]),
+            [1, 2, 3, 4]
+        );
+        assert_eq!(
+            convert(vk::Format::B8G8R8A8_UNORM, [1, 2, 3, 4]),
+            [3, 2, 1, 4]
+        );
+
+        // R = 1023, G = 512, B = 0, A = 3
+        let packed: u32 = 1023 | (512 << 10) | (3 << 30);
+        assert_eq!(
+            convert(vk::Format::A2B10G10R10_UNORM_PACK32, packed.to_le_bytes()),
+            [255, 128, 0, 255]
+        );
+        let packed: u32 = (1023 << 20) | (512 << 10) | (1 << 30);
+        assert_eq!(
+            convert(vk::Format::A2R10G10B10_UNORM_PACK32, packed.to_le_bytes()),
+            [255, 128, 0, 85]
+        );
+
+        assert!(!is_readback_format(vk::Format::R16G16B16A16_SFLOAT));
+    }
+}
@@ -138,10 +138,5 @@ pub fn raw_bytes<T>(data: &[T]) -> &[u8]
 where
     T: Copy,
 {
-    unsafe {
-        from_raw_parts(
-            data.as_ptr() as *const u8,
-            data.len() * std::mem::size_of::<T>(),
-        )
-    }
+    unsafe { from_raw_parts(data.as_ptr() as *const u8, std::mem::size_of_val(data)) }
 }
@@ -0,0 +1,93 @@
+// Golden image regression test of the CPU reference tracer (sdf_tracer.rs). The reference
+// image is regenerated with (cmd):
+// cargo run --release --bin sdftool trace tests/data/golden.sdf tests/data/golden.png -s 160 120 -c 0 1.4 2.8 -fov 45
+
+use rust_test::minivector::*;
+use rust_test::sdf::*;
+use rust_test::sdf_mips::*;
+use rust_test::sdf_tracer::*;
+
+const WIDTH: u32 = 160;
+const HEIGHT: u32 = 120;
+
+// Channel difference allowed for float differences between platforms
+const TOLERANCE: u8 = 2;
+
+fn data_file(name: &str) -> String {
+    format!("{}/tests/data/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+// sdftool trace defaults, camera of the command line above
+fn render(position: Vec3) -> Vec<u8> {
+    let sdf = load_any_sdf(&data_file("golden.sdf")).unwrap();
+    let tracer = SdfTracer::new(
+        &sdf,
+        TRACE_DEFAULT_LEVELS,
+        DEFAULT_MIP_FILTER,
+        Vec3::from_scalar(0.0),
+    );
+    let camera = TraceCamera {
+        position,
+        direction: Vec3 {
+            x: 0.0,
+            y: -0.5,
+            z: -1.0,
+        }
+        .normalize(),
+        fovy: 45.0f32.to_radians(),
+    };
+    tracer.render(&camera, WIDTH, HEIGHT)
+}
+
+fn golden_camera() -> Vec3 {
+    Vec3 {
+        x: 0.0,
+        y: 1.4,
+        z: 2.8,
+    }
+}
+
+#[test]
+fn matches_golden_image() {
+    let pixels = render(golden_camera());
+
+    // Surface pixels (alpha = 1), the image must not be empty
+    let surface_pixels = pixels.chunks_exact(4).filter(|p| p[3] == 255).count();
+    assert!(surface_pixels > (WIDTH * HEIGHT / 10) as usize);
+
+    let diff = compare_png(&data_file("golden.png"), WIDTH, HEIGHT, &pixels, TOLERANCE)
+        .unwrap()
+        .expect("Golden image size mismatch");
+    // A few silhouette pixels may flip
+    assert!(
+        diff.different_pixels <= (WIDTH * HEIGHT / 500) as usize,
+        "Different pixels: {}, max difference: {}",
+        diff.different_pixels,
+        diff.max_difference
+    );
+}
+
+#[test]
+fn detects_differences() {
+    let moved = golden_camera()
+        + Vec3 {
+            x: 0.2,
+            y: 0.0,
+            z: 0.0,
+        };
+    let pixels = render(moved);
+    let diff = compare_png(&data_file("golden.png"), WIDTH, HEIGHT, &pixels, TOLERANCE)
+        .unwrap()
+        .unwrap();
+    assert!(diff.different_pixels > (WIDTH * HEIGHT / 100) as usize);
+
+    // Size mismatch
+    let diff = compare_png(
+        &data_file("golden.png"),
+        WIDTH / 2,
+        HEIGHT,
+        &pixels,
+        TOLERANCE,
+    );
+    assert!(diff.unwrap().is_none());
+}